@@ -0,0 +1,23 @@
+-- Client-encrypted attachments. `size` is the declared total length;
+-- `received` tracks resumable upload progress.
+CREATE TABLE attachments (
+    id TEXT PRIMARY KEY,
+    uploader_id UUID NOT NULL REFERENCES users(id),
+    size BIGINT NOT NULL,
+    received BIGINT NOT NULL DEFAULT 0,
+    referenced BOOLEAN NOT NULL DEFAULT FALSE,
+    created_at TIMESTAMPTZ NOT NULL,
+    completed_at TIMESTAMPTZ,
+    expires_at TIMESTAMPTZ NOT NULL
+);
+
+CREATE INDEX attachments_uploader_idx ON attachments (uploader_id);
+CREATE INDEX attachments_expires_at_idx ON attachments (expires_at);
+
+CREATE TABLE message_attachments (
+    message_id UUID NOT NULL REFERENCES messages(id),
+    attachment_id TEXT NOT NULL REFERENCES attachments(id) ON DELETE CASCADE,
+    PRIMARY KEY (message_id, attachment_id)
+);
+
+CREATE INDEX message_attachments_attachment_idx ON message_attachments (attachment_id);
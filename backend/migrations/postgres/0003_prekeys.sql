@@ -0,0 +1,19 @@
+-- X3DH prekeys. Each device has one current signed prekey and a pool of
+-- one-time prekeys, each of which is handed out at most once.
+CREATE TABLE signed_prekeys (
+    device_id UUID PRIMARY KEY REFERENCES devices(id) ON DELETE CASCADE,
+    key_id BIGINT NOT NULL,
+    public_key BYTEA NOT NULL,
+    signature BYTEA NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL
+);
+
+CREATE TABLE one_time_prekeys (
+    device_id UUID NOT NULL REFERENCES devices(id) ON DELETE CASCADE,
+    key_id BIGINT NOT NULL,
+    public_key BYTEA NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL,
+    PRIMARY KEY (device_id, key_id)
+);
+
+CREATE INDEX devices_user_idx ON devices (user_id);
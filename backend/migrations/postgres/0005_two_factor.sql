@@ -0,0 +1,21 @@
+-- Argon2 password hashes (PHC string format). Accounts created before this
+-- migration have none and cannot log in with a password.
+ALTER TABLE users ADD COLUMN password_hash TEXT;
+
+-- TOTP second factor. A row with enabled_at NULL is an enrollment that has
+-- not been confirmed with a code yet. last_used_step prevents code replay.
+CREATE TABLE totp_secrets (
+    user_id UUID PRIMARY KEY REFERENCES users(id) ON DELETE CASCADE,
+    secret BYTEA NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL,
+    enabled_at TIMESTAMPTZ,
+    last_used_step BIGINT
+);
+
+-- Single-use recovery codes, stored as SHA-256 hashes.
+CREATE TABLE recovery_codes (
+    user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    code_hash TEXT NOT NULL,
+    used_at TIMESTAMPTZ,
+    PRIMARY KEY (user_id, code_hash)
+);
@@ -0,0 +1,16 @@
+-- Accounts must confirm their email address. Accounts created before this
+-- migration are treated as verified.
+ALTER TABLE users ADD COLUMN email_verified_at TIMESTAMPTZ;
+UPDATE users SET email_verified_at = created_at;
+
+-- Single-use tokens sent by email, stored as SHA-256 hashes and deleted
+-- when used. purpose is 'verify_email' or 'reset_password'.
+CREATE TABLE email_tokens (
+    token_hash TEXT PRIMARY KEY,
+    user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    purpose TEXT NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL,
+    expires_at TIMESTAMPTZ NOT NULL
+);
+
+CREATE INDEX email_tokens_user_idx ON email_tokens (user_id, purpose);
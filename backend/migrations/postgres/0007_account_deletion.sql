@@ -0,0 +1,9 @@
+-- Accounts whose owner asked for deletion. The account and everything it
+-- owns is removed once delete_after has passed; logging in before then
+-- cancels the deletion.
+ALTER TABLE users ADD COLUMN delete_after TIMESTAMPTZ;
+
+CREATE INDEX users_delete_after_idx ON users (delete_after);
+CREATE INDEX messages_sender_idx ON messages (sender_id);
+CREATE INDEX messages_recipient_idx ON messages (recipient_id);
+CREATE INDEX chat_members_user_idx ON chat_members (user_id);
@@ -0,0 +1,18 @@
+-- Privacy settings. email_hash is the hex SHA-256 of the normalized email
+-- address, used for hashed contact discovery; it is only set while the user
+-- is discoverable by email.
+ALTER TABLE users ADD COLUMN discoverable_by_username BOOLEAN NOT NULL DEFAULT TRUE;
+ALTER TABLE users ADD COLUMN email_hash TEXT;
+
+CREATE UNIQUE INDEX users_email_hash_idx ON users (email_hash);
+
+-- Each user's contact list.
+CREATE TABLE contacts (
+    user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    contact_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    nickname TEXT,
+    created_at TIMESTAMPTZ NOT NULL,
+    PRIMARY KEY (user_id, contact_id)
+);
+
+CREATE INDEX contacts_contact_idx ON contacts (contact_id);
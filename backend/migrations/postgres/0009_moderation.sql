@@ -0,0 +1,33 @@
+ALTER TABLE users ADD COLUMN is_admin BOOLEAN NOT NULL DEFAULT FALSE;
+ALTER TABLE users ADD COLUMN suspended_at TIMESTAMPTZ;
+
+-- Messages from blocked_id to user_id are dropped.
+CREATE TABLE blocks (
+    user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    blocked_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    created_at TIMESTAMPTZ NOT NULL,
+    PRIMARY KEY (user_id, blocked_id)
+);
+
+CREATE INDEX blocks_blocked_idx ON blocks (blocked_id);
+
+-- Abuse reports. evidence is plaintext the reporter decrypted and chose to
+-- submit; the server cannot check it against the encrypted message.
+CREATE TABLE reports (
+    id UUID PRIMARY KEY,
+    reporter_id UUID REFERENCES users(id) ON DELETE SET NULL,
+    reported_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    message_id UUID,
+    category TEXT NOT NULL,
+    description TEXT,
+    evidence TEXT,
+    status TEXT NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL,
+    resolved_at TIMESTAMPTZ,
+    resolved_by UUID REFERENCES users(id) ON DELETE SET NULL,
+    resolution_note TEXT
+);
+
+CREATE INDEX reports_status_idx ON reports (status, created_at);
+CREATE INDEX reports_reporter_idx ON reports (reporter_id);
+CREATE INDEX reports_reported_idx ON reports (reported_id);
@@ -0,0 +1,6 @@
+-- What a message is: ordinary content or a control message such as a
+-- receipt. The content itself stays encrypted either way.
+ALTER TABLE messages ADD COLUMN kind TEXT NOT NULL DEFAULT 'message';
+
+-- Whether the user's clients send read receipts.
+ALTER TABLE users ADD COLUMN send_read_receipts BOOLEAN NOT NULL DEFAULT TRUE;
@@ -0,0 +1,5 @@
+-- The message that an edit, deletion or reaction refers to. Stored in the
+-- clear so that the server can check who may edit or delete it.
+ALTER TABLE messages ADD COLUMN target_id UUID;
+
+CREATE INDEX messages_target_idx ON messages (target_id);
@@ -0,0 +1,16 @@
+-- The chat a message belongs to and the thread within it, if any. Stored in
+-- the clear so that the server can list a thread's messages; who replied to
+-- what is only in the encrypted content.
+ALTER TABLE messages ADD COLUMN chat_id UUID;
+ALTER TABLE messages ADD COLUMN thread_id UUID;
+
+CREATE INDEX messages_thread_idx ON messages (recipient_id, chat_id, thread_id, created_at);
+
+-- How far each member has read each thread.
+CREATE TABLE thread_reads (
+    user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    chat_id UUID NOT NULL REFERENCES chats(id) ON DELETE CASCADE,
+    thread_id UUID NOT NULL,
+    read_at TIMESTAMPTZ NOT NULL,
+    PRIMARY KEY (user_id, chat_id, thread_id)
+);
@@ -0,0 +1,26 @@
+-- Links that let users join a group. Codes are meant to be shared, so they
+-- are stored as they are and shown to the group's admins.
+CREATE TABLE chat_invites (
+    id UUID PRIMARY KEY,
+    chat_id UUID NOT NULL REFERENCES chats(id) ON DELETE CASCADE,
+    code TEXT NOT NULL UNIQUE,
+    created_by UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    created_at TIMESTAMPTZ NOT NULL,
+    expires_at TIMESTAMPTZ,
+    max_uses BIGINT,
+    use_count BIGINT NOT NULL DEFAULT 0,
+    requires_approval BOOLEAN NOT NULL,
+    revoked_at TIMESTAMPTZ
+);
+
+CREATE INDEX chat_invites_chat_idx ON chat_invites (chat_id);
+
+-- Users waiting for an admin to let them in through an invite that needs
+-- approval.
+CREATE TABLE join_requests (
+    chat_id UUID NOT NULL REFERENCES chats(id) ON DELETE CASCADE,
+    user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    invite_id UUID NOT NULL REFERENCES chat_invites(id) ON DELETE CASCADE,
+    created_at TIMESTAMPTZ NOT NULL,
+    PRIMARY KEY (chat_id, user_id)
+);
@@ -0,0 +1,15 @@
+-- Channels are groups where only admins post, to read-only subscribers.
+ALTER TABLE chats ADD COLUMN is_channel BOOLEAN NOT NULL DEFAULT FALSE;
+
+-- A channel post is stored once, encrypted under a key the channel's
+-- members share, instead of once per subscriber like messages.
+CREATE TABLE channel_posts (
+    id UUID PRIMARY KEY,
+    chat_id UUID NOT NULL REFERENCES chats(id) ON DELETE CASCADE,
+    sender_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    content BYTEA NOT NULL,
+    associated_data BYTEA,
+    created_at TIMESTAMPTZ NOT NULL
+);
+
+CREATE INDEX channel_posts_chat_idx ON channel_posts (chat_id, created_at);
@@ -0,0 +1,26 @@
+-- Settings every member of a chat sees; admins change them.
+ALTER TABLE chats ADD COLUMN topic TEXT;
+ALTER TABLE chats ADD COLUMN avatar TEXT;
+ALTER TABLE chats ADD COLUMN disappear_after_seconds BIGINT;
+
+-- Each member mutes a chat for themselves.
+ALTER TABLE chat_members ADD COLUMN muted_until TIMESTAMPTZ;
+
+-- Pinned messages are named inside encrypted content, since every member
+-- holds a different copy of a message.
+CREATE TABLE chat_pins (
+    id UUID PRIMARY KEY,
+    chat_id UUID NOT NULL REFERENCES chats(id) ON DELETE CASCADE,
+    pinned_by UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    content BYTEA NOT NULL,
+    pinned_at TIMESTAMPTZ NOT NULL
+);
+
+CREATE INDEX chat_pins_chat_idx ON chat_pins (chat_id, pinned_at);
+
+CREATE TABLE starred_messages (
+    user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    message_id UUID NOT NULL REFERENCES messages(id) ON DELETE CASCADE,
+    starred_at TIMESTAMPTZ NOT NULL,
+    PRIMARY KEY (user_id, message_id)
+);
@@ -0,0 +1,25 @@
+-- Bots are users without a password or mailbox, managed by the user who
+-- created them. They authenticate with API keys instead of sessions.
+ALTER TABLE users ADD COLUMN bot_owner_id UUID REFERENCES users(id);
+
+CREATE INDEX users_bot_owner_idx ON users (bot_owner_id);
+
+-- Only a hash of each key is kept; the key itself is shown once.
+CREATE TABLE api_keys (
+    id UUID PRIMARY KEY,
+    bot_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    device_id UUID NOT NULL REFERENCES devices(id) ON DELETE CASCADE,
+    key_hash TEXT NOT NULL UNIQUE,
+    created_at TIMESTAMPTZ NOT NULL,
+    revoked_at TIMESTAMPTZ
+);
+
+CREATE INDEX api_keys_bot_idx ON api_keys (bot_id);
+
+-- Where messages addressed to a bot are posted, signed with the secret.
+CREATE TABLE bot_webhooks (
+    bot_id UUID PRIMARY KEY REFERENCES users(id) ON DELETE CASCADE,
+    url TEXT NOT NULL,
+    secret TEXT NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL
+);
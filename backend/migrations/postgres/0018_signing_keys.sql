@@ -0,0 +1,11 @@
+-- Key pairs that sign access tokens, named by the `kid` header of the
+-- tokens they sign. The newest key whose activation time has passed signs;
+-- older ones only verify until their tokens have expired.
+CREATE TABLE signing_keys (
+    id TEXT PRIMARY KEY,
+    algorithm TEXT NOT NULL,
+    private_key BYTEA NOT NULL,
+    public_key BYTEA NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL,
+    activates_at TIMESTAMPTZ NOT NULL
+);
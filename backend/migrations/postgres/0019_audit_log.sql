@@ -0,0 +1,29 @@
+-- Security-relevant events, append-only. Each entry carries the hash of the
+-- one before it, so editing or removing an entry breaks the chain from
+-- there on. `user_id` has no foreign key: entries outlive the account.
+CREATE TABLE audit_events (
+    id BIGINT PRIMARY KEY,
+    kind TEXT NOT NULL,
+    user_id UUID,
+    actor_id UUID,
+    device_id UUID,
+    details TEXT NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL,
+    prev_hash TEXT NOT NULL,
+    hash TEXT NOT NULL
+);
+
+CREATE INDEX audit_events_user_idx ON audit_events (user_id, id);
+CREATE INDEX audit_events_created_idx ON audit_events (created_at);
+
+CREATE FUNCTION audit_events_append_only() RETURNS trigger AS $$
+BEGIN
+    RAISE EXCEPTION 'audit_events is append-only';
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER audit_events_no_change BEFORE UPDATE OR DELETE ON audit_events
+    FOR EACH ROW EXECUTE FUNCTION audit_events_append_only();
+
+CREATE TRIGGER audit_events_no_truncate BEFORE TRUNCATE ON audit_events
+    FOR EACH STATEMENT EXECUTE FUNCTION audit_events_append_only();
@@ -0,0 +1,6 @@
+-- Reports outlive the reported account, so that deleting it does not erase
+-- the reports against it.
+ALTER TABLE reports ALTER COLUMN reported_id DROP NOT NULL;
+ALTER TABLE reports DROP CONSTRAINT reports_reported_id_fkey;
+ALTER TABLE reports ADD CONSTRAINT reports_reported_id_fkey
+    FOREIGN KEY (reported_id) REFERENCES users(id) ON DELETE SET NULL;
@@ -0,0 +1,11 @@
+-- Wrong codes entered at the second step of a login, counted per MFA token
+-- and per user to stop guessing. Rows older than the counting window are
+-- removed as new ones come in.
+CREATE TABLE mfa_failures (
+    token_id UUID NOT NULL,
+    user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    failed_at TIMESTAMPTZ NOT NULL
+);
+
+CREATE INDEX mfa_failures_token_idx ON mfa_failures (token_id);
+CREATE INDEX mfa_failures_user_idx ON mfa_failures (user_id, failed_at);
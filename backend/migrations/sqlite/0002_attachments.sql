@@ -0,0 +1,26 @@
+-- Client-encrypted attachments. `size` is the declared total length;
+-- `received` tracks resumable upload progress.
+CREATE TABLE attachments (
+    id TEXT PRIMARY KEY,
+    uploader_id TEXT NOT NULL,
+    size INTEGER NOT NULL,
+    received INTEGER NOT NULL DEFAULT 0,
+    referenced BOOLEAN NOT NULL DEFAULT 0,
+    created_at TEXT NOT NULL,
+    completed_at TEXT,
+    expires_at TEXT NOT NULL,
+    FOREIGN KEY (uploader_id) REFERENCES users(id)
+);
+
+CREATE INDEX attachments_uploader_idx ON attachments (uploader_id);
+CREATE INDEX attachments_expires_at_idx ON attachments (expires_at);
+
+CREATE TABLE message_attachments (
+    message_id TEXT NOT NULL,
+    attachment_id TEXT NOT NULL,
+    PRIMARY KEY (message_id, attachment_id),
+    FOREIGN KEY (message_id) REFERENCES messages(id),
+    FOREIGN KEY (attachment_id) REFERENCES attachments(id) ON DELETE CASCADE
+);
+
+CREATE INDEX message_attachments_attachment_idx ON message_attachments (attachment_id);
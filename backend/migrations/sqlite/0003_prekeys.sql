@@ -0,0 +1,21 @@
+-- X3DH prekeys. Each device has one current signed prekey and a pool of
+-- one-time prekeys, each of which is handed out at most once.
+CREATE TABLE signed_prekeys (
+    device_id TEXT PRIMARY KEY,
+    key_id INTEGER NOT NULL,
+    public_key BLOB NOT NULL,
+    signature BLOB NOT NULL,
+    created_at TEXT NOT NULL,
+    FOREIGN KEY (device_id) REFERENCES devices(id) ON DELETE CASCADE
+);
+
+CREATE TABLE one_time_prekeys (
+    device_id TEXT NOT NULL,
+    key_id INTEGER NOT NULL,
+    public_key BLOB NOT NULL,
+    created_at TEXT NOT NULL,
+    PRIMARY KEY (device_id, key_id),
+    FOREIGN KEY (device_id) REFERENCES devices(id) ON DELETE CASCADE
+);
+
+CREATE INDEX devices_user_idx ON devices (user_id);
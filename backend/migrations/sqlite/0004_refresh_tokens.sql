@@ -0,0 +1,11 @@
+-- Sessions now hold a rotating refresh token instead of the access token.
+-- Only SHA-256 hashes are stored; the previous hash is kept to detect reuse
+-- of a rotated-out token. Existing sessions cannot match and must log in
+-- again.
+ALTER TABLE sessions RENAME COLUMN token TO refresh_token_hash;
+ALTER TABLE sessions ADD COLUMN previous_refresh_token_hash TEXT;
+ALTER TABLE sessions ADD COLUMN last_used_at TEXT;
+ALTER TABLE sessions ADD COLUMN revoked_at TEXT;
+
+CREATE INDEX sessions_user_idx ON sessions (user_id);
+CREATE INDEX sessions_previous_refresh_token_idx ON sessions (previous_refresh_token_hash);
@@ -0,0 +1,23 @@
+-- Argon2 password hashes (PHC string format). Accounts created before this
+-- migration have none and cannot log in with a password.
+ALTER TABLE users ADD COLUMN password_hash TEXT;
+
+-- TOTP second factor. A row with enabled_at NULL is an enrollment that has
+-- not been confirmed with a code yet. last_used_step prevents code replay.
+CREATE TABLE totp_secrets (
+    user_id TEXT PRIMARY KEY,
+    secret BLOB NOT NULL,
+    created_at TEXT NOT NULL,
+    enabled_at TEXT,
+    last_used_step INTEGER,
+    FOREIGN KEY (user_id) REFERENCES users(id) ON DELETE CASCADE
+);
+
+-- Single-use recovery codes, stored as SHA-256 hashes.
+CREATE TABLE recovery_codes (
+    user_id TEXT NOT NULL,
+    code_hash TEXT NOT NULL,
+    used_at TEXT,
+    PRIMARY KEY (user_id, code_hash),
+    FOREIGN KEY (user_id) REFERENCES users(id) ON DELETE CASCADE
+);
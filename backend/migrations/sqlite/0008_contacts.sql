@@ -0,0 +1,20 @@
+-- Privacy settings. email_hash is the hex SHA-256 of the normalized email
+-- address, used for hashed contact discovery; it is only set while the user
+-- is discoverable by email.
+ALTER TABLE users ADD COLUMN discoverable_by_username BOOLEAN NOT NULL DEFAULT 1;
+ALTER TABLE users ADD COLUMN email_hash TEXT;
+
+CREATE UNIQUE INDEX users_email_hash_idx ON users (email_hash);
+
+-- Each user's contact list.
+CREATE TABLE contacts (
+    user_id TEXT NOT NULL,
+    contact_id TEXT NOT NULL,
+    nickname TEXT,
+    created_at TEXT NOT NULL,
+    PRIMARY KEY (user_id, contact_id),
+    FOREIGN KEY (user_id) REFERENCES users(id) ON DELETE CASCADE,
+    FOREIGN KEY (contact_id) REFERENCES users(id) ON DELETE CASCADE
+);
+
+CREATE INDEX contacts_contact_idx ON contacts (contact_id);
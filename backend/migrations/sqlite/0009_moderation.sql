@@ -0,0 +1,38 @@
+ALTER TABLE users ADD COLUMN is_admin BOOLEAN NOT NULL DEFAULT 0;
+ALTER TABLE users ADD COLUMN suspended_at TEXT;
+
+-- Messages from blocked_id to user_id are dropped.
+CREATE TABLE blocks (
+    user_id TEXT NOT NULL,
+    blocked_id TEXT NOT NULL,
+    created_at TEXT NOT NULL,
+    PRIMARY KEY (user_id, blocked_id),
+    FOREIGN KEY (user_id) REFERENCES users(id) ON DELETE CASCADE,
+    FOREIGN KEY (blocked_id) REFERENCES users(id) ON DELETE CASCADE
+);
+
+CREATE INDEX blocks_blocked_idx ON blocks (blocked_id);
+
+-- Abuse reports. evidence is plaintext the reporter decrypted and chose to
+-- submit; the server cannot check it against the encrypted message.
+CREATE TABLE reports (
+    id TEXT PRIMARY KEY,
+    reporter_id TEXT,
+    reported_id TEXT NOT NULL,
+    message_id TEXT,
+    category TEXT NOT NULL,
+    description TEXT,
+    evidence TEXT,
+    status TEXT NOT NULL,
+    created_at TEXT NOT NULL,
+    resolved_at TEXT,
+    resolved_by TEXT,
+    resolution_note TEXT,
+    FOREIGN KEY (reporter_id) REFERENCES users(id) ON DELETE SET NULL,
+    FOREIGN KEY (reported_id) REFERENCES users(id) ON DELETE CASCADE,
+    FOREIGN KEY (resolved_by) REFERENCES users(id) ON DELETE SET NULL
+);
+
+CREATE INDEX reports_status_idx ON reports (status, created_at);
+CREATE INDEX reports_reporter_idx ON reports (reporter_id);
+CREATE INDEX reports_reported_idx ON reports (reported_id);
@@ -0,0 +1,3 @@
+-- Whether others can see when the user is online and when they were last
+-- seen.
+ALTER TABLE users ADD COLUMN show_last_seen BOOLEAN NOT NULL DEFAULT 1;
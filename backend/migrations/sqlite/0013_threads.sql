@@ -0,0 +1,18 @@
+-- The chat a message belongs to and the thread within it, if any. Stored in
+-- the clear so that the server can list a thread's messages; who replied to
+-- what is only in the encrypted content.
+ALTER TABLE messages ADD COLUMN chat_id TEXT;
+ALTER TABLE messages ADD COLUMN thread_id TEXT;
+
+CREATE INDEX messages_thread_idx ON messages (recipient_id, chat_id, thread_id, created_at);
+
+-- How far each member has read each thread.
+CREATE TABLE thread_reads (
+    user_id TEXT NOT NULL,
+    chat_id TEXT NOT NULL,
+    thread_id TEXT NOT NULL,
+    read_at TEXT NOT NULL,
+    PRIMARY KEY (user_id, chat_id, thread_id),
+    FOREIGN KEY (user_id) REFERENCES users(id) ON DELETE CASCADE,
+    FOREIGN KEY (chat_id) REFERENCES chats(id) ON DELETE CASCADE
+);
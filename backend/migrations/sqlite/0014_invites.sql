@@ -0,0 +1,31 @@
+-- Links that let users join a group. Codes are meant to be shared, so they
+-- are stored as they are and shown to the group's admins.
+CREATE TABLE chat_invites (
+    id TEXT PRIMARY KEY,
+    chat_id TEXT NOT NULL,
+    code TEXT NOT NULL UNIQUE,
+    created_by TEXT NOT NULL,
+    created_at TEXT NOT NULL,
+    expires_at TEXT,
+    max_uses INTEGER,
+    use_count INTEGER NOT NULL DEFAULT 0,
+    requires_approval BOOLEAN NOT NULL,
+    revoked_at TEXT,
+    FOREIGN KEY (chat_id) REFERENCES chats(id) ON DELETE CASCADE,
+    FOREIGN KEY (created_by) REFERENCES users(id) ON DELETE CASCADE
+);
+
+CREATE INDEX chat_invites_chat_idx ON chat_invites (chat_id);
+
+-- Users waiting for an admin to let them in through an invite that needs
+-- approval.
+CREATE TABLE join_requests (
+    chat_id TEXT NOT NULL,
+    user_id TEXT NOT NULL,
+    invite_id TEXT NOT NULL,
+    created_at TEXT NOT NULL,
+    PRIMARY KEY (chat_id, user_id),
+    FOREIGN KEY (chat_id) REFERENCES chats(id) ON DELETE CASCADE,
+    FOREIGN KEY (user_id) REFERENCES users(id) ON DELETE CASCADE,
+    FOREIGN KEY (invite_id) REFERENCES chat_invites(id) ON DELETE CASCADE
+);
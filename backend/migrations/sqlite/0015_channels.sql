@@ -0,0 +1,17 @@
+-- Channels are groups where only admins post, to read-only subscribers.
+ALTER TABLE chats ADD COLUMN is_channel BOOLEAN NOT NULL DEFAULT 0;
+
+-- A channel post is stored once, encrypted under a key the channel's
+-- members share, instead of once per subscriber like messages.
+CREATE TABLE channel_posts (
+    id TEXT PRIMARY KEY,
+    chat_id TEXT NOT NULL,
+    sender_id TEXT NOT NULL,
+    content BLOB NOT NULL,
+    associated_data BLOB,
+    created_at TEXT NOT NULL,
+    FOREIGN KEY (chat_id) REFERENCES chats(id) ON DELETE CASCADE,
+    FOREIGN KEY (sender_id) REFERENCES users(id) ON DELETE CASCADE
+);
+
+CREATE INDEX channel_posts_chat_idx ON channel_posts (chat_id, created_at);
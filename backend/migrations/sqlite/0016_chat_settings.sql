@@ -0,0 +1,30 @@
+-- Settings every member of a chat sees; admins change them.
+ALTER TABLE chats ADD COLUMN topic TEXT;
+ALTER TABLE chats ADD COLUMN avatar TEXT;
+ALTER TABLE chats ADD COLUMN disappear_after_seconds INTEGER;
+
+-- Each member mutes a chat for themselves.
+ALTER TABLE chat_members ADD COLUMN muted_until TEXT;
+
+-- Pinned messages are named inside encrypted content, since every member
+-- holds a different copy of a message.
+CREATE TABLE chat_pins (
+    id TEXT PRIMARY KEY,
+    chat_id TEXT NOT NULL,
+    pinned_by TEXT NOT NULL,
+    content BLOB NOT NULL,
+    pinned_at TEXT NOT NULL,
+    FOREIGN KEY (chat_id) REFERENCES chats(id) ON DELETE CASCADE,
+    FOREIGN KEY (pinned_by) REFERENCES users(id) ON DELETE CASCADE
+);
+
+CREATE INDEX chat_pins_chat_idx ON chat_pins (chat_id, pinned_at);
+
+CREATE TABLE starred_messages (
+    user_id TEXT NOT NULL,
+    message_id TEXT NOT NULL,
+    starred_at TEXT NOT NULL,
+    PRIMARY KEY (user_id, message_id),
+    FOREIGN KEY (user_id) REFERENCES users(id) ON DELETE CASCADE,
+    FOREIGN KEY (message_id) REFERENCES messages(id) ON DELETE CASCADE
+);
@@ -0,0 +1,28 @@
+-- Bots are users without a password or mailbox, managed by the user who
+-- created them. They authenticate with API keys instead of sessions.
+ALTER TABLE users ADD COLUMN bot_owner_id TEXT REFERENCES users(id);
+
+CREATE INDEX users_bot_owner_idx ON users (bot_owner_id);
+
+-- Only a hash of each key is kept; the key itself is shown once.
+CREATE TABLE api_keys (
+    id TEXT PRIMARY KEY,
+    bot_id TEXT NOT NULL,
+    device_id TEXT NOT NULL,
+    key_hash TEXT NOT NULL UNIQUE,
+    created_at TEXT NOT NULL,
+    revoked_at TEXT,
+    FOREIGN KEY (bot_id) REFERENCES users(id) ON DELETE CASCADE,
+    FOREIGN KEY (device_id) REFERENCES devices(id) ON DELETE CASCADE
+);
+
+CREATE INDEX api_keys_bot_idx ON api_keys (bot_id);
+
+-- Where messages addressed to a bot are posted, signed with the secret.
+CREATE TABLE bot_webhooks (
+    bot_id TEXT PRIMARY KEY,
+    url TEXT NOT NULL,
+    secret TEXT NOT NULL,
+    created_at TEXT NOT NULL,
+    FOREIGN KEY (bot_id) REFERENCES users(id) ON DELETE CASCADE
+);
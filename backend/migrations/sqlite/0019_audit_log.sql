@@ -0,0 +1,27 @@
+-- Security-relevant events, append-only. Each entry carries the hash of the
+-- one before it, so editing or removing an entry breaks the chain from
+-- there on. `user_id` has no foreign key: entries outlive the account.
+CREATE TABLE audit_events (
+    id INTEGER PRIMARY KEY,
+    kind TEXT NOT NULL,
+    user_id TEXT,
+    actor_id TEXT,
+    device_id TEXT,
+    details TEXT NOT NULL,
+    created_at TEXT NOT NULL,
+    prev_hash TEXT NOT NULL,
+    hash TEXT NOT NULL
+);
+
+CREATE INDEX audit_events_user_idx ON audit_events (user_id, id);
+CREATE INDEX audit_events_created_idx ON audit_events (created_at);
+
+CREATE TRIGGER audit_events_no_update BEFORE UPDATE ON audit_events
+BEGIN
+    SELECT RAISE(ABORT, 'audit_events is append-only');
+END;
+
+CREATE TRIGGER audit_events_no_delete BEFORE DELETE ON audit_events
+BEGIN
+    SELECT RAISE(ABORT, 'audit_events is append-only');
+END;
@@ -0,0 +1,32 @@
+-- Reports outlive the reported account, so that deleting it does not erase
+-- the reports against it. SQLite cannot change a foreign key in place, so
+-- the table is rebuilt.
+CREATE TABLE reports_new (
+    id TEXT PRIMARY KEY,
+    reporter_id TEXT,
+    reported_id TEXT,
+    message_id TEXT,
+    category TEXT NOT NULL,
+    description TEXT,
+    evidence TEXT,
+    status TEXT NOT NULL,
+    created_at TEXT NOT NULL,
+    resolved_at TEXT,
+    resolved_by TEXT,
+    resolution_note TEXT,
+    FOREIGN KEY (reporter_id) REFERENCES users(id) ON DELETE SET NULL,
+    FOREIGN KEY (reported_id) REFERENCES users(id) ON DELETE SET NULL,
+    FOREIGN KEY (resolved_by) REFERENCES users(id) ON DELETE SET NULL
+);
+
+INSERT INTO reports_new SELECT
+    id, reporter_id, reported_id, message_id, category, description, evidence,
+    status, created_at, resolved_at, resolved_by, resolution_note
+FROM reports;
+
+DROP TABLE reports;
+ALTER TABLE reports_new RENAME TO reports;
+
+CREATE INDEX reports_status_idx ON reports (status, created_at);
+CREATE INDEX reports_reporter_idx ON reports (reporter_id);
+CREATE INDEX reports_reported_idx ON reports (reported_id);
@@ -0,0 +1,12 @@
+-- Wrong codes entered at the second step of a login, counted per MFA token
+-- and per user to stop guessing. Rows older than the counting window are
+-- removed as new ones come in.
+CREATE TABLE mfa_failures (
+    token_id TEXT NOT NULL,
+    user_id TEXT NOT NULL,
+    failed_at TEXT NOT NULL,
+    FOREIGN KEY (user_id) REFERENCES users(id) ON DELETE CASCADE
+);
+
+CREATE INDEX mfa_failures_token_idx ON mfa_failures (token_id);
+CREATE INDEX mfa_failures_user_idx ON mfa_failures (user_id, failed_at);
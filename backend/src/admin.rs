@@ -0,0 +1,217 @@
+//! Operator tasks behind the `pulse-admin` binary. They work on the database
+//! directly, so they also run while no server is up; a running server
+//! notices revoked sessions and suspensions on the next request, and closes
+//! open event streams of those sessions within `EVENT_STREAM_CHECK_SECONDS`.
+//! Changes are recorded in the audit log like those made through the API,
+//! marked as coming from here.
+
+use std::sync::Arc;
+
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use uuid::Uuid;
+
+use crate::api;
+use crate::audit::{self, Entry, Verification};
+use crate::blob_store::BlobStore;
+use crate::config::ConfigError;
+use crate::db::{AuditQuery, Database, DatabaseError, QueueStats};
+use crate::jwt::{JwtConfig, KeyError, KeyRing};
+use crate::models::{AuditEvent, AuditKind, Device, Session, SigningKey, User};
+use crate::realtime::Hub;
+
+#[derive(Debug, thiserror::Error)]
+pub enum AdminError {
+    #[error(transparent)]
+    Database(#[from] DatabaseError),
+    #[error("No user matches {0}")]
+    UserNotFound(String),
+    #[error("{0} has no device {1}")]
+    DeviceNotFound(String, Uuid),
+    #[error(transparent)]
+    Key(#[from] KeyError),
+    #[error("Purge failed: {0}")]
+    Purge(Box<dyn std::error::Error + Send + Sync>),
+    #[error(transparent)]
+    Config(#[from] ConfigError),
+}
+
+/// A user with everything an operator needs to judge the account.
+#[derive(Debug, Serialize)]
+pub struct UserDetails {
+    pub user: User,
+    pub devices: Vec<Device>,
+    /// Active sessions only.
+    pub sessions: Vec<Session>,
+    pub bots: Vec<User>,
+}
+
+/// What one `purge` run removed.
+#[derive(Debug, Default, Serialize)]
+pub struct PurgeReport {
+    pub attachments: usize,
+    pub sessions: u64,
+    pub email_tokens: u64,
+    pub accounts: usize,
+    /// Accounts due for deletion that could not be deleted; see the log.
+    pub failed_accounts: usize,
+}
+
+pub struct Admin {
+    db: Database,
+    blobs: Arc<dyn BlobStore>,
+}
+
+impl Admin {
+    pub fn new(db: Database, blobs: Arc<dyn BlobStore>) -> Self {
+        Self { db, blobs }
+    }
+
+    /// Resolves a user id, email address or username.
+    pub async fn find_user(&self, who: &str) -> Result<User, AdminError> {
+        let user = if let Ok(id) = Uuid::try_parse(who) {
+            self.db.get_user(id).await?
+        } else if who.contains('@') {
+            self.db.get_user_by_email(who).await?
+        } else {
+            self.db.get_user_by_username(who).await?
+        };
+        user.ok_or_else(|| AdminError::UserNotFound(who.to_string()))
+    }
+
+    pub async fn users(&self, query: Option<&str>, limit: i64) -> Result<Vec<User>, AdminError> {
+        Ok(self.db.search_users(query, limit).await?)
+    }
+
+    pub async fn user_details(&self, who: &str) -> Result<UserDetails, AdminError> {
+        let user = self.find_user(who).await?;
+        Ok(UserDetails {
+            devices: self.db.get_user_devices(user.id).await?,
+            sessions: self.db.list_sessions(user.id).await?,
+            bots: self.db.get_user_bots(user.id).await?,
+            user,
+        })
+    }
+
+    /// Suspending also signs out every session, as it does through the API.
+    pub async fn set_suspended(&self, who: &str, suspended: bool) -> Result<User, AdminError> {
+        let user = self.find_user(who).await?;
+        self.db.set_suspended(user.id, suspended).await?;
+        let entry = if suspended {
+            let revoked = self.db.revoke_user_sessions(user.id, None).await?;
+            Entry::new(AuditKind::AccountSuspended, Some(user.id)).detail("sessions", revoked)
+        } else {
+            Entry::new(AuditKind::AccountUnsuspended, Some(user.id))
+        };
+        self.record(entry).await?;
+        self.find_user(&user.id.to_string()).await
+    }
+
+    pub async fn set_admin(&self, who: &str, is_admin: bool) -> Result<User, AdminError> {
+        let user = self.find_user(who).await?;
+        self.db.set_admin(user.id, is_admin).await?;
+        let role = if is_admin { "admin" } else { "user" };
+        self.record(Entry::new(AuditKind::RoleChanged, Some(user.id)).detail("role", role))
+            .await?;
+        self.find_user(&user.id.to_string()).await
+    }
+
+    /// Returns the ids of the sessions revoked.
+    pub async fn revoke_sessions(&self, who: &str) -> Result<Vec<Uuid>, AdminError> {
+        let user = self.find_user(who).await?;
+        let revoked = self.db.revoke_user_sessions(user.id, None).await?;
+        if !revoked.is_empty() {
+            let entry = Entry::new(AuditKind::SessionsRevoked, Some(user.id))
+                .detail("sessions", &revoked)
+                .detail("reason", "operator");
+            self.record(entry).await?;
+        }
+        Ok(revoked)
+    }
+
+    /// Removes the device with its sessions and keys; the user has to sign
+    /// in on it again.
+    pub async fn revoke_device(&self, who: &str, device_id: Uuid) -> Result<(), AdminError> {
+        let user = self.find_user(who).await?;
+        if !self.db.delete_device(user.id, device_id).await? {
+            return Err(AdminError::DeviceNotFound(who.to_string(), device_id));
+        }
+        self.record(Entry::new(AuditKind::DeviceRemoved, Some(user.id)).device(device_id))
+            .await?;
+        Ok(())
+    }
+
+    /// Runs the server's periodic clean-up once.
+    pub async fn purge(&self) -> Result<PurgeReport, AdminError> {
+        // No event streams are open in this process.
+        let hub = Hub::new();
+        let mut report = PurgeReport {
+            attachments: api::purge_expired_attachments(&self.db, self.blobs.as_ref())
+                .await
+                .map_err(AdminError::Purge)?,
+            sessions: self.db.purge_sessions().await?,
+            email_tokens: self.db.purge_email_tokens().await?,
+            ..PurgeReport::default()
+        };
+        let accounts = api::purge_deleted_accounts(&self.db, self.blobs.as_ref(), &hub)
+            .await
+            .map_err(AdminError::Purge)?;
+        report.accounts = accounts.deleted;
+        report.failed_accounts = accounts.failed;
+        Ok(report)
+    }
+
+    pub async fn queue_stats(&self, top: i64) -> Result<QueueStats, AdminError> {
+        Ok(self.db.queue_stats(top).await?)
+    }
+
+    /// Token signing keys in order of activation, including replaced keys
+    /// the servers have yet to drop.
+    pub async fn signing_keys(&self) -> Result<Vec<SigningKey>, AdminError> {
+        Ok(self.db.get_signing_keys().await?)
+    }
+
+    /// Adds a signing key ahead of schedule, of the algorithm and with the
+    /// publish delay the environment configures. With `immediate` it signs
+    /// at once, before other services may have fetched it; servers pick it
+    /// up within a minute either way.
+    pub async fn rotate_signing_key(&self, immediate: bool) -> Result<SigningKey, AdminError> {
+        let keys = KeyRing::new(JwtConfig::from_env(None)?, api::SessionConfig::from_env()?.max_token_lifetime());
+        Ok(keys.rotate(&self.db, immediate).await?)
+    }
+
+    /// Audit log entries, newest first, optionally about one user and
+    /// within `[since, until)`. A user id is taken as is, so entries about
+    /// deleted accounts can be found.
+    pub async fn audit_log(
+        &self,
+        who: Option<&str>,
+        since: Option<DateTime<Utc>>,
+        until: Option<DateTime<Utc>>,
+        limit: i64,
+    ) -> Result<Vec<AuditEvent>, AdminError> {
+        let user_id = match who {
+            Some(who) => match Uuid::try_parse(who) {
+                Ok(id) => Some(id),
+                Err(_) => Some(self.find_user(who).await?.id),
+            },
+            None => None,
+        };
+        let query = AuditQuery {
+            user_id,
+            since,
+            until,
+            before: None,
+            limit,
+        };
+        Ok(self.db.list_audit_events(&query).await?)
+    }
+
+    pub async fn verify_audit_log(&self) -> Result<Verification, AdminError> {
+        Ok(audit::verify(&self.db).await?)
+    }
+
+    async fn record(&self, entry: Entry) -> Result<AuditEvent, AdminError> {
+        Ok(audit::append(&self.db, entry.detail("via", "pulse-admin")).await?)
+    }
+}
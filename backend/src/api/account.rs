@@ -0,0 +1,283 @@
+use std::collections::HashSet;
+use std::io::{Cursor, Write};
+
+use axum::{
+    extract::{State, Json},
+    http::{header, StatusCode},
+    response::IntoResponse,
+};
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+use zip::{write::SimpleFileOptions, ZipWriter};
+
+use crate::{
+    audit::{self, Entry},
+    blob_store::BlobStore,
+    config::{env_at_least, ConfigError},
+    db::Database,
+    mailer::Email,
+    models::{AuditKind, Device},
+    realtime::Hub,
+};
+use super::{security, two_factor, verify_password, ApiError, AppState, AuthUser};
+
+#[derive(Debug, Clone)]
+pub struct AccountConfig {
+    /// How long a requested deletion waits before it is carried out. Logging
+    /// in during this time cancels it.
+    pub deletion_grace_period: Duration,
+}
+
+impl AccountConfig {
+    pub fn from_env() -> Result<Self, ConfigError> {
+        Ok(Self {
+            deletion_grace_period: Duration::days(env_at_least("ACCOUNT_DELETION_GRACE_DAYS", 30, 0)?),
+        })
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub(super) struct DeleteAccountRequest {
+    password: String,
+    /// Required when two-factor authentication is enabled.
+    #[serde(default)]
+    code: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+struct DeleteAccountResponse {
+    delete_after: DateTime<Utc>,
+}
+
+/// Devices without their keys, which are public anyway and only useful to
+/// peers.
+#[derive(Debug, Serialize)]
+struct ExportedDevice {
+    id: uuid::Uuid,
+    name: String,
+    last_seen: DateTime<Utc>,
+}
+
+impl From<Device> for ExportedDevice {
+    fn from(device: Device) -> Self {
+        Self {
+            id: device.id,
+            name: device.name,
+            last_seen: device.last_seen,
+        }
+    }
+}
+
+const EXPORT_README: &str = "\
+This archive contains the data Pulse holds about your account.
+
+profile.json           your account details
+devices.json           devices you have signed in from
+sessions.json          sign-in sessions, including ended ones not yet cleaned up
+chat_memberships.json  chats you belong to
+contacts.json          your contact list
+blocks.json            users you blocked
+reports.json           abuse reports you filed
+messages.json          messages you sent or received, without their content
+attachments.json       attachments you uploaded, without their content
+security_events.json   sign-ins, security changes and other events on your account
+
+Message and attachment content is end-to-end encrypted; the server cannot
+read it. Your devices hold the only readable copy.
+";
+
+fn json_entry(zip: &mut ZipWriter<Cursor<Vec<u8>>>, name: &str, value: &impl Serialize) -> Result<(), ApiError> {
+    let json = serde_json::to_vec_pretty(value).map_err(|e| {
+        tracing::error!("Failed to serialize {}: {}", name, e);
+        ApiError::Internal
+    })?;
+    zip.start_file(name, SimpleFileOptions::default())
+        .and_then(|_| zip.write_all(&json).map_err(Into::into))
+        .map_err(|e| {
+            tracing::error!("Failed to write {} to export: {}", name, e);
+            ApiError::Internal
+        })
+}
+
+/// Everything the server stores about the caller, as a zip of JSON files.
+pub(super) async fn export(
+    State(state): State<AppState>,
+    auth: AuthUser,
+) -> Result<impl IntoResponse, ApiError> {
+    let user = state.db.get_user(auth.user_id).await?.ok_or(ApiError::NotFound)?;
+    let devices: Vec<ExportedDevice> = state
+        .db
+        .get_user_devices(user.id)
+        .await?
+        .into_iter()
+        .map(ExportedDevice::from)
+        .collect();
+    let sessions = state.db.list_sessions(user.id).await?;
+    let memberships = state.db.get_chat_memberships(user.id).await?;
+    let messages = state.db.get_message_metadata(user.id).await?;
+    let attachments = state.db.get_user_attachments(user.id).await?;
+    let contacts = state.db.list_contacts(user.id).await?;
+    let blocks = state.db.list_blocks(user.id).await?;
+    let reports = state.db.get_user_reports(user.id).await?;
+    let security_events = security::all_events(&state, user.id).await?;
+    let profile = serde_json::json!({
+        "user": user,
+        "two_factor_enabled": two_factor::is_enabled(&state, user.id).await?,
+        "privacy": state.db.get_privacy_settings(user.id).await?,
+    });
+
+    let mut zip = ZipWriter::new(Cursor::new(Vec::new()));
+    zip.start_file("README.txt", SimpleFileOptions::default())
+        .and_then(|_| zip.write_all(EXPORT_README.as_bytes()).map_err(Into::into))
+        .map_err(|e| {
+            tracing::error!("Failed to write export README: {}", e);
+            ApiError::Internal
+        })?;
+    json_entry(&mut zip, "profile.json", &profile)?;
+    json_entry(&mut zip, "devices.json", &devices)?;
+    json_entry(&mut zip, "sessions.json", &sessions)?;
+    json_entry(&mut zip, "chat_memberships.json", &memberships)?;
+    json_entry(&mut zip, "contacts.json", &contacts)?;
+    json_entry(&mut zip, "blocks.json", &blocks)?;
+    json_entry(&mut zip, "reports.json", &reports)?;
+    json_entry(&mut zip, "messages.json", &messages)?;
+    json_entry(&mut zip, "attachments.json", &attachments)?;
+    json_entry(&mut zip, "security_events.json", &security_events)?;
+    let archive = zip
+        .finish()
+        .map_err(|e| {
+            tracing::error!("Failed to finish export: {}", e);
+            ApiError::Internal
+        })?
+        .into_inner();
+
+    let disposition = format!(
+        "attachment; filename=\"pulse-export-{}.zip\"",
+        Utc::now().format("%Y-%m-%d")
+    );
+    Ok((
+        StatusCode::OK,
+        [
+            (header::CONTENT_TYPE, "application/zip".to_string()),
+            (header::CONTENT_DISPOSITION, disposition),
+        ],
+        archive,
+    ))
+}
+
+/// Schedules the caller's account for deletion and signs out every session.
+/// Logging in again before the grace period ends cancels the deletion.
+pub(super) async fn request_deletion(
+    State(state): State<AppState>,
+    auth: AuthUser,
+    Json(req): Json<DeleteAccountRequest>,
+) -> Result<impl IntoResponse, ApiError> {
+    let user = state.db.get_user(auth.user_id).await?.ok_or(ApiError::NotFound)?;
+    if !verify_password(req.password, user.password_hash.clone()).await? {
+        return Err(ApiError::InvalidCredentials);
+    }
+    if two_factor::is_enabled(&state, user.id).await? {
+        let code = req.code.ok_or(ApiError::InvalidCode)?;
+        if !two_factor::verify_second_factor(&state, user.id, &code).await? {
+            return Err(ApiError::InvalidCode);
+        }
+    }
+
+    let delete_after = Utc::now() + state.account.deletion_grace_period;
+    state.db.set_delete_after(user.id, Some(delete_after)).await?;
+    let revoked = state.db.revoke_user_sessions(user.id, None).await?;
+    for session_id in &revoked {
+        state.hub.disconnect_session(*session_id);
+    }
+    let entry = Entry::new(AuditKind::DeletionRequested, Some(user.id))
+        .device(auth.device_id)
+        .detail("delete_after", delete_after)
+        .detail("sessions", &revoked);
+    security::record(&state, entry).await?;
+
+    let email = Email {
+        to: user.email.clone(),
+        subject: "Your Pulse account will be deleted".to_string(),
+        body: format!(
+            "Hi {},\n\nYour Pulse account and everything stored with it will be deleted on {}. \
+             To keep your account, log in again before then.\n",
+            user.username,
+            delete_after.format("%Y-%m-%d %H:%M UTC"),
+        ),
+    };
+    if let Err(e) = state.mailer.send(&email).await {
+        tracing::error!("Failed to send deletion notice to {}: {}", user.id, e);
+    }
+
+    Ok((StatusCode::ACCEPTED, Json(DeleteAccountResponse { delete_after })))
+}
+
+/// What one `purge_deleted` run did.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct AccountPurge {
+    pub deleted: usize,
+    /// Accounts that could not be deleted; they are tried again next run.
+    pub failed: usize,
+}
+
+/// Deletes accounts whose grace period has ended, then their attachment
+/// blobs. Event streams of their bots are closed on `hub`. An account that
+/// fails is logged and skipped, so that it does not hold up the others.
+pub async fn purge_deleted(
+    db: &Database,
+    blobs: &dyn BlobStore,
+    hub: &Hub,
+) -> Result<AccountPurge, Box<dyn std::error::Error + Send + Sync>> {
+    let mut purge = AccountPurge::default();
+    let mut failed = HashSet::new();
+
+    loop {
+        // Failed accounts are still due; ask for enough to get past them.
+        let due: Vec<Uuid> = db
+            .users_due_for_deletion(100 + failed.len() as i64)
+            .await?
+            .into_iter()
+            .filter(|user_id| !failed.contains(user_id))
+            .collect();
+        if due.is_empty() {
+            break;
+        }
+
+        for user_id in due {
+            match purge_account(db, blobs, hub, user_id).await {
+                Ok(()) => {
+                    tracing::info!("Deleted account {}", user_id);
+                    purge.deleted += 1;
+                }
+                Err(e) => {
+                    tracing::error!("Deleting account {} failed: {}", user_id, e);
+                    failed.insert(user_id);
+                    purge.failed += 1;
+                }
+            }
+        }
+    }
+
+    Ok(purge)
+}
+
+async fn purge_account(
+    db: &Database,
+    blobs: &dyn BlobStore,
+    hub: &Hub,
+    user_id: Uuid,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    for bot in db.get_user_bots(user_id).await? {
+        super::bots::delete_bot_account(db, blobs, hub, bot.id, None).await?;
+    }
+    // The rows are gone now, so the blobs go next whatever else fails;
+    // nothing would point at them afterwards.
+    for attachment_id in db.delete_user(user_id).await? {
+        if let Err(e) = blobs.delete(&attachment_id).await {
+            tracing::error!("Failed to delete blob of attachment {}: {}", attachment_id, e);
+        }
+    }
+    audit::append(db, Entry::new(AuditKind::AccountDeleted, Some(user_id))).await?;
+    Ok(())
+}
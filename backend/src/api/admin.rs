@@ -0,0 +1,154 @@
+use axum::{
+    extract::{Path, Query, State, Json},
+    http::StatusCode,
+    response::IntoResponse,
+};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::audit::Entry;
+use crate::models::{AuditKind, Report, ReportStatus, User};
+use super::{security, AdminUser, ApiError, AppState};
+
+#[derive(Debug, Deserialize)]
+pub(super) struct ListReportsQuery {
+    /// `open` (the default), `dismissed`, `actioned` or `all`.
+    #[serde(default)]
+    status: Option<String>,
+    #[serde(default = "default_limit")]
+    limit: i64,
+}
+
+fn default_limit() -> i64 {
+    100
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum ReportAction {
+    Dismiss,
+    /// Suspend the reported account.
+    Suspend,
+}
+
+#[derive(Debug, Deserialize)]
+pub(super) struct ResolveReportRequest {
+    action: ReportAction,
+    #[serde(default)]
+    note: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+struct ReportDetails {
+    report: Report,
+    /// `None` if the account has since been deleted.
+    reported_user: Option<User>,
+}
+
+/// The review queue, oldest first.
+pub(super) async fn list_reports(
+    State(state): State<AppState>,
+    _admin: AdminUser,
+    Query(query): Query<ListReportsQuery>,
+) -> Result<impl IntoResponse, ApiError> {
+    let status = match query.status.as_deref().unwrap_or("open") {
+        "all" => None,
+        s => Some(ReportStatus::parse(s).ok_or_else(|| {
+            ApiError::BadRequest(format!("Unknown report status: {}", s))
+        })?),
+    };
+
+    let reports = state.db.list_reports(status, query.limit.clamp(1, 500)).await?;
+    Ok(Json(reports))
+}
+
+pub(super) async fn get_report(
+    State(state): State<AppState>,
+    _admin: AdminUser,
+    Path(id): Path<Uuid>,
+) -> Result<impl IntoResponse, ApiError> {
+    let report = state.db.get_report(id).await?.ok_or(ApiError::NotFound)?;
+    let reported_user = match report.reported_id {
+        Some(id) => state.db.get_user(id).await?,
+        None => None,
+    };
+    Ok(Json(ReportDetails { report, reported_user }))
+}
+
+/// Closes an open report, suspending the reported account if asked to.
+pub(super) async fn resolve_report(
+    State(state): State<AppState>,
+    AdminUser(admin): AdminUser,
+    Path(id): Path<Uuid>,
+    Json(req): Json<ResolveReportRequest>,
+) -> Result<impl IntoResponse, ApiError> {
+    let report = state.db.get_report(id).await?.ok_or(ApiError::NotFound)?;
+    if report.status != ReportStatus::Open {
+        return Err(ApiError::Conflict("Report is already resolved".to_string()));
+    }
+
+    let status = match req.action {
+        ReportAction::Dismiss => ReportStatus::Dismissed,
+        ReportAction::Suspend => {
+            let reported_id = report.reported_id.ok_or_else(|| {
+                ApiError::Conflict("The reported account no longer exists".to_string())
+            })?;
+            suspend(&state, admin.user_id, reported_id).await?;
+            ReportStatus::Actioned
+        }
+    };
+    let note = req.note.as_deref().map(str::trim).filter(|n| !n.is_empty());
+    if !state.db.resolve_report(id, status, admin.user_id, note).await? {
+        return Err(ApiError::Conflict("Report is already resolved".to_string()));
+    }
+
+    tracing::info!("Admin {} resolved report {} as {}", admin.user_id, id, status.as_str());
+    let report = state.db.get_report(id).await?.ok_or(ApiError::NotFound)?;
+    Ok(Json(report))
+}
+
+pub(super) async fn suspend_user(
+    State(state): State<AppState>,
+    AdminUser(admin): AdminUser,
+    Path(user_id): Path<Uuid>,
+) -> Result<impl IntoResponse, ApiError> {
+    if user_id == admin.user_id {
+        return Err(ApiError::BadRequest("Cannot suspend yourself".to_string()));
+    }
+    state.db.get_user(user_id).await?.ok_or(ApiError::NotFound)?;
+
+    suspend(&state, admin.user_id, user_id).await?;
+    tracing::info!("Admin {} suspended {}", admin.user_id, user_id);
+    Ok(StatusCode::NO_CONTENT)
+}
+
+pub(super) async fn unsuspend_user(
+    State(state): State<AppState>,
+    AdminUser(admin): AdminUser,
+    Path(user_id): Path<Uuid>,
+) -> Result<impl IntoResponse, ApiError> {
+    state.db.get_user(user_id).await?.ok_or(ApiError::NotFound)?;
+
+    state.db.set_suspended(user_id, false).await?;
+    security::record(&state, Entry::new(AuditKind::AccountUnsuspended, Some(user_id)).actor(admin.user_id)).await?;
+    tracing::info!("Admin {} lifted the suspension of {}", admin.user_id, user_id);
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Suspends the account and signs out every session at once.
+async fn suspend(state: &AppState, admin_id: Uuid, user_id: Uuid) -> Result<(), ApiError> {
+    state.db.set_suspended(user_id, true).await?;
+    let revoked = state.db.revoke_user_sessions(user_id, None).await?;
+    for session_id in &revoked {
+        state.hub.disconnect_session(*session_id);
+    }
+    let entry = Entry::new(AuditKind::AccountSuspended, Some(user_id))
+        .actor(admin_id)
+        .detail("sessions", &revoked);
+    security::record(state, entry).await?;
+    // API keys stay valid but are refused while the bot is suspended.
+    for key in state.db.get_bot_api_keys(user_id).await? {
+        state.hub.disconnect_session(key.id);
+    }
+    Ok(())
+}
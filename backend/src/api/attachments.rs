@@ -0,0 +1,272 @@
+use axum::{
+    body::{Body, Bytes},
+    extract::{Path, State, Json},
+    http::{header, HeaderMap, HeaderValue, StatusCode},
+    response::{IntoResponse, Response},
+};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use chrono::{Duration, Utc};
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use tokio_util::io::ReaderStream;
+use uuid::Uuid;
+
+use crate::{
+    blob_store::{BlobError, BlobStore},
+    config::{env_at_least, ConfigError},
+    db::Database,
+    models::Attachment,
+};
+use super::{ApiError, AppState, AuthUser, VerifiedUser};
+
+const UPLOAD_OFFSET: &str = "upload-offset";
+const UPLOAD_LENGTH: &str = "upload-length";
+
+#[derive(Debug, Clone)]
+pub struct AttachmentConfig {
+    /// Largest single attachment, in bytes.
+    pub max_size: i64,
+    /// Total bytes a user may have stored at once.
+    pub user_quota: i64,
+    /// Largest chunk accepted by one upload request.
+    pub max_chunk_size: usize,
+    /// How long an upload may stay unreferenced by any message.
+    pub upload_ttl: Duration,
+    /// How long a referenced attachment is kept when its message does not
+    /// expire sooner.
+    pub retention: Duration,
+}
+
+impl AttachmentConfig {
+    pub fn from_env() -> Result<Self, ConfigError> {
+        Ok(Self {
+            max_size: env_at_least("ATTACHMENT_MAX_SIZE", 100 * 1024 * 1024, 1)?,
+            user_quota: env_at_least("ATTACHMENT_USER_QUOTA", 1024 * 1024 * 1024, 1)?,
+            max_chunk_size: env_at_least("ATTACHMENT_MAX_CHUNK_SIZE", 8 * 1024 * 1024, 1)? as usize,
+            upload_ttl: Duration::hours(env_at_least("ATTACHMENT_UPLOAD_TTL_HOURS", 24, 1)?),
+            retention: Duration::days(env_at_least("ATTACHMENT_RETENTION_DAYS", 30, 1)?),
+        })
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub(super) struct CreateAttachmentRequest {
+    size: i64,
+}
+
+#[derive(Debug, Serialize)]
+struct CreateAttachmentResponse {
+    #[serde(flatten)]
+    attachment: Attachment,
+    max_chunk_size: usize,
+}
+
+/// 256 random bits; attachment ids double as capability-style download
+/// handles, so they must not be guessable.
+fn new_attachment_id() -> String {
+    let mut bytes = [0u8; 32];
+    rand::thread_rng().fill_bytes(&mut bytes);
+    URL_SAFE_NO_PAD.encode(bytes)
+}
+
+fn offset_headers(attachment: &Attachment) -> [(&'static str, HeaderValue); 2] {
+    [
+        (UPLOAD_OFFSET, HeaderValue::from(attachment.received)),
+        (UPLOAD_LENGTH, HeaderValue::from(attachment.size)),
+    ]
+}
+
+pub(super) async fn create_attachment(
+    State(state): State<AppState>,
+    VerifiedUser(auth): VerifiedUser,
+    Json(req): Json<CreateAttachmentRequest>,
+) -> Result<impl IntoResponse, ApiError> {
+    let config = &state.attachments;
+    if req.size <= 0 {
+        return Err(ApiError::BadRequest("Attachment size must be positive".to_string()));
+    }
+    if req.size > config.max_size {
+        return Err(ApiError::PayloadTooLarge("Attachment exceeds the maximum size".to_string()));
+    }
+    let now = Utc::now();
+    let attachment = Attachment {
+        id: new_attachment_id(),
+        uploader_id: auth.user_id,
+        size: req.size,
+        received: 0,
+        created_at: now,
+        completed_at: None,
+        expires_at: now + config.upload_ttl,
+    };
+    if !state.db.create_attachment(&attachment, config.user_quota).await? {
+        return Err(ApiError::QuotaExceeded);
+    }
+
+    let response = CreateAttachmentResponse {
+        attachment,
+        max_chunk_size: config.max_chunk_size,
+    };
+    Ok((StatusCode::CREATED, Json(response)))
+}
+
+/// The attachment, if it exists and was uploaded by `user_id`.
+async fn own_attachment(state: &AppState, id: &str, user_id: Uuid) -> Result<Attachment, ApiError> {
+    match state.db.get_attachment(id).await? {
+        Some(attachment) if attachment.uploader_id == user_id => Ok(attachment),
+        _ => Err(ApiError::NotFound),
+    }
+}
+
+/// Brings the recorded progress up to what the blob store holds. A chunk
+/// appended without its progress being recorded, because the database write
+/// failed or the request was dropped in between, would otherwise get every
+/// retry at the recorded offset rejected.
+async fn catch_up(state: &AppState, attachment: Attachment) -> Result<Attachment, ApiError> {
+    if attachment.completed_at.is_some() {
+        return Ok(attachment);
+    }
+    let stored = match state.blobs.size(&attachment.id).await?.map(|size| size as i64) {
+        Some(stored) if stored > attachment.received && stored <= attachment.size => stored,
+        _ => return Ok(attachment),
+    };
+
+    let completed_at = (stored == attachment.size).then(Utc::now);
+    if !state.db.record_attachment_progress(&attachment.id, attachment.received, stored, completed_at).await? {
+        // Recorded by a concurrent request in the meantime.
+        return state.db.get_attachment(&attachment.id).await?.ok_or(ApiError::NotFound);
+    }
+    Ok(Attachment {
+        received: stored,
+        completed_at,
+        ..attachment
+    })
+}
+
+/// Reports upload progress so an interrupted client knows where to resume.
+pub(super) async fn upload_status(
+    State(state): State<AppState>,
+    auth: AuthUser,
+    Path(id): Path<String>,
+) -> Result<impl IntoResponse, ApiError> {
+    let attachment = own_attachment(&state, &id, auth.user_id).await?;
+    let attachment = catch_up(&state, attachment).await?;
+    Ok((StatusCode::OK, offset_headers(&attachment)))
+}
+
+/// Appends one chunk. The `Upload-Offset` header must match the number of
+/// bytes already received; otherwise the response is a bare 409 carrying the
+/// current offset so the client can resume from there.
+pub(super) async fn upload_chunk(
+    State(state): State<AppState>,
+    auth: AuthUser,
+    Path(id): Path<String>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Result<Response, ApiError> {
+    let attachment = own_attachment(&state, &id, auth.user_id).await?;
+    let attachment = catch_up(&state, attachment).await?;
+
+    if attachment.completed_at.is_some() {
+        return Err(ApiError::Conflict("Upload already completed".to_string()));
+    }
+
+    let offset = headers
+        .get(UPLOAD_OFFSET)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.parse::<i64>().ok())
+        .ok_or_else(|| ApiError::BadRequest("Missing or invalid Upload-Offset header".to_string()))?;
+
+    if offset != attachment.received {
+        return Ok((StatusCode::CONFLICT, offset_headers(&attachment)).into_response());
+    }
+    if offset + body.len() as i64 > attachment.size {
+        return Err(ApiError::PayloadTooLarge("Chunk exceeds the declared attachment size".to_string()));
+    }
+
+    let received = match state.blobs.append(&id, offset as u64, &body).await {
+        Ok(received) => received as i64,
+        Err(BlobError::OffsetMismatch { .. }) => {
+            return Ok((StatusCode::CONFLICT, offset_headers(&attachment)).into_response());
+        }
+        Err(e) => return Err(e.into()),
+    };
+
+    let completed_at = (received == attachment.size).then(Utc::now);
+    if !state.db.record_attachment_progress(&id, offset, received, completed_at).await? {
+        return Err(ApiError::Conflict("Concurrent upload detected".to_string()));
+    }
+
+    let updated = Attachment {
+        received,
+        completed_at,
+        ..attachment
+    };
+    Ok((StatusCode::NO_CONTENT, offset_headers(&updated)).into_response())
+}
+
+pub(super) async fn download_attachment(
+    State(state): State<AppState>,
+    auth: AuthUser,
+    Path(id): Path<String>,
+) -> Result<impl IntoResponse, ApiError> {
+    // Unknown, unauthorized and expired attachments all look the same.
+    let attachment = match state.db.get_attachment(&id).await? {
+        Some(attachment) if attachment.expires_at > Utc::now() => attachment,
+        _ => return Err(ApiError::NotFound),
+    };
+    if !state.db.can_access_attachment(&id, auth.user_id).await? {
+        return Err(ApiError::NotFound);
+    }
+
+    if attachment.completed_at.is_none() {
+        return Err(ApiError::Conflict("Upload not completed".to_string()));
+    }
+
+    let reader = state.blobs.get(&id).await?;
+    Ok((
+        StatusCode::OK,
+        [
+            (header::CONTENT_TYPE, HeaderValue::from_static("application/octet-stream")),
+            (header::CONTENT_LENGTH, HeaderValue::from(attachment.size)),
+        ],
+        Body::from_stream(ReaderStream::new(reader)),
+    ))
+}
+
+pub(super) async fn delete_attachment(
+    State(state): State<AppState>,
+    auth: AuthUser,
+    Path(id): Path<String>,
+) -> Result<impl IntoResponse, ApiError> {
+    own_attachment(&state, &id, auth.user_id).await?;
+
+    state.db.delete_attachment(&id).await?;
+    if let Err(e) = state.blobs.delete(&id).await {
+        tracing::warn!("Failed to delete blob for attachment {}: {}", id, e);
+    }
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Deletes expired attachments, including uploads that were never
+/// referenced by a message. Returns how many were removed.
+pub async fn purge_expired(db: &Database, blobs: &dyn BlobStore) -> Result<usize, Box<dyn std::error::Error + Send + Sync>> {
+    let mut purged = 0;
+
+    loop {
+        let expired = db.expired_attachments(100).await?;
+        if expired.is_empty() {
+            break;
+        }
+
+        for id in expired {
+            // Drop the row first: a missing blob is harmless, a row pointing
+            // at a deleted blob is not.
+            db.delete_attachment(&id).await?;
+            blobs.delete(&id).await?;
+            purged += 1;
+        }
+    }
+
+    Ok(purged)
+}
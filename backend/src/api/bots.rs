@@ -0,0 +1,393 @@
+use axum::{
+    extract::{Path, State, Json},
+    http::StatusCode,
+    response::IntoResponse,
+};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use chrono::Utc;
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use uuid::Uuid;
+
+use crate::audit::{self, Entry};
+use crate::blob_store::BlobStore;
+use crate::config::{env_at_least, env_flag, ConfigError};
+use crate::db::Database;
+use crate::models::{ApiKey, AuditKind, BotWebhook, Device, Message, User};
+use crate::realtime::Hub;
+use crate::webhooks::{self, WebhookPayload};
+use super::{security, ApiError, AppState, AuthUser, VerifiedUser};
+
+/// Bearer tokens starting with this are API keys rather than access tokens.
+pub(crate) const API_KEY_PREFIX: &str = "pulse_bot_";
+
+/// Most unrevoked keys a bot can hold at once.
+const MAX_KEYS_PER_BOT: usize = 20;
+
+#[derive(Debug, Clone)]
+pub struct BotConfig {
+    pub max_bots_per_user: usize,
+    /// How long a webhook endpoint has to answer.
+    pub webhook_timeout: std::time::Duration,
+    /// Accept plain `http://` webhook URLs and private or loopback
+    /// addresses. Only for local development.
+    pub allow_insecure_webhooks: bool,
+}
+
+impl BotConfig {
+    pub fn from_env() -> Result<Self, ConfigError> {
+        Ok(Self {
+            max_bots_per_user: env_at_least("BOTS_PER_USER", 10, 0)? as usize,
+            webhook_timeout: std::time::Duration::from_secs(env_at_least("WEBHOOK_TIMEOUT_SECONDS", 10, 1)? as u64),
+            allow_insecure_webhooks: env_flag("WEBHOOK_ALLOW_INSECURE", false)?,
+        })
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub(super) struct CreateBotRequest {
+    username: String,
+    public_key: Vec<u8>,
+    #[serde(default)]
+    device_name: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub(super) struct CreateKeyRequest {
+    public_key: Vec<u8>,
+    #[serde(default)]
+    device_name: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub(super) struct SetWebhookRequest {
+    url: String,
+}
+
+/// A key as returned once, when it is created.
+#[derive(Debug, Serialize)]
+struct NewApiKey {
+    #[serde(flatten)]
+    key: ApiKey,
+    api_key: String,
+}
+
+#[derive(Debug, Serialize)]
+struct CreateBotResponse {
+    bot: User,
+    key: NewApiKey,
+}
+
+#[derive(Debug, Serialize)]
+struct WebhookResponse {
+    url: String,
+    /// Only returned when the webhook is set.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    secret: Option<String>,
+}
+
+/// Keys name their bot, so that the rate limiter can charge requests to it
+/// without a database lookup.
+fn new_api_key(bot_id: Uuid) -> String {
+    let mut bytes = [0u8; 32];
+    rand::thread_rng().fill_bytes(&mut bytes);
+    format!("{}{}_{}", API_KEY_PREFIX, bot_id.simple(), URL_SAFE_NO_PAD.encode(bytes))
+}
+
+fn hash_api_key(key: &str) -> String {
+    Sha256::digest(key.as_bytes())
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+/// The bot an API key claims to belong to. Unverified.
+pub(crate) fn api_key_bot(key: &str) -> Option<Uuid> {
+    let (bot_id, _) = key.strip_prefix(API_KEY_PREFIX)?.split_once('_')?;
+    Uuid::try_parse(bot_id).ok()
+}
+
+/// Resolves an API key to its bot and device. The key's id stands in for
+/// the session id.
+pub(super) async fn authenticate(state: &AppState, key: &str) -> Result<AuthUser, ApiError> {
+    let key = state
+        .db
+        .get_api_key_by_hash(&hash_api_key(key))
+        .await?
+        .ok_or(ApiError::Unauthorized)?;
+    let bot = state.db.get_user(key.bot_id).await?.ok_or(ApiError::Unauthorized)?;
+    if bot.suspended_at.is_some() {
+        return Err(ApiError::AccountSuspended);
+    }
+
+    Ok(AuthUser {
+        user_id: key.bot_id,
+        device_id: key.device_id,
+        session_id: key.id,
+    })
+}
+
+/// Stores a new key, and a device for it to stand for.
+async fn issue_key(
+    state: &AppState,
+    owner_id: Uuid,
+    bot_id: Uuid,
+    device_name: Option<String>,
+    public_key: Vec<u8>,
+) -> Result<NewApiKey, ApiError> {
+    let now = Utc::now();
+    let device = Device {
+        id: Uuid::new_v4(),
+        user_id: bot_id,
+        name: device_name.unwrap_or_else(|| "API key".to_string()),
+        public_key,
+        last_seen: now,
+        is_online: false,
+    };
+    state.db.create_device(&device).await?;
+
+    let api_key = new_api_key(bot_id);
+    let key = ApiKey {
+        id: Uuid::new_v4(),
+        bot_id,
+        device_id: device.id,
+        key_hash: hash_api_key(&api_key),
+        created_at: now,
+        revoked_at: None,
+    };
+    state.db.create_api_key(&key).await?;
+    let entry = Entry::new(AuditKind::DeviceRegistered, Some(bot_id))
+        .actor(owner_id)
+        .device(device.id)
+        .detail("device_name", &device.name);
+    security::record(state, entry).await?;
+
+    Ok(NewApiKey { key, api_key })
+}
+
+/// The caller's bot with this id. Other users' bots look the same as
+/// missing ones.
+async fn require_bot(state: &AppState, owner_id: Uuid, bot_id: Uuid) -> Result<User, ApiError> {
+    match state.db.get_user(bot_id).await? {
+        Some(bot) if bot.bot_owner_id == Some(owner_id) => Ok(bot),
+        _ => Err(ApiError::NotFound),
+    }
+}
+
+/// Deletes a bot along with its keys, webhook and messages, ending its
+/// open event streams. `owner_id` is set when its owner deleted it, rather
+/// than the owner's account being deleted.
+pub(super) async fn delete_bot_account(
+    db: &Database,
+    blobs: &dyn BlobStore,
+    hub: &Hub,
+    bot_id: Uuid,
+    owner_id: Option<Uuid>,
+) -> Result<(), ApiError> {
+    for key in db.get_bot_api_keys(bot_id).await? {
+        hub.disconnect_session(key.id);
+    }
+    for attachment_id in db.delete_user(bot_id).await? {
+        if let Err(e) = blobs.delete(&attachment_id).await {
+            tracing::error!("Failed to delete blob of attachment {}: {}", attachment_id, e);
+        }
+    }
+    let mut entry = Entry::new(AuditKind::AccountDeleted, Some(bot_id)).detail("bot", true);
+    if let Some(owner_id) = owner_id {
+        entry = entry.actor(owner_id);
+    }
+    audit::append(db, entry).await?;
+    Ok(())
+}
+
+/// Posts a message to its recipient's webhook, if the recipient is a bot
+/// with one. Delivery happens in the background.
+pub(super) async fn dispatch_webhook(state: &AppState, message: &Message) -> Result<(), ApiError> {
+    let Some(webhook) = state.db.get_bot_webhook(message.recipient_id).await? else {
+        return Ok(());
+    };
+
+    let sender = state.webhooks.clone();
+    let message = message.clone();
+    tokio::spawn(async move {
+        let payload = WebhookPayload::Message { message: &message };
+        if let Err(e) = sender.deliver(&webhook, &payload).await {
+            tracing::warn!("Webhook delivery to bot {} failed: {}", webhook.bot_id, e);
+        }
+    });
+    Ok(())
+}
+
+/// Creates a bot owned by the caller, with a first device and API key.
+pub(super) async fn create_bot(
+    State(state): State<AppState>,
+    VerifiedUser(auth): VerifiedUser,
+    Json(req): Json<CreateBotRequest>,
+) -> Result<impl IntoResponse, ApiError> {
+    let owner = state.db.get_user(auth.user_id).await?.ok_or(ApiError::Unauthorized)?;
+    if owner.bot_owner_id.is_some() {
+        return Err(ApiError::Forbidden);
+    }
+    let username = req.username.trim();
+    if username.is_empty() {
+        return Err(ApiError::BadRequest("A bot needs a username".to_string()));
+    }
+    if state.db.get_user_bots(owner.id).await?.len() >= state.bots.max_bots_per_user {
+        return Err(ApiError::Conflict(format!(
+            "A user can have at most {} bots",
+            state.bots.max_bots_per_user
+        )));
+    }
+
+    let now = Utc::now();
+    let id = Uuid::new_v4();
+    let bot = User {
+        id,
+        username: username.to_string(),
+        // Bots have no mailbox; the address only has to be unique.
+        email: format!("bot-{}@bots.invalid", id.simple()),
+        public_key: req.public_key.clone(),
+        created_at: now,
+        last_seen: now,
+        password_hash: None,
+        email_verified_at: Some(now),
+        delete_after: None,
+        is_admin: false,
+        suspended_at: None,
+        bot_owner_id: Some(owner.id),
+    };
+    state.db.create_user(&bot).await?;
+    let key = issue_key(&state, owner.id, bot.id, req.device_name, req.public_key).await?;
+
+    tracing::info!("User {} created bot {}", owner.id, bot.id);
+    Ok((StatusCode::CREATED, Json(CreateBotResponse { bot, key })))
+}
+
+pub(super) async fn list_bots(
+    State(state): State<AppState>,
+    auth: AuthUser,
+) -> Result<impl IntoResponse, ApiError> {
+    Ok(Json(state.db.get_user_bots(auth.user_id).await?))
+}
+
+pub(super) async fn delete_bot(
+    State(state): State<AppState>,
+    auth: AuthUser,
+    Path(bot_id): Path<Uuid>,
+) -> Result<impl IntoResponse, ApiError> {
+    require_bot(&state, auth.user_id, bot_id).await?;
+    delete_bot_account(&state.db, state.blobs.as_ref(), &state.hub, bot_id, Some(auth.user_id)).await?;
+    tracing::info!("User {} deleted bot {}", auth.user_id, bot_id);
+    Ok(StatusCode::NO_CONTENT)
+}
+
+pub(super) async fn list_keys(
+    State(state): State<AppState>,
+    auth: AuthUser,
+    Path(bot_id): Path<Uuid>,
+) -> Result<impl IntoResponse, ApiError> {
+    require_bot(&state, auth.user_id, bot_id).await?;
+    Ok(Json(state.db.get_bot_api_keys(bot_id).await?))
+}
+
+/// Issues another key, for a new device of the bot.
+pub(super) async fn create_key(
+    State(state): State<AppState>,
+    auth: AuthUser,
+    Path(bot_id): Path<Uuid>,
+    Json(req): Json<CreateKeyRequest>,
+) -> Result<impl IntoResponse, ApiError> {
+    require_bot(&state, auth.user_id, bot_id).await?;
+    let active = state
+        .db
+        .get_bot_api_keys(bot_id)
+        .await?
+        .iter()
+        .filter(|k| k.revoked_at.is_none())
+        .count();
+    if active >= MAX_KEYS_PER_BOT {
+        return Err(ApiError::Conflict(format!("A bot has at most {} keys", MAX_KEYS_PER_BOT)));
+    }
+
+    let key = issue_key(&state, auth.user_id, bot_id, req.device_name, req.public_key).await?;
+    Ok((StatusCode::CREATED, Json(key)))
+}
+
+pub(super) async fn revoke_key(
+    State(state): State<AppState>,
+    auth: AuthUser,
+    Path((bot_id, key_id)): Path<(Uuid, Uuid)>,
+) -> Result<impl IntoResponse, ApiError> {
+    require_bot(&state, auth.user_id, bot_id).await?;
+    if !state.db.revoke_api_key(bot_id, key_id, Utc::now()).await? {
+        return Err(ApiError::NotFound);
+    }
+    state.hub.disconnect_session(key_id);
+    let entry = Entry::new(AuditKind::SessionsRevoked, Some(bot_id))
+        .actor(auth.user_id)
+        .detail("sessions", [key_id])
+        .detail("reason", "api_key_revoked");
+    security::record(&state, entry).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+pub(super) async fn get_webhook(
+    State(state): State<AppState>,
+    auth: AuthUser,
+    Path(bot_id): Path<Uuid>,
+) -> Result<impl IntoResponse, ApiError> {
+    require_bot(&state, auth.user_id, bot_id).await?;
+    let webhook = state.db.get_bot_webhook(bot_id).await?.ok_or(ApiError::NotFound)?;
+    Ok(Json(WebhookResponse { url: webhook.url, secret: None }))
+}
+
+/// Sets the bot's webhook with a fresh signing secret, which is returned
+/// once.
+pub(super) async fn set_webhook(
+    State(state): State<AppState>,
+    auth: AuthUser,
+    Path(bot_id): Path<Uuid>,
+    Json(req): Json<SetWebhookRequest>,
+) -> Result<impl IntoResponse, ApiError> {
+    require_bot(&state, auth.user_id, bot_id).await?;
+    let url = reqwest::Url::parse(req.url.trim())
+        .map_err(|_| ApiError::BadRequest("Invalid webhook URL".to_string()))?;
+    match url.scheme() {
+        "https" => {}
+        "http" if state.bots.allow_insecure_webhooks => {}
+        _ => return Err(ApiError::BadRequest("Webhook URLs must use https".to_string())),
+    }
+    if !state.bots.allow_insecure_webhooks {
+        webhooks::check_url(&url)
+            .await
+            .map_err(|e| ApiError::BadRequest(format!("Invalid webhook URL: {}", e)))?;
+    }
+
+    let mut secret = [0u8; 32];
+    rand::thread_rng().fill_bytes(&mut secret);
+    let webhook = BotWebhook {
+        bot_id,
+        url: url.to_string(),
+        secret: secret.iter().map(|b| format!("{:02x}", b)).collect(),
+        created_at: Utc::now(),
+    };
+    state.db.set_bot_webhook(&webhook).await?;
+
+    Ok(Json(WebhookResponse {
+        url: webhook.url,
+        secret: Some(webhook.secret),
+    }))
+}
+
+pub(super) async fn delete_webhook(
+    State(state): State<AppState>,
+    auth: AuthUser,
+    Path(bot_id): Path<Uuid>,
+) -> Result<impl IntoResponse, ApiError> {
+    require_bot(&state, auth.user_id, bot_id).await?;
+    if !state.db.delete_bot_webhook(bot_id).await? {
+        return Err(ApiError::NotFound);
+    }
+    Ok(StatusCode::NO_CONTENT)
+}
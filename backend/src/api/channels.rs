@@ -0,0 +1,98 @@
+use axum::{
+    extract::{Path, Query, State, Json},
+    http::StatusCode,
+    response::IntoResponse,
+};
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::models::ChannelPost;
+use crate::realtime::Event;
+use super::{
+    chats::{online_members, require_admin, require_member},
+    presence::send_to_user,
+    ApiError, AppState, AuthUser, VerifiedUser,
+};
+
+const MAX_PAGE_SIZE: i64 = 200;
+
+#[derive(Debug, Deserialize)]
+pub(super) struct CreatePostRequest {
+    content: Vec<u8>,
+    #[serde(default)]
+    associated_data: Option<Vec<u8>>,
+}
+
+#[derive(Debug, Serialize)]
+struct CreatePostResponse {
+    id: Uuid,
+}
+
+#[derive(Debug, Deserialize)]
+pub(super) struct PostsQuery {
+    /// The oldest post of the previous page.
+    #[serde(default)]
+    before: Option<Uuid>,
+    #[serde(default = "default_limit")]
+    limit: i64,
+}
+
+fn default_limit() -> i64 {
+    50
+}
+
+async fn require_channel(state: &AppState, chat_id: Uuid) -> Result<(), ApiError> {
+    match state.db.get_chat(chat_id).await? {
+        Some(chat) if chat.is_channel => Ok(()),
+        Some(_) => Err(ApiError::BadRequest("Not a channel".to_string())),
+        None => Err(ApiError::NotFound),
+    }
+}
+
+/// Posts to the channel. The post is stored once, encrypted under the
+/// channel's key, rather than once per subscriber; online subscribers are
+/// told about it and the rest fetch it when they next open the channel.
+pub(super) async fn create_post(
+    State(state): State<AppState>,
+    VerifiedUser(auth): VerifiedUser,
+    Path(chat_id): Path<Uuid>,
+    Json(req): Json<CreatePostRequest>,
+) -> Result<impl IntoResponse, ApiError> {
+    require_admin(&state, chat_id, auth.user_id).await?;
+    require_channel(&state, chat_id).await?;
+
+    let post = ChannelPost {
+        id: Uuid::new_v4(),
+        chat_id,
+        sender_id: auth.user_id,
+        content: req.content,
+        associated_data: req.associated_data,
+        created_at: Utc::now(),
+    };
+    state.db.create_channel_post(&post).await?;
+
+    let event = Event::ChannelPost { chat_id, id: post.id };
+    for member in online_members(&state, chat_id).await? {
+        if member.user_id != auth.user_id {
+            send_to_user(&state, member.user_id, &event);
+        }
+    }
+    Ok((StatusCode::CREATED, Json(CreatePostResponse { id: post.id })))
+}
+
+/// A page of the channel's posts, newest first.
+pub(super) async fn list_posts(
+    State(state): State<AppState>,
+    auth: AuthUser,
+    Path(chat_id): Path<Uuid>,
+    Query(query): Query<PostsQuery>,
+) -> Result<impl IntoResponse, ApiError> {
+    require_member(&state, chat_id, auth.user_id).await?;
+    require_channel(&state, chat_id).await?;
+    let posts = state
+        .db
+        .get_channel_posts(chat_id, query.before, query.limit.clamp(1, MAX_PAGE_SIZE))
+        .await?;
+    Ok(Json(posts))
+}
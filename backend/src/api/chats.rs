@@ -0,0 +1,380 @@
+use axum::{
+    extract::{Path, State, Json},
+    http::StatusCode,
+    response::IntoResponse,
+};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::audit::Entry;
+use crate::models::{AuditKind, Chat, ChatMember, ChatRole, ChatSettings};
+use crate::realtime::Event;
+use super::{presence::send_to_user, security, ApiError, AppState, AuthUser, VerifiedUser};
+
+const MAX_CHAT_NAME_LENGTH: usize = 100;
+const MAX_INITIAL_MEMBERS: usize = 256;
+const MAX_TOPIC_LENGTH: usize = 500;
+const MAX_AVATAR_LENGTH: usize = 500;
+
+#[derive(Debug, Deserialize)]
+pub(super) struct CreateChatRequest {
+    /// Required for groups. A chat without a name and with a single other
+    /// member is a direct chat.
+    #[serde(default)]
+    name: Option<String>,
+    #[serde(default)]
+    member_ids: Vec<Uuid>,
+    /// Creates a channel: a named group where the other members are
+    /// read-only subscribers.
+    #[serde(default)]
+    channel: bool,
+}
+
+#[derive(Debug, Deserialize)]
+pub(super) struct AddMemberRequest {
+    user_id: Uuid,
+    #[serde(default)]
+    role: Option<ChatRole>,
+}
+
+#[derive(Debug, Deserialize)]
+pub(super) struct SetRoleRequest {
+    role: ChatRole,
+}
+
+#[derive(Debug, Deserialize)]
+pub(super) struct MuteRequest {
+    /// Unmutes the chat when absent.
+    #[serde(default)]
+    until: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Serialize)]
+struct ChatDetails {
+    #[serde(flatten)]
+    chat: Chat,
+    member_count: i64,
+    /// For channels, subscribers are only listed to admins; others see the
+    /// admins only.
+    members: Vec<ChatMember>,
+    /// Until when the caller muted the chat.
+    muted_until: Option<DateTime<Utc>>,
+}
+
+/// The caller's membership of the chat, or `NotAMember`.
+pub(super) async fn require_member(
+    state: &AppState,
+    chat_id: Uuid,
+    user_id: Uuid,
+) -> Result<ChatMember, ApiError> {
+    state
+        .db
+        .get_chat_member(chat_id, user_id)
+        .await?
+        .ok_or(ApiError::NotAMember)
+}
+
+pub(super) async fn require_admin(state: &AppState, chat_id: Uuid, user_id: Uuid) -> Result<ChatMember, ApiError> {
+    let member = require_member(state, chat_id, user_id).await?;
+    if member.role != ChatRole::Admin {
+        return Err(ApiError::Forbidden);
+    }
+    Ok(member)
+}
+
+/// Tells the chat's members, and `former` if given, that a member with
+/// `role` joined or left. Read-only members come and go without the others
+/// noticing, since they cannot write: only members who can are told, so
+/// that a channel's subscribers are not each told of every other one.
+pub(super) async fn notify_members_changed(
+    state: &AppState,
+    chat_id: Uuid,
+    role: ChatRole,
+    former: Option<Uuid>,
+) -> Result<(), ApiError> {
+    let event = Event::ChatMembersChanged { chat_id };
+    let told = if role == ChatRole::ReadOnly {
+        let mut writers = state.db.get_chat_members_with_role(chat_id, ChatRole::Admin).await?;
+        writers.extend(state.db.get_chat_members_with_role(chat_id, ChatRole::Member).await?);
+        writers
+    } else {
+        online_members(state, chat_id).await?
+    };
+    for user_id in told.iter().map(|m| m.user_id).chain(former) {
+        send_to_user(state, user_id, &event);
+    }
+    Ok(())
+}
+
+/// The chat's members with an open event stream, the only ones events can
+/// reach. Lists whichever is shorter, the members or the users online, so
+/// that a large channel is not read whole for every event.
+pub(super) async fn online_members(state: &AppState, chat_id: Uuid) -> Result<Vec<ChatMember>, ApiError> {
+    let online = state.presence.online_users();
+    if (online.len() as i64) < state.db.count_chat_members(chat_id).await? {
+        return Ok(state.db.get_chat_members_among(chat_id, &online).await?);
+    }
+    let members = state.db.get_chat_members(chat_id).await?;
+    Ok(members.into_iter().filter(|m| state.presence.is_online(m.user_id)).collect())
+}
+
+/// Users who blocked the caller cannot be added to chats by them, and look
+/// the same as unknown users.
+pub(super) async fn check_addable(state: &AppState, by: Uuid, user_id: Uuid) -> Result<(), ApiError> {
+    state.db.get_user(user_id).await?.ok_or(ApiError::NotFound)?;
+    if state.db.is_blocked(user_id, by).await? {
+        return Err(ApiError::NotFound);
+    }
+    Ok(())
+}
+
+/// Tells the chat's members that its settings or pins changed.
+pub(super) async fn notify_chat_updated(state: &AppState, chat_id: Uuid) -> Result<(), ApiError> {
+    let event = Event::ChatUpdated { chat_id };
+    for member in online_members(state, chat_id).await? {
+        send_to_user(state, member.user_id, &event);
+    }
+    Ok(())
+}
+
+/// The role new members get unless an admin picks one.
+pub(super) fn default_role(chat: &Chat) -> ChatRole {
+    if chat.is_channel {
+        ChatRole::ReadOnly
+    } else {
+        ChatRole::Member
+    }
+}
+
+/// Starts a direct chat, or returns the existing one, or creates a group or
+/// channel with the caller as its admin.
+pub(super) async fn create_chat(
+    State(state): State<AppState>,
+    VerifiedUser(auth): VerifiedUser,
+    Json(req): Json<CreateChatRequest>,
+) -> Result<impl IntoResponse, ApiError> {
+    let name = req.name.as_deref().map(str::trim).filter(|n| !n.is_empty());
+    if name.is_some_and(|n| n.chars().count() > MAX_CHAT_NAME_LENGTH) {
+        return Err(ApiError::BadRequest(format!(
+            "Chat name must be at most {} characters",
+            MAX_CHAT_NAME_LENGTH
+        )));
+    }
+    let mut member_ids: Vec<Uuid> = req
+        .member_ids
+        .into_iter()
+        .filter(|id| *id != auth.user_id)
+        .collect();
+    member_ids.sort();
+    member_ids.dedup();
+    if req.channel && name.is_none() {
+        return Err(ApiError::BadRequest("A channel needs a name".to_string()));
+    }
+    if member_ids.is_empty() && !req.channel {
+        return Err(ApiError::BadRequest("A chat needs at least one other member".to_string()));
+    }
+    if member_ids.len() > MAX_INITIAL_MEMBERS {
+        return Err(ApiError::BadRequest(format!(
+            "At most {} members can be added at once",
+            MAX_INITIAL_MEMBERS
+        )));
+    }
+    for id in &member_ids {
+        check_addable(&state, auth.user_id, *id).await?;
+    }
+
+    let is_group = req.channel || name.is_some() || member_ids.len() > 1;
+    if !is_group {
+        if let Some(chat) = state.db.find_direct_chat(auth.user_id, member_ids[0]).await? {
+            return Ok((StatusCode::OK, Json(chat)));
+        }
+    }
+
+    let now = Utc::now();
+    let chat = Chat {
+        id: Uuid::new_v4(),
+        name: name.map(str::to_string),
+        is_group,
+        is_channel: req.channel,
+        settings: ChatSettings::default(),
+        created_at: now,
+        last_message_at: now,
+    };
+    // Both sides of a direct chat are equal.
+    let others_role = if is_group { default_role(&chat) } else { ChatRole::Admin };
+    let members: Vec<ChatMember> = std::iter::once((auth.user_id, ChatRole::Admin))
+        .chain(member_ids.iter().map(|id| (*id, others_role)))
+        .map(|(user_id, role)| ChatMember {
+            chat_id: chat.id,
+            user_id,
+            role,
+            joined_at: now,
+            muted_until: None,
+        })
+        .collect();
+    state.db.create_chat(&chat, &members).await?;
+
+    Ok((StatusCode::CREATED, Json(chat)))
+}
+
+pub(super) async fn list_chats(
+    State(state): State<AppState>,
+    auth: AuthUser,
+) -> Result<impl IntoResponse, ApiError> {
+    Ok(Json(state.db.get_user_chats(auth.user_id).await?))
+}
+
+pub(super) async fn get_chat(
+    State(state): State<AppState>,
+    auth: AuthUser,
+    Path(chat_id): Path<Uuid>,
+) -> Result<impl IntoResponse, ApiError> {
+    let caller = require_member(&state, chat_id, auth.user_id).await?;
+    let chat = state.db.get_chat(chat_id).await?.ok_or(ApiError::NotFound)?;
+    let member_count = state.db.count_chat_members(chat_id).await?;
+    let mut members = if chat.is_channel && caller.role != ChatRole::Admin {
+        state.db.get_chat_members_with_role(chat_id, ChatRole::Admin).await?
+    } else {
+        state.db.get_chat_members(chat_id).await?
+    };
+    // Whether others muted the chat is their business.
+    for member in &mut members {
+        member.muted_until = None;
+    }
+    Ok(Json(ChatDetails {
+        chat,
+        member_count,
+        members,
+        muted_until: caller.muted_until,
+    }))
+}
+
+/// Replaces the chat's topic, avatar and disappearing-message timer.
+pub(super) async fn update_settings(
+    State(state): State<AppState>,
+    auth: AuthUser,
+    Path(chat_id): Path<Uuid>,
+    Json(settings): Json<ChatSettings>,
+) -> Result<impl IntoResponse, ApiError> {
+    require_admin(&state, chat_id, auth.user_id).await?;
+    if settings.topic.as_ref().is_some_and(|t| t.chars().count() > MAX_TOPIC_LENGTH) {
+        return Err(ApiError::BadRequest(format!(
+            "Topics are at most {} characters",
+            MAX_TOPIC_LENGTH
+        )));
+    }
+    if settings.avatar.as_ref().is_some_and(|a| a.len() > MAX_AVATAR_LENGTH) {
+        return Err(ApiError::BadRequest("Avatar reference is too long".to_string()));
+    }
+    if settings.disappear_after_seconds.is_some_and(|s| s <= 0) {
+        return Err(ApiError::BadRequest("disappear_after_seconds must be positive".to_string()));
+    }
+    if !state.db.update_chat_settings(chat_id, &settings).await? {
+        return Err(ApiError::NotFound);
+    }
+    notify_chat_updated(&state, chat_id).await?;
+    Ok(Json(settings))
+}
+
+/// Mutes the chat for the caller on all their devices, or unmutes it.
+pub(super) async fn mute(
+    State(state): State<AppState>,
+    auth: AuthUser,
+    Path(chat_id): Path<Uuid>,
+    Json(req): Json<MuteRequest>,
+) -> Result<impl IntoResponse, ApiError> {
+    if !state.db.set_chat_muted(chat_id, auth.user_id, req.until).await? {
+        return Err(ApiError::NotAMember);
+    }
+    send_to_user(&state, auth.user_id, &Event::ChatUpdated { chat_id });
+    Ok(StatusCode::NO_CONTENT)
+}
+
+pub(super) async fn add_member(
+    State(state): State<AppState>,
+    VerifiedUser(auth): VerifiedUser,
+    Path(chat_id): Path<Uuid>,
+    Json(req): Json<AddMemberRequest>,
+) -> Result<impl IntoResponse, ApiError> {
+    require_admin(&state, chat_id, auth.user_id).await?;
+    let chat = state.db.get_chat(chat_id).await?.ok_or(ApiError::NotFound)?;
+    if !chat.is_group {
+        return Err(ApiError::BadRequest("Members can only be added to groups".to_string()));
+    }
+    check_addable(&state, auth.user_id, req.user_id).await?;
+
+    let member = ChatMember {
+        chat_id,
+        user_id: req.user_id,
+        role: req.role.unwrap_or_else(|| default_role(&chat)),
+        joined_at: Utc::now(),
+        muted_until: None,
+    };
+    if !state.db.add_chat_member(&member).await? {
+        return Err(ApiError::Conflict("Already a member".to_string()));
+    }
+    notify_members_changed(&state, chat_id, member.role, None).await?;
+    Ok((StatusCode::CREATED, Json(member)))
+}
+
+pub(super) async fn set_member_role(
+    State(state): State<AppState>,
+    auth: AuthUser,
+    Path((chat_id, user_id)): Path<(Uuid, Uuid)>,
+    Json(req): Json<SetRoleRequest>,
+) -> Result<impl IntoResponse, ApiError> {
+    require_admin(&state, chat_id, auth.user_id).await?;
+    if user_id == auth.user_id && req.role != ChatRole::Admin {
+        ensure_other_admin(&state, chat_id, auth.user_id).await?;
+    }
+    let member = state.db.get_chat_member(chat_id, user_id).await?.ok_or(ApiError::NotFound)?;
+    if !state.db.set_chat_member_role(chat_id, user_id, req.role).await? {
+        return Err(ApiError::NotFound);
+    }
+    // Who may write changed, and with it who hears of read-only members:
+    // told like a writer joining unless the member stays read-only.
+    let changed = if req.role == ChatRole::ReadOnly { member.role } else { req.role };
+    notify_members_changed(&state, chat_id, changed, None).await?;
+
+    let mut entry = Entry::new(AuditKind::RoleChanged, Some(user_id))
+        .detail("chat_id", chat_id)
+        .detail("role", req.role.as_str());
+    if user_id != auth.user_id {
+        entry = entry.actor(auth.user_id);
+    }
+    security::record(&state, entry).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Admins can remove anyone; everyone can leave.
+pub(super) async fn remove_member(
+    State(state): State<AppState>,
+    auth: AuthUser,
+    Path((chat_id, user_id)): Path<(Uuid, Uuid)>,
+) -> Result<impl IntoResponse, ApiError> {
+    let caller = require_member(&state, chat_id, auth.user_id).await?;
+    if user_id != auth.user_id && caller.role != ChatRole::Admin {
+        return Err(ApiError::Forbidden);
+    }
+    if user_id == auth.user_id && caller.role == ChatRole::Admin {
+        ensure_other_admin(&state, chat_id, auth.user_id).await?;
+    }
+    let removed = state.db.get_chat_member(chat_id, user_id).await?.ok_or(ApiError::NotFound)?;
+    if !state.db.remove_chat_member(chat_id, user_id).await? {
+        return Err(ApiError::NotFound);
+    }
+    notify_members_changed(&state, chat_id, removed.role, Some(user_id)).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// A chat with members must keep at least one admin. The last member may
+/// leave, which deletes the chat.
+async fn ensure_other_admin(state: &AppState, chat_id: Uuid, user_id: Uuid) -> Result<(), ApiError> {
+    let admins = state.db.get_chat_members_with_role(chat_id, ChatRole::Admin).await?;
+    let has_others = state.db.count_chat_members(chat_id).await? > 1;
+    if has_others && !admins.iter().any(|m| m.user_id != user_id) {
+        return Err(ApiError::Conflict("Make another member admin first".to_string()));
+    }
+    Ok(())
+}
@@ -0,0 +1,175 @@
+use axum::{
+    extract::{Path, Query, State, Json},
+    http::StatusCode,
+    response::IntoResponse,
+};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use uuid::Uuid;
+
+use crate::models::{PrivacySettings, UserProfile};
+use super::{ApiError, AppState, AuthUser, VerifiedUser};
+
+/// Most email hashes accepted in one discovery request.
+const MAX_DISCOVERY_HASHES: usize = 1000;
+const MAX_NICKNAME_LENGTH: usize = 64;
+
+#[derive(Debug, Deserialize)]
+pub(super) struct LookupQuery {
+    username: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub(super) struct DiscoverRequest {
+    email_hashes: Vec<String>,
+}
+
+#[derive(Debug, Serialize)]
+struct DiscoveredUser {
+    email_hash: String,
+    user: UserProfile,
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub(super) struct AddContactRequest {
+    #[serde(default)]
+    nickname: Option<String>,
+}
+
+/// Hex SHA-256 of the trimmed, lowercased address. Clients hash their
+/// address book the same way before calling `discover`.
+pub(super) fn email_hash(email: &str) -> String {
+    Sha256::digest(email.trim().to_lowercase().as_bytes())
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+/// The public profile of a user whose id the caller already knows, e.g.
+/// from a message or a chat.
+pub(super) async fn get_user(
+    State(state): State<AppState>,
+    _auth: AuthUser,
+    Path(user_id): Path<Uuid>,
+) -> Result<impl IntoResponse, ApiError> {
+    let user = state.db.get_user(user_id).await?.ok_or(ApiError::NotFound)?;
+    Ok(Json(UserProfile::from(user)))
+}
+
+/// Exact-match lookup. Users who turned off discovery by username are
+/// reported as not found.
+pub(super) async fn lookup(
+    State(state): State<AppState>,
+    _auth: VerifiedUser,
+    Query(query): Query<LookupQuery>,
+) -> Result<impl IntoResponse, ApiError> {
+    let user = state
+        .db
+        .find_user_by_username(query.username.trim())
+        .await?
+        .ok_or(ApiError::NotFound)?;
+    Ok(Json(user))
+}
+
+/// Matches hashed email addresses against users who allow discovery by
+/// email, so that clients can find their address book contacts without
+/// uploading the addresses themselves.
+pub(super) async fn discover(
+    State(state): State<AppState>,
+    VerifiedUser(auth): VerifiedUser,
+    Json(req): Json<DiscoverRequest>,
+) -> Result<impl IntoResponse, ApiError> {
+    if req.email_hashes.len() > MAX_DISCOVERY_HASHES {
+        return Err(ApiError::BadRequest(format!(
+            "At most {} hashes per request",
+            MAX_DISCOVERY_HASHES
+        )));
+    }
+    let hashes: Vec<String> = req
+        .email_hashes
+        .iter()
+        .map(|h| h.to_lowercase())
+        .collect();
+
+    let matches: Vec<DiscoveredUser> = state
+        .db
+        .find_users_by_email_hash(&hashes)
+        .await?
+        .into_iter()
+        .filter(|(_, user)| user.id != auth.user_id)
+        .map(|(email_hash, user)| DiscoveredUser { email_hash, user })
+        .collect();
+    Ok(Json(matches))
+}
+
+pub(super) async fn list_contacts(
+    State(state): State<AppState>,
+    auth: AuthUser,
+) -> Result<impl IntoResponse, ApiError> {
+    Ok(Json(state.db.list_contacts(auth.user_id).await?))
+}
+
+/// Adds the user to the caller's contacts, or renames an existing contact.
+pub(super) async fn add_contact(
+    State(state): State<AppState>,
+    VerifiedUser(auth): VerifiedUser,
+    Path(contact_id): Path<Uuid>,
+    req: Option<Json<AddContactRequest>>,
+) -> Result<impl IntoResponse, ApiError> {
+    if contact_id == auth.user_id {
+        return Err(ApiError::BadRequest("Cannot add yourself as a contact".to_string()));
+    }
+    let Json(req) = req.unwrap_or_default();
+    let nickname = req
+        .nickname
+        .as_deref()
+        .map(str::trim)
+        .filter(|n| !n.is_empty());
+    if nickname.is_some_and(|n| n.chars().count() > MAX_NICKNAME_LENGTH) {
+        return Err(ApiError::BadRequest(format!(
+            "Nickname must be at most {} characters",
+            MAX_NICKNAME_LENGTH
+        )));
+    }
+
+    state.db.get_user(contact_id).await?.ok_or(ApiError::NotFound)?;
+    state.db.add_contact(auth.user_id, contact_id, nickname).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+pub(super) async fn remove_contact(
+    State(state): State<AppState>,
+    auth: AuthUser,
+    Path(contact_id): Path<Uuid>,
+) -> Result<impl IntoResponse, ApiError> {
+    if !state.db.remove_contact(auth.user_id, contact_id).await? {
+        return Err(ApiError::NotFound);
+    }
+    Ok(StatusCode::NO_CONTENT)
+}
+
+pub(super) async fn get_privacy(
+    State(state): State<AppState>,
+    auth: AuthUser,
+) -> Result<impl IntoResponse, ApiError> {
+    Ok(Json(state.db.get_privacy_settings(auth.user_id).await?))
+}
+
+/// Discovery by email is only offered for a confirmed address; otherwise
+/// anyone could sign up with someone else's address and be found as them.
+pub(super) async fn set_privacy(
+    State(state): State<AppState>,
+    auth: AuthUser,
+    Json(settings): Json<PrivacySettings>,
+) -> Result<impl IntoResponse, ApiError> {
+    let user = state.db.get_user(auth.user_id).await?.ok_or(ApiError::NotFound)?;
+    if settings.discoverable_by_email && user.email_verified_at.is_none() {
+        return Err(ApiError::EmailNotVerified);
+    }
+
+    state
+        .db
+        .set_privacy_settings(user.id, settings, &email_hash(&user.email))
+        .await?;
+    Ok(Json(settings))
+}
@@ -0,0 +1,208 @@
+use axum::{
+    extract::{State, Json},
+    http::StatusCode,
+    response::IntoResponse,
+};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use chrono::{Duration, Utc};
+use rand::RngCore;
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+
+use crate::{
+    audit::Entry,
+    config::{env_at_least, env_or, ConfigError},
+    mailer::Email,
+    models::{AuditKind, EmailToken, EmailTokenPurpose, User},
+};
+use super::{hash_password, security, validate_password, ApiError, AppState, AuthUser};
+
+#[derive(Debug, Clone)]
+pub struct EmailConfig {
+    /// Base URL of the web client; links in emails point below it.
+    pub app_url: String,
+    pub verification_ttl: Duration,
+    pub password_reset_ttl: Duration,
+}
+
+impl EmailConfig {
+    pub fn from_env() -> Result<Self, ConfigError> {
+        Ok(Self {
+            app_url: env_or("APP_URL", "http://localhost:8080".to_string())?
+                .trim_end_matches('/')
+                .to_string(),
+            verification_ttl: Duration::hours(env_at_least("EMAIL_VERIFICATION_TTL_HOURS", 24, 1)?),
+            password_reset_ttl: Duration::minutes(env_at_least("PASSWORD_RESET_TTL_MINUTES", 60, 1)?),
+        })
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub(super) struct VerifyEmailRequest {
+    token: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub(super) struct PasswordResetRequest {
+    email: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub(super) struct ResetPasswordRequest {
+    token: String,
+    password: String,
+}
+
+fn new_token() -> String {
+    let mut bytes = [0u8; 32];
+    rand::thread_rng().fill_bytes(&mut bytes);
+    URL_SAFE_NO_PAD.encode(bytes)
+}
+
+fn hash_token(token: &str) -> String {
+    Sha256::digest(token.as_bytes())
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+/// Stores a new token for the user, replacing any earlier one for the same
+/// purpose, and returns it.
+async fn issue_token(state: &AppState, user: &User, purpose: EmailTokenPurpose) -> Result<String, ApiError> {
+    let ttl = match purpose {
+        EmailTokenPurpose::VerifyEmail => state.email.verification_ttl,
+        EmailTokenPurpose::ResetPassword => state.email.password_reset_ttl,
+    };
+    let token = new_token();
+    let now = Utc::now();
+    state
+        .db
+        .create_email_token(&EmailToken {
+            token_hash: hash_token(&token),
+            user_id: user.id,
+            purpose,
+            created_at: now,
+            expires_at: now + ttl,
+        })
+        .await?;
+
+    Ok(token)
+}
+
+/// Sends the verification email for a new or unverified account. Delivery
+/// failures are logged; the user can ask for the email again.
+pub(super) async fn send_verification(state: &AppState, user: &User) -> Result<(), ApiError> {
+    let token = issue_token(state, user, EmailTokenPurpose::VerifyEmail).await?;
+    let email = Email {
+        to: user.email.clone(),
+        subject: "Confirm your Pulse email address".to_string(),
+        body: format!(
+            "Hi {},\n\nConfirm your email address by opening this link:\n\n{}/verify-email?token={}\n\n\
+             The link expires in {} hours. If you did not create a Pulse account, ignore this email.\n",
+            user.username,
+            state.email.app_url,
+            token,
+            state.email.verification_ttl.num_hours(),
+        ),
+    };
+
+    if let Err(e) = state.mailer.send(&email).await {
+        tracing::error!("Failed to send verification email to {}: {}", user.id, e);
+    }
+    Ok(())
+}
+
+pub(super) async fn verify_email(
+    State(state): State<AppState>,
+    Json(req): Json<VerifyEmailRequest>,
+) -> Result<impl IntoResponse, ApiError> {
+    let user_id = state
+        .db
+        .consume_email_token(&hash_token(&req.token), EmailTokenPurpose::VerifyEmail)
+        .await?
+        .ok_or(ApiError::InvalidToken)?;
+
+    state.db.mark_email_verified(user_id).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+pub(super) async fn resend_verification(
+    State(state): State<AppState>,
+    auth: AuthUser,
+) -> Result<impl IntoResponse, ApiError> {
+    let user = state.db.get_user(auth.user_id).await?.ok_or(ApiError::NotFound)?;
+    if user.email_verified_at.is_some() {
+        return Err(ApiError::Conflict("Email address is already verified".to_string()));
+    }
+
+    send_verification(&state, &user).await?;
+    Ok(StatusCode::ACCEPTED)
+}
+
+/// Emails a reset link if the address belongs to an account. The response
+/// is the same either way, and the email is sent in the background so that
+/// timing does not reveal which addresses are registered.
+pub(super) async fn request_password_reset(
+    State(state): State<AppState>,
+    Json(req): Json<PasswordResetRequest>,
+) -> Result<impl IntoResponse, ApiError> {
+    let user = match state.db.get_user_by_email(&req.email).await? {
+        Some(user) => user,
+        None => return Ok(StatusCode::ACCEPTED),
+    };
+
+    let token = issue_token(&state, &user, EmailTokenPurpose::ResetPassword).await?;
+    let email = Email {
+        to: user.email.clone(),
+        subject: "Reset your Pulse password".to_string(),
+        body: format!(
+            "Hi {},\n\nChoose a new password by opening this link:\n\n{}/reset-password?token={}\n\n\
+             The link expires in {} minutes. If you did not ask to reset your password, ignore this \
+             email; your password has not changed.\n",
+            user.username,
+            state.email.app_url,
+            token,
+            state.email.password_reset_ttl.num_minutes(),
+        ),
+    };
+
+    let mailer = state.mailer.clone();
+    tokio::spawn(async move {
+        if let Err(e) = mailer.send(&email).await {
+            tracing::error!("Failed to send password reset email to {}: {}", user.id, e);
+        }
+    });
+
+    Ok(StatusCode::ACCEPTED)
+}
+
+/// Sets a new password and signs out every session, since whoever held the
+/// old password may still be signed in. Following the link also proves the
+/// email address.
+pub(super) async fn reset_password(
+    State(state): State<AppState>,
+    Json(req): Json<ResetPasswordRequest>,
+) -> Result<impl IntoResponse, ApiError> {
+    validate_password(&req.password)?;
+
+    let user_id = state
+        .db
+        .consume_email_token(&hash_token(&req.token), EmailTokenPurpose::ResetPassword)
+        .await?
+        .ok_or(ApiError::InvalidToken)?;
+
+    let password_hash = hash_password(req.password).await?;
+    state.db.set_password_hash(user_id, &password_hash).await?;
+    state.db.mark_email_verified(user_id).await?;
+
+    let revoked = state.db.revoke_user_sessions(user_id, None).await?;
+    for session_id in &revoked {
+        state.hub.disconnect_session(*session_id);
+    }
+    let entry = Entry::new(AuditKind::PasswordChanged, Some(user_id))
+        .detail("reason", "reset")
+        .detail("sessions", &revoked);
+    security::record(&state, entry).await?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
@@ -0,0 +1,179 @@
+use axum::{
+    http::{header, HeaderValue, StatusCode},
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde::Serialize;
+
+use crate::{blob_store::BlobError, db::DatabaseError};
+
+/// Error returned by every API route. Serialized as
+/// `{"error": {"code": "...", "message": "..."}}`; clients match on `code`,
+/// which is stable, and may show `message`, which is not.
+#[derive(Debug, thiserror::Error)]
+pub enum ApiError {
+    #[error("{0}")]
+    BadRequest(String),
+    #[error("Missing or invalid bearer token")]
+    Unauthorized,
+    #[error("Access token expired")]
+    TokenExpired,
+    #[error("Session expired")]
+    SessionExpired,
+    #[error("Invalid email or password")]
+    InvalidCredentials,
+    #[error("Invalid or already used code")]
+    InvalidCode,
+    #[error("Invalid or expired link")]
+    InvalidToken,
+    #[error("Confirm your email address first")]
+    EmailNotVerified,
+    #[error("This account has been suspended")]
+    AccountSuspended,
+    #[error("You are not allowed to do that")]
+    Forbidden,
+    #[error("This message can no longer be edited or deleted")]
+    EditWindowExpired,
+    #[error("Username is already taken")]
+    UsernameTaken,
+    #[error("Email is already registered")]
+    EmailTaken,
+    #[error("You are not a member of this chat")]
+    NotAMember,
+    #[error("Not found")]
+    NotFound,
+    #[error("{0}")]
+    Conflict(String),
+    #[error("{0}")]
+    PayloadTooLarge(String),
+    #[error("Attachment quota exceeded")]
+    QuotaExceeded,
+    #[error("Too many requests")]
+    RateLimited { retry_after: u64 },
+    #[error("The request took too long")]
+    Timeout,
+    /// Details are logged, never sent to the client.
+    #[error("Internal server error")]
+    Internal,
+}
+
+#[derive(Serialize)]
+struct ErrorBody<'a> {
+    error: ErrorDetail<'a>,
+}
+
+#[derive(Serialize)]
+struct ErrorDetail<'a> {
+    code: &'a str,
+    message: String,
+}
+
+impl ApiError {
+    pub fn code(&self) -> &'static str {
+        match self {
+            ApiError::BadRequest(_) => "bad_request",
+            ApiError::Unauthorized => "unauthorized",
+            ApiError::TokenExpired => "token_expired",
+            ApiError::SessionExpired => "session_expired",
+            ApiError::InvalidCredentials => "invalid_credentials",
+            ApiError::InvalidCode => "invalid_code",
+            ApiError::InvalidToken => "invalid_token",
+            ApiError::EmailNotVerified => "email_not_verified",
+            ApiError::AccountSuspended => "account_suspended",
+            ApiError::Forbidden => "forbidden",
+            ApiError::EditWindowExpired => "edit_window_expired",
+            ApiError::UsernameTaken => "username_taken",
+            ApiError::EmailTaken => "email_taken",
+            ApiError::NotAMember => "not_a_member",
+            ApiError::NotFound => "not_found",
+            ApiError::Conflict(_) => "conflict",
+            ApiError::PayloadTooLarge(_) => "payload_too_large",
+            ApiError::QuotaExceeded => "quota_exceeded",
+            ApiError::RateLimited { .. } => "rate_limited",
+            ApiError::Timeout => "timeout",
+            ApiError::Internal => "internal_error",
+        }
+    }
+
+    pub fn status(&self) -> StatusCode {
+        match self {
+            ApiError::BadRequest(_) | ApiError::InvalidToken => StatusCode::BAD_REQUEST,
+            ApiError::Unauthorized
+            | ApiError::TokenExpired
+            | ApiError::SessionExpired
+            | ApiError::InvalidCredentials
+            | ApiError::InvalidCode => StatusCode::UNAUTHORIZED,
+            ApiError::UsernameTaken | ApiError::EmailTaken | ApiError::Conflict(_) => StatusCode::CONFLICT,
+            ApiError::EmailNotVerified
+            | ApiError::AccountSuspended
+            | ApiError::Forbidden
+            | ApiError::EditWindowExpired
+            | ApiError::NotAMember => StatusCode::FORBIDDEN,
+            ApiError::NotFound => StatusCode::NOT_FOUND,
+            ApiError::PayloadTooLarge(_) | ApiError::QuotaExceeded => StatusCode::PAYLOAD_TOO_LARGE,
+            ApiError::RateLimited { .. } => StatusCode::TOO_MANY_REQUESTS,
+            ApiError::Timeout => StatusCode::REQUEST_TIMEOUT,
+            ApiError::Internal => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        let body = ErrorBody {
+            error: ErrorDetail {
+                code: self.code(),
+                message: self.to_string(),
+            },
+        };
+        let mut response = (self.status(), Json(body)).into_response();
+
+        if matches!(
+            self,
+            ApiError::Unauthorized
+                | ApiError::TokenExpired
+                | ApiError::SessionExpired
+                | ApiError::InvalidCredentials
+                | ApiError::InvalidCode
+                | ApiError::AccountSuspended
+        ) {
+            metrics::counter!("pulse_auth_failures_total", "code" => self.code()).increment(1);
+        }
+
+        if let ApiError::RateLimited { retry_after } = self {
+            response
+                .headers_mut()
+                .insert(header::RETRY_AFTER, HeaderValue::from(retry_after.max(1)));
+        }
+
+        response
+    }
+}
+
+impl From<DatabaseError> for ApiError {
+    fn from(e: DatabaseError) -> Self {
+        match e {
+            DatabaseError::UniqueViolation(target) => match target.as_str() {
+                "users.username" => ApiError::UsernameTaken,
+                "users.email" => ApiError::EmailTaken,
+                _ => ApiError::Conflict("Already exists".to_string()),
+            },
+            e => {
+                tracing::error!("Database error: {}", e);
+                ApiError::Internal
+            }
+        }
+    }
+}
+
+impl From<BlobError> for ApiError {
+    fn from(e: BlobError) -> Self {
+        match e {
+            BlobError::NotFound(_) => ApiError::NotFound,
+            e => {
+                tracing::error!("Blob store error: {}", e);
+                ApiError::Internal
+            }
+        }
+    }
+}
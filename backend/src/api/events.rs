@@ -0,0 +1,58 @@
+use std::convert::Infallible;
+
+use axum::{
+    extract::State,
+    response::sse::{Event as SseEvent, KeepAlive, Sse},
+};
+use futures::{stream, Stream, StreamExt};
+use uuid::Uuid;
+
+use crate::db::Database;
+use crate::realtime::Event;
+use super::{presence::PresenceGuard, ApiError, AppState, AuthUser};
+
+/// Server-sent event stream of `realtime::Event`s for the caller's device.
+pub(super) async fn events(
+    State(state): State<AppState>,
+    auth: AuthUser,
+) -> Result<Sse<impl Stream<Item = Result<SseEvent, Infallible>>>, ApiError> {
+    let subscription = state.hub.subscribe(auth.device_id, auth.session_id);
+    let presence = PresenceGuard::connect(&state, auth);
+
+    // Warnings raised while the device was offline are not queued, so repeat
+    // the current state on connect.
+    let remaining = state.db.count_one_time_prekeys(auth.device_id).await?;
+    let initial = (remaining < state.prekeys.low_threshold).then_some(Event::PrekeysLow { remaining });
+
+    // The stream owns the presence guard, so the device goes offline when
+    // the client disconnects.
+    let events = stream::iter(initial)
+        .chain(subscription)
+        .take_until(session_ended(state.db.clone(), auth.session_id, state.sessions.stream_check_interval))
+        .map(move |event| {
+            let _ = &presence;
+            Ok(SseEvent::default()
+                .event(event.name())
+                .json_data(&event)
+                .unwrap())
+        });
+
+    Ok(Sse::new(events).keep_alive(KeepAlive::default()))
+}
+
+/// Resolves once the session is revoked or expires. The hub disconnects
+/// streams of sessions revoked through this server at once; this catches
+/// the rest, such as revocations by another server or `pulse-admin`.
+async fn session_ended(db: Database, session_id: Uuid, interval: std::time::Duration) {
+    let mut ticks = tokio::time::interval(interval);
+    ticks.tick().await;
+    loop {
+        ticks.tick().await;
+        match db.get_active_session(session_id).await {
+            Ok(Some(_)) => {}
+            Ok(None) => return,
+            // Keep the stream rather than drop every client on a hiccup.
+            Err(e) => tracing::warn!("Checking session {} of an event stream failed: {}", session_id, e),
+        }
+    }
+}
@@ -0,0 +1,235 @@
+use axum::{
+    extract::{Path, State, Json},
+    http::StatusCode,
+    response::IntoResponse,
+};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use chrono::{Duration, Utc};
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::models::{ChatInvite, ChatMember, ChatRole, JoinRequest};
+use crate::realtime::Event;
+use super::{
+    chats::{default_role, notify_members_changed, require_admin},
+    presence::send_to_user,
+    ApiError, AppState, AuthUser, VerifiedUser,
+};
+
+#[derive(Debug, Deserialize)]
+pub(super) struct CreateInviteRequest {
+    #[serde(default)]
+    expires_in_minutes: Option<i64>,
+    #[serde(default)]
+    max_uses: Option<i64>,
+    #[serde(default)]
+    requires_approval: bool,
+}
+
+/// What someone holding an invite code gets to see before joining.
+#[derive(Debug, Serialize)]
+struct InvitePreview {
+    chat_id: Uuid,
+    name: Option<String>,
+    member_count: i64,
+    requires_approval: bool,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(tag = "status", rename_all = "snake_case")]
+enum JoinResult {
+    Joined { member: ChatMember },
+    /// Waiting for an admin's approval.
+    Pending,
+}
+
+fn new_code() -> String {
+    let mut bytes = [0u8; 16];
+    rand::thread_rng().fill_bytes(&mut bytes);
+    URL_SAFE_NO_PAD.encode(bytes)
+}
+
+/// A usable invite by its code. Unknown, expired, used up and revoked
+/// invites all look the same.
+async fn usable_invite(state: &AppState, code: &str) -> Result<ChatInvite, ApiError> {
+    match state.db.get_invite_by_code(code).await? {
+        Some(invite) if invite.is_usable(Utc::now()) => Ok(invite),
+        _ => Err(ApiError::NotFound),
+    }
+}
+
+async fn add_member(state: &AppState, chat_id: Uuid, user_id: Uuid) -> Result<ChatMember, ApiError> {
+    let chat = state.db.get_chat(chat_id).await?.ok_or(ApiError::NotFound)?;
+    let member = ChatMember {
+        chat_id,
+        user_id,
+        role: default_role(&chat),
+        joined_at: Utc::now(),
+        muted_until: None,
+    };
+    if !state.db.add_chat_member(&member).await? {
+        return Err(ApiError::Conflict("Already a member".to_string()));
+    }
+    notify_members_changed(state, chat_id, member.role, None).await?;
+    Ok(member)
+}
+
+pub(super) async fn create_invite(
+    State(state): State<AppState>,
+    VerifiedUser(auth): VerifiedUser,
+    Path(chat_id): Path<Uuid>,
+    Json(req): Json<CreateInviteRequest>,
+) -> Result<impl IntoResponse, ApiError> {
+    require_admin(&state, chat_id, auth.user_id).await?;
+    let chat = state.db.get_chat(chat_id).await?.ok_or(ApiError::NotFound)?;
+    if !chat.is_group {
+        return Err(ApiError::BadRequest("Only groups have invite links".to_string()));
+    }
+    if req.expires_in_minutes.is_some_and(|m| m <= 0) || req.max_uses.is_some_and(|n| n <= 0) {
+        return Err(ApiError::BadRequest(
+            "expires_in_minutes and max_uses must be positive".to_string(),
+        ));
+    }
+
+    let now = Utc::now();
+    let invite = ChatInvite {
+        id: Uuid::new_v4(),
+        chat_id,
+        code: new_code(),
+        created_by: auth.user_id,
+        created_at: now,
+        expires_at: req.expires_in_minutes.map(|m| now + Duration::minutes(m)),
+        max_uses: req.max_uses,
+        use_count: 0,
+        requires_approval: req.requires_approval,
+        revoked_at: None,
+    };
+    state.db.create_invite(&invite).await?;
+    Ok((StatusCode::CREATED, Json(invite)))
+}
+
+pub(super) async fn list_invites(
+    State(state): State<AppState>,
+    auth: AuthUser,
+    Path(chat_id): Path<Uuid>,
+) -> Result<impl IntoResponse, ApiError> {
+    require_admin(&state, chat_id, auth.user_id).await?;
+    Ok(Json(state.db.get_chat_invites(chat_id).await?))
+}
+
+/// Stops the invite from being used. Pending requests made through it are
+/// dropped with it.
+pub(super) async fn revoke_invite(
+    State(state): State<AppState>,
+    auth: AuthUser,
+    Path((chat_id, invite_id)): Path<(Uuid, Uuid)>,
+) -> Result<impl IntoResponse, ApiError> {
+    require_admin(&state, chat_id, auth.user_id).await?;
+    match state.db.get_invite(invite_id).await? {
+        Some(invite) if invite.chat_id == chat_id => {}
+        _ => return Err(ApiError::NotFound),
+    }
+    state.db.revoke_invite(invite_id, Utc::now()).await?;
+    for request in state.db.get_join_requests(chat_id).await? {
+        if request.invite_id == invite_id {
+            state.db.take_join_request(chat_id, request.user_id).await?;
+        }
+    }
+    Ok(StatusCode::NO_CONTENT)
+}
+
+pub(super) async fn preview_invite(
+    State(state): State<AppState>,
+    _auth: AuthUser,
+    Path(code): Path<String>,
+) -> Result<impl IntoResponse, ApiError> {
+    let invite = usable_invite(&state, &code).await?;
+    let chat = state.db.get_chat(invite.chat_id).await?.ok_or(ApiError::NotFound)?;
+    let member_count = state.db.count_chat_members(chat.id).await?;
+    Ok(Json(InvitePreview {
+        chat_id: chat.id,
+        name: chat.name,
+        member_count,
+        requires_approval: invite.requires_approval,
+    }))
+}
+
+/// Joins the group straight away, or asks its admins to let the caller in
+/// if the invite needs approval. Either way the invite counts as used.
+pub(super) async fn join(
+    State(state): State<AppState>,
+    VerifiedUser(auth): VerifiedUser,
+    Path(code): Path<String>,
+) -> Result<impl IntoResponse, ApiError> {
+    let invite = usable_invite(&state, &code).await?;
+    // To someone its creator has blocked, the invite does not work.
+    if state.db.is_blocked(invite.created_by, auth.user_id).await? {
+        return Err(ApiError::NotFound);
+    }
+    if state.db.get_chat_member(invite.chat_id, auth.user_id).await?.is_some() {
+        return Err(ApiError::Conflict("Already a member".to_string()));
+    }
+    if !state.db.redeem_invite(invite.id, Utc::now()).await? {
+        return Err(ApiError::NotFound);
+    }
+
+    if !invite.requires_approval {
+        let member = add_member(&state, invite.chat_id, auth.user_id).await?;
+        return Ok((StatusCode::CREATED, Json(JoinResult::Joined { member })));
+    }
+
+    let request = JoinRequest {
+        chat_id: invite.chat_id,
+        user_id: auth.user_id,
+        invite_id: invite.id,
+        created_at: Utc::now(),
+    };
+    if state.db.create_join_request(&request).await? {
+        let event = Event::JoinRequested { chat_id: invite.chat_id, user_id: auth.user_id };
+        for admin in state.db.get_chat_members(invite.chat_id).await? {
+            if admin.role == ChatRole::Admin {
+                send_to_user(&state, admin.user_id, &event);
+            }
+        }
+    }
+    Ok((StatusCode::ACCEPTED, Json(JoinResult::Pending)))
+}
+
+pub(super) async fn list_join_requests(
+    State(state): State<AppState>,
+    auth: AuthUser,
+    Path(chat_id): Path<Uuid>,
+) -> Result<impl IntoResponse, ApiError> {
+    require_admin(&state, chat_id, auth.user_id).await?;
+    Ok(Json(state.db.get_join_requests(chat_id).await?))
+}
+
+pub(super) async fn approve_join_request(
+    State(state): State<AppState>,
+    auth: AuthUser,
+    Path((chat_id, user_id)): Path<(Uuid, Uuid)>,
+) -> Result<impl IntoResponse, ApiError> {
+    require_admin(&state, chat_id, auth.user_id).await?;
+    state
+        .db
+        .take_join_request(chat_id, user_id)
+        .await?
+        .ok_or(ApiError::NotFound)?;
+    let member = add_member(&state, chat_id, user_id).await?;
+    Ok((StatusCode::CREATED, Json(member)))
+}
+
+pub(super) async fn decline_join_request(
+    State(state): State<AppState>,
+    auth: AuthUser,
+    Path((chat_id, user_id)): Path<(Uuid, Uuid)>,
+) -> Result<impl IntoResponse, ApiError> {
+    require_admin(&state, chat_id, auth.user_id).await?;
+    state
+        .db
+        .take_join_request(chat_id, user_id)
+        .await?
+        .ok_or(ApiError::NotFound)?;
+    Ok(StatusCode::NO_CONTENT)
+}
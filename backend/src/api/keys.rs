@@ -0,0 +1,160 @@
+use axum::{
+    extract::{Path, State, Json},
+    http::StatusCode,
+    response::IntoResponse,
+};
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::{
+    config::{env_at_least, ConfigError},
+    models::{OneTimePrekey, PrekeyBundle, SignedPrekey},
+    realtime::Event,
+};
+use super::{ApiError, AppState, AuthUser, VerifiedUser};
+
+/// Most one-time prekeys accepted in one upload.
+const MAX_UPLOAD_BATCH: usize = 100;
+
+#[derive(Debug, Clone)]
+pub struct PrekeyConfig {
+    /// Most one-time prekeys a device may have stored at once.
+    pub max_one_time_prekeys: i64,
+    /// The owning device is warned when its pool drops below this.
+    pub low_threshold: i64,
+}
+
+impl PrekeyConfig {
+    pub fn from_env() -> Result<Self, ConfigError> {
+        Ok(Self {
+            max_one_time_prekeys: env_at_least("PREKEY_MAX_ONE_TIME", 500, 1)?,
+            low_threshold: env_at_least("PREKEY_LOW_THRESHOLD", 20, 0)?,
+        })
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub(super) struct SignedPrekeyRequest {
+    key_id: i64,
+    public_key: Vec<u8>,
+    signature: Vec<u8>,
+}
+
+#[derive(Debug, Deserialize)]
+pub(super) struct OneTimePrekeysRequest {
+    prekeys: Vec<OneTimePrekey>,
+}
+
+#[derive(Debug, Serialize)]
+struct PrekeyCountResponse {
+    one_time_prekeys: i64,
+}
+
+pub(super) async fn set_signed_prekey(
+    State(state): State<AppState>,
+    auth: AuthUser,
+    Json(req): Json<SignedPrekeyRequest>,
+) -> Result<impl IntoResponse, ApiError> {
+    if req.public_key.is_empty() || req.signature.is_empty() {
+        return Err(ApiError::BadRequest("Signed prekey requires a key and signature".to_string()));
+    }
+
+    let prekey = SignedPrekey {
+        device_id: auth.device_id,
+        key_id: req.key_id,
+        public_key: req.public_key,
+        signature: req.signature,
+        created_at: Utc::now(),
+    };
+    state.db.set_signed_prekey(&prekey).await?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+pub(super) async fn upload_one_time_prekeys(
+    State(state): State<AppState>,
+    auth: AuthUser,
+    Json(req): Json<OneTimePrekeysRequest>,
+) -> Result<impl IntoResponse, ApiError> {
+    if req.prekeys.is_empty() || req.prekeys.len() > MAX_UPLOAD_BATCH {
+        return Err(ApiError::BadRequest(format!(
+            "Upload between 1 and {} one-time prekeys at a time",
+            MAX_UPLOAD_BATCH
+        )));
+    }
+    if req.prekeys.iter().any(|p| p.public_key.is_empty()) {
+        return Err(ApiError::BadRequest("One-time prekey is empty".to_string()));
+    }
+
+    let stored = state.db.count_one_time_prekeys(auth.device_id).await?;
+    if stored + req.prekeys.len() as i64 > state.prekeys.max_one_time_prekeys {
+        return Err(ApiError::Conflict(format!(
+            "A device may store at most {} one-time prekeys",
+            state.prekeys.max_one_time_prekeys
+        )));
+    }
+
+    state.db.add_one_time_prekeys(auth.device_id, &req.prekeys).await?;
+
+    let response = PrekeyCountResponse {
+        one_time_prekeys: stored + req.prekeys.len() as i64,
+    };
+    Ok((StatusCode::CREATED, Json(response)))
+}
+
+pub(super) async fn prekey_count(
+    State(state): State<AppState>,
+    auth: AuthUser,
+) -> Result<impl IntoResponse, ApiError> {
+    let count = state.db.count_one_time_prekeys(auth.device_id).await?;
+    Ok(Json(PrekeyCountResponse { one_time_prekeys: count }))
+}
+
+/// Returns a bundle for each of the user's devices that has published a
+/// signed prekey, consuming one one-time prekey per device. The caller's own
+/// device is skipped.
+pub(super) async fn get_prekey_bundles(
+    State(state): State<AppState>,
+    VerifiedUser(auth): VerifiedUser,
+    Path(user_id): Path<Uuid>,
+) -> Result<impl IntoResponse, ApiError> {
+    if state.db.get_user(user_id).await?.is_none() {
+        return Err(ApiError::NotFound);
+    }
+
+    let mut bundles = Vec::new();
+    for device in state.db.get_user_devices(user_id).await? {
+        if device.id == auth.device_id {
+            continue;
+        }
+        let signed_prekey = match state.db.get_signed_prekey(device.id).await? {
+            Some(prekey) => prekey,
+            None => continue,
+        };
+
+        let one_time_prekey = state.db.claim_one_time_prekey(device.id).await?;
+        if one_time_prekey.is_some() {
+            warn_if_low(&state, device.id).await?;
+        }
+
+        bundles.push(PrekeyBundle {
+            device_id: device.id,
+            identity_key: device.public_key,
+            signed_prekey,
+            one_time_prekey,
+        });
+    }
+
+    Ok(Json(bundles))
+}
+
+/// Warns the device once when its pool drops below the threshold and again
+/// when it runs out, rather than on every claim.
+async fn warn_if_low(state: &AppState, device_id: Uuid) -> Result<(), ApiError> {
+    let remaining = state.db.count_one_time_prekeys(device_id).await?;
+    if remaining == state.prekeys.low_threshold - 1 || remaining == 0 {
+        state.hub.send_to_device(device_id, Event::PrekeysLow { remaining });
+    }
+    Ok(())
+}
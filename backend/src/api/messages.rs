@@ -0,0 +1,77 @@
+use chrono::{Duration, Utc};
+use uuid::Uuid;
+
+use crate::config::{env_at_least, ConfigError};
+use crate::models::{Message, MessageKind};
+use super::{ApiError, AppState};
+
+#[derive(Debug, Clone)]
+pub struct MessageConfig {
+    /// How long after sending a message its sender may edit it.
+    pub edit_window: Duration,
+    /// How long after sending a message its sender may delete it for
+    /// everyone.
+    pub delete_window: Duration,
+}
+
+impl MessageConfig {
+    pub fn from_env() -> Result<Self, ConfigError> {
+        Ok(Self {
+            edit_window: Duration::minutes(env_at_least("MESSAGE_EDIT_WINDOW_MINUTES", 15, 0)?),
+            delete_window: Duration::minutes(env_at_least("MESSAGE_DELETE_WINDOW_MINUTES", 48 * 60, 0)?),
+        })
+    }
+}
+
+/// Checks an edit, deletion or reaction against the message it refers to,
+/// and returns that message. Other kinds must not refer to one.
+///
+/// Control messages go to the other party of the target message. Only its
+/// sender may edit or delete it, and only within the configured windows;
+/// either party may react.
+pub(super) async fn check_target(
+    state: &AppState,
+    sender_id: Uuid,
+    recipient_id: Uuid,
+    kind: MessageKind,
+    target_id: Option<Uuid>,
+) -> Result<Option<Message>, ApiError> {
+    let window = match kind {
+        MessageKind::Message | MessageKind::Receipt => {
+            if target_id.is_some() {
+                return Err(ApiError::BadRequest(format!(
+                    "A {} cannot refer to another message",
+                    kind.as_str()
+                )));
+            }
+            return Ok(None);
+        }
+        MessageKind::Edit => Some(state.messages.edit_window),
+        MessageKind::Delete => Some(state.messages.delete_window),
+        MessageKind::Reaction => None,
+    };
+
+    let target_id = target_id
+        .ok_or_else(|| ApiError::BadRequest(format!("A {} needs a target_id", kind.as_str())))?;
+    // Messages of other conversations look the same as missing ones.
+    let target = match state.db.get_message(target_id).await? {
+        Some(m) if m.kind == MessageKind::Message && (m.sender_id == sender_id || m.recipient_id == sender_id) => m,
+        _ => return Err(ApiError::NotFound),
+    };
+    let other = if target.sender_id == sender_id { target.recipient_id } else { target.sender_id };
+    if recipient_id != other {
+        return Err(ApiError::BadRequest(
+            "Send it to the other party of the target message".to_string(),
+        ));
+    }
+
+    if let Some(window) = window {
+        if target.sender_id != sender_id {
+            return Err(ApiError::Forbidden);
+        }
+        if Utc::now() - target.created_at > window {
+            return Err(ApiError::EditWindowExpired);
+        }
+    }
+    Ok(Some(target))
+}
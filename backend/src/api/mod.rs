@@ -0,0 +1,595 @@
+mod account;
+mod admin;
+mod attachments;
+mod bots;
+mod channels;
+mod chats;
+mod contacts;
+mod email;
+mod error;
+mod events;
+mod invites;
+mod keys;
+mod messages;
+mod moderation;
+mod monitoring;
+mod pins;
+mod presence;
+mod security;
+mod sessions;
+mod threads;
+mod two_factor;
+
+use std::sync::{Arc, OnceLock};
+
+use axum::{
+    async_trait,
+    error_handling::HandleErrorLayer,
+    routing::{delete, get, post, put},
+    BoxError, Router,
+    extract::{DefaultBodyLimit, FromRequestParts, State, Json},
+    response::{IntoResponse, Response},
+    http::{header::AUTHORIZATION, request::Parts, StatusCode},
+};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+use chrono::{DateTime, Duration, Utc};
+use argon2::{
+    password_hash::{PasswordHash, PasswordHasher, PasswordVerifier, SaltString},
+    Argon2,
+};
+
+use tower::{timeout::TimeoutLayer, ServiceBuilder};
+
+use crate::{
+    audit::Entry,
+    config::LimitsConfig,
+    jwt::{KeyRing, TokenError, ACCESS_TOKEN_TYPE},
+    models::{AuditKind, User, Message, MessageKind, Device},
+    db::Database,
+    blob_store::BlobStore,
+    mailer::Mailer,
+    rate_limit::{RateLimiter, RateLimitLayer},
+    telemetry::MetricsLayer,
+    presence::Presence,
+    realtime::{Event, Hub},
+    webhooks::WebhookSender,
+};
+
+pub use account::{AccountConfig, AccountPurge, purge_deleted as purge_deleted_accounts};
+pub use attachments::{AttachmentConfig, purge_expired as purge_expired_attachments};
+pub use bots::BotConfig;
+pub(crate) use bots::api_key_bot;
+pub use email::EmailConfig;
+pub use error::ApiError;
+pub use keys::PrekeyConfig;
+pub use messages::MessageConfig;
+pub use sessions::SessionConfig;
+
+const MIN_PASSWORD_LENGTH: usize = 8;
+
+#[derive(Debug, Serialize, Deserialize)]
+pub(crate) struct Claims {
+    pub sub: String, // user id
+    #[serde(default)]
+    pub did: Option<String>, // device id
+    #[serde(default)]
+    pub sid: Option<String>, // session id
+    pub exp: usize,
+    pub iat: usize,
+}
+
+#[derive(Debug, Deserialize)]
+struct CreateUserRequest {
+    username: String,
+    email: String,
+    password: String,
+    public_key: Vec<u8>,
+}
+
+#[derive(Debug, Deserialize)]
+struct LoginRequest {
+    email: String,
+    password: String,
+    device_name: String,
+    public_key: Vec<u8>,
+}
+
+#[derive(Debug, Serialize)]
+struct LoginResponse {
+    #[serde(flatten)]
+    tokens: sessions::TokenPair,
+    user: User,
+}
+
+#[derive(Debug, Deserialize)]
+struct SendMessageRequest {
+    recipient_id: Uuid,
+    content: Vec<u8>,
+    associated_data: Option<Vec<u8>>,
+    expires_at: Option<DateTime<Utc>>,
+    #[serde(default)]
+    attachment_ids: Vec<String>,
+    #[serde(default)]
+    kind: MessageKind,
+    #[serde(default)]
+    target_id: Option<Uuid>,
+    #[serde(default)]
+    chat_id: Option<Uuid>,
+    #[serde(default)]
+    thread_id: Option<Uuid>,
+}
+
+#[derive(Clone)]
+pub struct AppState {
+    pub db: Database,
+    pub keys: Arc<KeyRing>,
+    pub blobs: Arc<dyn BlobStore>,
+    pub attachments: AttachmentConfig,
+    pub rate_limiter: Arc<RateLimiter>,
+    pub prekeys: PrekeyConfig,
+    pub sessions: SessionConfig,
+    pub hub: Arc<Hub>,
+    pub presence: Arc<Presence>,
+    pub mailer: Arc<dyn Mailer>,
+    pub email: EmailConfig,
+    pub account: AccountConfig,
+    pub messages: MessageConfig,
+    pub bots: BotConfig,
+    pub webhooks: Arc<WebhookSender>,
+    pub limits: LimitsConfig,
+}
+
+/// The caller of an authenticated route, resolved from the bearer token.
+#[derive(Debug, Clone, Copy)]
+pub struct AuthUser {
+    pub user_id: Uuid,
+    pub device_id: Uuid,
+    pub session_id: Uuid,
+}
+
+#[async_trait]
+impl FromRequestParts<AppState> for AuthUser {
+    type Rejection = ApiError;
+
+    async fn from_request_parts(parts: &mut Parts, state: &AppState) -> Result<Self, Self::Rejection> {
+        let token = parts
+            .headers
+            .get(AUTHORIZATION)
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.strip_prefix("Bearer "))
+            .ok_or(ApiError::Unauthorized)?;
+        if token.starts_with(bots::API_KEY_PREFIX) {
+            return bots::authenticate(state, token).await;
+        }
+
+        let claims: Claims = state
+            .keys
+            .verify_or_reload(&state.db, token, ACCESS_TOKEN_TYPE)
+            .await
+            .map_err(|e| match e {
+                TokenError::Expired => ApiError::TokenExpired,
+                TokenError::UnknownKey | TokenError::Invalid => ApiError::Unauthorized,
+            })?;
+
+        // Checked on every request so that revocation takes effect at once,
+        // not when the access token expires.
+        let session_id = claims
+            .sid
+            .and_then(|sid| sid.parse().ok())
+            .ok_or(ApiError::Unauthorized)?;
+        let session = state
+            .db
+            .get_active_session(session_id)
+            .await?
+            .ok_or(ApiError::SessionExpired)?;
+
+        Ok(AuthUser {
+            user_id: session.user_id,
+            device_id: session.device_id,
+            session_id: session.id,
+        })
+    }
+}
+
+/// An authenticated caller whose email address is confirmed. Unverified
+/// accounts can sign in and manage their account, but cannot reach other
+/// users.
+#[derive(Debug, Clone, Copy)]
+pub struct VerifiedUser(pub AuthUser);
+
+#[async_trait]
+impl FromRequestParts<AppState> for VerifiedUser {
+    type Rejection = ApiError;
+
+    async fn from_request_parts(parts: &mut Parts, state: &AppState) -> Result<Self, Self::Rejection> {
+        let auth = AuthUser::from_request_parts(parts, state).await?;
+        let user = state.db.get_user(auth.user_id).await?.ok_or(ApiError::Unauthorized)?;
+        if user.email_verified_at.is_none() {
+            return Err(ApiError::EmailNotVerified);
+        }
+        Ok(VerifiedUser(auth))
+    }
+}
+
+/// An authenticated caller with admin rights.
+#[derive(Debug, Clone, Copy)]
+pub struct AdminUser(pub AuthUser);
+
+#[async_trait]
+impl FromRequestParts<AppState> for AdminUser {
+    type Rejection = ApiError;
+
+    async fn from_request_parts(parts: &mut Parts, state: &AppState) -> Result<Self, Self::Rejection> {
+        let auth = AuthUser::from_request_parts(parts, state).await?;
+        let user = state.db.get_user(auth.user_id).await?.ok_or(ApiError::Unauthorized)?;
+        if !user.is_admin {
+            return Err(ApiError::Forbidden);
+        }
+        Ok(AdminUser(auth))
+    }
+}
+
+pub fn create_router(state: AppState) -> Router {
+    let max_chunk_size = state.attachments.max_chunk_size;
+    let rate_limit = RateLimitLayer::new(state.rate_limiter.clone());
+    let timeout = ServiceBuilder::new()
+        .layer(HandleErrorLayer::new(|_: BoxError| async { ApiError::Timeout }))
+        .layer(TimeoutLayer::new(state.limits.request_timeout));
+
+    // Long-lived by nature, so exempt from the request timeout.
+    let streaming = Router::new()
+        .route("/api/events", get(events::events))
+        .route(
+            "/api/attachments/:id",
+            get(attachments::download_attachment)
+                .head(attachments::upload_status)
+                .patch(attachments::upload_chunk)
+                .delete(attachments::delete_attachment)
+                .layer(DefaultBodyLimit::max(max_chunk_size)),
+        );
+
+    Router::new()
+        .route("/api/users", post(create_user))
+        .route("/api/users/lookup", get(contacts::lookup))
+        .route("/api/users/discover", post(contacts::discover))
+        .route("/api/users/:id", get(contacts::get_user))
+        .route("/api/users/:id/presence", get(presence::get_presence))
+        .route("/api/auth/login", post(login))
+        .route("/api/auth/login/mfa", post(two_factor::login_mfa))
+        .route("/api/auth/refresh", post(sessions::refresh))
+        .route("/api/auth/logout", post(sessions::logout))
+        .route("/.well-known/jwks.json", get(sessions::jwks))
+        .route("/api/auth/password-reset", post(email::request_password_reset))
+        .route("/api/auth/password-reset/confirm", post(email::reset_password))
+        .route(
+            "/api/sessions",
+            get(sessions::list_sessions).delete(sessions::revoke_other_sessions),
+        )
+        .route("/api/sessions/:id", delete(sessions::revoke_session))
+        .route("/api/account/export", get(account::export))
+        .route("/api/account/delete", post(account::request_deletion))
+        .route(
+            "/api/account/privacy",
+            get(contacts::get_privacy).put(contacts::set_privacy),
+        )
+        .route("/api/account/security-events", get(security::list_events))
+        .route("/api/account/verify-email", post(email::verify_email))
+        .route("/api/account/verify-email/resend", post(email::resend_verification))
+        .route("/api/account/2fa/setup", post(two_factor::setup))
+        .route("/api/account/2fa/enable", post(two_factor::enable))
+        .route("/api/account/2fa/disable", post(two_factor::disable))
+        .route("/api/contacts", get(contacts::list_contacts))
+        .route(
+            "/api/contacts/:id",
+            put(contacts::add_contact).delete(contacts::remove_contact),
+        )
+        .route("/api/blocks", get(moderation::list_blocks))
+        .route(
+            "/api/blocks/:id",
+            put(moderation::block_user).delete(moderation::unblock_user),
+        )
+        .route("/api/reports", post(moderation::create_report))
+        .route("/api/admin/reports", get(admin::list_reports))
+        .route("/api/admin/reports/:id", get(admin::get_report))
+        .route("/api/admin/reports/:id/resolve", post(admin::resolve_report))
+        .route("/api/admin/users/:id/suspend", post(admin::suspend_user))
+        .route("/api/admin/users/:id/unsuspend", post(admin::unsuspend_user))
+        .route("/api/bots", get(bots::list_bots).post(bots::create_bot))
+        .route("/api/bots/:id", delete(bots::delete_bot))
+        .route("/api/bots/:id/keys", get(bots::list_keys).post(bots::create_key))
+        .route("/api/bots/:id/keys/:key_id", delete(bots::revoke_key))
+        .route(
+            "/api/bots/:id/webhook",
+            get(bots::get_webhook).put(bots::set_webhook).delete(bots::delete_webhook),
+        )
+        .route("/api/chats", get(chats::list_chats).post(chats::create_chat))
+        .route("/api/chats/:id", get(chats::get_chat))
+        .route("/api/chats/:id/members", post(chats::add_member))
+        .route("/api/chats/:id/settings", put(chats::update_settings))
+        .route("/api/chats/:id/mute", put(chats::mute))
+        .route("/api/chats/:id/pins", get(pins::list_pins).post(pins::create_pin))
+        .route("/api/chats/:id/pins/:pin_id", delete(pins::delete_pin))
+        .route(
+            "/api/chats/:id/members/:user_id",
+            put(chats::set_member_role).delete(chats::remove_member),
+        )
+        .route("/api/chats/:id/invites", get(invites::list_invites).post(invites::create_invite))
+        .route("/api/chats/:id/invites/:invite_id", delete(invites::revoke_invite))
+        .route("/api/chats/:id/join-requests", get(invites::list_join_requests))
+        .route(
+            "/api/chats/:id/join-requests/:user_id",
+            post(invites::approve_join_request).delete(invites::decline_join_request),
+        )
+        .route("/api/invites/:code", get(invites::preview_invite))
+        .route("/api/invites/:code/join", post(invites::join))
+        .route("/api/chats/:id/posts", get(channels::list_posts).post(channels::create_post))
+        .route("/api/chats/:id/threads", get(threads::list_threads))
+        .route("/api/chats/:id/threads/:thread_id/messages", get(threads::get_thread_messages))
+        .route("/api/chats/:id/threads/:thread_id/read", post(threads::mark_thread_read))
+        .route("/api/stars", get(pins::list_stars))
+        .route("/api/stars/:message_id", put(pins::star).delete(pins::unstar))
+        .route("/api/typing", post(presence::typing))
+        .route("/api/messages", post(send_message))
+        .route("/api/messages", get(get_messages))
+        .route("/api/keys/signed-prekey", put(keys::set_signed_prekey))
+        .route("/api/keys/one-time-prekeys", post(keys::upload_one_time_prekeys))
+        .route("/api/keys/count", get(keys::prekey_count))
+        .route("/api/users/:id/prekeys", get(keys::get_prekey_bundles))
+        .route("/api/attachments", post(attachments::create_attachment))
+        .layer(timeout)
+        .merge(streaming)
+        .layer(DefaultBodyLimit::max(state.limits.max_body_bytes))
+        .route_layer(rate_limit)
+        .route_layer(MetricsLayer)
+        // Probes and scrapes are neither rate limited nor measured.
+        .route("/healthz", get(monitoring::healthz))
+        .route("/readyz", get(monitoring::readyz))
+        .route("/metrics", get(monitoring::metrics))
+        .with_state(state)
+}
+
+async fn create_user(
+    State(state): State<AppState>,
+    Json(req): Json<CreateUserRequest>,
+) -> Result<impl IntoResponse, ApiError> {
+    validate_password(&req.password)?;
+
+    let user = User {
+        id: Uuid::new_v4(),
+        username: req.username,
+        email: req.email,
+        public_key: req.public_key,
+        created_at: Utc::now(),
+        last_seen: Utc::now(),
+        password_hash: Some(hash_password(req.password).await?),
+        email_verified_at: None,
+        delete_after: None,
+        is_admin: false,
+        suspended_at: None,
+        bot_owner_id: None,
+    };
+
+    state.db.create_user(&user).await?;
+    email::send_verification(&state, &user).await?;
+
+    Ok((StatusCode::CREATED, Json(user)))
+}
+
+async fn login(
+    State(state): State<AppState>,
+    Json(req): Json<LoginRequest>,
+) -> Result<Response, ApiError> {
+    let user = state.db.get_user_by_email(&req.email).await?;
+    let password_hash = user.as_ref().and_then(|u| u.password_hash.clone());
+    // Unknown accounts still cost a hash verification, so response times do
+    // not reveal which emails are registered.
+    if !verify_password(req.password, password_hash).await? {
+        // Attempts on unknown accounts keep nothing of what was typed: the log
+        // cannot be redacted, and people do type passwords into the email field.
+        let entry = Entry::new(AuditKind::LoginFailed, user.as_ref().map(|u| u.id));
+        security::record_or_retry(&state, entry.detail("reason", "password")).await;
+        return Err(ApiError::InvalidCredentials);
+    }
+    let user = user.ok_or(ApiError::InvalidCredentials)?;
+
+    if two_factor::is_enabled(&state, user.id).await? {
+        return Ok(two_factor::challenge(&state, &user, req.device_name, req.public_key)?.into_response());
+    }
+
+    Ok(complete_login(&state, user, req.device_name, req.public_key)
+        .await?
+        .into_response())
+}
+
+/// Registers the device and starts its session once every authentication
+/// step has passed.
+async fn complete_login(
+    state: &AppState,
+    mut user: User,
+    device_name: String,
+    public_key: Vec<u8>,
+) -> Result<impl IntoResponse, ApiError> {
+    if user.suspended_at.is_some() {
+        let entry = Entry::new(AuditKind::LoginFailed, Some(user.id)).detail("reason", "suspended");
+        security::record_or_retry(state, entry).await;
+        return Err(ApiError::AccountSuspended);
+    }
+    if user.delete_after.is_some() {
+        state.db.set_delete_after(user.id, None).await?;
+        user.delete_after = None;
+        security::record_or_retry(state, Entry::new(AuditKind::DeletionCancelled, Some(user.id))).await;
+        tracing::info!("Login cancelled the scheduled deletion of account {}", user.id);
+    }
+
+    let device = Device {
+        id: Uuid::new_v4(),
+        user_id: user.id,
+        name: device_name,
+        public_key,
+        last_seen: Utc::now(),
+        is_online: false,
+    };
+    // Recorded first, so that a session hardly ever exists before its entry,
+    // but without letting a busy audit log turn logins away.
+    let entry = Entry::new(AuditKind::Login, Some(device.user_id))
+        .device(device.id)
+        .detail("device_name", &device.name);
+    security::record_or_retry(state, entry).await;
+    state.db.create_device(&device).await?;
+
+    let response = LoginResponse {
+        tokens: sessions::start_session(state, user.id, device.id).await?,
+        user,
+    };
+
+    Ok((StatusCode::OK, Json(response)))
+}
+
+fn validate_password(password: &str) -> Result<(), ApiError> {
+    if password.chars().count() < MIN_PASSWORD_LENGTH {
+        return Err(ApiError::BadRequest(format!(
+            "Password must be at least {} characters",
+            MIN_PASSWORD_LENGTH
+        )));
+    }
+    Ok(())
+}
+
+async fn hash_password(password: String) -> Result<String, ApiError> {
+    // Argon2 is deliberately slow; keep it off the async workers.
+    tokio::task::spawn_blocking(move || {
+        let salt = SaltString::encode_b64(&rand::random::<[u8; 16]>())?;
+        Argon2::default()
+            .hash_password(password.as_bytes(), &salt)
+            .map(|hash| hash.to_string())
+    })
+    .await
+    .map_err(|e| {
+        tracing::error!("Password hashing task failed: {}", e);
+        ApiError::Internal
+    })?
+    .map_err(|e| {
+        tracing::error!("Password hashing failed: {}", e);
+        ApiError::Internal
+    })
+}
+
+/// Hash of a random password, verified against when an account has no
+/// password so that failures take the same time either way.
+fn dummy_password_hash() -> &'static str {
+    static HASH: OnceLock<String> = OnceLock::new();
+    HASH.get_or_init(|| {
+        let salt = SaltString::encode_b64(&rand::random::<[u8; 16]>()).unwrap();
+        Argon2::default()
+            .hash_password(&rand::random::<[u8; 16]>(), &salt)
+            .unwrap()
+            .to_string()
+    })
+}
+
+/// Checks a password against a stored hash. A missing hash is checked
+/// against a dummy one and always fails, taking the same time as a real
+/// check.
+async fn verify_password(password: String, password_hash: Option<String>) -> Result<bool, ApiError> {
+    tokio::task::spawn_blocking(move || {
+        let stored = password_hash.as_deref().unwrap_or_else(|| dummy_password_hash());
+        let valid = PasswordHash::new(stored)
+            .map(|hash| Argon2::default().verify_password(password.as_bytes(), &hash).is_ok())
+            .unwrap_or(false);
+        valid && password_hash.is_some()
+    })
+    .await
+    .map_err(|e| {
+        tracing::error!("Password verification task failed: {}", e);
+        ApiError::Internal
+    })
+}
+
+async fn send_message(
+    State(state): State<AppState>,
+    VerifiedUser(auth): VerifiedUser,
+    Json(req): Json<SendMessageRequest>,
+) -> Result<impl IntoResponse, ApiError> {
+    if !matches!(req.kind, MessageKind::Message | MessageKind::Edit) && !req.attachment_ids.is_empty() {
+        return Err(ApiError::BadRequest("Only messages and edits can have attachments".to_string()));
+    }
+    messages::check_target(&state, auth.user_id, req.recipient_id, req.kind, req.target_id).await?;
+    threads::check_chat(&state, auth.user_id, req.recipient_id, req.chat_id, req.thread_id).await?;
+    for id in &req.attachment_ids {
+        match state.db.get_attachment(id).await? {
+            Some(attachment)
+                if attachment.uploader_id == auth.user_id && attachment.completed_at.is_some() => {}
+            _ => return Err(ApiError::BadRequest(format!("Unknown or incomplete attachment: {}", id))),
+        }
+    }
+
+    let now = Utc::now();
+    let mut expires_at = req.expires_at;
+    if let (None, Some(chat_id)) = (expires_at, req.chat_id) {
+        let timer = state.db.get_chat(chat_id).await?.and_then(|c| c.settings.disappear_after_seconds);
+        expires_at = timer.map(|seconds| now + Duration::seconds(seconds));
+    }
+
+    let message = Message {
+        id: Uuid::new_v4(),
+        sender_id: auth.user_id,
+        recipient_id: req.recipient_id,
+        content: req.content,
+        associated_data: req.associated_data,
+        created_at: now,
+        expires_at,
+        kind: req.kind,
+        target_id: req.target_id,
+        chat_id: req.chat_id,
+        thread_id: req.thread_id,
+    };
+
+    // Deleting is up to the sender even if the recipient has since blocked
+    // them.
+    if message.kind == MessageKind::Delete {
+        state.db.delete_message(req.target_id.unwrap()).await?;
+    }
+
+    // Pretend to deliver, so that blocking is not revealed to the sender.
+    if state.db.is_blocked(message.recipient_id, message.sender_id).await? {
+        tracing::debug!(
+            "Dropped message from {} to {}, who blocked them",
+            message.sender_id,
+            message.recipient_id
+        );
+        return Ok((StatusCode::CREATED, Json(message)));
+    }
+
+    state.db.create_message(&message).await?;
+    bots::dispatch_webhook(&state, &message).await?;
+
+    if !req.attachment_ids.is_empty() {
+        // Attachments live as long as the message that references them, but
+        // never longer than the retention period.
+        let retain_until = message.created_at + state.attachments.retention;
+        let expires_at = message.expires_at.map_or(retain_until, |e| e.min(retain_until));
+        state.db.link_attachments(message.id, &req.attachment_ids, expires_at).await?;
+    }
+
+    // Receipts are pushed to the sender's devices straight away; the stored
+    // copy is for devices that are offline.
+    if message.kind == MessageKind::Receipt {
+        let event = Event::Receipt {
+            id: message.id,
+            sender_id: message.sender_id,
+            content: message.content.clone(),
+        };
+        presence::send_to_user(&state, message.recipient_id, &event);
+    }
+
+    Ok((StatusCode::CREATED, Json(message)))
+}
+
+async fn get_messages(
+    State(state): State<AppState>,
+    auth: AuthUser,
+) -> Result<impl IntoResponse, ApiError> {
+    let messages = state.db.get_messages(auth.user_id, 50).await?;
+    Ok((StatusCode::OK, Json(messages)))
+} 
\ No newline at end of file
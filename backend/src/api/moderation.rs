@@ -0,0 +1,128 @@
+use axum::{
+    extract::{Path, State, Json},
+    http::StatusCode,
+    response::IntoResponse,
+};
+use chrono::Utc;
+use serde::Deserialize;
+use uuid::Uuid;
+
+use crate::models::{Report, ReportCategory, ReportStatus};
+use super::{ApiError, AppState, AuthUser};
+
+const MAX_DESCRIPTION_LENGTH: usize = 2000;
+/// Evidence is message plaintext, so allow for long messages.
+const MAX_EVIDENCE_BYTES: usize = 64 * 1024;
+
+#[derive(Debug, Deserialize)]
+pub(super) struct CreateReportRequest {
+    user_id: Uuid,
+    /// A message the reported user sent to the reporter.
+    #[serde(default)]
+    message_id: Option<Uuid>,
+    category: ReportCategory,
+    #[serde(default)]
+    description: Option<String>,
+    /// The decrypted message, if the reporter chooses to share it.
+    #[serde(default)]
+    evidence: Option<String>,
+    /// Also block the reported user.
+    #[serde(default)]
+    block: bool,
+}
+
+pub(super) async fn list_blocks(
+    State(state): State<AppState>,
+    auth: AuthUser,
+) -> Result<impl IntoResponse, ApiError> {
+    Ok(Json(state.db.list_blocks(auth.user_id).await?))
+}
+
+/// Messages from a blocked user are accepted but never delivered, so the
+/// sender cannot tell that they were blocked.
+pub(super) async fn block_user(
+    State(state): State<AppState>,
+    auth: AuthUser,
+    Path(user_id): Path<Uuid>,
+) -> Result<impl IntoResponse, ApiError> {
+    if user_id == auth.user_id {
+        return Err(ApiError::BadRequest("Cannot block yourself".to_string()));
+    }
+    state.db.get_user(user_id).await?.ok_or(ApiError::NotFound)?;
+
+    state.db.block_user(auth.user_id, user_id).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+pub(super) async fn unblock_user(
+    State(state): State<AppState>,
+    auth: AuthUser,
+    Path(user_id): Path<Uuid>,
+) -> Result<impl IntoResponse, ApiError> {
+    if !state.db.unblock_user(auth.user_id, user_id).await? {
+        return Err(ApiError::NotFound);
+    }
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Files a report for the admins to review. A reported message must have
+/// been sent to the reporter by the reported user.
+pub(super) async fn create_report(
+    State(state): State<AppState>,
+    auth: AuthUser,
+    Json(req): Json<CreateReportRequest>,
+) -> Result<impl IntoResponse, ApiError> {
+    if req.user_id == auth.user_id {
+        return Err(ApiError::BadRequest("Cannot report yourself".to_string()));
+    }
+    let description = req
+        .description
+        .map(|d| d.trim().to_string())
+        .filter(|d| !d.is_empty());
+    if description.as_ref().is_some_and(|d| d.chars().count() > MAX_DESCRIPTION_LENGTH) {
+        return Err(ApiError::BadRequest(format!(
+            "Description must be at most {} characters",
+            MAX_DESCRIPTION_LENGTH
+        )));
+    }
+    if req.evidence.as_ref().is_some_and(|e| e.len() > MAX_EVIDENCE_BYTES) {
+        return Err(ApiError::PayloadTooLarge(format!(
+            "Evidence must be at most {} bytes",
+            MAX_EVIDENCE_BYTES
+        )));
+    }
+
+    state.db.get_user(req.user_id).await?.ok_or(ApiError::NotFound)?;
+    if let Some(message_id) = req.message_id {
+        match state.db.get_message(message_id).await? {
+            Some(m) if m.sender_id == req.user_id && m.recipient_id == auth.user_id => {}
+            _ => {
+                return Err(ApiError::BadRequest(
+                    "The message was not sent to you by this user".to_string(),
+                ))
+            }
+        }
+    }
+
+    let report = Report {
+        id: Uuid::new_v4(),
+        reporter_id: Some(auth.user_id),
+        reported_id: Some(req.user_id),
+        message_id: req.message_id,
+        category: req.category,
+        description,
+        evidence: req.evidence,
+        status: ReportStatus::Open,
+        created_at: Utc::now(),
+        resolved_at: None,
+        resolved_by: None,
+        resolution_note: None,
+    };
+    state.db.create_report(&report).await?;
+    if req.block {
+        state.db.block_user(auth.user_id, req.user_id).await?;
+    }
+
+    tracing::info!("User {} reported {} ({})", auth.user_id, req.user_id, report.category.as_str());
+    Ok((StatusCode::CREATED, Json(report)))
+}
@@ -0,0 +1,40 @@
+use axum::{
+    extract::State,
+    http::{header, StatusCode},
+    response::IntoResponse,
+};
+
+use super::{ApiError, AppState};
+
+/// Liveness: the process is up and serving requests.
+pub(super) async fn healthz() -> impl IntoResponse {
+    (StatusCode::OK, "ok")
+}
+
+/// Readiness: the database answers, so requests can be served.
+pub(super) async fn readyz(State(state): State<AppState>) -> impl IntoResponse {
+    match state.db.ping().await {
+        Ok(()) => (StatusCode::OK, "ok"),
+        Err(e) => {
+            tracing::warn!("Readiness check failed: {}", e);
+            (StatusCode::SERVICE_UNAVAILABLE, "database unavailable")
+        }
+    }
+}
+
+/// Prometheus text exposition. Gauges of server state are sampled here
+/// rather than kept up to date as it changes.
+pub(super) async fn metrics(State(state): State<AppState>) -> Result<impl IntoResponse, ApiError> {
+    let pool = state.db.pool_stats();
+    metrics::gauge!("pulse_db_pool_connections", "state" => "idle").set(pool.idle as f64);
+    metrics::gauge!("pulse_db_pool_connections", "state" => "in_use")
+        .set(pool.size.saturating_sub(pool.idle as u32) as f64);
+    metrics::gauge!("pulse_event_streams_active").set(state.hub.connection_count() as f64);
+    metrics::gauge!("pulse_users_online").set(state.presence.online_count() as f64);
+    metrics::gauge!("pulse_messages_queued").set(state.db.count_messages().await? as f64);
+
+    Ok((
+        [(header::CONTENT_TYPE, "text/plain; version=0.0.4")],
+        crate::telemetry::handle().render(),
+    ))
+}
@@ -0,0 +1,105 @@
+use axum::{
+    extract::{Path, State, Json},
+    http::StatusCode,
+    response::IntoResponse,
+};
+use chrono::Utc;
+use serde::Deserialize;
+use uuid::Uuid;
+
+use crate::models::ChatPin;
+use crate::realtime::Event;
+use super::{
+    chats::{notify_chat_updated, require_admin, require_member},
+    presence::send_to_user,
+    ApiError, AppState, AuthUser,
+};
+
+const MAX_PINS: usize = 50;
+
+#[derive(Debug, Deserialize)]
+pub(super) struct CreatePinRequest {
+    /// Names the pinned message, encrypted under the chat's key.
+    content: Vec<u8>,
+}
+
+pub(super) async fn list_pins(
+    State(state): State<AppState>,
+    auth: AuthUser,
+    Path(chat_id): Path<Uuid>,
+) -> Result<impl IntoResponse, ApiError> {
+    require_member(&state, chat_id, auth.user_id).await?;
+    Ok(Json(state.db.get_chat_pins(chat_id).await?))
+}
+
+pub(super) async fn create_pin(
+    State(state): State<AppState>,
+    auth: AuthUser,
+    Path(chat_id): Path<Uuid>,
+    Json(req): Json<CreatePinRequest>,
+) -> Result<impl IntoResponse, ApiError> {
+    require_admin(&state, chat_id, auth.user_id).await?;
+    if state.db.get_chat_pins(chat_id).await?.len() >= MAX_PINS {
+        return Err(ApiError::Conflict(format!("A chat has at most {} pins", MAX_PINS)));
+    }
+
+    let pin = ChatPin {
+        id: Uuid::new_v4(),
+        chat_id,
+        pinned_by: auth.user_id,
+        content: req.content,
+        pinned_at: Utc::now(),
+    };
+    state.db.create_pin(&pin).await?;
+    notify_chat_updated(&state, chat_id).await?;
+    Ok((StatusCode::CREATED, Json(pin)))
+}
+
+pub(super) async fn delete_pin(
+    State(state): State<AppState>,
+    auth: AuthUser,
+    Path((chat_id, pin_id)): Path<(Uuid, Uuid)>,
+) -> Result<impl IntoResponse, ApiError> {
+    require_admin(&state, chat_id, auth.user_id).await?;
+    if !state.db.delete_pin(chat_id, pin_id).await? {
+        return Err(ApiError::NotFound);
+    }
+    notify_chat_updated(&state, chat_id).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// The caller's starred messages, most recently starred first.
+pub(super) async fn list_stars(
+    State(state): State<AppState>,
+    auth: AuthUser,
+) -> Result<impl IntoResponse, ApiError> {
+    Ok(Json(state.db.get_starred_messages(auth.user_id).await?))
+}
+
+/// Stars a message the caller sent or received. Starring twice is fine.
+pub(super) async fn star(
+    State(state): State<AppState>,
+    auth: AuthUser,
+    Path(message_id): Path<Uuid>,
+) -> Result<impl IntoResponse, ApiError> {
+    match state.db.get_message(message_id).await? {
+        Some(m) if m.recipient_id == auth.user_id || m.sender_id == auth.user_id => {}
+        _ => return Err(ApiError::NotFound),
+    }
+    if state.db.star_message(auth.user_id, message_id, Utc::now()).await? {
+        send_to_user(&state, auth.user_id, &Event::StarsChanged);
+    }
+    Ok(StatusCode::NO_CONTENT)
+}
+
+pub(super) async fn unstar(
+    State(state): State<AppState>,
+    auth: AuthUser,
+    Path(message_id): Path<Uuid>,
+) -> Result<impl IntoResponse, ApiError> {
+    if !state.db.unstar_message(auth.user_id, message_id).await? {
+        return Err(ApiError::NotFound);
+    }
+    send_to_user(&state, auth.user_id, &Event::StarsChanged);
+    Ok(StatusCode::NO_CONTENT)
+}
@@ -0,0 +1,184 @@
+use axum::{
+    extract::{Path, State, Json},
+    http::StatusCode,
+    response::IntoResponse,
+};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::models::ChatRole;
+use crate::realtime::Event;
+use super::{chats::require_member, ApiError, AppState, AuthUser, VerifiedUser};
+
+#[derive(Debug, Deserialize)]
+pub(super) struct TypingRequest {
+    /// Either the chat being typed in, or the user being typed to.
+    #[serde(default)]
+    chat_id: Option<Uuid>,
+    #[serde(default)]
+    recipient_id: Option<Uuid>,
+    typing: bool,
+}
+
+#[derive(Debug, Serialize)]
+struct PresenceResponse {
+    user_id: Uuid,
+    online: bool,
+    last_seen: DateTime<Utc>,
+}
+
+/// Marks the device online for as long as it is held, i.e. for the lifetime
+/// of an event stream. Dropping the last one of a user records their
+/// last-seen time and tells their contacts.
+pub(super) struct PresenceGuard {
+    state: AppState,
+    auth: AuthUser,
+}
+
+impl PresenceGuard {
+    pub(super) fn connect(state: &AppState, auth: AuthUser) -> Self {
+        if state.presence.connect(auth.user_id, auth.device_id) {
+            let state = state.clone();
+            tokio::spawn(async move {
+                if let Err(e) = broadcast(&state, auth.user_id, true, None).await {
+                    tracing::error!("Presence update for {} failed: {}", auth.user_id, e);
+                }
+            });
+        }
+        Self { state: state.clone(), auth }
+    }
+}
+
+impl Drop for PresenceGuard {
+    fn drop(&mut self) {
+        let went_offline = self.state.presence.disconnect(self.auth.user_id, self.auth.device_id);
+        let state = self.state.clone();
+        let auth = self.auth;
+        let now = Utc::now();
+        // The runtime may be shutting down, in which case there is no one
+        // left to tell.
+        let Ok(runtime) = tokio::runtime::Handle::try_current() else {
+            return;
+        };
+        runtime.spawn(async move {
+            if let Err(e) = state.db.record_last_seen(auth.user_id, auth.device_id, now).await {
+                tracing::error!("Recording last seen of {} failed: {}", auth.user_id, e);
+            }
+            if went_offline {
+                if let Err(e) = broadcast(&state, auth.user_id, false, Some(now)).await {
+                    tracing::error!("Presence update for {} failed: {}", auth.user_id, e);
+                }
+            }
+        });
+    }
+}
+
+/// Tells the user's contacts and chat peers that they came online or went
+/// offline, unless the user hides their last-seen time.
+async fn broadcast(
+    state: &AppState,
+    user_id: Uuid,
+    online: bool,
+    last_seen: Option<DateTime<Utc>>,
+) -> Result<(), ApiError> {
+    if !state.db.get_privacy_settings(user_id).await?.show_last_seen {
+        return Ok(());
+    }
+
+    let event = Event::Presence { user_id, online, last_seen };
+    for subscriber in state.db.get_presence_subscribers(user_id).await? {
+        if state.presence.is_online(subscriber) && !state.db.is_blocked(user_id, subscriber).await? {
+            send_to_user(state, subscriber, &event);
+        }
+    }
+    Ok(())
+}
+
+/// Sends the event to every online device of the user. Returns whether
+/// there was one.
+pub(super) fn send_to_user(state: &AppState, user_id: Uuid, event: &Event) -> bool {
+    let mut delivered = false;
+    for device_id in state.presence.online_devices(user_id) {
+        delivered |= state.hub.send_to_device(device_id, event.clone());
+    }
+    delivered
+}
+
+/// Whether the user is online and when they were last seen, for the same
+/// audience `broadcast` tells: contacts and chat peers, unless the user
+/// turned off last-seen or blocked the caller. Anyone else gets a 404.
+pub(super) async fn get_presence(
+    State(state): State<AppState>,
+    auth: AuthUser,
+    Path(user_id): Path<Uuid>,
+) -> Result<impl IntoResponse, ApiError> {
+    let user = state.db.get_user(user_id).await?.ok_or(ApiError::NotFound)?;
+    let visible = user_id == auth.user_id
+        || (state.db.is_presence_subscriber(user_id, auth.user_id).await?
+            && state.db.get_privacy_settings(user_id).await?.show_last_seen
+            && !state.db.is_blocked(user_id, auth.user_id).await?);
+    if !visible {
+        return Err(ApiError::NotFound);
+    }
+
+    Ok(Json(PresenceResponse {
+        user_id,
+        online: state.presence.is_online(user_id),
+        last_seen: user.last_seen,
+    }))
+}
+
+/// Relays a typing indicator to the online devices of the chat's other
+/// members, or of the recipient, who has to be a contact or chat peer of
+/// the user either way round. Nothing is stored; clients send `typing:
+/// true` again every few seconds while the user keeps typing.
+pub(super) async fn typing(
+    State(state): State<AppState>,
+    VerifiedUser(auth): VerifiedUser,
+    Json(req): Json<TypingRequest>,
+) -> Result<impl IntoResponse, ApiError> {
+    let recipients = match (req.chat_id, req.recipient_id) {
+        (Some(chat_id), None) => {
+            let member = require_member(&state, chat_id, auth.user_id).await?;
+            if member.role == ChatRole::ReadOnly {
+                return Err(ApiError::Forbidden);
+            }
+            state
+                .db
+                .get_chat_members(chat_id)
+                .await?
+                .into_iter()
+                .map(|m| m.user_id)
+                .filter(|id| *id != auth.user_id)
+                .collect()
+        }
+        (None, Some(recipient_id)) => {
+            if recipient_id == auth.user_id
+                || !(state.db.is_presence_subscriber(auth.user_id, recipient_id).await?
+                    || state.db.is_presence_subscriber(recipient_id, auth.user_id).await?)
+            {
+                return Err(ApiError::NotFound);
+            }
+            vec![recipient_id]
+        }
+        _ => {
+            return Err(ApiError::BadRequest(
+                "Exactly one of chat_id and recipient_id is required".to_string(),
+            ))
+        }
+    };
+
+    let event = if req.typing {
+        Event::TypingStarted { chat_id: req.chat_id, user_id: auth.user_id }
+    } else {
+        Event::TypingStopped { chat_id: req.chat_id, user_id: auth.user_id }
+    };
+    for recipient in recipients {
+        // Like messages, typing from a blocked user is silently dropped.
+        if state.presence.is_online(recipient) && !state.db.is_blocked(recipient, auth.user_id).await? {
+            send_to_user(&state, recipient, &event);
+        }
+    }
+    Ok(StatusCode::NO_CONTENT)
+}
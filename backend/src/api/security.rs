@@ -0,0 +1,131 @@
+use axum::{
+    extract::{Query, State, Json},
+    response::IntoResponse,
+};
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::audit::{self, Entry};
+use crate::db::AuditQuery;
+use crate::models::{AuditEvent, AuditKind};
+use crate::realtime::Event;
+use super::{presence::send_to_user, ApiError, AppState, AuthUser};
+
+#[derive(Debug, Deserialize)]
+pub(super) struct ListEventsQuery {
+    /// Only events older than this one, for paging.
+    #[serde(default)]
+    before: Option<i64>,
+    #[serde(default = "default_limit")]
+    limit: i64,
+}
+
+fn default_limit() -> i64 {
+    50
+}
+
+/// An audit log entry as its user sees it. Who acted and the hash chain
+/// are for operators.
+#[derive(Debug, Serialize)]
+pub(super) struct SecurityEventResponse {
+    id: i64,
+    kind: AuditKind,
+    device_id: Option<Uuid>,
+    details: serde_json::Value,
+    created_at: DateTime<Utc>,
+}
+
+impl From<AuditEvent> for SecurityEventResponse {
+    fn from(event: AuditEvent) -> Self {
+        Self {
+            id: event.id,
+            kind: event.kind,
+            device_id: event.device_id,
+            details: event.details,
+            created_at: event.created_at,
+        }
+    }
+}
+
+/// Appends to the audit log and tells the user's online devices.
+pub(super) async fn record(state: &AppState, entry: Entry) -> Result<AuditEvent, ApiError> {
+    let event = audit::append(&state.db, entry).await?;
+    if let Some(user_id) = event.user_id {
+        send_to_user(state, user_id, &Event::SecurityEvent { id: event.id, kind: event.kind });
+    }
+    Ok(event)
+}
+
+/// How often `record_or_retry` tries again in the background.
+const BACKGROUND_ATTEMPTS: u32 = 5;
+
+/// `record` for the login path, which must keep working while the audit log
+/// is busy or unavailable: an entry that cannot be appended now is retried
+/// in the background, and logged if it is lost after all.
+pub(super) async fn record_or_retry(state: &AppState, entry: Entry) {
+    let Err(e) = record(state, entry.clone()).await else {
+        return;
+    };
+    tracing::warn!("Could not append {} to the audit log, retrying: {}", entry.kind().as_str(), e);
+    let state = state.clone();
+    tokio::spawn(async move {
+        for attempt in 0..BACKGROUND_ATTEMPTS {
+            tokio::time::sleep(Duration::from_secs(1 << attempt)).await;
+            match record(&state, entry.clone()).await {
+                Ok(_) => return,
+                Err(e) if attempt + 1 == BACKGROUND_ATTEMPTS => {
+                    tracing::error!("Lost audit log entry {:?}: {}", entry, e);
+                }
+                Err(_) => {}
+            }
+        }
+    });
+}
+
+/// The caller's own security events, newest first.
+pub(super) async fn list_events(
+    State(state): State<AppState>,
+    auth: AuthUser,
+    Query(query): Query<ListEventsQuery>,
+) -> Result<impl IntoResponse, ApiError> {
+    let events = state
+        .db
+        .list_audit_events(&AuditQuery {
+            user_id: Some(auth.user_id),
+            since: None,
+            until: None,
+            before: query.before,
+            limit: query.limit.clamp(1, 200),
+        })
+        .await?;
+
+    let response: Vec<SecurityEventResponse> = events.into_iter().map(SecurityEventResponse::from).collect();
+
+    Ok(Json(response))
+}
+
+/// Every security event of a user, newest first, for the data export.
+pub(super) async fn all_events(state: &AppState, user_id: Uuid) -> Result<Vec<SecurityEventResponse>, ApiError> {
+    const PAGE: i64 = 1000;
+    let mut events = Vec::new();
+    loop {
+        let page = state
+            .db
+            .list_audit_events(&AuditQuery {
+                user_id: Some(user_id),
+                since: None,
+                until: None,
+                before: events.last().map(|e: &SecurityEventResponse| e.id),
+                limit: PAGE,
+            })
+            .await?;
+        let done = (page.len() as i64) < PAGE;
+        events.extend(page.into_iter().map(SecurityEventResponse::from));
+        if done {
+            return Ok(events);
+        }
+    }
+}
@@ -0,0 +1,269 @@
+use axum::{
+    extract::{Path, State, Json},
+    http::{header, StatusCode},
+    response::IntoResponse,
+};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use chrono::{DateTime, Duration, Utc};
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use uuid::Uuid;
+
+use crate::audit::Entry;
+use crate::config::{env_at_least, ConfigError};
+use crate::jwt::ACCESS_TOKEN_TYPE;
+use crate::models::{AuditKind, Session};
+use super::{security, ApiError, AppState, AuthUser, Claims};
+
+#[derive(Debug, Clone)]
+pub struct SessionConfig {
+    /// Lifetime of an access token. Revocation is checked on every request,
+    /// so this only bounds how long a stolen token stays useful.
+    pub access_token_ttl: Duration,
+    /// How long a session survives without being refreshed.
+    pub refresh_token_ttl: Duration,
+    /// How often an open event stream checks that its session is still
+    /// active. Revocations this server does not see itself, and expiry, end
+    /// the stream within this long.
+    pub stream_check_interval: std::time::Duration,
+}
+
+impl SessionConfig {
+    pub fn from_env() -> Result<Self, ConfigError> {
+        Ok(Self {
+            access_token_ttl: Duration::minutes(env_at_least("ACCESS_TOKEN_TTL_MINUTES", 15, 1)?),
+            refresh_token_ttl: Duration::days(env_at_least("REFRESH_TOKEN_TTL_DAYS", 30, 1)?),
+            // A zero period would make every event stream's timer panic.
+            stream_check_interval: std::time::Duration::from_secs(
+                env_at_least("EVENT_STREAM_CHECK_SECONDS", 30, 1)? as u64,
+            ),
+        })
+    }
+
+    /// The longest lifetime of any signed token, access token or MFA
+    /// challenge; signing keys verify for this long after being replaced.
+    pub fn max_token_lifetime(&self) -> Duration {
+        self.access_token_ttl
+            .max(Duration::minutes(super::two_factor::MFA_TOKEN_TTL_MINUTES))
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub(super) struct TokenPair {
+    access_token: String,
+    refresh_token: String,
+    /// Seconds until `access_token` expires.
+    expires_in: i64,
+}
+
+#[derive(Debug, Deserialize)]
+pub(super) struct RefreshRequest {
+    refresh_token: String,
+}
+
+#[derive(Debug, Serialize)]
+struct SessionResponse {
+    id: Uuid,
+    device_id: Uuid,
+    device_name: Option<String>,
+    created_at: DateTime<Utc>,
+    last_used_at: Option<DateTime<Utc>>,
+    expires_at: DateTime<Utc>,
+    current: bool,
+    /// Whether the device has an event stream open right now.
+    online: bool,
+}
+
+fn new_refresh_token() -> String {
+    let mut bytes = [0u8; 32];
+    rand::thread_rng().fill_bytes(&mut bytes);
+    URL_SAFE_NO_PAD.encode(bytes)
+}
+
+fn hash_refresh_token(token: &str) -> String {
+    Sha256::digest(token.as_bytes())
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+fn sign_access_token(state: &AppState, session: &Session) -> Result<String, ApiError> {
+    let now = Utc::now();
+    let claims = Claims {
+        sub: session.user_id.to_string(),
+        did: Some(session.device_id.to_string()),
+        sid: Some(session.id.to_string()),
+        exp: (now + state.sessions.access_token_ttl).timestamp() as usize,
+        iat: now.timestamp() as usize,
+    };
+
+    state.keys.sign(ACCESS_TOKEN_TYPE, &claims).map_err(|e| {
+        tracing::error!("Failed to sign access token: {}", e);
+        ApiError::Internal
+    })
+}
+
+fn token_pair(state: &AppState, session: &Session, refresh_token: String) -> Result<TokenPair, ApiError> {
+    Ok(TokenPair {
+        access_token: sign_access_token(state, session)?,
+        refresh_token,
+        expires_in: state.sessions.access_token_ttl.num_seconds(),
+    })
+}
+
+/// Starts a session for a freshly authenticated device.
+pub(super) async fn start_session(
+    state: &AppState,
+    user_id: Uuid,
+    device_id: Uuid,
+) -> Result<TokenPair, ApiError> {
+    let refresh_token = new_refresh_token();
+    let now = Utc::now();
+    let session = Session {
+        id: Uuid::new_v4(),
+        user_id,
+        device_id,
+        refresh_token_hash: hash_refresh_token(&refresh_token),
+        previous_refresh_token_hash: None,
+        created_at: now,
+        last_used_at: None,
+        expires_at: now + state.sessions.refresh_token_ttl,
+        revoked_at: None,
+    };
+    state.db.create_session(&session).await?;
+
+    token_pair(state, &session, refresh_token)
+}
+
+/// Exchanges a refresh token for a new access token and a new refresh token.
+/// Each refresh token works once; presenting a rotated-out one revokes the
+/// whole session, since either the client or an attacker holds a copy.
+pub(super) async fn refresh(
+    State(state): State<AppState>,
+    Json(req): Json<RefreshRequest>,
+) -> Result<impl IntoResponse, ApiError> {
+    let hash = hash_refresh_token(&req.refresh_token);
+    let session = state
+        .db
+        .find_session_by_refresh_token(&hash)
+        .await?
+        .ok_or(ApiError::SessionExpired)?;
+
+    if session.previous_refresh_token_hash.as_deref() == Some(hash.as_str()) {
+        tracing::warn!("Refresh token reuse detected for session {}", session.id);
+        state.db.revoke_session(session.id).await?;
+        state.hub.disconnect_session(session.id);
+        record_revoked(&state, session.user_id, session.device_id, &[session.id], "refresh_token_reuse").await?;
+        return Err(ApiError::SessionExpired);
+    }
+
+    let refresh_token = new_refresh_token();
+    let expires_at = Utc::now() + state.sessions.refresh_token_ttl;
+    if !state
+        .db
+        .rotate_refresh_token(session.id, &hash, &hash_refresh_token(&refresh_token), expires_at)
+        .await?
+    {
+        // Expired, revoked, or rotated by a concurrent request.
+        return Err(ApiError::SessionExpired);
+    }
+
+    Ok(Json(token_pair(&state, &session, refresh_token)?))
+}
+
+pub(super) async fn logout(
+    State(state): State<AppState>,
+    auth: AuthUser,
+) -> Result<impl IntoResponse, ApiError> {
+    state.db.revoke_session(auth.session_id).await?;
+    state.hub.disconnect_session(auth.session_id);
+    record_revoked(&state, auth.user_id, auth.device_id, &[auth.session_id], "logout").await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// The caller's active sessions, one per signed-in device.
+pub(super) async fn list_sessions(
+    State(state): State<AppState>,
+    auth: AuthUser,
+) -> Result<impl IntoResponse, ApiError> {
+    let sessions = state.db.list_sessions(auth.user_id).await?;
+    let devices = state.db.get_user_devices(auth.user_id).await?;
+
+    let response: Vec<SessionResponse> = sessions
+        .into_iter()
+        .map(|session| SessionResponse {
+            id: session.id,
+            device_id: session.device_id,
+            device_name: devices
+                .iter()
+                .find(|d| d.id == session.device_id)
+                .map(|d| d.name.clone()),
+            created_at: session.created_at,
+            last_used_at: session.last_used_at,
+            expires_at: session.expires_at,
+            current: session.id == auth.session_id,
+            online: state.presence.is_device_online(auth.user_id, session.device_id),
+        })
+        .collect();
+
+    Ok(Json(response))
+}
+
+pub(super) async fn revoke_session(
+    State(state): State<AppState>,
+    auth: AuthUser,
+    Path(id): Path<Uuid>,
+) -> Result<impl IntoResponse, ApiError> {
+    // Sessions of other users look the same as unknown ones.
+    match state.db.get_active_session(id).await? {
+        Some(session) if session.user_id == auth.user_id => {}
+        _ => return Err(ApiError::NotFound),
+    }
+
+    state.db.revoke_session(id).await?;
+    state.hub.disconnect_session(id);
+    record_revoked(&state, auth.user_id, auth.device_id, &[id], "revoked").await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Signs out every other device.
+pub(super) async fn revoke_other_sessions(
+    State(state): State<AppState>,
+    auth: AuthUser,
+) -> Result<impl IntoResponse, ApiError> {
+    let revoked = state
+        .db
+        .revoke_user_sessions(auth.user_id, Some(auth.session_id))
+        .await?;
+    for id in &revoked {
+        state.hub.disconnect_session(*id);
+    }
+    if !revoked.is_empty() {
+        record_revoked(&state, auth.user_id, auth.device_id, &revoked, "signed_out_elsewhere").await?;
+    }
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Records sessions ended through the API. `device_id` is the device that
+/// asked, or the one whose session was ended for it.
+async fn record_revoked(
+    state: &AppState,
+    user_id: Uuid,
+    device_id: Uuid,
+    sessions: &[Uuid],
+    reason: &str,
+) -> Result<(), ApiError> {
+    let entry = Entry::new(AuditKind::SessionsRevoked, Some(user_id))
+        .device(device_id)
+        .detail("sessions", sessions)
+        .detail("reason", reason);
+    security::record(state, entry).await?;
+    Ok(())
+}
+
+/// The public keys tokens are signed with, for services that verify them.
+/// New keys are published well before they sign, so caching is safe.
+pub(super) async fn jwks(State(state): State<AppState>) -> impl IntoResponse {
+    ([(header::CACHE_CONTROL, "public, max-age=300")], Json(state.keys.jwks()))
+}
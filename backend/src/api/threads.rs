@@ -0,0 +1,110 @@
+use axum::{
+    extract::{Path, Query, State, Json},
+    http::StatusCode,
+    response::IntoResponse,
+};
+use serde::Deserialize;
+use uuid::Uuid;
+
+use crate::models::ChatRole;
+use super::{chats::require_member, ApiError, AppState, AuthUser};
+
+const MAX_PAGE_SIZE: i64 = 200;
+
+#[derive(Debug, Deserialize)]
+pub(super) struct ThreadMessagesQuery {
+    /// The oldest message of the previous page.
+    #[serde(default)]
+    before: Option<Uuid>,
+    #[serde(default = "default_limit")]
+    limit: i64,
+}
+
+fn default_limit() -> i64 {
+    50
+}
+
+#[derive(Debug, Deserialize)]
+pub(super) struct MarkReadRequest {
+    /// The newest message of the thread the user has seen; it and everything
+    /// before it count as read.
+    up_to: Uuid,
+}
+
+/// Checks where a message is being sent. A message in a chat must go from
+/// one member to another, and only messages in a chat can be in a thread.
+pub(super) async fn check_chat(
+    state: &AppState,
+    sender_id: Uuid,
+    recipient_id: Uuid,
+    chat_id: Option<Uuid>,
+    thread_id: Option<Uuid>,
+) -> Result<(), ApiError> {
+    let Some(chat_id) = chat_id else {
+        if thread_id.is_some() {
+            return Err(ApiError::BadRequest("A thread_id needs a chat_id".to_string()));
+        }
+        return Ok(());
+    };
+
+    let sender = require_member(state, chat_id, sender_id).await?;
+    if sender.role == ChatRole::ReadOnly {
+        return Err(ApiError::Forbidden);
+    }
+    if state.db.get_chat_member(chat_id, recipient_id).await?.is_none() {
+        return Err(ApiError::BadRequest("The recipient is not a member of the chat".to_string()));
+    }
+    Ok(())
+}
+
+/// The chat's threads with their unread counts, most recently active first.
+pub(super) async fn list_threads(
+    State(state): State<AppState>,
+    auth: AuthUser,
+    Path(chat_id): Path<Uuid>,
+) -> Result<impl IntoResponse, ApiError> {
+    require_member(&state, chat_id, auth.user_id).await?;
+    Ok(Json(state.db.get_thread_summaries(auth.user_id, chat_id).await?))
+}
+
+/// A page of the thread's messages addressed to the caller, newest first.
+pub(super) async fn get_thread_messages(
+    State(state): State<AppState>,
+    auth: AuthUser,
+    Path((chat_id, thread_id)): Path<(Uuid, Uuid)>,
+    Query(query): Query<ThreadMessagesQuery>,
+) -> Result<impl IntoResponse, ApiError> {
+    require_member(&state, chat_id, auth.user_id).await?;
+    let messages = state
+        .db
+        .get_thread_messages(
+            auth.user_id,
+            chat_id,
+            thread_id,
+            query.before,
+            query.limit.clamp(1, MAX_PAGE_SIZE),
+        )
+        .await?;
+    Ok(Json(messages))
+}
+
+pub(super) async fn mark_thread_read(
+    State(state): State<AppState>,
+    auth: AuthUser,
+    Path((chat_id, thread_id)): Path<(Uuid, Uuid)>,
+    Json(req): Json<MarkReadRequest>,
+) -> Result<impl IntoResponse, ApiError> {
+    require_member(&state, chat_id, auth.user_id).await?;
+    let message = match state.db.get_message(req.up_to).await? {
+        Some(m)
+            if m.recipient_id == auth.user_id
+                && m.chat_id == Some(chat_id)
+                && m.thread_id == Some(thread_id) => m,
+        _ => return Err(ApiError::NotFound),
+    };
+    state
+        .db
+        .mark_thread_read(auth.user_id, chat_id, thread_id, message.created_at)
+        .await?;
+    Ok(StatusCode::NO_CONTENT)
+}
@@ -0,0 +1,273 @@
+use axum::{
+    extract::{State, Json},
+    http::StatusCode,
+    response::IntoResponse,
+};
+use chrono::{Duration, Utc};
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::{audit::Entry, models::{AuditKind, User}, totp};
+use super::{complete_login, hash_password, security, verify_password, ApiError, AppState, AuthUser};
+
+const ISSUER: &str = "Pulse";
+const RECOVERY_CODE_COUNT: usize = 10;
+/// Characters of a recovery code without its separators.
+const RECOVERY_CODE_LENGTH: usize = 16;
+const MFA_PURPOSE: &str = "mfa";
+/// `typ` header of MFA tokens, so they are never mistaken for access tokens.
+const MFA_TOKEN_TYPE: &str = "mfa+jwt";
+/// How long the second step of a login may take.
+pub(super) const MFA_TOKEN_TTL_MINUTES: i64 = 5;
+/// Wrong codes after which an MFA token is void and the password step has
+/// to be repeated.
+const MAX_FAILURES_PER_TOKEN: i64 = 5;
+/// Wrong codes for one user, across MFA tokens, within the window below
+/// after which further attempts are refused until it has passed.
+const MAX_FAILURES_PER_USER: i64 = 10;
+const MFA_FAILURE_WINDOW_MINUTES: i64 = 15;
+
+/// Proof that the password step of a login succeeded, exchanged for tokens
+/// together with a second factor. Carries the device details from the first
+/// step so the client does not send them twice.
+#[derive(Debug, Serialize, Deserialize)]
+struct MfaClaims {
+    sub: String,
+    /// Identifies the token, so that wrong codes are counted against it.
+    jti: Uuid,
+    purpose: String,
+    device_name: String,
+    public_key: Vec<u8>,
+    exp: usize,
+    iat: usize,
+}
+
+#[derive(Debug, Serialize)]
+struct MfaChallenge {
+    mfa_required: bool,
+    mfa_token: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub(super) struct MfaLoginRequest {
+    mfa_token: String,
+    /// A current TOTP code or an unused recovery code.
+    code: String,
+}
+
+#[derive(Debug, Serialize)]
+struct SetupResponse {
+    secret: String,
+    provisioning_uri: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub(super) struct EnableRequest {
+    code: String,
+}
+
+#[derive(Debug, Serialize)]
+struct EnableResponse {
+    recovery_codes: Vec<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub(super) struct DisableRequest {
+    password: String,
+    code: String,
+}
+
+/// 80 random bits as `xxxx-xxxx-xxxx-xxxx`.
+fn new_recovery_code() -> String {
+    let mut bytes = [0u8; 10];
+    rand::thread_rng().fill_bytes(&mut bytes);
+    let code = totp::base32_encode(&bytes).to_lowercase();
+    let groups: Vec<&str> = (0..code.len()).step_by(4).map(|i| &code[i..i + 4]).collect();
+    groups.join("-")
+}
+
+/// Ignores case and separators, so codes can be typed loosely.
+fn normalize_recovery_code(code: &str) -> String {
+    code.chars()
+        .filter(|c| c.is_ascii_alphanumeric())
+        .map(|c| c.to_ascii_lowercase())
+        .collect()
+}
+
+/// Recovery codes are hashed like passwords: whoever reads the database
+/// must not be able to try them all offline.
+async fn hash_recovery_codes(codes: &[String]) -> Result<Vec<String>, ApiError> {
+    let mut hashes = Vec::with_capacity(codes.len());
+    for code in codes {
+        hashes.push(hash_password(normalize_recovery_code(code)).await?);
+    }
+    Ok(hashes)
+}
+
+/// Consumes the unused recovery code `code` matches, if any.
+async fn use_recovery_code(state: &AppState, user_id: Uuid, code: &str) -> Result<bool, ApiError> {
+    // Anything else, a mistyped TOTP code say, is not worth an argon2
+    // verification per stored code.
+    let code = normalize_recovery_code(code);
+    if code.len() != RECOVERY_CODE_LENGTH {
+        return Ok(false);
+    }
+    for code_hash in state.db.get_unused_recovery_codes(user_id).await? {
+        if verify_password(code.clone(), Some(code_hash.clone())).await? {
+            return Ok(state.db.use_recovery_code(user_id, &code_hash).await?);
+        }
+    }
+    Ok(false)
+}
+
+/// Whether two-factor authentication is enabled for the user.
+pub(super) async fn is_enabled(state: &AppState, user_id: Uuid) -> Result<bool, ApiError> {
+    Ok(state
+        .db
+        .get_totp_secret(user_id)
+        .await?
+        .is_some_and(|secret| secret.enabled_at.is_some()))
+}
+
+/// Checks a TOTP code or, failing that, consumes a recovery code.
+pub(super) async fn verify_second_factor(state: &AppState, user_id: Uuid, code: &str) -> Result<bool, ApiError> {
+    let secret = match state.db.get_totp_secret(user_id).await? {
+        Some(secret) if secret.enabled_at.is_some() => secret,
+        _ => return Ok(false),
+    };
+
+    if let Some(step) = totp::verify(&secret.secret, code, Utc::now().timestamp(), secret.last_used_step) {
+        return Ok(state.db.record_totp_step(user_id, step).await?);
+    }
+
+    use_recovery_code(state, user_id, code).await
+}
+
+/// Second half of `login` for accounts with two-factor authentication: the
+/// password was right, now a code is needed.
+pub(super) fn challenge(state: &AppState, user: &User, device_name: String, public_key: Vec<u8>) -> Result<impl IntoResponse, ApiError> {
+    let now = Utc::now();
+    let claims = MfaClaims {
+        sub: user.id.to_string(),
+        jti: Uuid::new_v4(),
+        purpose: MFA_PURPOSE.to_string(),
+        device_name,
+        public_key,
+        exp: (now + Duration::minutes(MFA_TOKEN_TTL_MINUTES)).timestamp() as usize,
+        iat: now.timestamp() as usize,
+    };
+
+    let mfa_token = state.keys.sign(MFA_TOKEN_TYPE, &claims).map_err(|e| {
+        tracing::error!("Failed to sign MFA token: {}", e);
+        ApiError::Internal
+    })?;
+
+    Ok(Json(MfaChallenge {
+        mfa_required: true,
+        mfa_token,
+    }))
+}
+
+pub(super) async fn login_mfa(
+    State(state): State<AppState>,
+    Json(req): Json<MfaLoginRequest>,
+) -> Result<impl IntoResponse, ApiError> {
+    let claims: MfaClaims = state
+        .keys
+        .verify_or_reload(&state.db, &req.mfa_token, MFA_TOKEN_TYPE)
+        .await
+        .map_err(|_| ApiError::Unauthorized)?;
+    if claims.purpose != MFA_PURPOSE {
+        return Err(ApiError::Unauthorized);
+    }
+
+    let user_id: Uuid = claims.sub.parse().map_err(|_| ApiError::Unauthorized)?;
+    let user = state.db.get_user(user_id).await?.ok_or(ApiError::Unauthorized)?;
+
+    let since = Utc::now() - Duration::minutes(MFA_FAILURE_WINDOW_MINUTES);
+    let (token_failures, user_failures) = state.db.count_mfa_failures(claims.jti, user.id, since).await?;
+    if token_failures >= MAX_FAILURES_PER_TOKEN {
+        return Err(ApiError::Unauthorized);
+    }
+    if user_failures >= MAX_FAILURES_PER_USER {
+        return Err(ApiError::RateLimited {
+            retry_after: MFA_FAILURE_WINDOW_MINUTES as u64 * 60,
+        });
+    }
+
+    if !verify_second_factor(&state, user.id, &req.code).await? {
+        state.db.record_mfa_failure(claims.jti, user.id, since).await?;
+        let entry = Entry::new(AuditKind::LoginFailed, Some(user.id)).detail("reason", "second_factor");
+        security::record_or_retry(&state, entry).await;
+        return Err(ApiError::InvalidCode);
+    }
+
+    complete_login(&state, user, claims.device_name, claims.public_key).await
+}
+
+/// Starts enrollment with a fresh secret. Two-factor authentication stays
+/// off until `enable` confirms a code generated from it.
+pub(super) async fn setup(
+    State(state): State<AppState>,
+    auth: AuthUser,
+) -> Result<impl IntoResponse, ApiError> {
+    if is_enabled(&state, auth.user_id).await? {
+        return Err(ApiError::Conflict("Two-factor authentication is already enabled".to_string()));
+    }
+    let user = state.db.get_user(auth.user_id).await?.ok_or(ApiError::NotFound)?;
+
+    let secret = totp::generate_secret();
+    state.db.set_pending_totp_secret(auth.user_id, &secret).await?;
+
+    Ok(Json(SetupResponse {
+        secret: totp::base32_encode(&secret),
+        provisioning_uri: totp::provisioning_uri(ISSUER, &user.email, &secret),
+    }))
+}
+
+/// Confirms enrollment and returns recovery codes. They are only ever shown
+/// here; the server keeps hashes.
+pub(super) async fn enable(
+    State(state): State<AppState>,
+    auth: AuthUser,
+    Json(req): Json<EnableRequest>,
+) -> Result<impl IntoResponse, ApiError> {
+    let secret = match state.db.get_totp_secret(auth.user_id).await? {
+        Some(secret) if secret.enabled_at.is_none() => secret,
+        Some(_) => return Err(ApiError::Conflict("Two-factor authentication is already enabled".to_string())),
+        None => return Err(ApiError::BadRequest("Start two-factor setup first".to_string())),
+    };
+
+    let step = totp::verify(&secret.secret, &req.code, Utc::now().timestamp(), None)
+        .ok_or(ApiError::InvalidCode)?;
+
+    let recovery_codes: Vec<String> = (0..RECOVERY_CODE_COUNT).map(|_| new_recovery_code()).collect();
+    let hashes = hash_recovery_codes(&recovery_codes).await?;
+    state.db.enable_totp(auth.user_id, step, &hashes).await?;
+    let entry = Entry::new(AuditKind::TwoFactorEnabled, Some(auth.user_id)).device(auth.device_id);
+    security::record(&state, entry).await?;
+
+    Ok(Json(EnableResponse { recovery_codes }))
+}
+
+/// Turns two-factor authentication off. Requires the password and a current
+/// code, so a stolen session alone cannot do it.
+pub(super) async fn disable(
+    State(state): State<AppState>,
+    auth: AuthUser,
+    Json(req): Json<DisableRequest>,
+) -> Result<impl IntoResponse, ApiError> {
+    let user = state.db.get_user(auth.user_id).await?.ok_or(ApiError::NotFound)?;
+    if !verify_password(req.password, user.password_hash).await? {
+        return Err(ApiError::InvalidCredentials);
+    }
+    if !verify_second_factor(&state, auth.user_id, &req.code).await? {
+        return Err(ApiError::InvalidCode);
+    }
+
+    state.db.disable_totp(auth.user_id).await?;
+    let entry = Entry::new(AuditKind::TwoFactorDisabled, Some(auth.user_id)).device(auth.device_id);
+    security::record(&state, entry).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
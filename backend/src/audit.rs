@@ -0,0 +1,167 @@
+//! The audit log: an append-only record of security-relevant events. Each
+//! entry's hash covers its fields and the hash of the entry before, so an
+//! entry edited or removed in the database no longer matches, and neither
+//! does anything after it. Removing the newest entries leaves a valid
+//! chain; operators who note the last hash somewhere else catch that too.
+
+use std::time::Duration;
+
+use chrono::{SecondsFormat, SubsecRound, Utc};
+use serde::Serialize;
+use serde_json::{Map, Value};
+use sha2::{Digest, Sha256};
+use uuid::Uuid;
+
+use crate::db::{Database, DatabaseError};
+use crate::models::{AuditEvent, AuditKind};
+
+/// `prev_hash` of the first entry.
+pub const GENESIS_HASH: &str = "0000000000000000000000000000000000000000000000000000000000000000";
+
+/// How often an append is retried when another one took its place.
+const APPEND_ATTEMPTS: u32 = 10;
+
+/// Upper bound of the first retry's random delay; it doubles with each
+/// attempt so that a crowd of appenders spreads out instead of colliding
+/// again.
+const APPEND_BACKOFF: Duration = Duration::from_millis(5);
+
+/// An event about to be appended.
+#[derive(Debug, Clone)]
+pub struct Entry {
+    kind: AuditKind,
+    user_id: Option<Uuid>,
+    actor_id: Option<Uuid>,
+    device_id: Option<Uuid>,
+    details: Map<String, Value>,
+}
+
+impl Entry {
+    pub fn new(kind: AuditKind, user_id: Option<Uuid>) -> Self {
+        Self {
+            kind,
+            user_id,
+            actor_id: None,
+            device_id: None,
+            details: Map::new(),
+        }
+    }
+
+    pub fn kind(&self) -> AuditKind {
+        self.kind
+    }
+
+    pub fn actor(mut self, actor_id: Uuid) -> Self {
+        self.actor_id = Some(actor_id);
+        self
+    }
+
+    pub fn device(mut self, device_id: Uuid) -> Self {
+        self.device_id = Some(device_id);
+        self
+    }
+
+    pub fn detail(mut self, key: &str, value: impl Serialize) -> Self {
+        self.details
+            .insert(key.to_string(), serde_json::to_value(value).unwrap_or(Value::Null));
+        self
+    }
+}
+
+/// Appends `entry` after the newest event. Servers and `pulse-admin`
+/// append concurrently; whoever loses the race for an id tries again on top
+/// of the winner.
+pub async fn append(db: &Database, entry: Entry) -> Result<AuditEvent, DatabaseError> {
+    let details = Value::Object(entry.details);
+    let mut attempt = 1;
+    loop {
+        let last = db.last_audit_event().await?;
+        let mut event = AuditEvent {
+            id: last.as_ref().map_or(1, |e| e.id + 1),
+            kind: entry.kind,
+            user_id: entry.user_id,
+            actor_id: entry.actor_id,
+            device_id: entry.device_id,
+            details: details.clone(),
+            // PostgreSQL keeps microseconds; the hash must survive the trip.
+            created_at: Utc::now().trunc_subsecs(6),
+            prev_hash: last.map_or_else(|| GENESIS_HASH.to_string(), |e| e.hash),
+            hash: String::new(),
+        };
+        event.hash = hash(&event);
+
+        match db.append_audit_event(&event).await {
+            Ok(()) => return Ok(event),
+            Err(DatabaseError::UniqueViolation(_)) if attempt < APPEND_ATTEMPTS => {
+                let ceiling = APPEND_BACKOFF * 2u32.pow(attempt - 1);
+                tokio::time::sleep(ceiling.mul_f64(rand::random::<f64>())).await;
+                attempt += 1;
+            }
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+/// SHA-256 over every field but `hash`, one per line, as hex.
+pub fn hash(event: &AuditEvent) -> String {
+    let optional = |id: Option<Uuid>| id.map(|id| id.to_string()).unwrap_or_default();
+    let canonical = [
+        event.id.to_string(),
+        event.kind.as_str().to_string(),
+        optional(event.user_id),
+        optional(event.actor_id),
+        optional(event.device_id),
+        // Object keys are sorted, so this is stable across a round trip.
+        event.details.to_string(),
+        event.created_at.to_rfc3339_opts(SecondsFormat::Micros, true),
+        event.prev_hash.clone(),
+    ]
+    .join("\n");
+
+    Sha256::digest(canonical.as_bytes())
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+/// The outcome of checking the whole chain.
+#[derive(Debug, Clone, Serialize)]
+pub struct Verification {
+    /// Entries checked, up to the first broken one.
+    pub entries: i64,
+    /// Hash of the newest entry, if the chain is intact. Keep it elsewhere
+    /// to detect the newest entries being removed later.
+    pub last_hash: Option<String>,
+    /// The first entry that is missing, altered, or follows one that is.
+    pub broken_at: Option<i64>,
+}
+
+/// Recomputes every hash from the first entry on.
+pub async fn verify(db: &Database) -> Result<Verification, DatabaseError> {
+    const PAGE: i64 = 1000;
+    let mut entries = 0;
+    let mut prev_hash = GENESIS_HASH.to_string();
+    loop {
+        let page = db.audit_events_after(entries, PAGE).await?;
+        for event in &page {
+            if event.id != entries + 1 || event.prev_hash != prev_hash || event.hash != hash(event) {
+                return Ok(Verification {
+                    entries,
+                    last_hash: None,
+                    broken_at: Some(entries + 1),
+                });
+            }
+            entries = event.id;
+            prev_hash = event.hash.clone();
+        }
+        if (page.len() as i64) < PAGE {
+            break;
+        }
+    }
+
+    Ok(Verification {
+        entries,
+        last_hash: (entries > 0).then_some(prev_hash),
+        broken_at: None,
+    })
+}
@@ -0,0 +1,363 @@
+//! Maintenance commands for operators. Reads the same config file and
+//! environment as the server.
+
+use std::env;
+use std::sync::Arc;
+
+use chrono::{DateTime, NaiveDate, Utc};
+use dotenv::dotenv;
+use serde::Serialize;
+use serde_json::json;
+use tracing::Level;
+use tracing_subscriber::FmtSubscriber;
+use uuid::Uuid;
+
+use pulse_backend::admin::Admin;
+use pulse_backend::blob_store::FsBlobStore;
+use pulse_backend::{config, db};
+
+const USAGE: &str = "\
+Usage: pulse-admin [--json] <command>
+
+Commands:
+  users [<query>] [--limit <n>]   List users, or those whose username or email contains <query>
+  user <user>                     Show a user with their devices, sessions and bots
+  suspend <user>                  Suspend an account and sign out its sessions
+  unsuspend <user>                Lift a suspension
+  grant-admin <user>              Let a user review reports and suspend accounts
+  revoke-admin <user>             Take admin rights away
+  revoke-sessions <user>          Sign out every session of a user
+  revoke-device <user> <device>   Remove a device with its sessions and keys
+  migrate [status]                Apply pending migrations, or list them
+  purge                           Remove expired attachments, sessions, email tokens and due account deletions
+  stats [--top <n>]               Show how many messages are waiting for delivery
+  audit [--user <user>] [--since <time>] [--until <time>] [--limit <n>]
+                                  List audit log entries, newest first
+  audit verify                    Check the audit log's hash chain
+  jwt-keys                        List the token signing keys
+  rotate-jwt-key [--now]          Add a signing key that takes over after the publish delay, or at once
+
+<user> is a user id, email address or username. <time> is RFC 3339 or a
+date (YYYY-MM-DD, midnight UTC).";
+
+#[tokio::main]
+async fn main() {
+    if let Err(e) = run().await {
+        eprintln!("error: {}", e);
+        std::process::exit(1);
+    }
+}
+
+async fn run() -> Result<(), Box<dyn std::error::Error>> {
+    let mut args: Vec<String> = env::args().skip(1).collect();
+    let json = take_flag(&mut args, "--json");
+    let Some(command) = (!args.is_empty()).then(|| args.remove(0)) else {
+        println!("{}", USAGE);
+        return Ok(());
+    };
+    if command == "help" || command == "--help" {
+        println!("{}", USAGE);
+        return Ok(());
+    }
+
+    dotenv().ok();
+    FmtSubscriber::builder()
+        .with_max_level(Level::WARN)
+        .with_writer(std::io::stderr)
+        .try_init()
+        .map_err(|e| e as Box<dyn std::error::Error>)?;
+
+    let config = config::ServerConfig::load()?;
+    let db = db::connect(&config.database_url).await?;
+
+    if command == "migrate" {
+        return migrate(&db, json, args.first().map(String::as_str)).await;
+    }
+    if db.migration_status().await?.iter().any(|m| m.applied_at.is_none()) {
+        return Err("The database has pending migrations; run `pulse-admin migrate` first".into());
+    }
+
+    let admin = Admin::new(db, Arc::new(FsBlobStore::new(&config.attachment_dir).await?));
+    let user_arg = |args: &[String]| -> Result<String, Box<dyn std::error::Error>> {
+        args.first().cloned().ok_or_else(|| format!("Usage: pulse-admin {} <user>", command).into())
+    };
+
+    match command.as_str() {
+        "users" => {
+            let limit = take_value(&mut args, "--limit")?.unwrap_or(100);
+            let users = admin.users(args.first().map(String::as_str), limit).await?;
+            print(json, &users, || {
+                users
+                    .iter()
+                    .map(|u| {
+                        let mut flags = Vec::new();
+                        if u.is_admin {
+                            flags.push("admin");
+                        }
+                        if u.bot_owner_id.is_some() {
+                            flags.push("bot");
+                        }
+                        if u.suspended_at.is_some() {
+                            flags.push("suspended");
+                        }
+                        if u.delete_after.is_some() {
+                            flags.push("deleting");
+                        }
+                        format!("{}  {:<24} {:<32} {}", u.id, u.username, u.email, flags.join(","))
+                    })
+                    .collect::<Vec<_>>()
+                    .join("\n")
+            })
+        }
+        "user" => {
+            let details = admin.user_details(&user_arg(&args)?).await?;
+            print(json, &details, || {
+                let user = &details.user;
+                let mut lines = vec![
+                    format!("id:        {}", user.id),
+                    format!("username:  {}", user.username),
+                    format!("email:     {}", user.email),
+                    format!("created:   {}", user.created_at.to_rfc3339()),
+                    format!("last seen: {}", user.last_seen.to_rfc3339()),
+                    format!("verified:  {}", user.email_verified_at.is_some()),
+                    format!("admin:     {}", user.is_admin),
+                ];
+                if let Some(at) = user.suspended_at {
+                    lines.push(format!("suspended: {}", at.to_rfc3339()));
+                }
+                if let Some(at) = user.delete_after {
+                    lines.push(format!("deleting:  {}", at.to_rfc3339()));
+                }
+                lines.push(format!("devices ({}):", details.devices.len()));
+                for d in &details.devices {
+                    lines.push(format!("  {}  {:<24} last seen {}", d.id, d.name, d.last_seen.to_rfc3339()));
+                }
+                lines.push(format!("sessions ({}):", details.sessions.len()));
+                for s in &details.sessions {
+                    lines.push(format!("  {}  device {} expires {}", s.id, s.device_id, s.expires_at.to_rfc3339()));
+                }
+                if !details.bots.is_empty() {
+                    lines.push(format!("bots ({}):", details.bots.len()));
+                    for b in &details.bots {
+                        lines.push(format!("  {}  {}", b.id, b.username));
+                    }
+                }
+                lines.join("\n")
+            })
+        }
+        "suspend" | "unsuspend" => {
+            let user = admin.set_suspended(&user_arg(&args)?, command == "suspend").await?;
+            print(json, &user, || match user.suspended_at {
+                Some(_) => format!("{} is suspended and signed out everywhere", user.username),
+                None => format!("{} is no longer suspended", user.username),
+            })
+        }
+        "grant-admin" | "revoke-admin" => {
+            let user = admin.set_admin(&user_arg(&args)?, command == "grant-admin").await?;
+            print(json, &user, || match user.is_admin {
+                true => format!("{} is now an admin", user.username),
+                false => format!("{} is no longer an admin", user.username),
+            })
+        }
+        "revoke-sessions" => {
+            let revoked = admin.revoke_sessions(&user_arg(&args)?).await?;
+            print(json, &json!({ "revoked": revoked }), || format!("Revoked {} session(s)", revoked.len()))
+        }
+        "revoke-device" => {
+            let (who, device_id) = match args.as_slice() {
+                [who, device_id] => (who, Uuid::try_parse(device_id).map_err(|_| "Invalid device id")?),
+                _ => return Err("Usage: pulse-admin revoke-device <user> <device>".into()),
+            };
+            admin.revoke_device(who, device_id).await?;
+            print(json, &json!({ "revoked": device_id }), || format!("Removed device {}", device_id))
+        }
+        "purge" => {
+            let report = admin.purge().await?;
+            print(json, &report, || {
+                let mut text = format!(
+                    "Removed {} attachment(s), {} session(s), {} email token(s) and {} account(s)",
+                    report.attachments, report.sessions, report.email_tokens, report.accounts
+                );
+                if report.failed_accounts > 0 {
+                    text.push_str(&format!("; {} account(s) could not be deleted, see the log", report.failed_accounts));
+                }
+                text
+            })
+        }
+        "stats" => {
+            let top = take_value(&mut args, "--top")?.unwrap_or(10);
+            let stats = admin.queue_stats(top).await?;
+            print(json, &stats, || {
+                let mut lines = vec![format!("Messages waiting: {}", stats.messages)];
+                if let Some(oldest) = stats.oldest_at {
+                    lines.push(format!("Oldest:           {}", oldest.to_rfc3339()));
+                }
+                if !stats.recipients.is_empty() {
+                    lines.push("Top recipients:".to_string());
+                }
+                for r in &stats.recipients {
+                    lines.push(format!("  {}  {:>8}  oldest {}", r.user_id, r.messages, r.oldest_at.to_rfc3339()));
+                }
+                lines.join("\n")
+            })
+        }
+        "audit" if args.first().map(String::as_str) == Some("verify") => {
+            let report = admin.verify_audit_log().await?;
+            print(json, &report, || match report.broken_at {
+                Some(_) => String::new(),
+                None => format!(
+                    "{} entries intact; newest hash {}",
+                    report.entries,
+                    report.last_hash.as_deref().unwrap_or("-")
+                ),
+            })?;
+            match report.broken_at {
+                Some(id) => Err(format!("The audit log chain breaks at entry {}", id).into()),
+                None => Ok(()),
+            }
+        }
+        "audit" => {
+            let user = take_string(&mut args, "--user")?;
+            let since = take_string(&mut args, "--since")?.map(|s| parse_time(&s)).transpose()?;
+            let until = take_string(&mut args, "--until")?.map(|s| parse_time(&s)).transpose()?;
+            let limit = take_value(&mut args, "--limit")?.unwrap_or(100);
+            let events = admin.audit_log(user.as_deref(), since, until, limit).await?;
+            print(json, &events, || {
+                events
+                    .iter()
+                    .map(|e| {
+                        let id = |id: Option<Uuid>| id.map(|id| id.to_string()).unwrap_or_else(|| "-".to_string());
+                        format!(
+                            "{:>8}  {}  {:<20} user {}  by {}  device {}  {}",
+                            e.id,
+                            e.created_at.to_rfc3339(),
+                            e.kind.as_str(),
+                            id(e.user_id),
+                            id(e.actor_id),
+                            id(e.device_id),
+                            e.details
+                        )
+                    })
+                    .collect::<Vec<_>>()
+                    .join("\n")
+            })
+        }
+        "jwt-keys" => {
+            let keys = admin.signing_keys().await?;
+            print(json, &keys, || {
+                keys.iter()
+                    .map(|k| {
+                        format!(
+                            "{}  {:<6} created {}  signs from {}",
+                            k.id,
+                            k.algorithm.as_str(),
+                            k.created_at.to_rfc3339(),
+                            k.activates_at.to_rfc3339()
+                        )
+                    })
+                    .collect::<Vec<_>>()
+                    .join("\n")
+            })
+        }
+        "rotate-jwt-key" => {
+            let key = admin.rotate_signing_key(take_flag(&mut args, "--now")).await?;
+            print(json, &key, || {
+                format!(
+                    "Added {} key {}; servers sign with it from {}. Tokens signed with earlier keys stay \
+                     valid until they expire.",
+                    key.algorithm.as_str(),
+                    key.id,
+                    key.activates_at.to_rfc3339()
+                )
+            })
+        }
+        other => Err(format!("Unknown command: {}\n\n{}", other, USAGE).into()),
+    }
+}
+
+async fn migrate(db: &db::Database, json: bool, action: Option<&str>) -> Result<(), Box<dyn std::error::Error>> {
+    match action {
+        None | Some("up") => {
+            let applied = db.migrate().await?;
+            print(json, &json!({ "applied": applied }), || {
+                if applied.is_empty() {
+                    return "Database is up to date".to_string();
+                }
+                applied
+                    .iter()
+                    .map(|version| format!("Applied migration {}", version))
+                    .collect::<Vec<_>>()
+                    .join("\n")
+            })
+        }
+        Some("status") => {
+            let status = db.migration_status().await?;
+            let rows: Vec<_> = status
+                .iter()
+                .map(|m| json!({ "version": m.version, "name": m.name, "applied_at": m.applied_at }))
+                .collect();
+            print(json, &rows, || {
+                status
+                    .iter()
+                    .map(|m| {
+                        let applied_at = m
+                            .applied_at
+                            .map(|t| t.to_rfc3339())
+                            .unwrap_or_else(|| "pending".to_string());
+                        format!("{:>4}  {:<24} {}", m.version, m.name, applied_at)
+                    })
+                    .collect::<Vec<_>>()
+                    .join("\n")
+            })
+        }
+        Some(other) => Err(format!("Unknown migrate action: {}", other).into()),
+    }
+}
+
+/// Prints `value` as JSON with `--json`, otherwise the text `text` makes.
+fn print<T: Serialize>(
+    json: bool,
+    value: &T,
+    text: impl FnOnce() -> String,
+) -> Result<(), Box<dyn std::error::Error>> {
+    if json {
+        println!("{}", serde_json::to_string_pretty(value)?);
+    } else {
+        let text = text();
+        if !text.is_empty() {
+            println!("{}", text);
+        }
+    }
+    Ok(())
+}
+
+fn take_flag(args: &mut Vec<String>, flag: &str) -> bool {
+    let before = args.len();
+    args.retain(|a| a != flag);
+    args.len() != before
+}
+
+fn take_string(args: &mut Vec<String>, flag: &str) -> Result<Option<String>, Box<dyn std::error::Error>> {
+    let Some(i) = args.iter().position(|a| a == flag) else {
+        return Ok(None);
+    };
+    if i + 1 >= args.len() {
+        return Err(format!("{} needs a value", flag).into());
+    }
+    Ok(args.drain(i..i + 2).nth(1))
+}
+
+fn take_value(args: &mut Vec<String>, flag: &str) -> Result<Option<i64>, Box<dyn std::error::Error>> {
+    match take_string(args, flag)? {
+        Some(value) => Ok(Some(value.parse().map_err(|_| format!("{} must be a number", flag))?)),
+        None => Ok(None),
+    }
+}
+
+fn parse_time(s: &str) -> Result<DateTime<Utc>, Box<dyn std::error::Error>> {
+    if let Ok(time) = DateTime::parse_from_rfc3339(s) {
+        return Ok(time.with_timezone(&Utc));
+    }
+    let date = NaiveDate::parse_from_str(s, "%Y-%m-%d").map_err(|_| format!("Invalid time: {}", s))?;
+    Ok(date.and_hms_opt(0, 0, 0).unwrap_or_default().and_utc())
+}
@@ -0,0 +1,124 @@
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+
+use async_trait::async_trait;
+use tokio::{
+    fs::{self, OpenOptions},
+    io::{AsyncRead, AsyncWriteExt},
+};
+
+#[derive(Debug, thiserror::Error)]
+pub enum BlobError {
+    #[error("I/O error: {0}")]
+    IoError(#[from] std::io::Error),
+    #[error("Blob not found: {0}")]
+    NotFound(String),
+    #[error("Upload offset mismatch: expected {expected}, got {actual}")]
+    OffsetMismatch { expected: u64, actual: u64 },
+    #[error("Invalid key: {0}")]
+    InvalidKey(String),
+}
+
+pub type BlobReader = Pin<Box<dyn AsyncRead + Send>>;
+
+/// Object-store-style storage for opaque (client-encrypted) blobs. Objects are
+/// written by appending parts at an explicit offset so that interrupted
+/// uploads can resume where they stopped.
+#[async_trait]
+pub trait BlobStore: Send + Sync {
+    /// Appends `data` at `offset`, which must equal the current size of the
+    /// object. Creates the object on the first part. Returns the new size.
+    async fn append(&self, key: &str, offset: u64, data: &[u8]) -> Result<u64, BlobError>;
+    async fn size(&self, key: &str) -> Result<Option<u64>, BlobError>;
+    async fn get(&self, key: &str) -> Result<BlobReader, BlobError>;
+    async fn delete(&self, key: &str) -> Result<(), BlobError>;
+}
+
+/// Stores each blob as one file under `root`.
+pub struct FsBlobStore {
+    root: PathBuf,
+    // Appends to the same key are serialized so that two racing parts cannot
+    // both pass the offset check.
+    locks: Mutex<HashMap<String, Arc<tokio::sync::Mutex<()>>>>,
+}
+
+impl FsBlobStore {
+    pub async fn new(root: impl Into<PathBuf>) -> Result<Self, BlobError> {
+        let root = root.into();
+        fs::create_dir_all(&root).await?;
+        Ok(Self {
+            root,
+            locks: Mutex::new(HashMap::new()),
+        })
+    }
+
+    fn lock_for(&self, key: &str) -> Arc<tokio::sync::Mutex<()>> {
+        let mut locks = self.locks.lock().unwrap();
+        locks.retain(|_, lock| Arc::strong_count(lock) > 1);
+        locks.entry(key.to_string()).or_default().clone()
+    }
+
+    fn path(&self, key: &str) -> Result<PathBuf, BlobError> {
+        let valid = !key.is_empty()
+            && key.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+        if !valid {
+            return Err(BlobError::InvalidKey(key.to_string()));
+        }
+        Ok(self.root.join(key))
+    }
+}
+
+#[async_trait]
+impl BlobStore for FsBlobStore {
+    async fn append(&self, key: &str, offset: u64, data: &[u8]) -> Result<u64, BlobError> {
+        let path = self.path(key)?;
+        let lock = self.lock_for(key);
+        let _guard = lock.lock().await;
+
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&path)
+            .await?;
+
+        let current = file.metadata().await?.len();
+        if current != offset {
+            return Err(BlobError::OffsetMismatch {
+                expected: current,
+                actual: offset,
+            });
+        }
+
+        file.write_all(data).await?;
+        file.sync_data().await?;
+        Ok(current + data.len() as u64)
+    }
+
+    async fn size(&self, key: &str) -> Result<Option<u64>, BlobError> {
+        match fs::metadata(self.path(key)?).await {
+            Ok(metadata) => Ok(Some(metadata.len())),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    async fn get(&self, key: &str) -> Result<BlobReader, BlobError> {
+        match fs::File::open(self.path(key)?).await {
+            Ok(file) => Ok(Box::pin(file)),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                Err(BlobError::NotFound(key.to_string()))
+            }
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    async fn delete(&self, key: &str) -> Result<(), BlobError> {
+        match fs::remove_file(self.path(key)?).await {
+            Ok(()) => Ok(()),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(()),
+            Err(e) => Err(e.into()),
+        }
+    }
+}
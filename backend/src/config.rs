@@ -0,0 +1,255 @@
+use std::env;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::time::Duration;
+
+use serde::Deserialize;
+
+/// Read when `PULSE_CONFIG` is not set, if it exists.
+const DEFAULT_CONFIG_PATH: &str = "pulse.toml";
+
+#[derive(Debug, thiserror::Error)]
+pub enum ConfigError {
+    #[error("Cannot read {path}: {source}")]
+    Io { path: PathBuf, source: std::io::Error },
+    #[error("Invalid config file {path}: {source}")]
+    Parse { path: PathBuf, source: toml::de::Error },
+    #[error("Missing setting `{0}` (set it in the config file or with {1})")]
+    Missing(&'static str, &'static str),
+    #[error("Invalid value for {0}: {1}")]
+    Invalid(&'static str, String),
+}
+
+/// An environment setting of the loaders outside this file, or `None` when
+/// it is unset or empty. A value that does not parse is an error, so that a
+/// typo stops the server instead of quietly falling back to the default.
+pub fn env_opt<T: FromStr>(name: &'static str) -> Result<Option<T>, ConfigError>
+where
+    T::Err: std::fmt::Display,
+{
+    env::var(name)
+        .ok()
+        .filter(|v| !v.trim().is_empty())
+        .map(|v| v.trim().parse().map_err(|e: T::Err| ConfigError::Invalid(name, e.to_string())))
+        .transpose()
+}
+
+pub fn env_or<T: FromStr>(name: &'static str, default: T) -> Result<T, ConfigError>
+where
+    T::Err: std::fmt::Display,
+{
+    Ok(env_opt(name)?.unwrap_or(default))
+}
+
+/// `env_or` for counts and durations that must be at least `min`.
+pub fn env_at_least(name: &'static str, default: i64, min: i64) -> Result<i64, ConfigError> {
+    let value = env_or(name, default)?;
+    if value < min {
+        return Err(ConfigError::Invalid(name, format!("must be at least {}", min)));
+    }
+    Ok(value)
+}
+
+/// A switch: `1` or `true` turns it on, `0` or `false` off.
+pub fn env_flag(name: &'static str, default: bool) -> Result<bool, ConfigError> {
+    match env_opt::<String>(name)? {
+        None => Ok(default),
+        Some(v) if v == "1" || v.eq_ignore_ascii_case("true") => Ok(true),
+        Some(v) if v == "0" || v.eq_ignore_ascii_case("false") => Ok(false),
+        Some(v) => Err(ConfigError::Invalid(name, format!("{} is neither true nor false", v))),
+    }
+}
+
+/// Where and how the server listens, and what it connects to. Read from a
+/// TOML file, with environment variables taking precedence; other settings
+/// (rate limits, sessions, mail and so on) come from the environment only,
+/// read with the `env_*` functions below.
+#[derive(Debug, Clone)]
+pub struct ServerConfig {
+    pub database_url: String,
+    /// The HMAC secret tokens were signed with before signing keys were
+    /// introduced. Only verifies tokens issued before the upgrade.
+    pub jwt_secret: Option<String>,
+    pub listen: SocketAddr,
+    /// Terminate TLS in the server instead of behind a proxy.
+    pub tls: Option<TlsConfig>,
+    pub attachment_dir: PathBuf,
+    pub limits: LimitsConfig,
+}
+
+#[derive(Debug, Clone)]
+pub struct TlsConfig {
+    /// PEM certificate chain.
+    pub cert_path: PathBuf,
+    /// PEM private key.
+    pub key_path: PathBuf,
+}
+
+#[derive(Debug, Clone)]
+pub struct LimitsConfig {
+    /// Largest request body accepted, except on attachment uploads, which
+    /// have their own chunk limit.
+    pub max_body_bytes: usize,
+    /// How long a request may take. The event stream and attachment
+    /// transfers are exempt.
+    pub request_timeout: Duration,
+    /// How long in-flight requests get to finish after SIGTERM.
+    pub shutdown_grace: Duration,
+}
+
+impl Default for LimitsConfig {
+    fn default() -> Self {
+        Self {
+            max_body_bytes: 2 * 1024 * 1024,
+            request_timeout: Duration::from_secs(30),
+            shutdown_grace: Duration::from_secs(30),
+        }
+    }
+}
+
+/// The file as written; every field is optional so that the environment
+/// can supply it instead.
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RawConfig {
+    database_url: Option<String>,
+    jwt_secret: Option<String>,
+    host: Option<String>,
+    port: Option<u16>,
+    attachment_dir: Option<PathBuf>,
+    #[serde(default)]
+    tls: RawTls,
+    #[serde(default)]
+    limits: RawLimits,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RawTls {
+    cert_path: Option<PathBuf>,
+    key_path: Option<PathBuf>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RawLimits {
+    max_body_bytes: Option<usize>,
+    request_timeout_seconds: Option<u64>,
+    shutdown_grace_seconds: Option<u64>,
+}
+
+impl ServerConfig {
+    /// Loads the file named by `PULSE_CONFIG` (or `pulse.toml`, if present),
+    /// applies environment overrides and validates the result.
+    pub fn load() -> Result<Self, ConfigError> {
+        let path = match env::var_os("PULSE_CONFIG") {
+            Some(path) => Some(PathBuf::from(path)),
+            None => Some(PathBuf::from(DEFAULT_CONFIG_PATH)).filter(|path| path.exists()),
+        };
+        let Some(path) = path else {
+            return Self::from_raw(RawConfig::default(), |name| env::var(name).ok());
+        };
+        let contents = std::fs::read_to_string(&path).map_err(|source| ConfigError::Io {
+            path: path.clone(),
+            source,
+        })?;
+        Self::from_toml(&contents, |name| env::var(name).ok()).map_err(|e| match e {
+            ConfigError::Parse { source, .. } => ConfigError::Parse { path, source },
+            e => e,
+        })
+    }
+
+    /// Parses a config file's contents, with `var` standing in for the
+    /// environment.
+    pub fn from_toml(contents: &str, var: impl Fn(&str) -> Option<String>) -> Result<Self, ConfigError> {
+        let raw = toml::from_str(contents).map_err(|source| ConfigError::Parse {
+            path: PathBuf::from("<string>"),
+            source,
+        })?;
+        Self::from_raw(raw, var)
+    }
+
+    fn from_raw(mut raw: RawConfig, var: impl Fn(&str) -> Option<String>) -> Result<Self, ConfigError> {
+        fn parsed<T: std::str::FromStr>(name: &'static str, value: Option<String>) -> Result<Option<T>, ConfigError>
+        where
+            T::Err: std::fmt::Display,
+        {
+            value
+                .map(|v| v.parse().map_err(|e: T::Err| ConfigError::Invalid(name, e.to_string())))
+                .transpose()
+        }
+
+        // Environment variables win over the file.
+        raw.database_url = var("DATABASE_URL").or(raw.database_url);
+        raw.jwt_secret = var("JWT_SECRET").or(raw.jwt_secret);
+        raw.host = var("SERVER_HOST").or(raw.host);
+        raw.port = parsed("SERVER_PORT", var("SERVER_PORT"))?.or(raw.port);
+        raw.attachment_dir = var("ATTACHMENT_DIR").map(PathBuf::from).or(raw.attachment_dir);
+        raw.tls.cert_path = var("TLS_CERT_PATH").map(PathBuf::from).or(raw.tls.cert_path);
+        raw.tls.key_path = var("TLS_KEY_PATH").map(PathBuf::from).or(raw.tls.key_path);
+        let limits = &mut raw.limits;
+        limits.max_body_bytes = parsed("MAX_BODY_BYTES", var("MAX_BODY_BYTES"))?.or(limits.max_body_bytes);
+        limits.request_timeout_seconds =
+            parsed("REQUEST_TIMEOUT_SECONDS", var("REQUEST_TIMEOUT_SECONDS"))?.or(limits.request_timeout_seconds);
+        limits.shutdown_grace_seconds =
+            parsed("SHUTDOWN_GRACE_SECONDS", var("SHUTDOWN_GRACE_SECONDS"))?.or(limits.shutdown_grace_seconds);
+
+        let database_url = raw
+            .database_url
+            .filter(|v| !v.is_empty())
+            .ok_or(ConfigError::Missing("database_url", "DATABASE_URL"))?;
+        let jwt_secret = raw.jwt_secret.filter(|v| !v.is_empty());
+
+        let host = raw.host.unwrap_or_else(|| "127.0.0.1".to_string());
+        let port = raw.port.unwrap_or(8080);
+        let listen = format!("{}:{}", host, port)
+            .parse()
+            .map_err(|_| ConfigError::Invalid("host", format!("{} is not an IP address", host)))?;
+
+        let tls = match (raw.tls.cert_path, raw.tls.key_path) {
+            (Some(cert_path), Some(key_path)) => Some(TlsConfig { cert_path, key_path }),
+            (None, None) => None,
+            _ => {
+                return Err(ConfigError::Invalid(
+                    "tls",
+                    "cert_path and key_path must be set together".to_string(),
+                ))
+            }
+        };
+
+        let defaults = LimitsConfig::default();
+        let max_body_bytes = raw.limits.max_body_bytes.unwrap_or(defaults.max_body_bytes);
+        if max_body_bytes == 0 {
+            return Err(ConfigError::Invalid("limits.max_body_bytes", "must be positive".to_string()));
+        }
+        let request_timeout = match raw.limits.request_timeout_seconds {
+            Some(0) => {
+                return Err(ConfigError::Invalid(
+                    "limits.request_timeout_seconds",
+                    "must be positive".to_string(),
+                ))
+            }
+            Some(seconds) => Duration::from_secs(seconds),
+            None => defaults.request_timeout,
+        };
+        let shutdown_grace = raw
+            .limits
+            .shutdown_grace_seconds
+            .map(Duration::from_secs)
+            .unwrap_or(defaults.shutdown_grace);
+
+        Ok(Self {
+            database_url,
+            jwt_secret,
+            listen,
+            tls,
+            attachment_dir: raw.attachment_dir.unwrap_or_else(|| PathBuf::from("attachments")),
+            limits: LimitsConfig {
+                max_body_bytes,
+                request_timeout,
+                shutdown_grace,
+            },
+        })
+    }
+}
@@ -0,0 +1,320 @@
+use chrono::{DateTime, Utc};
+use sha2::{Digest, Sha256};
+
+#[derive(Debug, thiserror::Error)]
+pub enum MigrationError {
+    #[error("Database schema is at version {database}, but this binary only knows up to version {binary}")]
+    DatabaseTooNew { database: i64, binary: i64 },
+    #[error("Checksum mismatch for migration {version} ({name}): the applied migration was modified")]
+    ChecksumMismatch { version: i64, name: String },
+}
+
+pub struct Migration {
+    pub version: i64,
+    pub name: &'static str,
+    pub sql: &'static str,
+}
+
+impl Migration {
+    pub fn checksum(&self) -> String {
+        Sha256::digest(self.sql.as_bytes())
+            .iter()
+            .map(|b| format!("{:02x}", b))
+            .collect()
+    }
+}
+
+/// SQLite migrations, ordered by version. Applied migrations must never be
+/// edited; add a new one instead. Every version needs a PostgreSQL
+/// counterpart in `POSTGRES`.
+pub const SQLITE: &[Migration] = &[
+    Migration {
+        version: 1,
+        name: "initial",
+        sql: include_str!("../../migrations/sqlite/0001_initial.sql"),
+    },
+    Migration {
+        version: 2,
+        name: "attachments",
+        sql: include_str!("../../migrations/sqlite/0002_attachments.sql"),
+    },
+    Migration {
+        version: 3,
+        name: "prekeys",
+        sql: include_str!("../../migrations/sqlite/0003_prekeys.sql"),
+    },
+    Migration {
+        version: 4,
+        name: "refresh_tokens",
+        sql: include_str!("../../migrations/sqlite/0004_refresh_tokens.sql"),
+    },
+    Migration {
+        version: 5,
+        name: "two_factor",
+        sql: include_str!("../../migrations/sqlite/0005_two_factor.sql"),
+    },
+    Migration {
+        version: 6,
+        name: "email_verification",
+        sql: include_str!("../../migrations/sqlite/0006_email_verification.sql"),
+    },
+    Migration {
+        version: 7,
+        name: "account_deletion",
+        sql: include_str!("../../migrations/sqlite/0007_account_deletion.sql"),
+    },
+    Migration {
+        version: 8,
+        name: "contacts",
+        sql: include_str!("../../migrations/sqlite/0008_contacts.sql"),
+    },
+    Migration {
+        version: 9,
+        name: "moderation",
+        sql: include_str!("../../migrations/sqlite/0009_moderation.sql"),
+    },
+    Migration {
+        version: 10,
+        name: "presence",
+        sql: include_str!("../../migrations/sqlite/0010_presence.sql"),
+    },
+    Migration {
+        version: 11,
+        name: "receipts",
+        sql: include_str!("../../migrations/sqlite/0011_receipts.sql"),
+    },
+    Migration {
+        version: 12,
+        name: "message_actions",
+        sql: include_str!("../../migrations/sqlite/0012_message_actions.sql"),
+    },
+    Migration {
+        version: 13,
+        name: "threads",
+        sql: include_str!("../../migrations/sqlite/0013_threads.sql"),
+    },
+    Migration {
+        version: 14,
+        name: "invites",
+        sql: include_str!("../../migrations/sqlite/0014_invites.sql"),
+    },
+    Migration {
+        version: 15,
+        name: "channels",
+        sql: include_str!("../../migrations/sqlite/0015_channels.sql"),
+    },
+    Migration {
+        version: 16,
+        name: "chat_settings",
+        sql: include_str!("../../migrations/sqlite/0016_chat_settings.sql"),
+    },
+    Migration {
+        version: 17,
+        name: "bots",
+        sql: include_str!("../../migrations/sqlite/0017_bots.sql"),
+    },
+    Migration {
+        version: 18,
+        name: "signing_keys",
+        sql: include_str!("../../migrations/sqlite/0018_signing_keys.sql"),
+    },
+    Migration {
+        version: 19,
+        name: "audit_log",
+        sql: include_str!("../../migrations/sqlite/0019_audit_log.sql"),
+    },
+    Migration {
+        version: 20,
+        name: "keep_reports",
+        sql: include_str!("../../migrations/sqlite/0020_keep_reports.sql"),
+    },
+    Migration {
+        version: 21,
+        name: "mfa_failures",
+        sql: include_str!("../../migrations/sqlite/0021_mfa_failures.sql"),
+    },
+];
+
+pub const POSTGRES: &[Migration] = &[
+    Migration {
+        version: 1,
+        name: "initial",
+        sql: include_str!("../../migrations/postgres/0001_initial.sql"),
+    },
+    Migration {
+        version: 2,
+        name: "attachments",
+        sql: include_str!("../../migrations/postgres/0002_attachments.sql"),
+    },
+    Migration {
+        version: 3,
+        name: "prekeys",
+        sql: include_str!("../../migrations/postgres/0003_prekeys.sql"),
+    },
+    Migration {
+        version: 4,
+        name: "refresh_tokens",
+        sql: include_str!("../../migrations/postgres/0004_refresh_tokens.sql"),
+    },
+    Migration {
+        version: 5,
+        name: "two_factor",
+        sql: include_str!("../../migrations/postgres/0005_two_factor.sql"),
+    },
+    Migration {
+        version: 6,
+        name: "email_verification",
+        sql: include_str!("../../migrations/postgres/0006_email_verification.sql"),
+    },
+    Migration {
+        version: 7,
+        name: "account_deletion",
+        sql: include_str!("../../migrations/postgres/0007_account_deletion.sql"),
+    },
+    Migration {
+        version: 8,
+        name: "contacts",
+        sql: include_str!("../../migrations/postgres/0008_contacts.sql"),
+    },
+    Migration {
+        version: 9,
+        name: "moderation",
+        sql: include_str!("../../migrations/postgres/0009_moderation.sql"),
+    },
+    Migration {
+        version: 10,
+        name: "presence",
+        sql: include_str!("../../migrations/postgres/0010_presence.sql"),
+    },
+    Migration {
+        version: 11,
+        name: "receipts",
+        sql: include_str!("../../migrations/postgres/0011_receipts.sql"),
+    },
+    Migration {
+        version: 12,
+        name: "message_actions",
+        sql: include_str!("../../migrations/postgres/0012_message_actions.sql"),
+    },
+    Migration {
+        version: 13,
+        name: "threads",
+        sql: include_str!("../../migrations/postgres/0013_threads.sql"),
+    },
+    Migration {
+        version: 14,
+        name: "invites",
+        sql: include_str!("../../migrations/postgres/0014_invites.sql"),
+    },
+    Migration {
+        version: 15,
+        name: "channels",
+        sql: include_str!("../../migrations/postgres/0015_channels.sql"),
+    },
+    Migration {
+        version: 16,
+        name: "chat_settings",
+        sql: include_str!("../../migrations/postgres/0016_chat_settings.sql"),
+    },
+    Migration {
+        version: 17,
+        name: "bots",
+        sql: include_str!("../../migrations/postgres/0017_bots.sql"),
+    },
+    Migration {
+        version: 18,
+        name: "signing_keys",
+        sql: include_str!("../../migrations/postgres/0018_signing_keys.sql"),
+    },
+    Migration {
+        version: 19,
+        name: "audit_log",
+        sql: include_str!("../../migrations/postgres/0019_audit_log.sql"),
+    },
+    Migration {
+        version: 20,
+        name: "keep_reports",
+        sql: include_str!("../../migrations/postgres/0020_keep_reports.sql"),
+    },
+    Migration {
+        version: 21,
+        name: "mfa_failures",
+        sql: include_str!("../../migrations/postgres/0021_mfa_failures.sql"),
+    },
+];
+
+/// A row of the `schema_migrations` table.
+#[derive(Debug, Clone)]
+pub struct AppliedMigration {
+    pub version: i64,
+    pub checksum: String,
+    pub applied_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone)]
+pub struct MigrationStatus {
+    pub version: i64,
+    pub name: &'static str,
+    pub applied_at: Option<DateTime<Utc>>,
+}
+
+pub fn latest_version(known: &[Migration]) -> i64 {
+    known.last().map(|m| m.version).unwrap_or(0)
+}
+
+/// Checks the applied migrations against the ones compiled into this binary
+/// and returns those still to be applied, in order. Refuses databases written
+/// by a newer binary and migrations whose SQL has changed since they were
+/// applied.
+pub fn pending<'a>(
+    known: &'a [Migration],
+    applied: &[AppliedMigration],
+) -> Result<Vec<&'a Migration>, MigrationError> {
+    if let Some(newest) = applied.iter().map(|a| a.version).max() {
+        if newest > latest_version(known) {
+            return Err(MigrationError::DatabaseTooNew {
+                database: newest,
+                binary: latest_version(known),
+            });
+        }
+    }
+
+    for a in applied {
+        match known.iter().find(|m| m.version == a.version) {
+            Some(m) if m.checksum() == a.checksum => {}
+            Some(m) => {
+                return Err(MigrationError::ChecksumMismatch {
+                    version: m.version,
+                    name: m.name.to_string(),
+                })
+            }
+            None => {
+                return Err(MigrationError::ChecksumMismatch {
+                    version: a.version,
+                    name: "<unknown>".to_string(),
+                })
+            }
+        }
+    }
+
+    Ok(known
+        .iter()
+        .filter(|m| !applied.iter().any(|a| a.version == m.version))
+        .collect())
+}
+
+pub fn status(known: &[Migration], applied: &[AppliedMigration]) -> Result<Vec<MigrationStatus>, MigrationError> {
+    pending(known, applied)?;
+
+    Ok(known
+        .iter()
+        .map(|m| MigrationStatus {
+            version: m.version,
+            name: m.name,
+            applied_at: applied
+                .iter()
+                .find(|a| a.version == m.version)
+                .map(|a| a.applied_at),
+        })
+        .collect())
+}
@@ -0,0 +1,438 @@
+mod postgres;
+mod sqlite;
+pub mod migrations;
+
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use uuid::Uuid;
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use crate::models::{User, Message, MessageMetadata, Device, Chat, ChatMember, ChatRole, Session, Attachment, SignedPrekey, OneTimePrekey, TotpSecret, EmailToken, EmailTokenPurpose, UserProfile, PrivacySettings, Contact, Block, Report, ReportStatus, ThreadSummary, ChatInvite, JoinRequest, ChannelPost, ChatSettings, ChatPin, StarredMessage, ApiKey, BotWebhook, SigningKey, AuditEvent};
+use migrations::{MigrationError, MigrationStatus};
+
+pub use postgres::PostgresStorage;
+pub use sqlite::SqliteStorage;
+
+#[derive(Debug, thiserror::Error)]
+pub enum DatabaseError {
+    #[error("Database error: {0}")]
+    SqlxError(sqlx::Error),
+    /// A unique constraint was violated. Holds the offending `table.column`
+    /// when the backend reports it.
+    #[error("Duplicate value for {0}")]
+    UniqueViolation(String),
+    #[error("Invalid data: {0}")]
+    InvalidData(String),
+    #[error("Migration error: {0}")]
+    MigrationError(#[from] MigrationError),
+}
+
+impl From<sqlx::Error> for DatabaseError {
+    fn from(e: sqlx::Error) -> Self {
+        match e.as_database_error() {
+            Some(db) if db.is_unique_violation() => DatabaseError::UniqueViolation(unique_target(db)),
+            _ => DatabaseError::SqlxError(e),
+        }
+    }
+}
+
+/// SQLite reports `UNIQUE constraint failed: users.username`; PostgreSQL
+/// names the default constraint `users_username_key`.
+fn unique_target(e: &dyn sqlx::error::DatabaseError) -> String {
+    if let Some(pg) = e.try_downcast_ref::<sqlx::postgres::PgDatabaseError>() {
+        if let (Some(table), Some(constraint)) = (pg.table(), pg.constraint()) {
+            let column = constraint
+                .strip_prefix(table)
+                .and_then(|c| c.strip_prefix('_'))
+                .and_then(|c| c.strip_suffix("_key"))
+                .unwrap_or(constraint);
+            return format!("{}.{}", table, column);
+        }
+    }
+
+    e.message()
+        .strip_prefix("UNIQUE constraint failed: ")
+        .unwrap_or_else(|| e.message())
+        .to_string()
+}
+
+/// Persistence used by the API handlers. Implemented for SQLite (single
+/// instance, local development) and PostgreSQL (multi-instance deployments);
+/// both run the conformance suite in `tests::storage_tests`.
+#[async_trait]
+pub trait Storage: Send + Sync {
+    /// Brings the schema up to date. Refuses to run against a database that
+    /// was migrated by a newer binary.
+    async fn migrate(&self) -> Result<Vec<i64>, DatabaseError>;
+    async fn migration_status(&self) -> Result<Vec<MigrationStatus>, DatabaseError>;
+    /// Runs a trivial query, for readiness checks.
+    async fn ping(&self) -> Result<(), DatabaseError>;
+    fn pool_stats(&self) -> PoolStats;
+
+    // User operations
+    async fn create_user(&self, user: &User) -> Result<(), DatabaseError>;
+    async fn get_user(&self, id: Uuid) -> Result<Option<User>, DatabaseError>;
+    async fn get_user_by_email(&self, email: &str) -> Result<Option<User>, DatabaseError>;
+    async fn get_user_by_username(&self, username: &str) -> Result<Option<User>, DatabaseError>;
+    /// Users whose username or email contains `query`, ignoring case, newest
+    /// first; all users without a query.
+    async fn search_users(&self, query: Option<&str>, limit: i64) -> Result<Vec<User>, DatabaseError>;
+    async fn mark_email_verified(&self, user_id: Uuid) -> Result<(), DatabaseError>;
+    async fn set_password_hash(&self, user_id: Uuid, password_hash: &str) -> Result<(), DatabaseError>;
+    /// Schedules (or, with `None`, cancels) the deletion of the account.
+    async fn set_delete_after(&self, user_id: Uuid, delete_after: Option<DateTime<Utc>>) -> Result<(), DatabaseError>;
+    /// Accounts whose scheduled deletion is due.
+    async fn users_due_for_deletion(&self, limit: i64) -> Result<Vec<Uuid>, DatabaseError>;
+    /// Deletes the user along with their messages (sent and received), chat
+    /// memberships, devices, sessions and attachments. Returns the ids of
+    /// the deleted attachments, whose blobs are left to the caller.
+    async fn delete_user(&self, user_id: Uuid) -> Result<Vec<String>, DatabaseError>;
+
+    // Email token operations
+    /// Stores a new token, invalidating the user's earlier tokens for the
+    /// same purpose.
+    async fn create_email_token(&self, token: &EmailToken) -> Result<(), DatabaseError>;
+    /// Deletes an unexpired token and returns its user, or `None` if there
+    /// is no such token.
+    async fn consume_email_token(&self, token_hash: &str, purpose: EmailTokenPurpose) -> Result<Option<Uuid>, DatabaseError>;
+    async fn purge_email_tokens(&self) -> Result<u64, DatabaseError>;
+
+    // Two-factor operations
+    async fn get_totp_secret(&self, user_id: Uuid) -> Result<Option<TotpSecret>, DatabaseError>;
+    /// Starts (or restarts) an enrollment. Does nothing if two-factor
+    /// authentication is already enabled.
+    async fn set_pending_totp_secret(&self, user_id: Uuid, secret: &[u8]) -> Result<(), DatabaseError>;
+    /// Confirms the enrollment and replaces the user's recovery codes.
+    async fn enable_totp(&self, user_id: Uuid, step: i64, recovery_code_hashes: &[String]) -> Result<(), DatabaseError>;
+    /// Records a successful code. Returns false if that step, or a later
+    /// one, was already used.
+    async fn record_totp_step(&self, user_id: Uuid, step: i64) -> Result<bool, DatabaseError>;
+    /// Hashes of the user's recovery codes that have not been used yet.
+    async fn get_unused_recovery_codes(&self, user_id: Uuid) -> Result<Vec<String>, DatabaseError>;
+    /// Marks an unused recovery code as used. Returns false if there is none.
+    async fn use_recovery_code(&self, user_id: Uuid, code_hash: &str) -> Result<bool, DatabaseError>;
+    async fn disable_totp(&self, user_id: Uuid) -> Result<(), DatabaseError>;
+    /// Failed second-factor attempts with an MFA token, and by its user since
+    /// `since`.
+    async fn count_mfa_failures(&self, token_id: Uuid, user_id: Uuid, since: DateTime<Utc>) -> Result<(i64, i64), DatabaseError>;
+    /// Records a failed attempt and forgets the user's failures from before
+    /// `since`.
+    async fn record_mfa_failure(&self, token_id: Uuid, user_id: Uuid, since: DateTime<Utc>) -> Result<(), DatabaseError>;
+
+    // Contact operations
+    async fn get_privacy_settings(&self, user_id: Uuid) -> Result<PrivacySettings, DatabaseError>;
+    /// `email_hash` is stored only while `discoverable_by_email` is set.
+    async fn set_privacy_settings(
+        &self,
+        user_id: Uuid,
+        settings: PrivacySettings,
+        email_hash: &str,
+    ) -> Result<(), DatabaseError>;
+    /// The user with exactly this username, if they allow lookup by username.
+    async fn find_user_by_username(&self, username: &str) -> Result<Option<UserProfile>, DatabaseError>;
+    /// Users discoverable by email whose email hash is among `email_hashes`,
+    /// with the matching hash.
+    async fn find_users_by_email_hash(&self, email_hashes: &[String]) -> Result<Vec<(String, UserProfile)>, DatabaseError>;
+    /// Adds a contact, or updates the nickname of an existing one.
+    async fn add_contact(&self, user_id: Uuid, contact_id: Uuid, nickname: Option<&str>) -> Result<(), DatabaseError>;
+    /// Returns false if there was no such contact.
+    async fn remove_contact(&self, user_id: Uuid, contact_id: Uuid) -> Result<bool, DatabaseError>;
+    async fn list_contacts(&self, user_id: Uuid) -> Result<Vec<Contact>, DatabaseError>;
+
+    // Moderation operations
+    async fn set_admin(&self, user_id: Uuid, is_admin: bool) -> Result<(), DatabaseError>;
+    /// Suspends the account, keeping the original time if it already is,
+    /// or lifts the suspension.
+    async fn set_suspended(&self, user_id: Uuid, suspended: bool) -> Result<(), DatabaseError>;
+    async fn block_user(&self, user_id: Uuid, blocked_id: Uuid) -> Result<(), DatabaseError>;
+    /// Returns false if the user was not blocked.
+    async fn unblock_user(&self, user_id: Uuid, blocked_id: Uuid) -> Result<bool, DatabaseError>;
+    /// Whether `user_id` has blocked `blocked_id`.
+    async fn is_blocked(&self, user_id: Uuid, blocked_id: Uuid) -> Result<bool, DatabaseError>;
+    async fn list_blocks(&self, user_id: Uuid) -> Result<Vec<Block>, DatabaseError>;
+    async fn create_report(&self, report: &Report) -> Result<(), DatabaseError>;
+    async fn get_report(&self, id: Uuid) -> Result<Option<Report>, DatabaseError>;
+    /// Oldest first, optionally only those with the given status.
+    async fn list_reports(&self, status: Option<ReportStatus>, limit: i64) -> Result<Vec<Report>, DatabaseError>;
+    /// Reports the user filed.
+    async fn get_user_reports(&self, reporter_id: Uuid) -> Result<Vec<Report>, DatabaseError>;
+    /// Closes an open report. Returns false if it is not open.
+    async fn resolve_report(
+        &self,
+        id: Uuid,
+        status: ReportStatus,
+        resolved_by: Uuid,
+        note: Option<&str>,
+    ) -> Result<bool, DatabaseError>;
+
+    // Device operations
+    async fn create_device(&self, device: &Device) -> Result<(), DatabaseError>;
+    async fn get_user_devices(&self, user_id: Uuid) -> Result<Vec<Device>, DatabaseError>;
+    /// Deletes the device together with its sessions, prekeys and API keys.
+    /// Returns false if the user has no such device.
+    async fn delete_device(&self, user_id: Uuid, device_id: Uuid) -> Result<bool, DatabaseError>;
+
+    // Prekey operations
+    /// Replaces the device's signed prekey.
+    async fn set_signed_prekey(&self, prekey: &SignedPrekey) -> Result<(), DatabaseError>;
+    async fn get_signed_prekey(&self, device_id: Uuid) -> Result<Option<SignedPrekey>, DatabaseError>;
+    async fn add_one_time_prekeys(&self, device_id: Uuid, prekeys: &[OneTimePrekey]) -> Result<(), DatabaseError>;
+    async fn count_one_time_prekeys(&self, device_id: Uuid) -> Result<i64, DatabaseError>;
+    /// Removes and returns one of the device's one-time prekeys. Concurrent
+    /// callers never receive the same key.
+    async fn claim_one_time_prekey(&self, device_id: Uuid) -> Result<Option<OneTimePrekey>, DatabaseError>;
+
+    // Message operations
+    async fn create_message(&self, message: &Message) -> Result<(), DatabaseError>;
+    async fn get_messages(&self, user_id: Uuid, limit: i64) -> Result<Vec<Message>, DatabaseError>;
+    async fn get_message(&self, id: Uuid) -> Result<Option<Message>, DatabaseError>;
+    /// Messages held for their recipients, across all users.
+    async fn count_messages(&self) -> Result<i64, DatabaseError>;
+    /// The same, with the `top` recipients holding the most.
+    async fn queue_stats(&self, top: i64) -> Result<QueueStats, DatabaseError>;
+    /// Deletes the message together with edits and reactions that refer to
+    /// it. Returns false if there was no such message.
+    async fn delete_message(&self, id: Uuid) -> Result<bool, DatabaseError>;
+    /// Every message the user sent or received, without content.
+    async fn get_message_metadata(&self, user_id: Uuid) -> Result<Vec<MessageMetadata>, DatabaseError>;
+
+    // Chat operations
+    async fn get_chat_memberships(&self, user_id: Uuid) -> Result<Vec<ChatMember>, DatabaseError>;
+    async fn create_chat(&self, chat: &Chat, members: &[ChatMember]) -> Result<(), DatabaseError>;
+    async fn get_chat(&self, id: Uuid) -> Result<Option<Chat>, DatabaseError>;
+    /// Chats the user belongs to, most recently active first.
+    async fn get_user_chats(&self, user_id: Uuid) -> Result<Vec<Chat>, DatabaseError>;
+    /// The one-to-one chat between the two users, if there is one.
+    async fn find_direct_chat(&self, user_id: Uuid, other_id: Uuid) -> Result<Option<Chat>, DatabaseError>;
+    async fn get_chat_members(&self, chat_id: Uuid) -> Result<Vec<ChatMember>, DatabaseError>;
+    async fn get_chat_member(&self, chat_id: Uuid, user_id: Uuid) -> Result<Option<ChatMember>, DatabaseError>;
+    /// Returns false if the user already is a member.
+    async fn add_chat_member(&self, member: &ChatMember) -> Result<bool, DatabaseError>;
+    /// Returns false if the user is not a member.
+    async fn set_chat_member_role(&self, chat_id: Uuid, user_id: Uuid, role: ChatRole) -> Result<bool, DatabaseError>;
+    /// Returns false if the user was not a member. Deletes the chat when its
+    /// last member leaves.
+    async fn remove_chat_member(&self, chat_id: Uuid, user_id: Uuid) -> Result<bool, DatabaseError>;
+    async fn count_chat_members(&self, chat_id: Uuid) -> Result<i64, DatabaseError>;
+    /// Members with the given role, for chats too large to list whole.
+    async fn get_chat_members_with_role(&self, chat_id: Uuid, role: ChatRole) -> Result<Vec<ChatMember>, DatabaseError>;
+    /// The members among `user_ids`, e.g. those online, for the same reason.
+    async fn get_chat_members_among(&self, chat_id: Uuid, user_ids: &[Uuid]) -> Result<Vec<ChatMember>, DatabaseError>;
+    /// Returns false if there is no such chat.
+    async fn update_chat_settings(&self, chat_id: Uuid, settings: &ChatSettings) -> Result<bool, DatabaseError>;
+    /// Returns false if the user is not a member.
+    async fn set_chat_muted(&self, chat_id: Uuid, user_id: Uuid, until: Option<DateTime<Utc>>) -> Result<bool, DatabaseError>;
+
+    // Channel operations
+    /// Stores the post and marks the channel as active.
+    async fn create_channel_post(&self, post: &ChannelPost) -> Result<(), DatabaseError>;
+    /// The channel's posts, newest first. With `before`, only those older
+    /// than that post.
+    async fn get_channel_posts(&self, chat_id: Uuid, before: Option<Uuid>, limit: i64) -> Result<Vec<ChannelPost>, DatabaseError>;
+
+    // Invite operations
+    async fn create_invite(&self, invite: &ChatInvite) -> Result<(), DatabaseError>;
+    async fn get_invite(&self, id: Uuid) -> Result<Option<ChatInvite>, DatabaseError>;
+    async fn get_invite_by_code(&self, code: &str) -> Result<Option<ChatInvite>, DatabaseError>;
+    /// The chat's invites, newest first, including used up and revoked ones.
+    async fn get_chat_invites(&self, chat_id: Uuid) -> Result<Vec<ChatInvite>, DatabaseError>;
+    /// Returns false if the invite was already revoked.
+    async fn revoke_invite(&self, id: Uuid, at: DateTime<Utc>) -> Result<bool, DatabaseError>;
+    /// Counts a use of the invite if it is still usable at `now`, and
+    /// returns whether it was. Concurrent callers never exceed `max_uses`.
+    async fn redeem_invite(&self, id: Uuid, now: DateTime<Utc>) -> Result<bool, DatabaseError>;
+    /// Returns false if the user already asked to join the chat.
+    async fn create_join_request(&self, request: &JoinRequest) -> Result<bool, DatabaseError>;
+    async fn get_join_requests(&self, chat_id: Uuid) -> Result<Vec<JoinRequest>, DatabaseError>;
+    /// Removes and returns the user's request to join the chat.
+    async fn take_join_request(&self, chat_id: Uuid, user_id: Uuid) -> Result<Option<JoinRequest>, DatabaseError>;
+
+    // Thread operations
+    /// The thread's messages addressed to the user, newest first. With
+    /// `before`, only those older than that message.
+    async fn get_thread_messages(
+        &self,
+        user_id: Uuid,
+        chat_id: Uuid,
+        thread_id: Uuid,
+        before: Option<Uuid>,
+        limit: i64,
+    ) -> Result<Vec<Message>, DatabaseError>;
+    /// Threads of the chat with messages addressed to the user, most
+    /// recently active first.
+    async fn get_thread_summaries(&self, user_id: Uuid, chat_id: Uuid) -> Result<Vec<ThreadSummary>, DatabaseError>;
+    /// Moves the user's read marker for the thread forward to `at`, never
+    /// back.
+    async fn mark_thread_read(&self, user_id: Uuid, chat_id: Uuid, thread_id: Uuid, at: DateTime<Utc>) -> Result<(), DatabaseError>;
+
+    // Pin operations
+    async fn create_pin(&self, pin: &ChatPin) -> Result<(), DatabaseError>;
+    /// The chat's pins, oldest first.
+    async fn get_chat_pins(&self, chat_id: Uuid) -> Result<Vec<ChatPin>, DatabaseError>;
+    /// Returns false if the chat has no such pin.
+    async fn delete_pin(&self, chat_id: Uuid, id: Uuid) -> Result<bool, DatabaseError>;
+
+    // Star operations
+    /// Returns false if the user already starred the message.
+    async fn star_message(&self, user_id: Uuid, message_id: Uuid, at: DateTime<Utc>) -> Result<bool, DatabaseError>;
+    /// Returns false if the message was not starred.
+    async fn unstar_message(&self, user_id: Uuid, message_id: Uuid) -> Result<bool, DatabaseError>;
+    /// Most recently starred first.
+    async fn get_starred_messages(&self, user_id: Uuid) -> Result<Vec<StarredMessage>, DatabaseError>;
+
+    // Presence operations
+    /// Users who may be told when this user comes online or goes offline:
+    /// those who have them as a contact or share a chat with them.
+    async fn get_presence_subscribers(&self, user_id: Uuid) -> Result<Vec<Uuid>, DatabaseError>;
+    /// Whether `subscriber_id` is one of `get_presence_subscribers(user_id)`.
+    async fn is_presence_subscriber(&self, user_id: Uuid, subscriber_id: Uuid) -> Result<bool, DatabaseError>;
+    /// Stores when the user and device were last connected.
+    async fn record_last_seen(&self, user_id: Uuid, device_id: Uuid, at: DateTime<Utc>) -> Result<(), DatabaseError>;
+
+    // Session operations
+    async fn create_session(&self, session: &Session) -> Result<(), DatabaseError>;
+    /// The session if it is neither expired nor revoked.
+    async fn get_active_session(&self, id: Uuid) -> Result<Option<Session>, DatabaseError>;
+    /// The session whose current or previous refresh token has this hash, in
+    /// any state.
+    async fn find_session_by_refresh_token(&self, hash: &str) -> Result<Option<Session>, DatabaseError>;
+    /// Replaces the refresh token of an active session, provided it is still
+    /// `expected_hash`. Returns false if it was rotated concurrently.
+    async fn rotate_refresh_token(
+        &self,
+        id: Uuid,
+        expected_hash: &str,
+        new_hash: &str,
+        expires_at: DateTime<Utc>,
+    ) -> Result<bool, DatabaseError>;
+    async fn list_sessions(&self, user_id: Uuid) -> Result<Vec<Session>, DatabaseError>;
+    /// Returns false if the session was not active.
+    async fn revoke_session(&self, id: Uuid) -> Result<bool, DatabaseError>;
+    /// Revokes all of the user's active sessions except `except`, returning
+    /// the ids of those revoked.
+    async fn revoke_user_sessions(&self, user_id: Uuid, except: Option<Uuid>) -> Result<Vec<Uuid>, DatabaseError>;
+    /// Deletes expired and revoked sessions.
+    async fn purge_sessions(&self) -> Result<u64, DatabaseError>;
+
+    // Audit log operations
+    /// Fails with `UniqueViolation` if an entry with the same id was added
+    /// first.
+    async fn append_audit_event(&self, event: &AuditEvent) -> Result<(), DatabaseError>;
+    async fn last_audit_event(&self) -> Result<Option<AuditEvent>, DatabaseError>;
+    /// Matching entries, newest first.
+    async fn list_audit_events(&self, query: &AuditQuery) -> Result<Vec<AuditEvent>, DatabaseError>;
+    /// Entries following `after` in the chain, oldest first.
+    async fn audit_events_after(&self, after: i64, limit: i64) -> Result<Vec<AuditEvent>, DatabaseError>;
+
+    // Signing key operations
+    async fn create_signing_key(&self, key: &SigningKey) -> Result<(), DatabaseError>;
+    /// Every key, in order of activation.
+    async fn get_signing_keys(&self) -> Result<Vec<SigningKey>, DatabaseError>;
+    /// Returns false if there was no such key.
+    async fn delete_signing_key(&self, id: &str) -> Result<bool, DatabaseError>;
+
+    // Bot operations
+    async fn get_user_bots(&self, owner_id: Uuid) -> Result<Vec<User>, DatabaseError>;
+    async fn create_api_key(&self, key: &ApiKey) -> Result<(), DatabaseError>;
+    /// The unrevoked key with this hash.
+    async fn get_api_key_by_hash(&self, key_hash: &str) -> Result<Option<ApiKey>, DatabaseError>;
+    /// The bot's keys, newest first, including revoked ones.
+    async fn get_bot_api_keys(&self, bot_id: Uuid) -> Result<Vec<ApiKey>, DatabaseError>;
+    /// Returns false if the bot has no such unrevoked key.
+    async fn revoke_api_key(&self, bot_id: Uuid, id: Uuid, at: DateTime<Utc>) -> Result<bool, DatabaseError>;
+    /// Sets or replaces the bot's webhook.
+    async fn set_bot_webhook(&self, webhook: &BotWebhook) -> Result<(), DatabaseError>;
+    async fn get_bot_webhook(&self, bot_id: Uuid) -> Result<Option<BotWebhook>, DatabaseError>;
+    /// Returns false if the bot had no webhook.
+    async fn delete_bot_webhook(&self, bot_id: Uuid) -> Result<bool, DatabaseError>;
+
+    // Attachment operations
+    /// Returns false, inserting nothing, if the attachment would take the
+    /// uploader's unexpired attachments past `quota` bytes. The check and the
+    /// insert are atomic, so concurrent uploads cannot overshoot the quota.
+    async fn create_attachment(&self, attachment: &Attachment, quota: i64) -> Result<bool, DatabaseError>;
+    async fn get_attachment(&self, id: &str) -> Result<Option<Attachment>, DatabaseError>;
+    async fn get_user_attachments(&self, uploader_id: Uuid) -> Result<Vec<Attachment>, DatabaseError>;
+    /// Moves upload progress from `expected` to `received` bytes. Returns
+    /// false if another upload already moved it.
+    async fn record_attachment_progress(
+        &self,
+        id: &str,
+        expected: i64,
+        received: i64,
+        completed_at: Option<DateTime<Utc>>,
+    ) -> Result<bool, DatabaseError>;
+    /// Total declared size of the user's attachments that have not expired.
+    async fn attachment_usage(&self, uploader_id: Uuid) -> Result<i64, DatabaseError>;
+    /// References attachments from a message. The first reference replaces
+    /// the upload expiry with `expires_at`; later ones can only extend it.
+    async fn link_attachments(
+        &self,
+        message_id: Uuid,
+        attachment_ids: &[String],
+        expires_at: DateTime<Utc>,
+    ) -> Result<(), DatabaseError>;
+    /// Whether the user uploaded the attachment or received a message
+    /// referencing it.
+    async fn can_access_attachment(&self, id: &str, user_id: Uuid) -> Result<bool, DatabaseError>;
+    async fn expired_attachments(&self, limit: i64) -> Result<Vec<String>, DatabaseError>;
+    async fn delete_attachment(&self, id: &str) -> Result<(), DatabaseError>;
+}
+
+/// Connections held by the pool.
+#[derive(Debug, Clone, Copy)]
+pub struct PoolStats {
+    pub size: u32,
+    pub idle: usize,
+}
+
+/// Messages held for their recipients.
+#[derive(Debug, Clone, Serialize)]
+pub struct QueueStats {
+    pub messages: i64,
+    /// When the longest-waiting message was sent.
+    pub oldest_at: Option<DateTime<Utc>>,
+    /// Recipients with the most messages waiting, most first.
+    pub recipients: Vec<RecipientQueue>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct RecipientQueue {
+    pub user_id: Uuid,
+    pub messages: i64,
+    pub oldest_at: DateTime<Utc>,
+}
+
+/// Which audit log entries to list. Unset filters match everything.
+#[derive(Debug, Clone)]
+pub struct AuditQuery {
+    pub user_id: Option<Uuid>,
+    pub since: Option<DateTime<Utc>>,
+    pub until: Option<DateTime<Utc>>,
+    /// Only entries older than this one, for paging.
+    pub before: Option<i64>,
+    pub limit: i64,
+}
+
+pub type Database = Arc<dyn Storage>;
+
+/// Escapes `%`, `_` and `\` for use in a LIKE pattern with `\` as the
+/// escape character.
+fn escape_like(s: &str) -> String {
+    s.replace('\\', "\\\\").replace('%', "\\%").replace('_', "\\_")
+}
+
+/// Opens the storage backend selected by the scheme of `database_url`:
+/// `sqlite:` or `postgres://` / `postgresql://`.
+pub async fn connect(database_url: &str) -> Result<Database, DatabaseError> {
+    let scheme = database_url.split(':').next().unwrap_or_default();
+    match scheme {
+        "sqlite" => Ok(Arc::new(SqliteStorage::new(database_url).await?)),
+        "postgres" | "postgresql" => Ok(Arc::new(PostgresStorage::new(database_url).await?)),
+        _ => Err(DatabaseError::InvalidData(format!(
+            "Unsupported database URL scheme: {}",
+            scheme
+        ))),
+    }
+}
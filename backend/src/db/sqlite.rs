@@ -0,0 +1,2725 @@
+use std::str::FromStr;
+
+use async_trait::async_trait;
+use sqlx::{
+    sqlite::{SqliteConnectOptions, SqlitePool, SqlitePoolOptions},
+    Executor, Row,
+};
+use uuid::Uuid;
+use chrono::{DateTime, Utc};
+use crate::models::{User, Message, MessageKind, MessageMetadata, Device, Chat, ChatMember, ChatRole, Session, Attachment, SignedPrekey, OneTimePrekey, TotpSecret, EmailToken, EmailTokenPurpose, UserProfile, PrivacySettings, Contact, Block, Report, ReportCategory, ReportStatus, ThreadSummary, ChatInvite, JoinRequest, ChannelPost, ChatSettings, ChatPin, StarredMessage, ApiKey, BotWebhook, SigningKey, SigningAlgorithm, AuditEvent, AuditKind};
+use super::{
+    migrations::{self, AppliedMigration, MigrationStatus},
+    escape_like, AuditQuery, DatabaseError, PoolStats, QueueStats, RecipientQueue, Storage,
+};
+
+pub struct SqliteStorage {
+    pool: SqlitePool,
+}
+
+impl SqliteStorage {
+    pub async fn new(database_url: &str) -> Result<Self, DatabaseError> {
+        let options = SqliteConnectOptions::from_str(database_url)?.create_if_missing(true);
+        // Every connection to an in-memory database gets its own empty
+        // database, so those have to stay on a single connection.
+        let max_connections = if database_url.contains(":memory:") { 1 } else { 10 };
+        let pool = SqlitePoolOptions::new()
+            .max_connections(max_connections)
+            .connect_with(options)
+            .await?;
+        Ok(Self { pool })
+    }
+
+    async fn applied_migrations(&self) -> Result<Vec<AppliedMigration>, DatabaseError> {
+        sqlx::query(
+            r#"
+            CREATE TABLE IF NOT EXISTS schema_migrations (
+                version INTEGER PRIMARY KEY,
+                name TEXT NOT NULL,
+                checksum TEXT NOT NULL,
+                applied_at TEXT NOT NULL
+            )
+            "#,
+        )
+        .execute(&self.pool)
+        .await?;
+
+        let rows = sqlx::query(
+            r#"
+            SELECT version, checksum, applied_at FROM schema_migrations ORDER BY version
+            "#,
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(rows
+            .into_iter()
+            .map(|r| AppliedMigration {
+                version: r.get("version"),
+                checksum: r.get("checksum"),
+                applied_at: parse_time(r.get("applied_at")),
+            })
+            .collect())
+    }
+}
+
+fn parse_time(s: &str) -> DateTime<Utc> {
+    DateTime::parse_from_rfc3339(s).unwrap().with_timezone(&Utc)
+}
+
+fn attachment_from_row(r: &sqlx::sqlite::SqliteRow) -> Attachment {
+    Attachment {
+        id: r.get("id"),
+        uploader_id: Uuid::parse_str(r.get("uploader_id")).unwrap(),
+        size: r.get("size"),
+        received: r.get("received"),
+        created_at: parse_time(r.get("created_at")),
+        completed_at: r.get::<Option<String>, _>("completed_at")
+            .map(|s| parse_time(&s)),
+        expires_at: parse_time(r.get("expires_at")),
+    }
+}
+
+fn user_from_row(r: &sqlx::sqlite::SqliteRow) -> User {
+    User {
+        id: Uuid::parse_str(r.get("id")).unwrap(),
+        username: r.get("username"),
+        email: r.get("email"),
+        public_key: r.get("public_key"),
+        created_at: parse_time(r.get("created_at")),
+        last_seen: parse_time(r.get("last_seen")),
+        password_hash: r.get("password_hash"),
+        email_verified_at: r.get::<Option<String>, _>("email_verified_at")
+            .map(|s| parse_time(&s)),
+        delete_after: r.get::<Option<String>, _>("delete_after")
+            .map(|s| parse_time(&s)),
+        is_admin: r.get("is_admin"),
+        suspended_at: r.get::<Option<String>, _>("suspended_at")
+            .map(|s| parse_time(&s)),
+        bot_owner_id: r.get::<Option<String>, _>("bot_owner_id")
+            .map(|s| Uuid::parse_str(&s).unwrap()),
+    }
+}
+
+fn profile_from_row(r: &sqlx::sqlite::SqliteRow) -> UserProfile {
+    UserProfile {
+        id: Uuid::parse_str(r.get("id")).unwrap(),
+        username: r.get("username"),
+        public_key: r.get("public_key"),
+        is_bot: r.get::<Option<String>, _>("bot_owner_id").is_some(),
+    }
+}
+
+fn message_from_row(r: &sqlx::sqlite::SqliteRow) -> Message {
+    let uuid = |column: &str| r.get::<Option<String>, _>(column).map(|s| Uuid::parse_str(&s).unwrap());
+    Message {
+        id: Uuid::parse_str(r.get("id")).unwrap(),
+        sender_id: Uuid::parse_str(r.get("sender_id")).unwrap(),
+        recipient_id: Uuid::parse_str(r.get("recipient_id")).unwrap(),
+        content: r.get("content"),
+        associated_data: r.get("associated_data"),
+        created_at: parse_time(r.get("created_at")),
+        expires_at: r.get::<Option<String>, _>("expires_at")
+            .map(|s| parse_time(&s)),
+        kind: MessageKind::parse(r.get("kind")).unwrap(),
+        target_id: uuid("target_id"),
+        chat_id: uuid("chat_id"),
+        thread_id: uuid("thread_id"),
+    }
+}
+
+fn report_from_row(r: &sqlx::sqlite::SqliteRow) -> Report {
+    let uuid = |column: &str| r.get::<Option<String>, _>(column).map(|s| Uuid::parse_str(&s).unwrap());
+    Report {
+        id: Uuid::parse_str(r.get("id")).unwrap(),
+        reporter_id: uuid("reporter_id"),
+        reported_id: uuid("reported_id"),
+        message_id: uuid("message_id"),
+        category: ReportCategory::parse(r.get("category")).unwrap_or(ReportCategory::Other),
+        description: r.get("description"),
+        evidence: r.get("evidence"),
+        status: ReportStatus::parse(r.get("status")).unwrap_or(ReportStatus::Open),
+        created_at: parse_time(r.get("created_at")),
+        resolved_at: r.get::<Option<String>, _>("resolved_at")
+            .map(|s| parse_time(&s)),
+        resolved_by: uuid("resolved_by"),
+        resolution_note: r.get("resolution_note"),
+    }
+}
+
+fn chat_from_row(r: &sqlx::sqlite::SqliteRow) -> Chat {
+    Chat {
+        id: Uuid::parse_str(r.get("id")).unwrap(),
+        name: r.get("name"),
+        is_group: r.get("is_group"),
+        is_channel: r.get("is_channel"),
+        settings: ChatSettings {
+            topic: r.get("topic"),
+            avatar: r.get("avatar"),
+            disappear_after_seconds: r.get("disappear_after_seconds"),
+        },
+        created_at: parse_time(r.get("created_at")),
+        last_message_at: parse_time(r.get("last_message_at")),
+    }
+}
+
+fn member_from_row(r: &sqlx::sqlite::SqliteRow) -> ChatMember {
+    ChatMember {
+        chat_id: Uuid::parse_str(r.get("chat_id")).unwrap(),
+        user_id: Uuid::parse_str(r.get("user_id")).unwrap(),
+        role: ChatRole::parse(r.get("role")).unwrap(),
+        joined_at: parse_time(r.get("joined_at")),
+        muted_until: r.get::<Option<String>, _>("muted_until").map(|s| parse_time(&s)),
+    }
+}
+
+fn invite_from_row(r: &sqlx::sqlite::SqliteRow) -> ChatInvite {
+    let time = |column: &str| r.get::<Option<String>, _>(column).map(|s| parse_time(&s));
+    ChatInvite {
+        id: Uuid::parse_str(r.get("id")).unwrap(),
+        chat_id: Uuid::parse_str(r.get("chat_id")).unwrap(),
+        code: r.get("code"),
+        created_by: Uuid::parse_str(r.get("created_by")).unwrap(),
+        created_at: parse_time(r.get("created_at")),
+        expires_at: time("expires_at"),
+        max_uses: r.get("max_uses"),
+        use_count: r.get("use_count"),
+        requires_approval: r.get("requires_approval"),
+        revoked_at: time("revoked_at"),
+    }
+}
+
+fn join_request_from_row(r: &sqlx::sqlite::SqliteRow) -> JoinRequest {
+    JoinRequest {
+        chat_id: Uuid::parse_str(r.get("chat_id")).unwrap(),
+        user_id: Uuid::parse_str(r.get("user_id")).unwrap(),
+        invite_id: Uuid::parse_str(r.get("invite_id")).unwrap(),
+        created_at: parse_time(r.get("created_at")),
+    }
+}
+
+fn api_key_from_row(r: &sqlx::sqlite::SqliteRow) -> ApiKey {
+    ApiKey {
+        id: Uuid::parse_str(r.get("id")).unwrap(),
+        bot_id: Uuid::parse_str(r.get("bot_id")).unwrap(),
+        device_id: Uuid::parse_str(r.get("device_id")).unwrap(),
+        key_hash: r.get("key_hash"),
+        created_at: parse_time(r.get("created_at")),
+        revoked_at: r.get::<Option<String>, _>("revoked_at").map(|s| parse_time(&s)),
+    }
+}
+
+fn audit_event_from_row(r: &sqlx::sqlite::SqliteRow) -> Result<AuditEvent, DatabaseError> {
+    let uuid = |column: &str| r.get::<Option<String>, _>(column).map(|s| Uuid::parse_str(&s).unwrap());
+    let kind: String = r.get("kind");
+    let details: String = r.get("details");
+    Ok(AuditEvent {
+        id: r.get("id"),
+        kind: AuditKind::parse(&kind)
+            .ok_or_else(|| DatabaseError::InvalidData(format!("Unknown audit event kind: {}", kind)))?,
+        user_id: uuid("user_id"),
+        actor_id: uuid("actor_id"),
+        device_id: uuid("device_id"),
+        details: serde_json::from_str(&details).map_err(|e| DatabaseError::InvalidData(e.to_string()))?,
+        created_at: parse_time(r.get("created_at")),
+        prev_hash: r.get("prev_hash"),
+        hash: r.get("hash"),
+    })
+}
+
+fn session_from_row(r: &sqlx::sqlite::SqliteRow) -> Session {
+    Session {
+        id: Uuid::parse_str(r.get("id")).unwrap(),
+        user_id: Uuid::parse_str(r.get("user_id")).unwrap(),
+        device_id: Uuid::parse_str(r.get("device_id")).unwrap(),
+        refresh_token_hash: r.get("refresh_token_hash"),
+        previous_refresh_token_hash: r.get("previous_refresh_token_hash"),
+        created_at: parse_time(r.get("created_at")),
+        last_used_at: r.get::<Option<String>, _>("last_used_at")
+            .map(|s| parse_time(&s)),
+        expires_at: parse_time(r.get("expires_at")),
+        revoked_at: r.get::<Option<String>, _>("revoked_at")
+            .map(|s| parse_time(&s)),
+    }
+}
+
+#[async_trait]
+impl Storage for SqliteStorage {
+    async fn migrate(&self) -> Result<Vec<i64>, DatabaseError> {
+        let applied = self.applied_migrations().await?;
+        let mut newly_applied = Vec::new();
+
+        for migration in migrations::pending(migrations::SQLITE, &applied)? {
+            let mut tx = self.pool.begin().await?;
+            // Without bind arguments the whole multi-statement script is sent as-is.
+            tx.execute(migration.sql).await?;
+            sqlx::query(
+                r#"
+                INSERT INTO schema_migrations (version, name, checksum, applied_at)
+                VALUES (?, ?, ?, ?)
+                "#,
+            )
+            .bind(migration.version)
+            .bind(migration.name)
+            .bind(migration.checksum())
+            .bind(Utc::now().to_rfc3339())
+            .execute(&mut *tx)
+            .await?;
+            tx.commit().await?;
+
+            tracing::info!("Applied migration {} ({})", migration.version, migration.name);
+            newly_applied.push(migration.version);
+        }
+
+        Ok(newly_applied)
+    }
+
+    async fn migration_status(&self) -> Result<Vec<MigrationStatus>, DatabaseError> {
+        let applied = self.applied_migrations().await?;
+        Ok(migrations::status(migrations::SQLITE, &applied)?)
+    }
+
+    async fn ping(&self) -> Result<(), DatabaseError> {
+        sqlx::query("SELECT 1").execute(&self.pool).await?;
+        Ok(())
+    }
+
+    fn pool_stats(&self) -> PoolStats {
+        PoolStats {
+            size: self.pool.size(),
+            idle: self.pool.num_idle(),
+        }
+    }
+
+    // User operations
+    async fn create_user(&self, user: &User) -> Result<(), DatabaseError> {
+        sqlx::query(
+            r#"
+            INSERT INTO users (id, username, email, public_key, created_at, last_seen, password_hash, email_verified_at, delete_after, is_admin, suspended_at, bot_owner_id)
+            VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)
+            "#,
+        )
+        .bind(user.id.to_string())
+        .bind(&user.username)
+        .bind(&user.email)
+        .bind(&user.public_key)
+        .bind(user.created_at.to_rfc3339())
+        .bind(user.last_seen.to_rfc3339())
+        .bind(&user.password_hash)
+        .bind(user.email_verified_at.map(|t| t.to_rfc3339()))
+        .bind(user.delete_after.map(|t| t.to_rfc3339()))
+        .bind(user.is_admin)
+        .bind(user.suspended_at.map(|t| t.to_rfc3339()))
+        .bind(user.bot_owner_id.map(|id| id.to_string()))
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    async fn get_user(&self, id: Uuid) -> Result<Option<User>, DatabaseError> {
+        let row = sqlx::query(
+            r#"
+            SELECT * FROM users WHERE id = ?
+            "#,
+        )
+        .bind(id.to_string())
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(row.as_ref().map(user_from_row))
+    }
+
+    async fn get_user_by_email(&self, email: &str) -> Result<Option<User>, DatabaseError> {
+        let row = sqlx::query(
+            r#"
+            SELECT * FROM users WHERE email = ?
+            "#,
+        )
+        .bind(email)
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(row.as_ref().map(user_from_row))
+    }
+
+    async fn get_user_by_username(&self, username: &str) -> Result<Option<User>, DatabaseError> {
+        let row = sqlx::query(
+            r#"
+            SELECT * FROM users WHERE username = ?
+            "#,
+        )
+        .bind(username)
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(row.as_ref().map(user_from_row))
+    }
+
+    async fn search_users(&self, query: Option<&str>, limit: i64) -> Result<Vec<User>, DatabaseError> {
+        // LIKE ignores ASCII case in SQLite.
+        let pattern = format!("%{}%", escape_like(query.unwrap_or_default()));
+        let rows = sqlx::query(
+            r#"
+            SELECT * FROM users
+            WHERE username LIKE ? ESCAPE '\' OR email LIKE ? ESCAPE '\'
+            ORDER BY created_at DESC
+            LIMIT ?
+            "#,
+        )
+        .bind(&pattern)
+        .bind(&pattern)
+        .bind(limit)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(rows.iter().map(user_from_row).collect())
+    }
+
+    async fn mark_email_verified(&self, user_id: Uuid) -> Result<(), DatabaseError> {
+        sqlx::query(
+            r#"
+            UPDATE users SET email_verified_at = ?
+            WHERE id = ? AND email_verified_at IS NULL
+            "#,
+        )
+        .bind(Utc::now().to_rfc3339())
+        .bind(user_id.to_string())
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    async fn set_password_hash(&self, user_id: Uuid, password_hash: &str) -> Result<(), DatabaseError> {
+        sqlx::query(
+            r#"
+            UPDATE users SET password_hash = ? WHERE id = ?
+            "#,
+        )
+        .bind(password_hash)
+        .bind(user_id.to_string())
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    async fn set_delete_after(&self, user_id: Uuid, delete_after: Option<DateTime<Utc>>) -> Result<(), DatabaseError> {
+        sqlx::query(
+            r#"
+            UPDATE users SET delete_after = ? WHERE id = ?
+            "#,
+        )
+        .bind(delete_after.map(|t| t.to_rfc3339()))
+        .bind(user_id.to_string())
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    async fn users_due_for_deletion(&self, limit: i64) -> Result<Vec<Uuid>, DatabaseError> {
+        let rows = sqlx::query(
+            r#"
+            SELECT id FROM users WHERE delete_after <= ? LIMIT ?
+            "#,
+        )
+        .bind(Utc::now().to_rfc3339())
+        .bind(limit)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(rows.iter().map(|r| Uuid::parse_str(r.get("id")).unwrap()).collect())
+    }
+
+    async fn delete_user(&self, user_id: Uuid) -> Result<Vec<String>, DatabaseError> {
+        let mut tx = self.pool.begin().await?;
+
+        sqlx::query(
+            r#"
+            DELETE FROM message_attachments
+            WHERE message_id IN (SELECT id FROM messages WHERE sender_id = ? OR recipient_id = ?)
+            "#,
+        )
+        .bind(user_id.to_string())
+        .bind(user_id.to_string())
+        .execute(&mut *tx)
+        .await?;
+
+        sqlx::query(
+            r#"
+            DELETE FROM messages WHERE sender_id = ? OR recipient_id = ?
+            "#,
+        )
+        .bind(user_id.to_string())
+        .bind(user_id.to_string())
+        .execute(&mut *tx)
+        .await?;
+
+        // Deleting an attachment also drops its links from other users'
+        // messages.
+        let attachment_ids: Vec<String> = sqlx::query(
+            r#"
+            DELETE FROM attachments WHERE uploader_id = ? RETURNING id
+            "#,
+        )
+        .bind(user_id.to_string())
+        .fetch_all(&mut *tx)
+        .await?
+        .iter()
+        .map(|r| r.get("id"))
+        .collect();
+
+        sqlx::query(
+            r#"
+            DELETE FROM chat_members WHERE user_id = ?
+            "#,
+        )
+        .bind(user_id.to_string())
+        .execute(&mut *tx)
+        .await?;
+
+        sqlx::query(
+            r#"
+            DELETE FROM sessions WHERE user_id = ?
+            "#,
+        )
+        .bind(user_id.to_string())
+        .execute(&mut *tx)
+        .await?;
+
+        sqlx::query(
+            r#"
+            DELETE FROM devices WHERE user_id = ?
+            "#,
+        )
+        .bind(user_id.to_string())
+        .execute(&mut *tx)
+        .await?;
+
+        sqlx::query(
+            r#"
+            DELETE FROM users WHERE id = ?
+            "#,
+        )
+        .bind(user_id.to_string())
+        .execute(&mut *tx)
+        .await?;
+
+        tx.commit().await?;
+        Ok(attachment_ids)
+    }
+
+    // Email token operations
+    async fn create_email_token(&self, token: &EmailToken) -> Result<(), DatabaseError> {
+        let mut tx = self.pool.begin().await?;
+
+        sqlx::query(
+            r#"
+            DELETE FROM email_tokens WHERE user_id = ? AND purpose = ?
+            "#,
+        )
+        .bind(token.user_id.to_string())
+        .bind(token.purpose.as_str())
+        .execute(&mut *tx)
+        .await?;
+
+        sqlx::query(
+            r#"
+            INSERT INTO email_tokens (token_hash, user_id, purpose, created_at, expires_at)
+            VALUES (?, ?, ?, ?, ?)
+            "#,
+        )
+        .bind(&token.token_hash)
+        .bind(token.user_id.to_string())
+        .bind(token.purpose.as_str())
+        .bind(token.created_at.to_rfc3339())
+        .bind(token.expires_at.to_rfc3339())
+        .execute(&mut *tx)
+        .await?;
+
+        tx.commit().await?;
+        Ok(())
+    }
+
+    async fn consume_email_token(&self, token_hash: &str, purpose: EmailTokenPurpose) -> Result<Option<Uuid>, DatabaseError> {
+        let row = sqlx::query(
+            r#"
+            DELETE FROM email_tokens
+            WHERE token_hash = ? AND purpose = ? AND expires_at > ?
+            RETURNING user_id
+            "#,
+        )
+        .bind(token_hash)
+        .bind(purpose.as_str())
+        .bind(Utc::now().to_rfc3339())
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(row.map(|r| Uuid::parse_str(r.get("user_id")).unwrap()))
+    }
+
+    async fn purge_email_tokens(&self) -> Result<u64, DatabaseError> {
+        let result = sqlx::query(
+            r#"
+            DELETE FROM email_tokens WHERE expires_at <= ?
+            "#,
+        )
+        .bind(Utc::now().to_rfc3339())
+        .execute(&self.pool)
+        .await?;
+
+        Ok(result.rows_affected())
+    }
+
+    // Two-factor operations
+    async fn get_totp_secret(&self, user_id: Uuid) -> Result<Option<TotpSecret>, DatabaseError> {
+        let row = sqlx::query(
+            r#"
+            SELECT * FROM totp_secrets WHERE user_id = ?
+            "#,
+        )
+        .bind(user_id.to_string())
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(row.map(|r| TotpSecret {
+            user_id: Uuid::parse_str(r.get("user_id")).unwrap(),
+            secret: r.get("secret"),
+            created_at: parse_time(r.get("created_at")),
+            enabled_at: r.get::<Option<String>, _>("enabled_at")
+                .map(|s| parse_time(&s)),
+            last_used_step: r.get("last_used_step"),
+        }))
+    }
+
+    async fn set_pending_totp_secret(&self, user_id: Uuid, secret: &[u8]) -> Result<(), DatabaseError> {
+        sqlx::query(
+            r#"
+            INSERT INTO totp_secrets (user_id, secret, created_at)
+            VALUES (?, ?, ?)
+            ON CONFLICT (user_id) DO UPDATE SET
+                secret = excluded.secret,
+                created_at = excluded.created_at
+            WHERE totp_secrets.enabled_at IS NULL
+            "#,
+        )
+        .bind(user_id.to_string())
+        .bind(secret)
+        .bind(Utc::now().to_rfc3339())
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    async fn enable_totp(&self, user_id: Uuid, step: i64, recovery_code_hashes: &[String]) -> Result<(), DatabaseError> {
+        let mut tx = self.pool.begin().await?;
+
+        sqlx::query(
+            r#"
+            UPDATE totp_secrets SET enabled_at = ?, last_used_step = ?
+            WHERE user_id = ?
+            "#,
+        )
+        .bind(Utc::now().to_rfc3339())
+        .bind(step)
+        .bind(user_id.to_string())
+        .execute(&mut *tx)
+        .await?;
+
+        sqlx::query(
+            r#"
+            DELETE FROM recovery_codes WHERE user_id = ?
+            "#,
+        )
+        .bind(user_id.to_string())
+        .execute(&mut *tx)
+        .await?;
+
+        for code_hash in recovery_code_hashes {
+            sqlx::query(
+                r#"
+                INSERT INTO recovery_codes (user_id, code_hash) VALUES (?, ?)
+                "#,
+            )
+            .bind(user_id.to_string())
+            .bind(code_hash)
+            .execute(&mut *tx)
+            .await?;
+        }
+
+        tx.commit().await?;
+        Ok(())
+    }
+
+    async fn record_totp_step(&self, user_id: Uuid, step: i64) -> Result<bool, DatabaseError> {
+        let result = sqlx::query(
+            r#"
+            UPDATE totp_secrets SET last_used_step = ?
+            WHERE user_id = ? AND (last_used_step IS NULL OR last_used_step < ?)
+            "#,
+        )
+        .bind(step)
+        .bind(user_id.to_string())
+        .bind(step)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(result.rows_affected() == 1)
+    }
+
+    async fn get_unused_recovery_codes(&self, user_id: Uuid) -> Result<Vec<String>, DatabaseError> {
+        let hashes = sqlx::query_scalar(
+            "SELECT code_hash FROM recovery_codes WHERE user_id = ? AND used_at IS NULL",
+        )
+        .bind(user_id.to_string())
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(hashes)
+    }
+
+    async fn use_recovery_code(&self, user_id: Uuid, code_hash: &str) -> Result<bool, DatabaseError> {
+        let result = sqlx::query(
+            r#"
+            UPDATE recovery_codes SET used_at = ?
+            WHERE user_id = ? AND code_hash = ? AND used_at IS NULL
+            "#,
+        )
+        .bind(Utc::now().to_rfc3339())
+        .bind(user_id.to_string())
+        .bind(code_hash)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(result.rows_affected() == 1)
+    }
+
+    async fn disable_totp(&self, user_id: Uuid) -> Result<(), DatabaseError> {
+        let mut tx = self.pool.begin().await?;
+
+        sqlx::query(
+            r#"
+            DELETE FROM recovery_codes WHERE user_id = ?
+            "#,
+        )
+        .bind(user_id.to_string())
+        .execute(&mut *tx)
+        .await?;
+
+        sqlx::query(
+            r#"
+            DELETE FROM totp_secrets WHERE user_id = ?
+            "#,
+        )
+        .bind(user_id.to_string())
+        .execute(&mut *tx)
+        .await?;
+
+        tx.commit().await?;
+        Ok(())
+    }
+
+    async fn count_mfa_failures(&self, token_id: Uuid, user_id: Uuid, since: DateTime<Utc>) -> Result<(i64, i64), DatabaseError> {
+        let row = sqlx::query(
+            r#"
+            SELECT (SELECT COUNT(*) FROM mfa_failures WHERE token_id = ?) AS token_failures,
+                   (SELECT COUNT(*) FROM mfa_failures WHERE user_id = ? AND failed_at > ?) AS user_failures
+            "#,
+        )
+        .bind(token_id.to_string())
+        .bind(user_id.to_string())
+        .bind(since.to_rfc3339())
+        .fetch_one(&self.pool)
+        .await?;
+
+        Ok((row.get("token_failures"), row.get("user_failures")))
+    }
+
+    async fn record_mfa_failure(&self, token_id: Uuid, user_id: Uuid, since: DateTime<Utc>) -> Result<(), DatabaseError> {
+        let mut tx = self.pool.begin().await?;
+
+        sqlx::query(
+            r#"
+            DELETE FROM mfa_failures WHERE user_id = ? AND failed_at <= ?
+            "#,
+        )
+        .bind(user_id.to_string())
+        .bind(since.to_rfc3339())
+        .execute(&mut *tx)
+        .await?;
+
+        sqlx::query(
+            r#"
+            INSERT INTO mfa_failures (token_id, user_id, failed_at) VALUES (?, ?, ?)
+            "#,
+        )
+        .bind(token_id.to_string())
+        .bind(user_id.to_string())
+        .bind(Utc::now().to_rfc3339())
+        .execute(&mut *tx)
+        .await?;
+
+        tx.commit().await?;
+        Ok(())
+    }
+
+    // Contact operations
+    async fn get_privacy_settings(&self, user_id: Uuid) -> Result<PrivacySettings, DatabaseError> {
+        let row = sqlx::query(
+            r#"
+            SELECT discoverable_by_username, email_hash IS NOT NULL AS discoverable_by_email, show_last_seen,
+                   send_read_receipts
+            FROM users WHERE id = ?
+            "#,
+        )
+        .bind(user_id.to_string())
+        .fetch_one(&self.pool)
+        .await?;
+
+        Ok(PrivacySettings {
+            discoverable_by_username: row.get("discoverable_by_username"),
+            discoverable_by_email: row.get("discoverable_by_email"),
+            show_last_seen: row.get("show_last_seen"),
+            send_read_receipts: row.get("send_read_receipts"),
+        })
+    }
+
+    async fn set_privacy_settings(
+        &self,
+        user_id: Uuid,
+        settings: PrivacySettings,
+        email_hash: &str,
+    ) -> Result<(), DatabaseError> {
+        sqlx::query(
+            r#"
+            UPDATE users
+            SET discoverable_by_username = ?, email_hash = ?, show_last_seen = ?, send_read_receipts = ?
+            WHERE id = ?
+            "#,
+        )
+        .bind(settings.discoverable_by_username)
+        .bind(settings.discoverable_by_email.then_some(email_hash))
+        .bind(settings.show_last_seen)
+        .bind(settings.send_read_receipts)
+        .bind(user_id.to_string())
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    async fn find_user_by_username(&self, username: &str) -> Result<Option<UserProfile>, DatabaseError> {
+        let row = sqlx::query(
+            r#"
+            SELECT id, username, public_key, bot_owner_id FROM users
+            WHERE username = ? AND discoverable_by_username
+            "#,
+        )
+        .bind(username)
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(row.as_ref().map(profile_from_row))
+    }
+
+    async fn find_users_by_email_hash(&self, email_hashes: &[String]) -> Result<Vec<(String, UserProfile)>, DatabaseError> {
+        if email_hashes.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        let placeholders = vec!["?"; email_hashes.len()].join(", ");
+        let sql = format!(
+            "SELECT id, username, public_key, bot_owner_id, email_hash FROM users WHERE email_hash IN ({})",
+            placeholders
+        );
+        let mut query = sqlx::query(&sql);
+        for hash in email_hashes {
+            query = query.bind(hash);
+        }
+        let rows = query.fetch_all(&self.pool).await?;
+
+        Ok(rows
+            .iter()
+            .map(|r| (r.get("email_hash"), profile_from_row(r)))
+            .collect())
+    }
+
+    async fn add_contact(&self, user_id: Uuid, contact_id: Uuid, nickname: Option<&str>) -> Result<(), DatabaseError> {
+        sqlx::query(
+            r#"
+            INSERT INTO contacts (user_id, contact_id, nickname, created_at)
+            VALUES (?, ?, ?, ?)
+            ON CONFLICT (user_id, contact_id) DO UPDATE SET nickname = excluded.nickname
+            "#,
+        )
+        .bind(user_id.to_string())
+        .bind(contact_id.to_string())
+        .bind(nickname)
+        .bind(Utc::now().to_rfc3339())
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    async fn remove_contact(&self, user_id: Uuid, contact_id: Uuid) -> Result<bool, DatabaseError> {
+        let result = sqlx::query(
+            r#"
+            DELETE FROM contacts WHERE user_id = ? AND contact_id = ?
+            "#,
+        )
+        .bind(user_id.to_string())
+        .bind(contact_id.to_string())
+        .execute(&self.pool)
+        .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn list_contacts(&self, user_id: Uuid) -> Result<Vec<Contact>, DatabaseError> {
+        let rows = sqlx::query(
+            r#"
+            SELECT u.id, u.username, u.public_key, u.bot_owner_id, c.nickname, c.created_at
+            FROM contacts c
+            JOIN users u ON u.id = c.contact_id
+            WHERE c.user_id = ?
+            ORDER BY lower(coalesce(c.nickname, u.username))
+            "#,
+        )
+        .bind(user_id.to_string())
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(rows
+            .iter()
+            .map(|r| Contact {
+                user_id,
+                contact: profile_from_row(r),
+                nickname: r.get("nickname"),
+                added_at: parse_time(r.get("created_at")),
+            })
+            .collect())
+    }
+
+    // Moderation operations
+    async fn set_admin(&self, user_id: Uuid, is_admin: bool) -> Result<(), DatabaseError> {
+        sqlx::query(
+            r#"
+            UPDATE users SET is_admin = ? WHERE id = ?
+            "#,
+        )
+        .bind(is_admin)
+        .bind(user_id.to_string())
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    async fn set_suspended(&self, user_id: Uuid, suspended: bool) -> Result<(), DatabaseError> {
+        let now = Utc::now().to_rfc3339();
+        sqlx::query(
+            r#"
+            UPDATE users SET suspended_at = CASE WHEN ? THEN coalesce(suspended_at, ?) END
+            WHERE id = ?
+            "#,
+        )
+        .bind(suspended)
+        .bind(now)
+        .bind(user_id.to_string())
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    async fn block_user(&self, user_id: Uuid, blocked_id: Uuid) -> Result<(), DatabaseError> {
+        sqlx::query(
+            r#"
+            INSERT INTO blocks (user_id, blocked_id, created_at)
+            VALUES (?, ?, ?)
+            ON CONFLICT (user_id, blocked_id) DO NOTHING
+            "#,
+        )
+        .bind(user_id.to_string())
+        .bind(blocked_id.to_string())
+        .bind(Utc::now().to_rfc3339())
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    async fn unblock_user(&self, user_id: Uuid, blocked_id: Uuid) -> Result<bool, DatabaseError> {
+        let result = sqlx::query(
+            r#"
+            DELETE FROM blocks WHERE user_id = ? AND blocked_id = ?
+            "#,
+        )
+        .bind(user_id.to_string())
+        .bind(blocked_id.to_string())
+        .execute(&self.pool)
+        .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn is_blocked(&self, user_id: Uuid, blocked_id: Uuid) -> Result<bool, DatabaseError> {
+        let row = sqlx::query(
+            r#"
+            SELECT 1 FROM blocks WHERE user_id = ? AND blocked_id = ?
+            "#,
+        )
+        .bind(user_id.to_string())
+        .bind(blocked_id.to_string())
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(row.is_some())
+    }
+
+    async fn list_blocks(&self, user_id: Uuid) -> Result<Vec<Block>, DatabaseError> {
+        let rows = sqlx::query(
+            r#"
+            SELECT u.id, u.username, u.public_key, u.bot_owner_id, b.created_at
+            FROM blocks b
+            JOIN users u ON u.id = b.blocked_id
+            WHERE b.user_id = ?
+            ORDER BY b.created_at
+            "#,
+        )
+        .bind(user_id.to_string())
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(rows
+            .iter()
+            .map(|r| Block {
+                user_id,
+                blocked: profile_from_row(r),
+                created_at: parse_time(r.get("created_at")),
+            })
+            .collect())
+    }
+
+    async fn create_report(&self, report: &Report) -> Result<(), DatabaseError> {
+        sqlx::query(
+            r#"
+            INSERT INTO reports (id, reporter_id, reported_id, message_id, category, description, evidence, status, created_at)
+            VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?)
+            "#,
+        )
+        .bind(report.id.to_string())
+        .bind(report.reporter_id.map(|id| id.to_string()))
+        .bind(report.reported_id.map(|id| id.to_string()))
+        .bind(report.message_id.map(|id| id.to_string()))
+        .bind(report.category.as_str())
+        .bind(&report.description)
+        .bind(&report.evidence)
+        .bind(report.status.as_str())
+        .bind(report.created_at.to_rfc3339())
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    async fn get_report(&self, id: Uuid) -> Result<Option<Report>, DatabaseError> {
+        let row = sqlx::query(
+            r#"
+            SELECT * FROM reports WHERE id = ?
+            "#,
+        )
+        .bind(id.to_string())
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(row.as_ref().map(report_from_row))
+    }
+
+    async fn list_reports(&self, status: Option<ReportStatus>, limit: i64) -> Result<Vec<Report>, DatabaseError> {
+        let rows = sqlx::query(
+            r#"
+            SELECT * FROM reports
+            WHERE ? IS NULL OR status = ?
+            ORDER BY created_at
+            LIMIT ?
+            "#,
+        )
+        .bind(status.map(|s| s.as_str()))
+        .bind(status.map(|s| s.as_str()))
+        .bind(limit)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(rows.iter().map(report_from_row).collect())
+    }
+
+    async fn get_user_reports(&self, reporter_id: Uuid) -> Result<Vec<Report>, DatabaseError> {
+        let rows = sqlx::query(
+            r#"
+            SELECT * FROM reports WHERE reporter_id = ? ORDER BY created_at
+            "#,
+        )
+        .bind(reporter_id.to_string())
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(rows.iter().map(report_from_row).collect())
+    }
+
+    async fn resolve_report(
+        &self,
+        id: Uuid,
+        status: ReportStatus,
+        resolved_by: Uuid,
+        note: Option<&str>,
+    ) -> Result<bool, DatabaseError> {
+        let result = sqlx::query(
+            r#"
+            UPDATE reports SET status = ?, resolved_at = ?, resolved_by = ?, resolution_note = ?
+            WHERE id = ? AND status = ?
+            "#,
+        )
+        .bind(status.as_str())
+        .bind(Utc::now().to_rfc3339())
+        .bind(resolved_by.to_string())
+        .bind(note)
+        .bind(id.to_string())
+        .bind(ReportStatus::Open.as_str())
+        .execute(&self.pool)
+        .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    // Device operations
+    async fn create_device(&self, device: &Device) -> Result<(), DatabaseError> {
+        sqlx::query(
+            r#"
+            INSERT INTO devices (id, user_id, name, public_key, last_seen, is_online)
+            VALUES (?, ?, ?, ?, ?, ?)
+            "#,
+        )
+        .bind(device.id.to_string())
+        .bind(device.user_id.to_string())
+        .bind(&device.name)
+        .bind(&device.public_key)
+        .bind(device.last_seen.to_rfc3339())
+        .bind(device.is_online)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    async fn get_user_devices(&self, user_id: Uuid) -> Result<Vec<Device>, DatabaseError> {
+        let rows = sqlx::query(
+            r#"
+            SELECT * FROM devices WHERE user_id = ? ORDER BY last_seen DESC
+            "#,
+        )
+        .bind(user_id.to_string())
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(rows
+            .into_iter()
+            .map(|r| Device {
+                id: Uuid::parse_str(r.get("id")).unwrap(),
+                user_id: Uuid::parse_str(r.get("user_id")).unwrap(),
+                name: r.get("name"),
+                public_key: r.get("public_key"),
+                last_seen: parse_time(r.get("last_seen")),
+                is_online: r.get("is_online"),
+            })
+            .collect())
+    }
+
+    async fn delete_device(&self, user_id: Uuid, device_id: Uuid) -> Result<bool, DatabaseError> {
+        let mut tx = self.pool.begin().await?;
+
+        // Sessions do not cascade; prekeys and API keys do.
+        sqlx::query(
+            r#"
+            DELETE FROM sessions WHERE device_id = ? AND user_id = ?
+            "#,
+        )
+        .bind(device_id.to_string())
+        .bind(user_id.to_string())
+        .execute(&mut *tx)
+        .await?;
+
+        let result = sqlx::query(
+            r#"
+            DELETE FROM devices WHERE id = ? AND user_id = ?
+            "#,
+        )
+        .bind(device_id.to_string())
+        .bind(user_id.to_string())
+        .execute(&mut *tx)
+        .await?;
+
+        tx.commit().await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    // Prekey operations
+    async fn set_signed_prekey(&self, prekey: &SignedPrekey) -> Result<(), DatabaseError> {
+        sqlx::query(
+            r#"
+            INSERT INTO signed_prekeys (device_id, key_id, public_key, signature, created_at)
+            VALUES (?, ?, ?, ?, ?)
+            ON CONFLICT (device_id) DO UPDATE SET
+                key_id = excluded.key_id,
+                public_key = excluded.public_key,
+                signature = excluded.signature,
+                created_at = excluded.created_at
+            "#,
+        )
+        .bind(prekey.device_id.to_string())
+        .bind(prekey.key_id)
+        .bind(&prekey.public_key)
+        .bind(&prekey.signature)
+        .bind(prekey.created_at.to_rfc3339())
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    async fn get_signed_prekey(&self, device_id: Uuid) -> Result<Option<SignedPrekey>, DatabaseError> {
+        let row = sqlx::query(
+            r#"
+            SELECT * FROM signed_prekeys WHERE device_id = ?
+            "#,
+        )
+        .bind(device_id.to_string())
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(row.map(|r| SignedPrekey {
+            device_id: Uuid::parse_str(r.get("device_id")).unwrap(),
+            key_id: r.get("key_id"),
+            public_key: r.get("public_key"),
+            signature: r.get("signature"),
+            created_at: parse_time(r.get("created_at")),
+        }))
+    }
+
+    async fn add_one_time_prekeys(&self, device_id: Uuid, prekeys: &[OneTimePrekey]) -> Result<(), DatabaseError> {
+        let mut tx = self.pool.begin().await?;
+        let now = Utc::now().to_rfc3339();
+
+        for prekey in prekeys {
+            sqlx::query(
+                r#"
+                INSERT INTO one_time_prekeys (device_id, key_id, public_key, created_at)
+                VALUES (?, ?, ?, ?)
+                "#,
+            )
+            .bind(device_id.to_string())
+            .bind(prekey.key_id)
+            .bind(&prekey.public_key)
+            .bind(&now)
+            .execute(&mut *tx)
+            .await?;
+        }
+
+        tx.commit().await?;
+        Ok(())
+    }
+
+    async fn count_one_time_prekeys(&self, device_id: Uuid) -> Result<i64, DatabaseError> {
+        let count = sqlx::query_scalar(
+            r#"
+            SELECT COUNT(*) FROM one_time_prekeys WHERE device_id = ?
+            "#,
+        )
+        .bind(device_id.to_string())
+        .fetch_one(&self.pool)
+        .await?;
+
+        Ok(count)
+    }
+
+    async fn claim_one_time_prekey(&self, device_id: Uuid) -> Result<Option<OneTimePrekey>, DatabaseError> {
+        // A single statement, so SQLite's database-level write lock makes the
+        // select-and-delete atomic.
+        let row = sqlx::query(
+            r#"
+            DELETE FROM one_time_prekeys
+            WHERE rowid = (
+                SELECT rowid FROM one_time_prekeys
+                WHERE device_id = ?
+                ORDER BY key_id
+                LIMIT 1
+            )
+            RETURNING key_id, public_key
+            "#,
+        )
+        .bind(device_id.to_string())
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(row.map(|r| OneTimePrekey {
+            key_id: r.get("key_id"),
+            public_key: r.get("public_key"),
+        }))
+    }
+
+    // Message operations
+    async fn create_message(&self, message: &Message) -> Result<(), DatabaseError> {
+        sqlx::query(
+            r#"
+            INSERT INTO messages (id, sender_id, recipient_id, content, associated_data, created_at, expires_at, kind, target_id, chat_id, thread_id)
+            VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)
+            "#,
+        )
+        .bind(message.id.to_string())
+        .bind(message.sender_id.to_string())
+        .bind(message.recipient_id.to_string())
+        .bind(&message.content)
+        .bind(&message.associated_data)
+        .bind(message.created_at.to_rfc3339())
+        .bind(message.expires_at.map(|dt| dt.to_rfc3339()))
+        .bind(message.kind.as_str())
+        .bind(message.target_id.map(|id| id.to_string()))
+        .bind(message.chat_id.map(|id| id.to_string()))
+        .bind(message.thread_id.map(|id| id.to_string()))
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    async fn get_messages(&self, user_id: Uuid, limit: i64) -> Result<Vec<Message>, DatabaseError> {
+        let rows = sqlx::query(
+            r#"
+            SELECT * FROM messages
+            WHERE recipient_id = ?
+            ORDER BY created_at DESC
+            LIMIT ?
+            "#,
+        )
+        .bind(user_id.to_string())
+        .bind(limit)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(rows.iter().map(message_from_row).collect())
+    }
+
+    async fn get_message(&self, id: Uuid) -> Result<Option<Message>, DatabaseError> {
+        let row = sqlx::query(
+            r#"
+            SELECT * FROM messages WHERE id = ?
+            "#,
+        )
+        .bind(id.to_string())
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(row.as_ref().map(message_from_row))
+    }
+
+    async fn count_messages(&self) -> Result<i64, DatabaseError> {
+        let row = sqlx::query("SELECT COUNT(*) AS count FROM messages")
+            .fetch_one(&self.pool)
+            .await?;
+        Ok(row.get("count"))
+    }
+
+    async fn queue_stats(&self, top: i64) -> Result<QueueStats, DatabaseError> {
+        let row = sqlx::query("SELECT COUNT(*) AS count, MIN(created_at) AS oldest_at FROM messages")
+            .fetch_one(&self.pool)
+            .await?;
+        let rows = sqlx::query(
+            r#"
+            SELECT recipient_id, COUNT(*) AS count, MIN(created_at) AS oldest_at
+            FROM messages
+            GROUP BY recipient_id
+            ORDER BY count DESC, oldest_at
+            LIMIT ?
+            "#,
+        )
+        .bind(top)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(QueueStats {
+            messages: row.get("count"),
+            oldest_at: row.get::<Option<String>, _>("oldest_at").as_deref().map(parse_time),
+            recipients: rows
+                .into_iter()
+                .map(|r| RecipientQueue {
+                    user_id: Uuid::parse_str(r.get("recipient_id")).unwrap(),
+                    messages: r.get("count"),
+                    oldest_at: parse_time(r.get("oldest_at")),
+                })
+                .collect(),
+        })
+    }
+
+    async fn delete_message(&self, id: Uuid) -> Result<bool, DatabaseError> {
+        let mut tx = self.pool.begin().await?;
+
+        sqlx::query(
+            r#"
+            DELETE FROM message_attachments
+            WHERE message_id IN (SELECT id FROM messages WHERE id = ? OR target_id = ?)
+            "#,
+        )
+        .bind(id.to_string())
+        .bind(id.to_string())
+        .execute(&mut *tx)
+        .await?;
+
+        let result = sqlx::query(
+            r#"
+            DELETE FROM messages WHERE id = ? OR target_id = ?
+            "#,
+        )
+        .bind(id.to_string())
+        .bind(id.to_string())
+        .execute(&mut *tx)
+        .await?;
+
+        tx.commit().await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn get_message_metadata(&self, user_id: Uuid) -> Result<Vec<MessageMetadata>, DatabaseError> {
+        let rows = sqlx::query(
+            r#"
+            SELECT id, sender_id, recipient_id, length(content) AS size, created_at, expires_at, kind
+            FROM messages
+            WHERE sender_id = ? OR recipient_id = ?
+            ORDER BY created_at
+            "#,
+        )
+        .bind(user_id.to_string())
+        .bind(user_id.to_string())
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(rows
+            .into_iter()
+            .map(|r| MessageMetadata {
+                id: Uuid::parse_str(r.get("id")).unwrap(),
+                sender_id: Uuid::parse_str(r.get("sender_id")).unwrap(),
+                recipient_id: Uuid::parse_str(r.get("recipient_id")).unwrap(),
+                size: r.get("size"),
+                created_at: parse_time(r.get("created_at")),
+                expires_at: r.get::<Option<String>, _>("expires_at")
+                    .map(|s| parse_time(&s)),
+                kind: MessageKind::parse(r.get("kind")).unwrap(),
+            })
+            .collect())
+    }
+
+    // Chat operations
+    async fn get_chat_memberships(&self, user_id: Uuid) -> Result<Vec<ChatMember>, DatabaseError> {
+        let rows = sqlx::query(
+            r#"
+            SELECT * FROM chat_members WHERE user_id = ? ORDER BY joined_at
+            "#,
+        )
+        .bind(user_id.to_string())
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(rows.iter().map(member_from_row).collect())
+    }
+
+    async fn create_chat(&self, chat: &Chat, members: &[ChatMember]) -> Result<(), DatabaseError> {
+        let mut tx = self.pool.begin().await?;
+
+        sqlx::query(
+            r#"
+            INSERT INTO chats (id, name, is_group, is_channel, topic, avatar, disappear_after_seconds, created_at, last_message_at)
+            VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?)
+            "#,
+        )
+        .bind(chat.id.to_string())
+        .bind(&chat.name)
+        .bind(chat.is_group)
+        .bind(chat.is_channel)
+        .bind(&chat.settings.topic)
+        .bind(&chat.settings.avatar)
+        .bind(chat.settings.disappear_after_seconds)
+        .bind(chat.created_at.to_rfc3339())
+        .bind(chat.last_message_at.to_rfc3339())
+        .execute(&mut *tx)
+        .await?;
+
+        for member in members {
+            sqlx::query(
+                r#"
+                INSERT INTO chat_members (chat_id, user_id, role, joined_at)
+                VALUES (?, ?, ?, ?)
+                "#,
+            )
+            .bind(member.chat_id.to_string())
+            .bind(member.user_id.to_string())
+            .bind(member.role.as_str())
+            .bind(member.joined_at.to_rfc3339())
+            .execute(&mut *tx)
+            .await?;
+        }
+
+        tx.commit().await?;
+        Ok(())
+    }
+
+    async fn get_chat(&self, id: Uuid) -> Result<Option<Chat>, DatabaseError> {
+        let row = sqlx::query(
+            r#"
+            SELECT * FROM chats WHERE id = ?
+            "#,
+        )
+        .bind(id.to_string())
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(row.as_ref().map(chat_from_row))
+    }
+
+    async fn get_user_chats(&self, user_id: Uuid) -> Result<Vec<Chat>, DatabaseError> {
+        let rows = sqlx::query(
+            r#"
+            SELECT c.* FROM chats c
+            JOIN chat_members m ON m.chat_id = c.id
+            WHERE m.user_id = ?
+            ORDER BY c.last_message_at DESC
+            "#,
+        )
+        .bind(user_id.to_string())
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(rows.iter().map(chat_from_row).collect())
+    }
+
+    async fn find_direct_chat(&self, user_id: Uuid, other_id: Uuid) -> Result<Option<Chat>, DatabaseError> {
+        let row = sqlx::query(
+            r#"
+            SELECT c.* FROM chats c
+            JOIN chat_members a ON a.chat_id = c.id AND a.user_id = ?
+            JOIN chat_members b ON b.chat_id = c.id AND b.user_id = ?
+            WHERE NOT c.is_group
+            LIMIT 1
+            "#,
+        )
+        .bind(user_id.to_string())
+        .bind(other_id.to_string())
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(row.as_ref().map(chat_from_row))
+    }
+
+    async fn get_chat_members(&self, chat_id: Uuid) -> Result<Vec<ChatMember>, DatabaseError> {
+        let rows = sqlx::query(
+            r#"
+            SELECT * FROM chat_members WHERE chat_id = ? ORDER BY joined_at
+            "#,
+        )
+        .bind(chat_id.to_string())
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(rows.iter().map(member_from_row).collect())
+    }
+
+    async fn get_chat_member(&self, chat_id: Uuid, user_id: Uuid) -> Result<Option<ChatMember>, DatabaseError> {
+        let row = sqlx::query(
+            r#"
+            SELECT * FROM chat_members WHERE chat_id = ? AND user_id = ?
+            "#,
+        )
+        .bind(chat_id.to_string())
+        .bind(user_id.to_string())
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(row.as_ref().map(member_from_row))
+    }
+
+    async fn add_chat_member(&self, member: &ChatMember) -> Result<bool, DatabaseError> {
+        let result = sqlx::query(
+            r#"
+            INSERT INTO chat_members (chat_id, user_id, role, joined_at)
+            VALUES (?, ?, ?, ?)
+            ON CONFLICT (chat_id, user_id) DO NOTHING
+            "#,
+        )
+        .bind(member.chat_id.to_string())
+        .bind(member.user_id.to_string())
+        .bind(member.role.as_str())
+        .bind(member.joined_at.to_rfc3339())
+        .execute(&self.pool)
+        .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn set_chat_member_role(&self, chat_id: Uuid, user_id: Uuid, role: ChatRole) -> Result<bool, DatabaseError> {
+        let result = sqlx::query(
+            r#"
+            UPDATE chat_members SET role = ? WHERE chat_id = ? AND user_id = ?
+            "#,
+        )
+        .bind(role.as_str())
+        .bind(chat_id.to_string())
+        .bind(user_id.to_string())
+        .execute(&self.pool)
+        .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn remove_chat_member(&self, chat_id: Uuid, user_id: Uuid) -> Result<bool, DatabaseError> {
+        let mut tx = self.pool.begin().await?;
+
+        let result = sqlx::query(
+            r#"
+            DELETE FROM chat_members WHERE chat_id = ? AND user_id = ?
+            "#,
+        )
+        .bind(chat_id.to_string())
+        .bind(user_id.to_string())
+        .execute(&mut *tx)
+        .await?;
+
+        sqlx::query(
+            r#"
+            DELETE FROM chats
+            WHERE id = ? AND NOT EXISTS (SELECT 1 FROM chat_members WHERE chat_id = ?)
+            "#,
+        )
+        .bind(chat_id.to_string())
+        .bind(chat_id.to_string())
+        .execute(&mut *tx)
+        .await?;
+
+        tx.commit().await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn count_chat_members(&self, chat_id: Uuid) -> Result<i64, DatabaseError> {
+        let row = sqlx::query(
+            r#"
+            SELECT COUNT(*) AS count FROM chat_members WHERE chat_id = ?
+            "#,
+        )
+        .bind(chat_id.to_string())
+        .fetch_one(&self.pool)
+        .await?;
+
+        Ok(row.get("count"))
+    }
+
+    async fn get_chat_members_with_role(&self, chat_id: Uuid, role: ChatRole) -> Result<Vec<ChatMember>, DatabaseError> {
+        let rows = sqlx::query(
+            r#"
+            SELECT * FROM chat_members WHERE chat_id = ? AND role = ? ORDER BY joined_at
+            "#,
+        )
+        .bind(chat_id.to_string())
+        .bind(role.as_str())
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(rows.iter().map(member_from_row).collect())
+    }
+
+    async fn get_chat_members_among(&self, chat_id: Uuid, user_ids: &[Uuid]) -> Result<Vec<ChatMember>, DatabaseError> {
+        // Well below SQLite's limit on bound parameters.
+        const CHUNK: usize = 500;
+        let mut members = Vec::new();
+        for chunk in user_ids.chunks(CHUNK) {
+            let placeholders = vec!["?"; chunk.len()].join(", ");
+            let sql = format!(
+                "SELECT * FROM chat_members WHERE chat_id = ? AND user_id IN ({})",
+                placeholders
+            );
+            let mut query = sqlx::query(&sql).bind(chat_id.to_string());
+            for user_id in chunk {
+                query = query.bind(user_id.to_string());
+            }
+            members.extend(query.fetch_all(&self.pool).await?.iter().map(member_from_row));
+        }
+
+        Ok(members)
+    }
+
+    async fn update_chat_settings(&self, chat_id: Uuid, settings: &ChatSettings) -> Result<bool, DatabaseError> {
+        let result = sqlx::query(
+            r#"
+            UPDATE chats SET topic = ?, avatar = ?, disappear_after_seconds = ? WHERE id = ?
+            "#,
+        )
+        .bind(&settings.topic)
+        .bind(&settings.avatar)
+        .bind(settings.disappear_after_seconds)
+        .bind(chat_id.to_string())
+        .execute(&self.pool)
+        .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn set_chat_muted(&self, chat_id: Uuid, user_id: Uuid, until: Option<DateTime<Utc>>) -> Result<bool, DatabaseError> {
+        let result = sqlx::query(
+            r#"
+            UPDATE chat_members SET muted_until = ? WHERE chat_id = ? AND user_id = ?
+            "#,
+        )
+        .bind(until.map(|dt| dt.to_rfc3339()))
+        .bind(chat_id.to_string())
+        .bind(user_id.to_string())
+        .execute(&self.pool)
+        .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    // Channel operations
+    async fn create_channel_post(&self, post: &ChannelPost) -> Result<(), DatabaseError> {
+        let mut tx = self.pool.begin().await?;
+
+        sqlx::query(
+            r#"
+            INSERT INTO channel_posts (id, chat_id, sender_id, content, associated_data, created_at)
+            VALUES (?, ?, ?, ?, ?, ?)
+            "#,
+        )
+        .bind(post.id.to_string())
+        .bind(post.chat_id.to_string())
+        .bind(post.sender_id.to_string())
+        .bind(&post.content)
+        .bind(&post.associated_data)
+        .bind(post.created_at.to_rfc3339())
+        .execute(&mut *tx)
+        .await?;
+
+        sqlx::query(
+            r#"
+            UPDATE chats SET last_message_at = ? WHERE id = ?
+            "#,
+        )
+        .bind(post.created_at.to_rfc3339())
+        .bind(post.chat_id.to_string())
+        .execute(&mut *tx)
+        .await?;
+
+        tx.commit().await?;
+        Ok(())
+    }
+
+    async fn get_channel_posts(&self, chat_id: Uuid, before: Option<Uuid>, limit: i64) -> Result<Vec<ChannelPost>, DatabaseError> {
+        let rows = sqlx::query(
+            r#"
+            SELECT * FROM channel_posts
+            WHERE chat_id = ?
+              AND (? IS NULL OR (created_at, id) < (SELECT created_at, id FROM channel_posts WHERE id = ?))
+            ORDER BY created_at DESC, id DESC
+            LIMIT ?
+            "#,
+        )
+        .bind(chat_id.to_string())
+        .bind(before.map(|id| id.to_string()))
+        .bind(before.map(|id| id.to_string()))
+        .bind(limit)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(rows
+            .into_iter()
+            .map(|r| ChannelPost {
+                id: Uuid::parse_str(r.get("id")).unwrap(),
+                chat_id: Uuid::parse_str(r.get("chat_id")).unwrap(),
+                sender_id: Uuid::parse_str(r.get("sender_id")).unwrap(),
+                content: r.get("content"),
+                associated_data: r.get("associated_data"),
+                created_at: parse_time(r.get("created_at")),
+            })
+            .collect())
+    }
+
+    // Invite operations
+    async fn create_invite(&self, invite: &ChatInvite) -> Result<(), DatabaseError> {
+        sqlx::query(
+            r#"
+            INSERT INTO chat_invites (id, chat_id, code, created_by, created_at, expires_at, max_uses, use_count, requires_approval, revoked_at)
+            VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?)
+            "#,
+        )
+        .bind(invite.id.to_string())
+        .bind(invite.chat_id.to_string())
+        .bind(&invite.code)
+        .bind(invite.created_by.to_string())
+        .bind(invite.created_at.to_rfc3339())
+        .bind(invite.expires_at.map(|dt| dt.to_rfc3339()))
+        .bind(invite.max_uses)
+        .bind(invite.use_count)
+        .bind(invite.requires_approval)
+        .bind(invite.revoked_at.map(|dt| dt.to_rfc3339()))
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    async fn get_invite(&self, id: Uuid) -> Result<Option<ChatInvite>, DatabaseError> {
+        let row = sqlx::query(
+            r#"
+            SELECT * FROM chat_invites WHERE id = ?
+            "#,
+        )
+        .bind(id.to_string())
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(row.as_ref().map(invite_from_row))
+    }
+
+    async fn get_invite_by_code(&self, code: &str) -> Result<Option<ChatInvite>, DatabaseError> {
+        let row = sqlx::query(
+            r#"
+            SELECT * FROM chat_invites WHERE code = ?
+            "#,
+        )
+        .bind(code)
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(row.as_ref().map(invite_from_row))
+    }
+
+    async fn get_chat_invites(&self, chat_id: Uuid) -> Result<Vec<ChatInvite>, DatabaseError> {
+        let rows = sqlx::query(
+            r#"
+            SELECT * FROM chat_invites WHERE chat_id = ? ORDER BY created_at DESC
+            "#,
+        )
+        .bind(chat_id.to_string())
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(rows.iter().map(invite_from_row).collect())
+    }
+
+    async fn revoke_invite(&self, id: Uuid, at: DateTime<Utc>) -> Result<bool, DatabaseError> {
+        let result = sqlx::query(
+            r#"
+            UPDATE chat_invites SET revoked_at = ? WHERE id = ? AND revoked_at IS NULL
+            "#,
+        )
+        .bind(at.to_rfc3339())
+        .bind(id.to_string())
+        .execute(&self.pool)
+        .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn redeem_invite(&self, id: Uuid, now: DateTime<Utc>) -> Result<bool, DatabaseError> {
+        let result = sqlx::query(
+            r#"
+            UPDATE chat_invites SET use_count = use_count + 1
+            WHERE id = ?
+              AND revoked_at IS NULL
+              AND (expires_at IS NULL OR expires_at > ?)
+              AND (max_uses IS NULL OR use_count < max_uses)
+            "#,
+        )
+        .bind(id.to_string())
+        .bind(now.to_rfc3339())
+        .execute(&self.pool)
+        .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn create_join_request(&self, request: &JoinRequest) -> Result<bool, DatabaseError> {
+        let result = sqlx::query(
+            r#"
+            INSERT INTO join_requests (chat_id, user_id, invite_id, created_at)
+            VALUES (?, ?, ?, ?)
+            ON CONFLICT (chat_id, user_id) DO NOTHING
+            "#,
+        )
+        .bind(request.chat_id.to_string())
+        .bind(request.user_id.to_string())
+        .bind(request.invite_id.to_string())
+        .bind(request.created_at.to_rfc3339())
+        .execute(&self.pool)
+        .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn get_join_requests(&self, chat_id: Uuid) -> Result<Vec<JoinRequest>, DatabaseError> {
+        let rows = sqlx::query(
+            r#"
+            SELECT * FROM join_requests WHERE chat_id = ? ORDER BY created_at
+            "#,
+        )
+        .bind(chat_id.to_string())
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(rows.iter().map(join_request_from_row).collect())
+    }
+
+    async fn take_join_request(&self, chat_id: Uuid, user_id: Uuid) -> Result<Option<JoinRequest>, DatabaseError> {
+        let row = sqlx::query(
+            r#"
+            DELETE FROM join_requests WHERE chat_id = ? AND user_id = ?
+            RETURNING *
+            "#,
+        )
+        .bind(chat_id.to_string())
+        .bind(user_id.to_string())
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(row.as_ref().map(join_request_from_row))
+    }
+
+    // Thread operations
+    async fn get_thread_messages(
+        &self,
+        user_id: Uuid,
+        chat_id: Uuid,
+        thread_id: Uuid,
+        before: Option<Uuid>,
+        limit: i64,
+    ) -> Result<Vec<Message>, DatabaseError> {
+        let rows = sqlx::query(
+            r#"
+            SELECT * FROM messages
+            WHERE recipient_id = ? AND chat_id = ? AND thread_id = ?
+              AND (? IS NULL OR (created_at, id) < (SELECT created_at, id FROM messages WHERE id = ?))
+            ORDER BY created_at DESC, id DESC
+            LIMIT ?
+            "#,
+        )
+        .bind(user_id.to_string())
+        .bind(chat_id.to_string())
+        .bind(thread_id.to_string())
+        .bind(before.map(|id| id.to_string()))
+        .bind(before.map(|id| id.to_string()))
+        .bind(limit)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(rows.iter().map(message_from_row).collect())
+    }
+
+    async fn get_thread_summaries(&self, user_id: Uuid, chat_id: Uuid) -> Result<Vec<ThreadSummary>, DatabaseError> {
+        let rows = sqlx::query(
+            r#"
+            SELECT m.thread_id,
+                   COUNT(*) AS message_count,
+                   SUM(CASE WHEN r.read_at IS NULL OR m.created_at > r.read_at THEN 1 ELSE 0 END) AS unread_count,
+                   MAX(m.created_at) AS last_message_at
+            FROM messages m
+            LEFT JOIN thread_reads r
+              ON r.user_id = m.recipient_id AND r.chat_id = m.chat_id AND r.thread_id = m.thread_id
+            WHERE m.recipient_id = ? AND m.chat_id = ? AND m.thread_id IS NOT NULL AND m.kind = 'message'
+            GROUP BY m.thread_id
+            ORDER BY last_message_at DESC
+            "#,
+        )
+        .bind(user_id.to_string())
+        .bind(chat_id.to_string())
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(rows
+            .into_iter()
+            .map(|r| ThreadSummary {
+                thread_id: Uuid::parse_str(r.get("thread_id")).unwrap(),
+                message_count: r.get("message_count"),
+                unread_count: r.get("unread_count"),
+                last_message_at: parse_time(r.get("last_message_at")),
+            })
+            .collect())
+    }
+
+    async fn mark_thread_read(&self, user_id: Uuid, chat_id: Uuid, thread_id: Uuid, at: DateTime<Utc>) -> Result<(), DatabaseError> {
+        sqlx::query(
+            r#"
+            INSERT INTO thread_reads (user_id, chat_id, thread_id, read_at)
+            VALUES (?, ?, ?, ?)
+            ON CONFLICT (user_id, chat_id, thread_id)
+            DO UPDATE SET read_at = MAX(read_at, excluded.read_at)
+            "#,
+        )
+        .bind(user_id.to_string())
+        .bind(chat_id.to_string())
+        .bind(thread_id.to_string())
+        .bind(at.to_rfc3339())
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    // Pin operations
+    async fn create_pin(&self, pin: &ChatPin) -> Result<(), DatabaseError> {
+        sqlx::query(
+            r#"
+            INSERT INTO chat_pins (id, chat_id, pinned_by, content, pinned_at)
+            VALUES (?, ?, ?, ?, ?)
+            "#,
+        )
+        .bind(pin.id.to_string())
+        .bind(pin.chat_id.to_string())
+        .bind(pin.pinned_by.to_string())
+        .bind(&pin.content)
+        .bind(pin.pinned_at.to_rfc3339())
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    async fn get_chat_pins(&self, chat_id: Uuid) -> Result<Vec<ChatPin>, DatabaseError> {
+        let rows = sqlx::query(
+            r#"
+            SELECT * FROM chat_pins WHERE chat_id = ? ORDER BY pinned_at, id
+            "#,
+        )
+        .bind(chat_id.to_string())
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(rows
+            .into_iter()
+            .map(|r| ChatPin {
+                id: Uuid::parse_str(r.get("id")).unwrap(),
+                chat_id: Uuid::parse_str(r.get("chat_id")).unwrap(),
+                pinned_by: Uuid::parse_str(r.get("pinned_by")).unwrap(),
+                content: r.get("content"),
+                pinned_at: parse_time(r.get("pinned_at")),
+            })
+            .collect())
+    }
+
+    async fn delete_pin(&self, chat_id: Uuid, id: Uuid) -> Result<bool, DatabaseError> {
+        let result = sqlx::query("DELETE FROM chat_pins WHERE id = ? AND chat_id = ?")
+            .bind(id.to_string())
+            .bind(chat_id.to_string())
+            .execute(&self.pool)
+            .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    // Star operations
+    async fn star_message(&self, user_id: Uuid, message_id: Uuid, at: DateTime<Utc>) -> Result<bool, DatabaseError> {
+        let result = sqlx::query(
+            r#"
+            INSERT INTO starred_messages (user_id, message_id, starred_at)
+            VALUES (?, ?, ?)
+            ON CONFLICT (user_id, message_id) DO NOTHING
+            "#,
+        )
+        .bind(user_id.to_string())
+        .bind(message_id.to_string())
+        .bind(at.to_rfc3339())
+        .execute(&self.pool)
+        .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn unstar_message(&self, user_id: Uuid, message_id: Uuid) -> Result<bool, DatabaseError> {
+        let result = sqlx::query("DELETE FROM starred_messages WHERE user_id = ? AND message_id = ?")
+            .bind(user_id.to_string())
+            .bind(message_id.to_string())
+            .execute(&self.pool)
+            .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn get_starred_messages(&self, user_id: Uuid) -> Result<Vec<StarredMessage>, DatabaseError> {
+        let rows = sqlx::query(
+            r#"
+            SELECT message_id, starred_at FROM starred_messages
+            WHERE user_id = ?
+            ORDER BY starred_at DESC
+            "#,
+        )
+        .bind(user_id.to_string())
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(rows
+            .into_iter()
+            .map(|r| StarredMessage {
+                message_id: Uuid::parse_str(r.get("message_id")).unwrap(),
+                starred_at: parse_time(r.get("starred_at")),
+            })
+            .collect())
+    }
+
+    // Presence operations
+    async fn get_presence_subscribers(&self, user_id: Uuid) -> Result<Vec<Uuid>, DatabaseError> {
+        let rows = sqlx::query(
+            r#"
+            SELECT user_id AS subscriber FROM contacts WHERE contact_id = ?
+            UNION
+            SELECT b.user_id FROM chat_members a
+            JOIN chat_members b ON b.chat_id = a.chat_id
+            WHERE a.user_id = ? AND b.user_id <> ?
+            "#,
+        )
+        .bind(user_id.to_string())
+        .bind(user_id.to_string())
+        .bind(user_id.to_string())
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(rows.iter().map(|r| Uuid::parse_str(r.get("subscriber")).unwrap()).collect())
+    }
+
+    async fn is_presence_subscriber(&self, user_id: Uuid, subscriber_id: Uuid) -> Result<bool, DatabaseError> {
+        let row = sqlx::query(
+            r#"
+            SELECT 1 FROM contacts WHERE user_id = ? AND contact_id = ?
+            UNION ALL
+            SELECT 1 FROM chat_members a
+            JOIN chat_members b ON b.chat_id = a.chat_id
+            WHERE a.user_id = ? AND b.user_id = ?
+            LIMIT 1
+            "#,
+        )
+        .bind(subscriber_id.to_string())
+        .bind(user_id.to_string())
+        .bind(user_id.to_string())
+        .bind(subscriber_id.to_string())
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(row.is_some())
+    }
+
+    async fn record_last_seen(&self, user_id: Uuid, device_id: Uuid, at: DateTime<Utc>) -> Result<(), DatabaseError> {
+        let mut tx = self.pool.begin().await?;
+
+        sqlx::query(
+            r#"
+            UPDATE users SET last_seen = ? WHERE id = ?
+            "#,
+        )
+        .bind(at.to_rfc3339())
+        .bind(user_id.to_string())
+        .execute(&mut *tx)
+        .await?;
+
+        sqlx::query(
+            r#"
+            UPDATE devices SET last_seen = ? WHERE id = ?
+            "#,
+        )
+        .bind(at.to_rfc3339())
+        .bind(device_id.to_string())
+        .execute(&mut *tx)
+        .await?;
+
+        tx.commit().await?;
+        Ok(())
+    }
+
+    // Session operations
+    async fn create_session(&self, session: &Session) -> Result<(), DatabaseError> {
+        sqlx::query(
+            r#"
+            INSERT INTO sessions (id, user_id, device_id, refresh_token_hash, created_at, expires_at)
+            VALUES (?, ?, ?, ?, ?, ?)
+            "#,
+        )
+        .bind(session.id.to_string())
+        .bind(session.user_id.to_string())
+        .bind(session.device_id.to_string())
+        .bind(&session.refresh_token_hash)
+        .bind(session.created_at.to_rfc3339())
+        .bind(session.expires_at.to_rfc3339())
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    async fn get_active_session(&self, id: Uuid) -> Result<Option<Session>, DatabaseError> {
+        // Timestamps are stored as RFC 3339 text, which compares correctly as
+        // strings but not against SQLite's own datetime('now') format.
+        let row = sqlx::query(
+            r#"
+            SELECT * FROM sessions
+            WHERE id = ? AND revoked_at IS NULL AND expires_at > ?
+            "#,
+        )
+        .bind(id.to_string())
+        .bind(Utc::now().to_rfc3339())
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(row.as_ref().map(session_from_row))
+    }
+
+    async fn find_session_by_refresh_token(&self, hash: &str) -> Result<Option<Session>, DatabaseError> {
+        let row = sqlx::query(
+            r#"
+            SELECT * FROM sessions
+            WHERE refresh_token_hash = ? OR previous_refresh_token_hash = ?
+            "#,
+        )
+        .bind(hash)
+        .bind(hash)
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(row.as_ref().map(session_from_row))
+    }
+
+    async fn rotate_refresh_token(
+        &self,
+        id: Uuid,
+        expected_hash: &str,
+        new_hash: &str,
+        expires_at: DateTime<Utc>,
+    ) -> Result<bool, DatabaseError> {
+        let now = Utc::now().to_rfc3339();
+        let result = sqlx::query(
+            r#"
+            UPDATE sessions
+            SET previous_refresh_token_hash = refresh_token_hash,
+                refresh_token_hash = ?,
+                expires_at = ?,
+                last_used_at = ?
+            WHERE id = ? AND refresh_token_hash = ? AND revoked_at IS NULL AND expires_at > ?
+            "#,
+        )
+        .bind(new_hash)
+        .bind(expires_at.to_rfc3339())
+        .bind(&now)
+        .bind(id.to_string())
+        .bind(expected_hash)
+        .bind(&now)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(result.rows_affected() == 1)
+    }
+
+    async fn list_sessions(&self, user_id: Uuid) -> Result<Vec<Session>, DatabaseError> {
+        let rows = sqlx::query(
+            r#"
+            SELECT * FROM sessions
+            WHERE user_id = ? AND revoked_at IS NULL AND expires_at > ?
+            ORDER BY created_at DESC
+            "#,
+        )
+        .bind(user_id.to_string())
+        .bind(Utc::now().to_rfc3339())
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(rows.iter().map(session_from_row).collect())
+    }
+
+    async fn revoke_session(&self, id: Uuid) -> Result<bool, DatabaseError> {
+        let now = Utc::now().to_rfc3339();
+        let result = sqlx::query(
+            r#"
+            UPDATE sessions SET revoked_at = ?
+            WHERE id = ? AND revoked_at IS NULL AND expires_at > ?
+            "#,
+        )
+        .bind(&now)
+        .bind(id.to_string())
+        .bind(&now)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(result.rows_affected() == 1)
+    }
+
+    async fn revoke_user_sessions(&self, user_id: Uuid, except: Option<Uuid>) -> Result<Vec<Uuid>, DatabaseError> {
+        let now = Utc::now().to_rfc3339();
+        let ids: Vec<String> = sqlx::query_scalar(
+            r#"
+            UPDATE sessions SET revoked_at = ?
+            WHERE user_id = ? AND id IS NOT ? AND revoked_at IS NULL AND expires_at > ?
+            RETURNING id
+            "#,
+        )
+        .bind(&now)
+        .bind(user_id.to_string())
+        .bind(except.map(|id| id.to_string()))
+        .bind(&now)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(ids.iter().map(|id| Uuid::parse_str(id).unwrap()).collect())
+    }
+
+    async fn purge_sessions(&self) -> Result<u64, DatabaseError> {
+        let result = sqlx::query(
+            r#"
+            DELETE FROM sessions WHERE revoked_at IS NOT NULL OR expires_at <= ?
+            "#,
+        )
+        .bind(Utc::now().to_rfc3339())
+        .execute(&self.pool)
+        .await?;
+
+        Ok(result.rows_affected())
+    }
+
+    // Audit log operations
+    async fn append_audit_event(&self, event: &AuditEvent) -> Result<(), DatabaseError> {
+        sqlx::query(
+            r#"
+            INSERT INTO audit_events (id, kind, user_id, actor_id, device_id, details, created_at, prev_hash, hash)
+            VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?)
+            "#,
+        )
+        .bind(event.id)
+        .bind(event.kind.as_str())
+        .bind(event.user_id.map(|id| id.to_string()))
+        .bind(event.actor_id.map(|id| id.to_string()))
+        .bind(event.device_id.map(|id| id.to_string()))
+        .bind(event.details.to_string())
+        .bind(event.created_at.to_rfc3339())
+        .bind(&event.prev_hash)
+        .bind(&event.hash)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    async fn last_audit_event(&self) -> Result<Option<AuditEvent>, DatabaseError> {
+        let row = sqlx::query(
+            r#"
+            SELECT * FROM audit_events ORDER BY id DESC LIMIT 1
+            "#,
+        )
+        .fetch_optional(&self.pool)
+        .await?;
+
+        row.as_ref().map(audit_event_from_row).transpose()
+    }
+
+    async fn list_audit_events(&self, query: &AuditQuery) -> Result<Vec<AuditEvent>, DatabaseError> {
+        let user_id = query.user_id.map(|id| id.to_string());
+        let since = query.since.map(|t| t.to_rfc3339());
+        let until = query.until.map(|t| t.to_rfc3339());
+        let rows = sqlx::query(
+            r#"
+            SELECT * FROM audit_events
+            WHERE (? IS NULL OR user_id = ?)
+              AND (? IS NULL OR created_at >= ?)
+              AND (? IS NULL OR created_at < ?)
+              AND (? IS NULL OR id < ?)
+            ORDER BY id DESC
+            LIMIT ?
+            "#,
+        )
+        .bind(&user_id)
+        .bind(&user_id)
+        .bind(&since)
+        .bind(&since)
+        .bind(&until)
+        .bind(&until)
+        .bind(query.before)
+        .bind(query.before)
+        .bind(query.limit)
+        .fetch_all(&self.pool)
+        .await?;
+
+        rows.iter().map(audit_event_from_row).collect()
+    }
+
+    async fn audit_events_after(&self, after: i64, limit: i64) -> Result<Vec<AuditEvent>, DatabaseError> {
+        let rows = sqlx::query(
+            r#"
+            SELECT * FROM audit_events WHERE id > ? ORDER BY id LIMIT ?
+            "#,
+        )
+        .bind(after)
+        .bind(limit)
+        .fetch_all(&self.pool)
+        .await?;
+
+        rows.iter().map(audit_event_from_row).collect()
+    }
+
+    // Signing key operations
+    async fn create_signing_key(&self, key: &SigningKey) -> Result<(), DatabaseError> {
+        sqlx::query(
+            r#"
+            INSERT INTO signing_keys (id, algorithm, private_key, public_key, created_at, activates_at)
+            VALUES (?, ?, ?, ?, ?, ?)
+            "#,
+        )
+        .bind(&key.id)
+        .bind(key.algorithm.as_str())
+        .bind(&key.private_key)
+        .bind(&key.public_key)
+        .bind(key.created_at.to_rfc3339())
+        .bind(key.activates_at.to_rfc3339())
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    async fn get_signing_keys(&self) -> Result<Vec<SigningKey>, DatabaseError> {
+        let rows = sqlx::query(
+            r#"
+            SELECT * FROM signing_keys ORDER BY activates_at, created_at
+            "#,
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        rows.iter()
+            .map(|r| {
+                let algorithm: String = r.get("algorithm");
+                Ok(SigningKey {
+                    id: r.get("id"),
+                    algorithm: SigningAlgorithm::parse(&algorithm)
+                        .ok_or_else(|| DatabaseError::InvalidData(format!("Unknown signing algorithm: {}", algorithm)))?,
+                    private_key: r.get("private_key"),
+                    public_key: r.get("public_key"),
+                    created_at: parse_time(r.get("created_at")),
+                    activates_at: parse_time(r.get("activates_at")),
+                })
+            })
+            .collect()
+    }
+
+    async fn delete_signing_key(&self, id: &str) -> Result<bool, DatabaseError> {
+        let result = sqlx::query(
+            r#"
+            DELETE FROM signing_keys WHERE id = ?
+            "#,
+        )
+        .bind(id)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    // Bot operations
+    async fn get_user_bots(&self, owner_id: Uuid) -> Result<Vec<User>, DatabaseError> {
+        let rows = sqlx::query(
+            r#"
+            SELECT * FROM users WHERE bot_owner_id = ? ORDER BY created_at
+            "#,
+        )
+        .bind(owner_id.to_string())
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(rows.iter().map(user_from_row).collect())
+    }
+
+    async fn create_api_key(&self, key: &ApiKey) -> Result<(), DatabaseError> {
+        sqlx::query(
+            r#"
+            INSERT INTO api_keys (id, bot_id, device_id, key_hash, created_at, revoked_at)
+            VALUES (?, ?, ?, ?, ?, ?)
+            "#,
+        )
+        .bind(key.id.to_string())
+        .bind(key.bot_id.to_string())
+        .bind(key.device_id.to_string())
+        .bind(&key.key_hash)
+        .bind(key.created_at.to_rfc3339())
+        .bind(key.revoked_at.map(|t| t.to_rfc3339()))
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    async fn get_api_key_by_hash(&self, key_hash: &str) -> Result<Option<ApiKey>, DatabaseError> {
+        let row = sqlx::query(
+            r#"
+            SELECT * FROM api_keys WHERE key_hash = ? AND revoked_at IS NULL
+            "#,
+        )
+        .bind(key_hash)
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(row.as_ref().map(api_key_from_row))
+    }
+
+    async fn get_bot_api_keys(&self, bot_id: Uuid) -> Result<Vec<ApiKey>, DatabaseError> {
+        let rows = sqlx::query(
+            r#"
+            SELECT * FROM api_keys WHERE bot_id = ? ORDER BY created_at DESC
+            "#,
+        )
+        .bind(bot_id.to_string())
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(rows.iter().map(api_key_from_row).collect())
+    }
+
+    async fn revoke_api_key(&self, bot_id: Uuid, id: Uuid, at: DateTime<Utc>) -> Result<bool, DatabaseError> {
+        let result = sqlx::query(
+            r#"
+            UPDATE api_keys SET revoked_at = ?
+            WHERE id = ? AND bot_id = ? AND revoked_at IS NULL
+            "#,
+        )
+        .bind(at.to_rfc3339())
+        .bind(id.to_string())
+        .bind(bot_id.to_string())
+        .execute(&self.pool)
+        .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn set_bot_webhook(&self, webhook: &BotWebhook) -> Result<(), DatabaseError> {
+        sqlx::query(
+            r#"
+            INSERT INTO bot_webhooks (bot_id, url, secret, created_at)
+            VALUES (?, ?, ?, ?)
+            ON CONFLICT (bot_id) DO UPDATE SET url = excluded.url, secret = excluded.secret, created_at = excluded.created_at
+            "#,
+        )
+        .bind(webhook.bot_id.to_string())
+        .bind(&webhook.url)
+        .bind(&webhook.secret)
+        .bind(webhook.created_at.to_rfc3339())
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    async fn get_bot_webhook(&self, bot_id: Uuid) -> Result<Option<BotWebhook>, DatabaseError> {
+        let row = sqlx::query(
+            r#"
+            SELECT * FROM bot_webhooks WHERE bot_id = ?
+            "#,
+        )
+        .bind(bot_id.to_string())
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(row.map(|r| BotWebhook {
+            bot_id: Uuid::parse_str(r.get("bot_id")).unwrap(),
+            url: r.get("url"),
+            secret: r.get("secret"),
+            created_at: parse_time(r.get("created_at")),
+        }))
+    }
+
+    async fn delete_bot_webhook(&self, bot_id: Uuid) -> Result<bool, DatabaseError> {
+        let result = sqlx::query("DELETE FROM bot_webhooks WHERE bot_id = ?")
+            .bind(bot_id.to_string())
+            .execute(&self.pool)
+            .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    // Attachment operations
+    async fn create_attachment(&self, attachment: &Attachment, quota: i64) -> Result<bool, DatabaseError> {
+        // A single statement: SQLite serializes writers, so the usage read
+        // here cannot change before the row lands.
+        let result = sqlx::query(
+            r#"
+            INSERT INTO attachments (id, uploader_id, size, received, created_at, completed_at, expires_at)
+            SELECT ?, ?, ?, ?, ?, ?, ?
+            WHERE (
+                SELECT COALESCE(SUM(size), 0) FROM attachments
+                WHERE uploader_id = ? AND expires_at > ?
+            ) + ? <= ?
+            "#,
+        )
+        .bind(&attachment.id)
+        .bind(attachment.uploader_id.to_string())
+        .bind(attachment.size)
+        .bind(attachment.received)
+        .bind(attachment.created_at.to_rfc3339())
+        .bind(attachment.completed_at.map(|dt| dt.to_rfc3339()))
+        .bind(attachment.expires_at.to_rfc3339())
+        .bind(attachment.uploader_id.to_string())
+        .bind(Utc::now().to_rfc3339())
+        .bind(attachment.size)
+        .bind(quota)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn get_attachment(&self, id: &str) -> Result<Option<Attachment>, DatabaseError> {
+        let row = sqlx::query(
+            r#"
+            SELECT * FROM attachments WHERE id = ?
+            "#,
+        )
+        .bind(id)
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(row.as_ref().map(attachment_from_row))
+    }
+
+    async fn get_user_attachments(&self, uploader_id: Uuid) -> Result<Vec<Attachment>, DatabaseError> {
+        let rows = sqlx::query(
+            r#"
+            SELECT * FROM attachments WHERE uploader_id = ? ORDER BY created_at
+            "#,
+        )
+        .bind(uploader_id.to_string())
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(rows.iter().map(attachment_from_row).collect())
+    }
+
+    async fn record_attachment_progress(
+        &self,
+        id: &str,
+        expected: i64,
+        received: i64,
+        completed_at: Option<DateTime<Utc>>,
+    ) -> Result<bool, DatabaseError> {
+        let result = sqlx::query(
+            r#"
+            UPDATE attachments SET received = ?, completed_at = ?
+            WHERE id = ? AND received = ?
+            "#,
+        )
+        .bind(received)
+        .bind(completed_at.map(|dt| dt.to_rfc3339()))
+        .bind(id)
+        .bind(expected)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(result.rows_affected() == 1)
+    }
+
+    async fn attachment_usage(&self, uploader_id: Uuid) -> Result<i64, DatabaseError> {
+        let usage: Option<i64> = sqlx::query_scalar(
+            r#"
+            SELECT SUM(size) FROM attachments
+            WHERE uploader_id = ? AND expires_at > ?
+            "#,
+        )
+        .bind(uploader_id.to_string())
+        .bind(Utc::now().to_rfc3339())
+        .fetch_one(&self.pool)
+        .await?;
+
+        Ok(usage.unwrap_or(0))
+    }
+
+    async fn link_attachments(
+        &self,
+        message_id: Uuid,
+        attachment_ids: &[String],
+        expires_at: DateTime<Utc>,
+    ) -> Result<(), DatabaseError> {
+        let mut tx = self.pool.begin().await?;
+
+        for attachment_id in attachment_ids {
+            sqlx::query(
+                r#"
+                INSERT INTO message_attachments (message_id, attachment_id)
+                VALUES (?, ?)
+                "#,
+            )
+            .bind(message_id.to_string())
+            .bind(attachment_id)
+            .execute(&mut *tx)
+            .await?;
+
+            sqlx::query(
+                r#"
+                UPDATE attachments
+                SET expires_at = CASE WHEN referenced THEN MAX(expires_at, ?) ELSE ? END,
+                    referenced = 1
+                WHERE id = ?
+                "#,
+            )
+            .bind(expires_at.to_rfc3339())
+            .bind(expires_at.to_rfc3339())
+            .bind(attachment_id)
+            .execute(&mut *tx)
+            .await?;
+        }
+
+        tx.commit().await?;
+        Ok(())
+    }
+
+    async fn can_access_attachment(&self, id: &str, user_id: Uuid) -> Result<bool, DatabaseError> {
+        let allowed: bool = sqlx::query_scalar(
+            r#"
+            SELECT EXISTS (
+                SELECT 1 FROM attachments WHERE id = ? AND uploader_id = ?
+            ) OR EXISTS (
+                SELECT 1 FROM message_attachments ma
+                JOIN messages m ON m.id = ma.message_id
+                WHERE ma.attachment_id = ? AND m.recipient_id = ?
+            )
+            "#,
+        )
+        .bind(id)
+        .bind(user_id.to_string())
+        .bind(id)
+        .bind(user_id.to_string())
+        .fetch_one(&self.pool)
+        .await?;
+
+        Ok(allowed)
+    }
+
+    async fn expired_attachments(&self, limit: i64) -> Result<Vec<String>, DatabaseError> {
+        let ids = sqlx::query_scalar(
+            r#"
+            SELECT id FROM attachments WHERE expires_at <= ? LIMIT ?
+            "#,
+        )
+        .bind(Utc::now().to_rfc3339())
+        .bind(limit)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(ids)
+    }
+
+    async fn delete_attachment(&self, id: &str) -> Result<(), DatabaseError> {
+        sqlx::query("DELETE FROM attachments WHERE id = ?")
+            .bind(id)
+            .execute(&self.pool)
+            .await?;
+
+        Ok(())
+    }
+}
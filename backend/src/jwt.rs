@@ -0,0 +1,348 @@
+//! Keys that sign access tokens. Each key has an id that tokens carry in
+//! their `kid` header. A new key is published for a while before it starts
+//! signing, so that other instances and services caching the key set know
+//! it by then; the key it replaces keeps verifying until the tokens it
+//! signed have expired.
+
+use std::collections::HashMap;
+use std::sync::RwLock;
+use std::time::Instant;
+
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use chrono::{DateTime, Duration, Utc};
+use jsonwebtoken::{
+    decode, decode_header, encode,
+    errors::ErrorKind,
+    jwk::{
+        AlgorithmParameters, CommonParameters, EllipticCurve, EllipticCurveKeyParameters, Jwk, JwkSet,
+        KeyAlgorithm, OctetKeyPairParameters, PublicKeyUse,
+    },
+    Algorithm, DecodingKey, EncodingKey, Header, Validation,
+};
+use ring::rand::SystemRandom;
+use ring::signature::{EcdsaKeyPair, Ed25519KeyPair, KeyPair, ECDSA_P256_SHA256_FIXED_SIGNING};
+use serde::{de::DeserializeOwned, Serialize};
+use uuid::Uuid;
+
+use crate::config::{env_at_least, env_opt, ConfigError};
+use crate::db::{Database, DatabaseError};
+use crate::models::{SigningAlgorithm, SigningKey};
+
+/// `typ` header of access tokens (RFC 9068). Services verifying tokens
+/// against the published keys should insist on it, since the same keys
+/// sign other, shorter-lived tokens.
+pub const ACCESS_TOKEN_TYPE: &str = "at+jwt";
+
+/// A token naming a key this instance does not know triggers a reload, but
+/// no more often than this.
+const RELOAD_COOLDOWN: std::time::Duration = std::time::Duration::from_secs(10);
+
+#[derive(Debug, Clone)]
+pub struct JwtConfig {
+    /// Algorithm of new keys; existing keys keep theirs.
+    pub algorithm: SigningAlgorithm,
+    /// How long a key signs before it is replaced.
+    pub rotation_interval: Duration,
+    /// How long a new key is published before it signs.
+    pub publish_delay: Duration,
+    /// The HMAC secret used before signing keys, if set: tokens without a
+    /// `kid` are verified with it. Nothing is signed with it any more.
+    pub legacy_secret: Option<String>,
+}
+
+impl JwtConfig {
+    pub fn from_env(legacy_secret: Option<String>) -> Result<Self, ConfigError> {
+        let algorithm = match env_opt::<String>("JWT_ALGORITHM")? {
+            None => SigningAlgorithm::EdDsa,
+            Some(name) => SigningAlgorithm::parse(&name)
+                .ok_or_else(|| ConfigError::Invalid("JWT_ALGORITHM", format!("{} is not EdDSA or ES256", name)))?,
+        };
+
+        Ok(Self {
+            algorithm,
+            rotation_interval: Duration::days(env_at_least("JWT_KEY_ROTATION_DAYS", 30, 1)?),
+            publish_delay: Duration::minutes(env_at_least("JWT_KEY_PUBLISH_MINUTES", 10, 0)?),
+            legacy_secret: legacy_secret.filter(|s| !s.is_empty()),
+        })
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum KeyError {
+    #[error(transparent)]
+    Database(#[from] DatabaseError),
+    #[error("Cannot generate key: {0}")]
+    Generate(String),
+    #[error("Signing key {0} is unusable: {1}")]
+    Invalid(String, String),
+    #[error("No signing key is active")]
+    NoSigningKey,
+    #[error("Cannot sign token: {0}")]
+    Sign(#[from] jsonwebtoken::errors::Error),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TokenError {
+    Expired,
+    /// Signed with a key this instance has not loaded.
+    UnknownKey,
+    Invalid,
+}
+
+#[derive(Default)]
+struct Loaded {
+    signing: Option<(String, Algorithm, EncodingKey)>,
+    verifying: HashMap<String, (Algorithm, DecodingKey)>,
+    published: Vec<Jwk>,
+    reloaded_at: Option<Instant>,
+}
+
+/// The keys an instance signs and verifies with, refreshed from the
+/// database so that every instance agrees on them.
+pub struct KeyRing {
+    config: JwtConfig,
+    /// The longest lifetime of any token signed; a replaced key verifies
+    /// for this long.
+    max_token_lifetime: Duration,
+    legacy: Option<DecodingKey>,
+    loaded: RwLock<Loaded>,
+}
+
+impl KeyRing {
+    /// An empty key ring; `reload` or `maintain` fills it.
+    pub fn new(config: JwtConfig, max_token_lifetime: Duration) -> Self {
+        Self {
+            legacy: config.legacy_secret.as_ref().map(|s| DecodingKey::from_secret(s.as_bytes())),
+            config,
+            max_token_lifetime,
+            loaded: RwLock::new(Loaded::default()),
+        }
+    }
+
+    /// Loads the keys in the database, as of now.
+    pub async fn reload(&self, db: &Database) -> Result<(), KeyError> {
+        let keys = db.get_signing_keys().await?;
+        let now = Utc::now();
+        let mut loaded = Loaded {
+            reloaded_at: Some(Instant::now()),
+            ..Loaded::default()
+        };
+
+        for key in self.live_keys(&keys, now) {
+            let jwk = jwk(key);
+            let algorithm = jwt_algorithm(key.algorithm);
+            let decoding = DecodingKey::from_jwk(&jwk).map_err(|e| KeyError::Invalid(key.id.clone(), e.to_string()))?;
+            loaded.verifying.insert(key.id.clone(), (algorithm, decoding));
+            loaded.published.push(jwk);
+            if key.activates_at <= now {
+                let encoding = match key.algorithm {
+                    SigningAlgorithm::EdDsa => EncodingKey::from_ed_der(&key.private_key),
+                    SigningAlgorithm::Es256 => EncodingKey::from_ec_der(&key.private_key),
+                };
+                loaded.signing = Some((key.id.clone(), algorithm, encoding));
+            }
+        }
+
+        *self.loaded.write().unwrap() = loaded;
+        Ok(())
+    }
+
+    /// Keys that sign now, will sign later, or signed tokens that may not
+    /// have expired yet. `keys` is in order of activation.
+    fn live_keys<'a>(&self, keys: &'a [SigningKey], now: DateTime<Utc>) -> Vec<&'a SigningKey> {
+        keys.iter()
+            .enumerate()
+            .filter(|(i, key)| {
+                // A key stops signing when the next one activates.
+                let replaced_at = keys[i + 1..]
+                    .iter()
+                    .map(|k| k.activates_at)
+                    .find(|at| *at > key.activates_at && *at <= now);
+                match replaced_at {
+                    Some(at) => at + self.max_token_lifetime > now,
+                    None => true,
+                }
+            })
+            .map(|(_, key)| key)
+            .collect()
+    }
+
+    /// Adds a new key of the configured algorithm, published at once and
+    /// signing after the publish delay, or straight away with `immediate`.
+    /// Returns it.
+    pub async fn rotate(&self, db: &Database, immediate: bool) -> Result<SigningKey, KeyError> {
+        let now = Utc::now();
+        let (private_key, public_key) = generate(self.config.algorithm)?;
+        let key = SigningKey {
+            id: Uuid::new_v4().simple().to_string(),
+            algorithm: self.config.algorithm,
+            private_key,
+            public_key,
+            created_at: now,
+            activates_at: if immediate { now } else { now + self.config.publish_delay },
+        };
+        db.create_signing_key(&key).await?;
+        self.reload(db).await?;
+        Ok(key)
+    }
+
+    /// Creates the first key if there is none, rotates when the newest key
+    /// has been signing for the rotation interval, and drops keys nothing
+    /// can be verified with any more. Returns a key it created. Instances
+    /// run this concurrently; at worst two keys are added at once, and
+    /// either one signs.
+    pub async fn maintain(&self, db: &Database) -> Result<Option<SigningKey>, KeyError> {
+        let keys = db.get_signing_keys().await?;
+        let now = Utc::now();
+
+        let created = match keys.last() {
+            None => Some(self.rotate(db, true).await?),
+            Some(newest) if newest.activates_at + self.config.rotation_interval <= now => {
+                Some(self.rotate(db, false).await?)
+            }
+            Some(_) => None,
+        };
+
+        let live: Vec<&str> = self.live_keys(&keys, now).iter().map(|k| k.id.as_str()).collect();
+        for key in keys.iter().filter(|k| !live.contains(&k.id.as_str())) {
+            db.delete_signing_key(&key.id).await?;
+            tracing::info!("Dropped signing key {}", key.id);
+        }
+
+        self.reload(db).await?;
+        Ok(created)
+    }
+
+    /// Signs `claims` with the active key, with `typ` in the header.
+    pub fn sign<T: Serialize>(&self, typ: &str, claims: &T) -> Result<String, KeyError> {
+        let loaded = self.loaded.read().unwrap();
+        let (kid, algorithm, key) = loaded.signing.as_ref().ok_or(KeyError::NoSigningKey)?;
+        let header = Header {
+            typ: Some(typ.to_string()),
+            kid: Some(kid.clone()),
+            ..Header::new(*algorithm)
+        };
+        Ok(encode(&header, claims, key)?)
+    }
+
+    /// Checks the signature, expiry and `typ` of a token and returns its
+    /// claims. Legacy tokens have no `kid` and are accepted whatever their
+    /// `typ`.
+    pub fn verify<T: DeserializeOwned>(&self, token: &str, typ: &str) -> Result<T, TokenError> {
+        let header = decode_header(token).map_err(|_| TokenError::Invalid)?;
+        let loaded = self.loaded.read().unwrap();
+        let (validation, key) = match &header.kid {
+            Some(kid) => {
+                let (algorithm, key) = loaded.verifying.get(kid).ok_or(TokenError::UnknownKey)?;
+                if header.typ.as_deref() != Some(typ) {
+                    return Err(TokenError::Invalid);
+                }
+                (Validation::new(*algorithm), key)
+            }
+            None => (Validation::new(Algorithm::HS256), self.legacy.as_ref().ok_or(TokenError::Invalid)?),
+        };
+
+        decode::<T>(token, key, &validation)
+            .map(|data| data.claims)
+            .map_err(|e| match e.kind() {
+                ErrorKind::ExpiredSignature => TokenError::Expired,
+                _ => TokenError::Invalid,
+            })
+    }
+
+    /// Like `verify`, but reloads first if the token was signed by a key
+    /// another instance just added.
+    pub async fn verify_or_reload<T: DeserializeOwned>(
+        &self,
+        db: &Database,
+        token: &str,
+        typ: &str,
+    ) -> Result<T, TokenError> {
+        match self.verify(token, typ) {
+            Err(TokenError::UnknownKey) => {
+                let reloaded_at = self.loaded.read().unwrap().reloaded_at;
+                if matches!(reloaded_at, Some(at) if at.elapsed() < RELOAD_COOLDOWN) {
+                    return Err(TokenError::UnknownKey);
+                }
+                if let Err(e) = self.reload(db).await {
+                    tracing::error!("Failed to reload signing keys: {}", e);
+                    return Err(TokenError::UnknownKey);
+                }
+                self.verify(token, typ)
+            }
+            result => result,
+        }
+    }
+
+    /// The public half of every live key, for `/.well-known/jwks.json`.
+    pub fn jwks(&self) -> JwkSet {
+        JwkSet {
+            keys: self.loaded.read().unwrap().published.clone(),
+        }
+    }
+}
+
+fn jwt_algorithm(algorithm: SigningAlgorithm) -> Algorithm {
+    match algorithm {
+        SigningAlgorithm::EdDsa => Algorithm::EdDSA,
+        SigningAlgorithm::Es256 => Algorithm::ES256,
+    }
+}
+
+/// Returns the PKCS#8 document and the public key.
+fn generate(algorithm: SigningAlgorithm) -> Result<(Vec<u8>, Vec<u8>), KeyError> {
+    let rng = SystemRandom::new();
+    let error = |e: ring::error::Unspecified| KeyError::Generate(e.to_string());
+    match algorithm {
+        SigningAlgorithm::EdDsa => {
+            let pkcs8 = Ed25519KeyPair::generate_pkcs8(&rng).map_err(error)?;
+            let pair = Ed25519KeyPair::from_pkcs8(pkcs8.as_ref())
+                .map_err(|e| KeyError::Generate(e.to_string()))?;
+            Ok((pkcs8.as_ref().to_vec(), pair.public_key().as_ref().to_vec()))
+        }
+        SigningAlgorithm::Es256 => {
+            let pkcs8 = EcdsaKeyPair::generate_pkcs8(&ECDSA_P256_SHA256_FIXED_SIGNING, &rng).map_err(error)?;
+            let pair = EcdsaKeyPair::from_pkcs8(&ECDSA_P256_SHA256_FIXED_SIGNING, pkcs8.as_ref(), &rng)
+                .map_err(|e| KeyError::Generate(e.to_string()))?;
+            Ok((pkcs8.as_ref().to_vec(), pair.public_key().as_ref().to_vec()))
+        }
+    }
+}
+
+fn jwk(key: &SigningKey) -> Jwk {
+    let common = CommonParameters {
+        public_key_use: Some(PublicKeyUse::Signature),
+        key_id: Some(key.id.clone()),
+        ..CommonParameters::default()
+    };
+    match key.algorithm {
+        SigningAlgorithm::EdDsa => Jwk {
+            common: CommonParameters {
+                key_algorithm: Some(KeyAlgorithm::EdDSA),
+                ..common
+            },
+            algorithm: AlgorithmParameters::OctetKeyPair(OctetKeyPairParameters {
+                curve: EllipticCurve::Ed25519,
+                x: URL_SAFE_NO_PAD.encode(&key.public_key),
+                ..OctetKeyPairParameters::default()
+            }),
+        },
+        SigningAlgorithm::Es256 => {
+            // Uncompressed point: 0x04, then x and y.
+            let point = key.public_key.get(1..).unwrap_or_default();
+            let (x, y) = point.split_at(point.len() / 2);
+            Jwk {
+                common: CommonParameters {
+                    key_algorithm: Some(KeyAlgorithm::ES256),
+                    ..common
+                },
+                algorithm: AlgorithmParameters::EllipticCurve(EllipticCurveKeyParameters {
+                    curve: EllipticCurve::P256,
+                    x: URL_SAFE_NO_PAD.encode(x),
+                    y: URL_SAFE_NO_PAD.encode(y),
+                    ..EllipticCurveKeyParameters::default()
+                }),
+            }
+        }
+    }
+}
@@ -0,0 +1,21 @@
+//! The Pulse server, shared by the `pulse-server` and `pulse-admin`
+//! binaries.
+
+pub mod admin;
+pub mod api;
+pub mod audit;
+pub mod blob_store;
+pub mod config;
+pub mod db;
+pub mod jwt;
+pub mod mailer;
+pub mod models;
+pub mod presence;
+pub mod rate_limit;
+pub mod realtime;
+pub mod telemetry;
+pub mod totp;
+pub mod webhooks;
+
+#[cfg(test)]
+mod tests;
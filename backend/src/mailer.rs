@@ -0,0 +1,149 @@
+use std::env;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use chrono::Utc;
+use lettre::{
+    message::Mailbox,
+    transport::smtp::authentication::Credentials,
+    AsyncSmtpTransport, AsyncTransport, Message, Tokio1Executor,
+};
+use tokio::fs;
+use uuid::Uuid;
+
+use crate::config::env_opt;
+
+#[derive(Debug, thiserror::Error)]
+pub enum MailError {
+    #[error("I/O error: {0}")]
+    IoError(#[from] std::io::Error),
+    #[error("Invalid address: {0}")]
+    InvalidAddress(String),
+    #[error("Invalid message: {0}")]
+    InvalidMessage(#[from] lettre::error::Error),
+    #[error("SMTP error: {0}")]
+    Smtp(#[from] lettre::transport::smtp::Error),
+    #[error("Invalid mail configuration: {0}")]
+    Config(String),
+}
+
+/// A plain-text email to one recipient.
+#[derive(Debug, Clone)]
+pub struct Email {
+    pub to: String,
+    pub subject: String,
+    pub body: String,
+}
+
+#[async_trait]
+pub trait Mailer: Send + Sync {
+    async fn send(&self, email: &Email) -> Result<(), MailError>;
+}
+
+/// Builds the mailer from the environment: SMTP when `SMTP_HOST` is set,
+/// otherwise a `FileMailer` writing to `MAIL_DIR`.
+pub async fn from_env() -> Result<Arc<dyn Mailer>, MailError> {
+    let from = env::var("MAIL_FROM").unwrap_or_else(|_| "Pulse <no-reply@localhost>".to_string());
+
+    match env::var("SMTP_HOST") {
+        Ok(host) => {
+            let credentials = match (env::var("SMTP_USERNAME"), env::var("SMTP_PASSWORD")) {
+                (Ok(username), Ok(password)) => Some(Credentials::new(username, password)),
+                _ => None,
+            };
+            let port = env_opt("SMTP_PORT").map_err(|e| MailError::Config(e.to_string()))?;
+            let tls = env::var("SMTP_TLS").unwrap_or_else(|_| "starttls".to_string());
+            Ok(Arc::new(SmtpMailer::new(&host, port, &tls, credentials, &from)?))
+        }
+        Err(_) => {
+            let dir = env::var("MAIL_DIR").unwrap_or_else(|_| "mail".to_string());
+            tracing::warn!("SMTP_HOST is not set; emails are written to {}", dir);
+            Ok(Arc::new(FileMailer::new(dir).await?))
+        }
+    }
+}
+
+/// Sends through an SMTP relay.
+pub struct SmtpMailer {
+    transport: AsyncSmtpTransport<Tokio1Executor>,
+    from: Mailbox,
+}
+
+impl SmtpMailer {
+    /// `tls` is `starttls` (upgrade a plain connection), `tls` (implicit
+    /// TLS) or `none` (plain text, for local relays only).
+    pub fn new(
+        host: &str,
+        port: Option<u16>,
+        tls: &str,
+        credentials: Option<Credentials>,
+        from: &str,
+    ) -> Result<Self, MailError> {
+        let mut builder = match tls {
+            "starttls" => AsyncSmtpTransport::<Tokio1Executor>::starttls_relay(host)?,
+            "tls" => AsyncSmtpTransport::<Tokio1Executor>::relay(host)?,
+            "none" => AsyncSmtpTransport::<Tokio1Executor>::builder_dangerous(host),
+            other => return Err(MailError::Config(format!("Unknown SMTP_TLS mode: {}", other))),
+        };
+        if let Some(port) = port {
+            builder = builder.port(port);
+        }
+        if let Some(credentials) = credentials {
+            builder = builder.credentials(credentials);
+        }
+
+        Ok(Self {
+            transport: builder.build(),
+            from: from.parse().map_err(|_| MailError::InvalidAddress(from.to_string()))?,
+        })
+    }
+}
+
+#[async_trait]
+impl Mailer for SmtpMailer {
+    async fn send(&self, email: &Email) -> Result<(), MailError> {
+        let to: Mailbox = email
+            .to
+            .parse()
+            .map_err(|_| MailError::InvalidAddress(email.to.clone()))?;
+        let message = Message::builder()
+            .from(self.from.clone())
+            .to(to)
+            .subject(email.subject.clone())
+            .body(email.body.clone())?;
+
+        self.transport.send(message).await?;
+        Ok(())
+    }
+}
+
+/// Writes each email to a file under `dir` instead of sending it, for local
+/// development and tests.
+pub struct FileMailer {
+    dir: PathBuf,
+}
+
+impl FileMailer {
+    pub async fn new(dir: impl Into<PathBuf>) -> Result<Self, MailError> {
+        let dir = dir.into();
+        fs::create_dir_all(&dir).await?;
+        Ok(Self { dir })
+    }
+}
+
+#[async_trait]
+impl Mailer for FileMailer {
+    async fn send(&self, email: &Email) -> Result<(), MailError> {
+        let path = self.dir.join(format!(
+            "{}-{}.eml",
+            Utc::now().timestamp_millis(),
+            Uuid::new_v4().simple()
+        ));
+        let contents = format!("To: {}\nSubject: {}\n\n{}", email.to, email.subject, email.body);
+        fs::write(&path, contents).await?;
+
+        tracing::info!("Wrote email \"{}\" for {} to {}", email.subject, email.to, path.display());
+        Ok(())
+    }
+}
@@ -1,53 +1,188 @@
-mod models;
-mod db;
-mod api;
-
 use tokio;
 use tracing::{info, Level};
 use tracing_subscriber::FmtSubscriber;
 use dotenv::dotenv;
 use std::env;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use pulse_backend::{api, blob_store, config, db, jwt, mailer, presence, rate_limit, realtime, telemetry, webhooks};
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    match env::args().nth(1).as_deref() {
+        None | Some("serve") => {}
+        Some(other) => {
+            return Err(format!("Unknown command: {} (maintenance commands are in pulse-admin)", other).into())
+        }
+    }
+
     // Load environment variables
     dotenv().ok();
-    env_logger::init();
 
-    // Initialize logging
-    let subscriber = FmtSubscriber::builder()
+    // Initialize logging; records from crates that use `log` are forwarded
+    // to the same subscriber.
+    FmtSubscriber::builder()
         .with_max_level(Level::INFO)
-        .finish();
-    tracing::subscriber::set_global_default(subscriber)?;
+        .try_init()
+        .map_err(|e| e as Box<dyn std::error::Error>)?;
 
     info!("Starting Pulse backend server...");
+    telemetry::handle();
+
+    let config = config::ServerConfig::load()?;
 
     // Initialize database
-    let database_url = env::var("DATABASE_URL")
-        .expect("DATABASE_URL must be set");
-    let db = db::Database::new(&database_url).await?;
-    db.init().await?;
+    let db = db::connect(&config.database_url).await?;
+
+    let applied = db.migrate().await?;
+    if !applied.is_empty() {
+        info!("Applied {} migration(s)", applied.len());
+    }
+
+    // Load the token signing keys, creating the first on a new database
+    let sessions = api::SessionConfig::from_env()?;
+    let keys = Arc::new(jwt::KeyRing::new(
+        jwt::JwtConfig::from_env(config.jwt_secret.clone())?,
+        sessions.max_token_lifetime(),
+    ));
+    if let Some(key) = keys.maintain(&db).await? {
+        info!("Created signing key {}", key.id);
+    }
 
     // Initialize API state
-    let jwt_secret = env::var("JWT_SECRET")
-        .expect("JWT_SECRET must be set");
+    let rate_limiter = Arc::new(rate_limit::RateLimiter::new(
+        rate_limit::RateLimitConfig::from_env()?,
+        keys.clone(),
+    ));
+    let bots = api::BotConfig::from_env()?;
     let state = api::AppState {
         db,
-        jwt_secret,
+        keys,
+        blobs: Arc::new(blob_store::FsBlobStore::new(&config.attachment_dir).await?),
+        attachments: api::AttachmentConfig::from_env()?,
+        rate_limiter,
+        prekeys: api::PrekeyConfig::from_env()?,
+        sessions,
+        hub: Arc::new(realtime::Hub::new()),
+        presence: Arc::new(presence::Presence::new()),
+        mailer: mailer::from_env().await?,
+        email: api::EmailConfig::from_env()?,
+        account: api::AccountConfig::from_env()?,
+        messages: api::MessageConfig::from_env()?,
+        webhooks: Arc::new(webhooks::WebhookSender::new(bots.webhook_timeout, bots.allow_insecure_webhooks)),
+        bots,
+        limits: config.limits.clone(),
     };
 
+    // Pick up keys other instances added, rotate when due and drop keys
+    // whose tokens have all expired
+    let key_state = state.clone();
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(Duration::from_secs(60));
+        loop {
+            interval.tick().await;
+            match key_state.keys.maintain(&key_state.db).await {
+                Ok(Some(key)) => info!("Created signing key {}, signing from {}", key.id, key.activates_at),
+                Ok(None) => {}
+                Err(e) => tracing::error!("Signing key maintenance failed: {}", e),
+            }
+        }
+    });
+
+    // Periodically remove expired attachments, abandoned uploads, dead
+    // sessions, expired email tokens and accounts due for deletion
+    let purge_state = state.clone();
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(Duration::from_secs(600));
+        loop {
+            interval.tick().await;
+            match api::purge_expired_attachments(&purge_state.db, purge_state.blobs.as_ref()).await {
+                Ok(0) => {}
+                Ok(n) => info!("Purged {} expired attachment(s)", n),
+                Err(e) => tracing::error!("Attachment purge failed: {}", e),
+            }
+            match purge_state.db.purge_sessions().await {
+                Ok(0) => {}
+                Ok(n) => info!("Purged {} expired or revoked session(s)", n),
+                Err(e) => tracing::error!("Session purge failed: {}", e),
+            }
+            match purge_state.db.purge_email_tokens().await {
+                Ok(0) => {}
+                Ok(n) => info!("Purged {} expired email token(s)", n),
+                Err(e) => tracing::error!("Email token purge failed: {}", e),
+            }
+            match api::purge_deleted_accounts(&purge_state.db, purge_state.blobs.as_ref(), &purge_state.hub).await {
+                Ok(purge) => {
+                    if purge.deleted > 0 {
+                        info!("Deleted {} account(s) at the end of their grace period", purge.deleted);
+                    }
+                    if purge.failed > 0 {
+                        tracing::error!("Failed to delete {} account(s); retrying next run", purge.failed);
+                    }
+                }
+                Err(e) => tracing::error!("Account deletion failed: {}", e),
+            }
+        }
+    });
+
     // Create and start the API server
-    let app = api::create_router(state);
-    let addr = format!(
-        "{}:{}",
-        env::var("SERVER_HOST").unwrap_or_else(|_| "127.0.0.1".to_string()),
-        env::var("SERVER_PORT").unwrap_or_else(|_| "8080".to_string())
-    );
-    
-    info!("Server running on {}", addr);
-    axum::Server::bind(&addr.parse()?)
-        .serve(app.into_make_service())
-        .await?;
+    let hub = state.hub.clone();
+    let app = api::create_router(state).into_make_service_with_connect_info::<SocketAddr>();
+    let handle = axum_server::Handle::new();
+    tokio::spawn(drain_on_shutdown(handle.clone(), hub, config.limits.shutdown_grace));
+
+    match &config.tls {
+        Some(tls) => {
+            let rustls = axum_server::tls_rustls::RustlsConfig::from_pem_file(&tls.cert_path, &tls.key_path)
+                .await
+                .map_err(|e| format!("Cannot load TLS certificate or key: {}", e))?;
+            info!("Server running on https://{}", config.listen);
+            axum_server::bind_rustls(config.listen, rustls).handle(handle).serve(app).await?;
+        }
+        None => {
+            info!("Server running on http://{}", config.listen);
+            axum_server::bind(config.listen).handle(handle).serve(app).await?;
+        }
+    }
 
+    info!("Server stopped");
     Ok(())
-} 
\ No newline at end of file
+}
+
+/// Waits for SIGTERM or Ctrl-C, then stops accepting connections and gives
+/// in-flight requests `grace` to finish. Event streams are closed at once,
+/// since they would otherwise hold the drain open; clients reconnect to
+/// another instance.
+async fn drain_on_shutdown(handle: axum_server::Handle, hub: Arc<realtime::Hub>, grace: Duration) {
+    let ctrl_c = async {
+        if let Err(e) = tokio::signal::ctrl_c().await {
+            tracing::error!("Cannot listen for Ctrl-C: {}", e);
+            std::future::pending::<()>().await;
+        }
+    };
+    #[cfg(unix)]
+    let terminate = async {
+        match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate()) {
+            Ok(mut signal) => {
+                signal.recv().await;
+            }
+            Err(e) => {
+                tracing::error!("Cannot listen for SIGTERM: {}", e);
+                std::future::pending::<()>().await;
+            }
+        }
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => {}
+        _ = terminate => {}
+    }
+
+    info!("Shutting down; waiting up to {}s for in-flight requests", grace.as_secs());
+    hub.disconnect_all();
+    handle.graceful_shutdown(Some(grace));
+}
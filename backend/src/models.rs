@@ -10,6 +10,20 @@ pub struct User {
     pub public_key: Vec<u8>,
     pub created_at: DateTime<Utc>,
     pub last_seen: DateTime<Utc>,
+    #[serde(skip)]
+    pub password_hash: Option<String>,
+    /// `None` until the user follows the link in the verification email.
+    pub email_verified_at: Option<DateTime<Utc>>,
+    /// Set while a requested deletion is pending; the account is removed
+    /// once this has passed.
+    pub delete_after: Option<DateTime<Utc>>,
+    /// Admins can review abuse reports and suspend accounts.
+    pub is_admin: bool,
+    /// Suspended accounts cannot log in.
+    pub suspended_at: Option<DateTime<Utc>>,
+    /// Set for bot accounts: the user who manages the bot.
+    #[serde(default)]
+    pub bot_owner_id: Option<Uuid>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -21,6 +35,72 @@ pub struct Message {
     pub associated_data: Option<Vec<u8>>,
     pub created_at: DateTime<Utc>,
     pub expires_at: Option<DateTime<Utc>>,
+    #[serde(default)]
+    pub kind: MessageKind,
+    /// The message an edit, deletion or reaction refers to.
+    #[serde(default)]
+    pub target_id: Option<Uuid>,
+    /// The chat the message was sent in, if any.
+    #[serde(default)]
+    pub chat_id: Option<Uuid>,
+    /// The thread within the chat. Thread ids are chosen by clients; the
+    /// message being replied to is only named in the encrypted content.
+    #[serde(default)]
+    pub thread_id: Option<Uuid>,
+}
+
+/// Visible to the server so that it can route and check control messages;
+/// what they say is encrypted like any other content.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MessageKind {
+    #[default]
+    Message,
+    /// Tells the sender that their messages were delivered or read.
+    Receipt,
+    /// New content for `target_id`. Only its sender may edit it, for a
+    /// limited time.
+    Edit,
+    /// Retracts `target_id` for everyone. The server deletes the original.
+    Delete,
+    /// A reaction to `target_id`, or its removal; either way encrypted.
+    Reaction,
+}
+
+impl MessageKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            MessageKind::Message => "message",
+            MessageKind::Receipt => "receipt",
+            MessageKind::Edit => "edit",
+            MessageKind::Delete => "delete",
+            MessageKind::Reaction => "reaction",
+        }
+    }
+
+    pub fn parse(s: &str) -> Option<Self> {
+        match s {
+            "message" => Some(MessageKind::Message),
+            "receipt" => Some(MessageKind::Receipt),
+            "edit" => Some(MessageKind::Edit),
+            "delete" => Some(MessageKind::Delete),
+            "reaction" => Some(MessageKind::Reaction),
+            _ => None,
+        }
+    }
+}
+
+/// What the server knows about a message without its (encrypted) content.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MessageMetadata {
+    pub id: Uuid,
+    pub sender_id: Uuid,
+    pub recipient_id: Uuid,
+    /// Size of the encrypted content in bytes.
+    pub size: i64,
+    pub created_at: DateTime<Utc>,
+    pub expires_at: Option<DateTime<Utc>>,
+    pub kind: MessageKind,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -28,7 +108,67 @@ pub struct Chat {
     pub id: Uuid,
     pub name: Option<String>,
     pub is_group: bool,
+    /// A group where only admins post and everyone else is a read-only
+    /// subscriber.
+    #[serde(default)]
+    pub is_channel: bool,
+    #[serde(flatten)]
+    pub settings: ChatSettings,
+    pub created_at: DateTime<Utc>,
+    pub last_message_at: DateTime<Utc>,
+}
+
+/// What the chat's admins set for every member.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct ChatSettings {
+    #[serde(default)]
+    pub topic: Option<String>,
+    /// Chosen by clients, e.g. an attachment id and its key; the server
+    /// does not look inside.
+    #[serde(default)]
+    pub avatar: Option<String>,
+    /// Messages sent to the chat without an expiry of their own expire this
+    /// long after they are sent.
+    #[serde(default)]
+    pub disappear_after_seconds: Option<i64>,
+}
+
+/// A pinned message of a chat. `content` names the message, encrypted
+/// under the chat's key, because each member holds a copy with its own id.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ChatPin {
+    pub id: Uuid,
+    pub chat_id: Uuid,
+    pub pinned_by: Uuid,
+    pub content: Vec<u8>,
+    pub pinned_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StarredMessage {
+    pub message_id: Uuid,
+    pub starred_at: DateTime<Utc>,
+}
+
+/// A message to all of a channel's subscribers, stored once.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ChannelPost {
+    pub id: Uuid,
+    pub chat_id: Uuid,
+    pub sender_id: Uuid,
+    pub content: Vec<u8>, // Encrypted under the channel's key
+    pub associated_data: Option<Vec<u8>>,
     pub created_at: DateTime<Utc>,
+}
+
+/// A thread of a chat as one member sees it: only messages addressed to
+/// them count.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ThreadSummary {
+    pub thread_id: Uuid,
+    pub message_count: i64,
+    /// Messages that arrived after the member last marked the thread read.
+    pub unread_count: i64,
     pub last_message_at: DateTime<Utc>,
 }
 
@@ -38,31 +178,464 @@ pub struct ChatMember {
     pub user_id: Uuid,
     pub role: ChatRole,
     pub joined_at: DateTime<Utc>,
+    /// Only ever shown to the member themselves.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub muted_until: Option<DateTime<Utc>>,
+}
+
+/// A link for joining a group, usable until it expires, runs out of uses or
+/// is revoked.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ChatInvite {
+    pub id: Uuid,
+    pub chat_id: Uuid,
+    pub code: String,
+    pub created_by: Uuid,
+    pub created_at: DateTime<Utc>,
+    pub expires_at: Option<DateTime<Utc>>,
+    pub max_uses: Option<i64>,
+    pub use_count: i64,
+    /// Joining only files a request that an admin has to approve.
+    pub requires_approval: bool,
+    pub revoked_at: Option<DateTime<Utc>>,
+}
+
+impl ChatInvite {
+    pub fn is_usable(&self, now: DateTime<Utc>) -> bool {
+        self.revoked_at.is_none()
+            && self.expires_at.is_none_or(|e| e > now)
+            && self.max_uses.is_none_or(|max| self.use_count < max)
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct JoinRequest {
+    pub chat_id: Uuid,
+    pub user_id: Uuid,
+    pub invite_id: Uuid,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
 pub enum ChatRole {
     Admin,
     Member,
     ReadOnly,
 }
 
+impl ChatRole {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ChatRole::Admin => "admin",
+            ChatRole::Member => "member",
+            ChatRole::ReadOnly => "read_only",
+        }
+    }
+
+    pub fn parse(s: &str) -> Option<Self> {
+        match s {
+            "admin" => Some(ChatRole::Admin),
+            "member" => Some(ChatRole::Member),
+            "read_only" => Some(ChatRole::ReadOnly),
+            _ => None,
+        }
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Device {
     pub id: Uuid,
     pub user_id: Uuid,
     pub name: String,
     pub public_key: Vec<u8>,
+    /// Updated when an event stream of the device closes.
     pub last_seen: DateTime<Utc>,
+    /// Not maintained in the database; see `presence::Presence` for who is
+    /// connected.
     pub is_online: bool,
 }
 
+/// A credential a bot uses instead of a session. It stands for one of the
+/// bot's devices.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ApiKey {
+    pub id: Uuid,
+    pub bot_id: Uuid,
+    pub device_id: Uuid,
+    /// SHA-256 of the key.
+    #[serde(skip_serializing)]
+    pub key_hash: String,
+    pub created_at: DateTime<Utc>,
+    pub revoked_at: Option<DateTime<Utc>>,
+}
+
+/// A key pair that signs access tokens. `id` goes into the `kid` header of
+/// the tokens it signs.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SigningKey {
+    pub id: String,
+    pub algorithm: SigningAlgorithm,
+    /// PKCS#8 document.
+    #[serde(skip_serializing)]
+    pub private_key: Vec<u8>,
+    /// Ed25519 public key, or uncompressed P-256 point.
+    pub public_key: Vec<u8>,
+    pub created_at: DateTime<Utc>,
+    /// Published from creation, used for signing from this time on.
+    pub activates_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum SigningAlgorithm {
+    #[serde(rename = "EdDSA")]
+    EdDsa,
+    #[serde(rename = "ES256")]
+    Es256,
+}
+
+impl SigningAlgorithm {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SigningAlgorithm::EdDsa => "EdDSA",
+            SigningAlgorithm::Es256 => "ES256",
+        }
+    }
+
+    pub fn parse(s: &str) -> Option<Self> {
+        match s {
+            "EdDSA" => Some(SigningAlgorithm::EdDsa),
+            "ES256" => Some(SigningAlgorithm::Es256),
+            _ => None,
+        }
+    }
+}
+
+/// An entry in the audit log. `hash` covers every other field, including
+/// `prev_hash`, the hash of the entry before it.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AuditEvent {
+    /// Position in the log, counting from 1 without gaps.
+    pub id: i64,
+    pub kind: AuditKind,
+    /// The account concerned, if known.
+    pub user_id: Option<Uuid>,
+    /// Who acted on the account, if not its user: an admin, a chat admin or
+    /// a bot's owner. Unset for the user, the server and operators.
+    pub actor_id: Option<Uuid>,
+    pub device_id: Option<Uuid>,
+    /// Kind-specific fields, such as the reason sessions were revoked.
+    pub details: serde_json::Value,
+    pub created_at: DateTime<Utc>,
+    pub prev_hash: String,
+    pub hash: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AuditKind {
+    /// Signed in; every login registers a new device.
+    Login,
+    /// A wrong password or second factor, or a suspended account.
+    LoginFailed,
+    /// A bot API key was issued, with a device of its own.
+    DeviceRegistered,
+    DeviceRemoved,
+    SessionsRevoked,
+    /// Admin rights, or a role in a chat, were granted or taken away.
+    RoleChanged,
+    AccountSuspended,
+    AccountUnsuspended,
+    PasswordChanged,
+    TwoFactorEnabled,
+    TwoFactorDisabled,
+    DeletionRequested,
+    DeletionCancelled,
+    AccountDeleted,
+}
+
+impl AuditKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AuditKind::Login => "login",
+            AuditKind::LoginFailed => "login_failed",
+            AuditKind::DeviceRegistered => "device_registered",
+            AuditKind::DeviceRemoved => "device_removed",
+            AuditKind::SessionsRevoked => "sessions_revoked",
+            AuditKind::RoleChanged => "role_changed",
+            AuditKind::AccountSuspended => "account_suspended",
+            AuditKind::AccountUnsuspended => "account_unsuspended",
+            AuditKind::PasswordChanged => "password_changed",
+            AuditKind::TwoFactorEnabled => "two_factor_enabled",
+            AuditKind::TwoFactorDisabled => "two_factor_disabled",
+            AuditKind::DeletionRequested => "deletion_requested",
+            AuditKind::DeletionCancelled => "deletion_cancelled",
+            AuditKind::AccountDeleted => "account_deleted",
+        }
+    }
+
+    pub fn parse(s: &str) -> Option<Self> {
+        match s {
+            "login" => Some(AuditKind::Login),
+            "login_failed" => Some(AuditKind::LoginFailed),
+            "device_registered" => Some(AuditKind::DeviceRegistered),
+            "device_removed" => Some(AuditKind::DeviceRemoved),
+            "sessions_revoked" => Some(AuditKind::SessionsRevoked),
+            "role_changed" => Some(AuditKind::RoleChanged),
+            "account_suspended" => Some(AuditKind::AccountSuspended),
+            "account_unsuspended" => Some(AuditKind::AccountUnsuspended),
+            "password_changed" => Some(AuditKind::PasswordChanged),
+            "two_factor_enabled" => Some(AuditKind::TwoFactorEnabled),
+            "two_factor_disabled" => Some(AuditKind::TwoFactorDisabled),
+            "deletion_requested" => Some(AuditKind::DeletionRequested),
+            "deletion_cancelled" => Some(AuditKind::DeletionCancelled),
+            "account_deleted" => Some(AuditKind::AccountDeleted),
+            _ => None,
+        }
+    }
+}
+
+/// Where messages addressed to a bot are posted.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BotWebhook {
+    pub bot_id: Uuid,
+    pub url: String,
+    /// Signs each delivery; shown once, when the webhook is set.
+    #[serde(skip_serializing)]
+    pub secret: String,
+    pub created_at: DateTime<Utc>,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Session {
     pub id: Uuid,
     pub user_id: Uuid,
     pub device_id: Uuid,
-    pub token: String,
+    /// SHA-256 of the current refresh token.
+    #[serde(skip_serializing)]
+    pub refresh_token_hash: String,
+    /// SHA-256 of the refresh token it replaced; presenting that one again
+    /// means the token was stolen.
+    #[serde(skip_serializing)]
+    pub previous_refresh_token_hash: Option<String>,
+    pub created_at: DateTime<Utc>,
+    pub last_used_at: Option<DateTime<Utc>>,
+    pub expires_at: DateTime<Utc>,
+    pub revoked_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Attachment {
+    pub id: String,
+    pub uploader_id: Uuid,
+    pub size: i64,
+    pub received: i64,
+    pub created_at: DateTime<Utc>,
+    pub completed_at: Option<DateTime<Utc>>,
+    pub expires_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SignedPrekey {
+    pub device_id: Uuid,
+    pub key_id: i64,
+    pub public_key: Vec<u8>,
+    /// Signature over `public_key` by the device identity key. Verified by
+    /// peers, not by the server.
+    pub signature: Vec<u8>,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct OneTimePrekey {
+    pub key_id: i64,
+    pub public_key: Vec<u8>,
+}
+
+/// Everything a peer needs to start a session with one device.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PrekeyBundle {
+    pub device_id: Uuid,
+    pub identity_key: Vec<u8>,
+    pub signed_prekey: SignedPrekey,
+    /// `None` once the device has run out; the handshake then proceeds
+    /// without one.
+    pub one_time_prekey: Option<OneTimePrekey>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TotpSecret {
+    pub user_id: Uuid,
+    #[serde(skip_serializing)]
+    pub secret: Vec<u8>,
+    pub created_at: DateTime<Utc>,
+    /// `None` until enrollment is confirmed with a valid code.
+    pub enabled_at: Option<DateTime<Utc>>,
+    pub last_used_step: Option<i64>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EmailTokenPurpose {
+    VerifyEmail,
+    ResetPassword,
+}
+
+impl EmailTokenPurpose {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            EmailTokenPurpose::VerifyEmail => "verify_email",
+            EmailTokenPurpose::ResetPassword => "reset_password",
+        }
+    }
+}
+
+/// A single-use token sent by email. Only its hash is stored.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EmailToken {
+    pub token_hash: String,
+    pub user_id: Uuid,
+    pub purpose: EmailTokenPurpose,
     pub created_at: DateTime<Utc>,
     pub expires_at: DateTime<Utc>,
-} 
\ No newline at end of file
+}
+
+/// What other users may see of an account.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct UserProfile {
+    pub id: Uuid,
+    pub username: String,
+    pub public_key: Vec<u8>,
+    /// Bots are labelled, so that people know who they are talking to.
+    pub is_bot: bool,
+}
+
+impl From<User> for UserProfile {
+    fn from(user: User) -> Self {
+        Self {
+            id: user.id,
+            username: user.username,
+            public_key: user.public_key,
+            is_bot: user.bot_owner_id.is_some(),
+        }
+    }
+}
+
+/// Whether other users can find the account by exact username or by the
+/// hash of its email address, and whether they can see when it is online.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PrivacySettings {
+    pub discoverable_by_username: bool,
+    pub discoverable_by_email: bool,
+    /// Hides both the online status and the last-seen time.
+    #[serde(default = "default_true")]
+    pub show_last_seen: bool,
+    /// Receipts are end-to-end encrypted, so this is honoured by the user's
+    /// own clients: with it off they neither send nor show read receipts.
+    #[serde(default = "default_true")]
+    pub send_read_receipts: bool,
+}
+
+fn default_true() -> bool {
+    true
+}
+
+/// An entry in a user's contact list.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Contact {
+    pub user_id: Uuid,
+    pub contact: UserProfile,
+    pub nickname: Option<String>,
+    pub added_at: DateTime<Utc>,
+}
+
+/// A user the owner of the list does not want messages from.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Block {
+    pub user_id: Uuid,
+    pub blocked: UserProfile,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ReportCategory {
+    Spam,
+    Harassment,
+    Impersonation,
+    IllegalContent,
+    Other,
+}
+
+impl ReportCategory {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ReportCategory::Spam => "spam",
+            ReportCategory::Harassment => "harassment",
+            ReportCategory::Impersonation => "impersonation",
+            ReportCategory::IllegalContent => "illegal_content",
+            ReportCategory::Other => "other",
+        }
+    }
+
+    pub fn parse(s: &str) -> Option<Self> {
+        match s {
+            "spam" => Some(ReportCategory::Spam),
+            "harassment" => Some(ReportCategory::Harassment),
+            "impersonation" => Some(ReportCategory::Impersonation),
+            "illegal_content" => Some(ReportCategory::IllegalContent),
+            "other" => Some(ReportCategory::Other),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ReportStatus {
+    Open,
+    /// Reviewed; no action taken.
+    Dismissed,
+    /// Reviewed; the reported account was suspended.
+    Actioned,
+}
+
+impl ReportStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ReportStatus::Open => "open",
+            ReportStatus::Dismissed => "dismissed",
+            ReportStatus::Actioned => "actioned",
+        }
+    }
+
+    pub fn parse(s: &str) -> Option<Self> {
+        match s {
+            "open" => Some(ReportStatus::Open),
+            "dismissed" => Some(ReportStatus::Dismissed),
+            "actioned" => Some(ReportStatus::Actioned),
+            _ => None,
+        }
+    }
+}
+
+/// An abuse report against a user, optionally about one message.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Report {
+    pub id: Uuid,
+    /// `None` once the reporter's account is deleted.
+    pub reporter_id: Option<Uuid>,
+    /// `None` once the reported account is deleted.
+    pub reported_id: Option<Uuid>,
+    pub message_id: Option<Uuid>,
+    pub category: ReportCategory,
+    pub description: Option<String>,
+    /// Message plaintext supplied by the reporter. The server only has the
+    /// ciphertext and cannot confirm it.
+    pub evidence: Option<String>,
+    pub status: ReportStatus,
+    pub created_at: DateTime<Utc>,
+    pub resolved_at: Option<DateTime<Utc>>,
+    pub resolved_by: Option<Uuid>,
+    pub resolution_note: Option<String>,
+}
@@ -0,0 +1,83 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+use uuid::Uuid;
+
+/// Which users are connected right now, and from which devices. Kept in
+/// memory only, so each server instance knows only its own connections;
+/// last-seen times are written to the database when a connection closes,
+/// not while it is open.
+#[derive(Default)]
+pub struct Presence {
+    /// Open event streams per device, grouped by user.
+    users: Mutex<HashMap<Uuid, HashMap<Uuid, usize>>>,
+}
+
+impl Presence {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Registers an open event stream. Returns whether it is the user's
+    /// first, i.e. the user just came online.
+    pub fn connect(&self, user_id: Uuid, device_id: Uuid) -> bool {
+        let mut users = self.users.lock().unwrap();
+        let devices = users.entry(user_id).or_default();
+        let came_online = devices.is_empty();
+        *devices.entry(device_id).or_default() += 1;
+        came_online
+    }
+
+    /// Unregisters an event stream. Returns whether it was the user's last,
+    /// i.e. the user just went offline.
+    pub fn disconnect(&self, user_id: Uuid, device_id: Uuid) -> bool {
+        let mut users = self.users.lock().unwrap();
+        let Some(devices) = users.get_mut(&user_id) else {
+            return false;
+        };
+        if let Some(count) = devices.get_mut(&device_id) {
+            *count -= 1;
+            if *count == 0 {
+                devices.remove(&device_id);
+            }
+        }
+        if devices.is_empty() {
+            users.remove(&user_id);
+            true
+        } else {
+            false
+        }
+    }
+
+    /// How many users have at least one open event stream.
+    pub fn online_count(&self) -> usize {
+        self.users.lock().unwrap().len()
+    }
+
+    pub fn is_online(&self, user_id: Uuid) -> bool {
+        self.users.lock().unwrap().contains_key(&user_id)
+    }
+
+    pub fn is_device_online(&self, user_id: Uuid, device_id: Uuid) -> bool {
+        self.users
+            .lock()
+            .unwrap()
+            .get(&user_id)
+            .is_some_and(|devices| devices.contains_key(&device_id))
+    }
+
+    /// Users with at least one open event stream.
+    pub fn online_users(&self) -> Vec<Uuid> {
+        self.users.lock().unwrap().keys().copied().collect()
+    }
+
+    /// The user's devices that have at least one open event stream.
+    pub fn online_devices(&self, user_id: Uuid) -> Vec<Uuid> {
+        self.users
+            .lock()
+            .unwrap()
+            .get(&user_id)
+            .map(|devices| devices.keys().copied().collect())
+            .unwrap_or_default()
+    }
+}
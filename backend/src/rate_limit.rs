@@ -0,0 +1,372 @@
+use std::collections::HashMap;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+
+use axum::{
+    body::Body,
+    extract::{ConnectInfo, MatchedPath},
+    http::{header, Method, Request},
+    response::{IntoResponse, Response},
+};
+use futures::future::BoxFuture;
+use tower::{Layer, Service};
+use uuid::Uuid;
+
+use crate::api::{ApiError, Claims};
+use crate::config::{env_flag, env_opt, ConfigError};
+use crate::jwt::{KeyRing, ACCESS_TOKEN_TYPE};
+
+/// `capacity` requests per `period`, refilled continuously. A full bucket
+/// allows a burst of `capacity` requests.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Quota {
+    pub capacity: u32,
+    pub period: Duration,
+}
+
+impl Quota {
+    pub const fn new(capacity: u32, period_secs: u64) -> Self {
+        Self {
+            capacity,
+            period: Duration::from_secs(period_secs),
+        }
+    }
+
+    fn refill_per_sec(&self) -> f64 {
+        self.capacity as f64 / self.period.as_secs_f64()
+    }
+
+    /// Parses `"<capacity>/<period in seconds>"`, e.g. `"10/60"`.
+    pub fn parse(s: &str) -> Option<Self> {
+        let (capacity, period) = s.split_once('/')?;
+        let capacity = capacity.trim().parse().ok()?;
+        let period: u64 = period.trim().parse().ok()?;
+        (capacity > 0 && period > 0).then(|| Self::new(capacity, period))
+    }
+}
+
+/// Which quota a request is charged against.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum RouteClass {
+    Signup,
+    Login,
+    SendMessage,
+    /// User lookup and contact discovery, limited separately to slow down
+    /// enumeration of accounts.
+    Discovery,
+    Authenticated,
+    Anonymous,
+}
+
+impl RouteClass {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            RouteClass::Signup => "signup",
+            RouteClass::Login => "login",
+            RouteClass::SendMessage => "send_message",
+            RouteClass::Discovery => "discovery",
+            RouteClass::Authenticated => "authenticated",
+            RouteClass::Anonymous => "anonymous",
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct RateLimitConfig {
+    pub signup: Quota,
+    pub login: Quota,
+    pub send_message: Quota,
+    pub discovery: Quota,
+    pub authenticated: Quota,
+    pub anonymous: Quota,
+    /// Take the client address from the first `X-Forwarded-For` entry. Only
+    /// enable behind a proxy that sets it.
+    pub trust_forwarded_for: bool,
+}
+
+impl Default for RateLimitConfig {
+    fn default() -> Self {
+        Self {
+            signup: Quota::new(5, 3600),
+            login: Quota::new(10, 300),
+            send_message: Quota::new(120, 60),
+            discovery: Quota::new(30, 300),
+            authenticated: Quota::new(300, 60),
+            anonymous: Quota::new(60, 60),
+            trust_forwarded_for: false,
+        }
+    }
+}
+
+impl RateLimitConfig {
+    pub fn from_env() -> Result<Self, ConfigError> {
+        let defaults = Self::default();
+        let quota = |name: &'static str, default: Quota| match env_opt::<String>(name)? {
+            None => Ok(default),
+            Some(v) => Quota::parse(&v).ok_or_else(|| {
+                ConfigError::Invalid(name, format!("{} is not <requests>/<seconds>, both positive", v))
+            }),
+        };
+
+        Ok(Self {
+            signup: quota("RATE_LIMIT_SIGNUP", defaults.signup)?,
+            login: quota("RATE_LIMIT_LOGIN", defaults.login)?,
+            send_message: quota("RATE_LIMIT_SEND_MESSAGE", defaults.send_message)?,
+            discovery: quota("RATE_LIMIT_DISCOVERY", defaults.discovery)?,
+            authenticated: quota("RATE_LIMIT_AUTHENTICATED", defaults.authenticated)?,
+            anonymous: quota("RATE_LIMIT_ANONYMOUS", defaults.anonymous)?,
+            trust_forwarded_for: env_flag("RATE_LIMIT_TRUST_FORWARDED_FOR", defaults.trust_forwarded_for)?,
+        })
+    }
+
+    fn quota(&self, class: RouteClass) -> Quota {
+        match class {
+            RouteClass::Signup => self.signup,
+            RouteClass::Login => self.login,
+            RouteClass::SendMessage => self.send_message,
+            RouteClass::Discovery => self.discovery,
+            RouteClass::Authenticated => self.authenticated,
+            RouteClass::Anonymous => self.anonymous,
+        }
+    }
+}
+
+/// Who a request is charged to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum RateKey {
+    Ip(IpAddr),
+    Device { user_id: Uuid, device_id: Option<Uuid> },
+    /// No usable address (e.g. in tests); all such requests share a bucket.
+    Unknown,
+}
+
+impl RateKey {
+    fn kind(&self) -> &'static str {
+        match self {
+            RateKey::Ip(_) => "ip",
+            RateKey::Device { .. } => "device",
+            RateKey::Unknown => "unknown",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct TokenBucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+impl TokenBucket {
+    pub fn full(quota: Quota, now: Instant) -> Self {
+        Self {
+            tokens: quota.capacity as f64,
+            updated: now,
+        }
+    }
+
+    /// Takes one token, or returns how long until one is available.
+    pub fn try_take(&mut self, quota: Quota, now: Instant) -> Result<(), Duration> {
+        let elapsed = now.saturating_duration_since(self.updated).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * quota.refill_per_sec()).min(quota.capacity as f64);
+        self.updated = now;
+
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            Ok(())
+        } else {
+            Err(Duration::from_secs_f64((1.0 - self.tokens) / quota.refill_per_sec()))
+        }
+    }
+
+    fn is_idle(&self, quota: Quota, now: Instant) -> bool {
+        now.saturating_duration_since(self.updated) >= quota.period
+    }
+}
+
+pub struct RateLimiter {
+    config: RateLimitConfig,
+    keys: Arc<KeyRing>,
+    buckets: Mutex<HashMap<(RouteClass, RateKey), TokenBucket>>,
+}
+
+impl RateLimiter {
+    pub fn new(config: RateLimitConfig, keys: Arc<KeyRing>) -> Self {
+        Self {
+            config,
+            keys,
+            buckets: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn check(&self, class: RouteClass, key: RateKey) -> Result<(), Duration> {
+        self.check_at(class, key, Instant::now())
+    }
+
+    pub fn check_at(&self, class: RouteClass, key: RateKey, now: Instant) -> Result<(), Duration> {
+        let quota = self.config.quota(class);
+        let mut buckets = self.buckets.lock().unwrap();
+
+        // Idle buckets have refilled completely and are equivalent to a new
+        // one, so they can be dropped to keep memory bounded.
+        if buckets.len() >= 10_000 {
+            let config = &self.config;
+            buckets.retain(|(class, _), bucket| !bucket.is_idle(config.quota(*class), now));
+        }
+
+        buckets
+            .entry((class, key))
+            .or_insert_with(|| TokenBucket::full(quota, now))
+            .try_take(quota, now)
+    }
+
+    fn classify(method: &Method, path: &str, authenticated: bool) -> RouteClass {
+        match (method, path) {
+            (&Method::POST, "/api/users") => RouteClass::Signup,
+            (
+                &Method::POST,
+                "/api/auth/login"
+                | "/api/auth/login/mfa"
+                | "/api/auth/refresh"
+                | "/api/auth/password-reset"
+                | "/api/auth/password-reset/confirm"
+                | "/api/account/verify-email",
+            ) => RouteClass::Login,
+            (&Method::POST, "/api/messages") if authenticated => RouteClass::SendMessage,
+            (&Method::GET, "/api/users/lookup") | (&Method::POST, "/api/users/discover") if authenticated => {
+                RouteClass::Discovery
+            }
+            _ if authenticated => RouteClass::Authenticated,
+            _ => RouteClass::Anonymous,
+        }
+    }
+
+    fn client_ip<B>(&self, req: &Request<B>) -> Option<IpAddr> {
+        if self.config.trust_forwarded_for {
+            let forwarded = req
+                .headers()
+                .get("x-forwarded-for")
+                .and_then(|v| v.to_str().ok())
+                .and_then(|v| v.split(',').next())
+                .and_then(|v| v.trim().parse().ok());
+            if forwarded.is_some() {
+                return forwarded;
+            }
+        }
+
+        req.extensions()
+            .get::<ConnectInfo<SocketAddr>>()
+            .map(|ConnectInfo(addr)| addr.ip())
+    }
+
+    /// The user (and device) behind a validly signed bearer token. Session
+    /// revocation is not checked here; the handler's `AuthUser` does that.
+    fn token_owner<B>(&self, req: &Request<B>) -> Option<RateKey> {
+        let token = req
+            .headers()
+            .get(header::AUTHORIZATION)?
+            .to_str()
+            .ok()?
+            .strip_prefix("Bearer ")?;
+        // API keys are checked by the handler. A made-up key only buys a
+        // bucket whose requests all fail authentication.
+        if let Some(bot_id) = crate::api::api_key_bot(token) {
+            return Some(RateKey::Device { user_id: bot_id, device_id: None });
+        }
+        // Tokens from a key this instance has yet to load count against the
+        // client's address until it reloads.
+        let claims: Claims = self.keys.verify(token, ACCESS_TOKEN_TYPE).ok()?;
+
+        Some(RateKey::Device {
+            user_id: claims.sub.parse().ok()?,
+            device_id: claims.did.and_then(|d| d.parse().ok()),
+        })
+    }
+
+    fn classify_request<B>(&self, req: &Request<B>) -> (RouteClass, RateKey) {
+        let path = req
+            .extensions()
+            .get::<MatchedPath>()
+            .map(|p| p.as_str())
+            .unwrap_or_else(|| req.uri().path());
+        let owner = self.token_owner(req);
+        let class = Self::classify(req.method(), path, owner.is_some());
+
+        let key = match (class, owner) {
+            (RouteClass::Signup | RouteClass::Login, _) | (_, None) => {
+                self.client_ip(req).map(RateKey::Ip).unwrap_or(RateKey::Unknown)
+            }
+            (_, Some(owner)) => owner,
+        };
+
+        (class, key)
+    }
+}
+
+fn too_many_requests(retry_after: Duration) -> Response {
+    let seconds = retry_after.as_secs() + u64::from(retry_after.subsec_nanos() > 0);
+    ApiError::RateLimited { retry_after: seconds }.into_response()
+}
+
+/// Tower layer enforcing `RateLimiter` quotas. Apply it with `route_layer`
+/// so that the matched route is known when classifying requests.
+#[derive(Clone)]
+pub struct RateLimitLayer {
+    limiter: Arc<RateLimiter>,
+}
+
+impl RateLimitLayer {
+    pub fn new(limiter: Arc<RateLimiter>) -> Self {
+        Self { limiter }
+    }
+}
+
+impl<S> Layer<S> for RateLimitLayer {
+    type Service = RateLimit<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        RateLimit {
+            inner,
+            limiter: self.limiter.clone(),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct RateLimit<S> {
+    inner: S,
+    limiter: Arc<RateLimiter>,
+}
+
+impl<S> Service<Request<Body>> for RateLimit<S>
+where
+    S: Service<Request<Body>, Response = Response> + Send + 'static,
+    S::Future: Send + 'static,
+{
+    type Response = Response;
+    type Error = S::Error;
+    type Future = BoxFuture<'static, Result<Response, S::Error>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: Request<Body>) -> Self::Future {
+        let (class, key) = self.limiter.classify_request(&req);
+
+        match self.limiter.check(class, key) {
+            Ok(()) => Box::pin(self.inner.call(req)),
+            Err(retry_after) => {
+                metrics::counter!(
+                    "pulse_rate_limited_total",
+                    "class" => class.as_str(),
+                    "key" => key.kind(),
+                )
+                .increment(1);
+                tracing::debug!("Rate limited {:?} on {}", key, class.as_str());
+                Box::pin(async move { Ok(too_many_requests(retry_after)) })
+            }
+        }
+    }
+}
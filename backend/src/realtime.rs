@@ -0,0 +1,180 @@
+use std::collections::HashMap;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+
+use chrono::{DateTime, Utc};
+use futures::Stream;
+use serde::Serialize;
+use tokio::sync::mpsc;
+use uuid::Uuid;
+
+use crate::models::AuditKind;
+
+/// Server-initiated notifications delivered to connected devices.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Event {
+    /// The device is running out of one-time prekeys and should upload more.
+    PrekeysLow { remaining: i64 },
+    /// A contact or chat peer came online or went offline. `last_seen` is
+    /// set when they went offline.
+    Presence {
+        user_id: Uuid,
+        online: bool,
+        last_seen: Option<DateTime<Utc>>,
+    },
+    /// Someone started typing, in a chat or, without `chat_id`, to the
+    /// device's user directly. Not repeated: clients hide the indicator
+    /// after a few seconds unless the typist sends it again.
+    TypingStarted { chat_id: Option<Uuid>, user_id: Uuid },
+    TypingStopped { chat_id: Option<Uuid>, user_id: Uuid },
+    /// An encrypted delivery or read receipt for messages the device's user
+    /// sent. Also stored with the user's messages in case no device is
+    /// online.
+    Receipt { id: Uuid, sender_id: Uuid, content: Vec<u8> },
+    /// Someone joined or left the chat, or their role changed. Clients fetch
+    /// the member list again, so that they encrypt to whoever is in the chat
+    /// now. Read-only members, who do not send to the chat, are not told of
+    /// other read-only members.
+    ChatMembersChanged { chat_id: Uuid },
+    /// Sent to the chat's admins when someone asks to join through an
+    /// invite that needs approval.
+    JoinRequested { chat_id: Uuid, user_id: Uuid },
+    /// An admin posted to a channel the device's user subscribes to. The
+    /// post itself is fetched from the channel.
+    ChannelPost { chat_id: Uuid, id: Uuid },
+    /// The chat's settings or pins changed, or the device's user muted or
+    /// unmuted it elsewhere. Clients fetch the chat again.
+    ChatUpdated { chat_id: Uuid },
+    /// The device's user starred or unstarred a message on another device.
+    StarsChanged,
+    /// Something security-relevant happened to the account, such as a
+    /// sign-in on a new device. Clients fetch the entry from
+    /// `/api/account/security-events` to alert the user.
+    SecurityEvent { id: i64, kind: AuditKind },
+}
+
+impl Event {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Event::PrekeysLow { .. } => "prekeys_low",
+            Event::Presence { .. } => "presence",
+            Event::TypingStarted { .. } => "typing_started",
+            Event::TypingStopped { .. } => "typing_stopped",
+            Event::Receipt { .. } => "receipt",
+            Event::ChatMembersChanged { .. } => "chat_members_changed",
+            Event::JoinRequested { .. } => "join_requested",
+            Event::ChannelPost { .. } => "channel_post",
+            Event::ChatUpdated { .. } => "chat_updated",
+            Event::StarsChanged => "stars_changed",
+            Event::SecurityEvent { .. } => "security_event",
+        }
+    }
+}
+
+struct Connection {
+    id: u64,
+    session_id: Uuid,
+    sender: mpsc::UnboundedSender<Event>,
+}
+
+/// Routes events to the open event streams of each device. A device may have
+/// several streams open at once; each gets every event. Only streams of this
+/// process are reached: events are not shared between server instances.
+#[derive(Default)]
+pub struct Hub {
+    next_id: Mutex<u64>,
+    devices: Mutex<HashMap<Uuid, Vec<Connection>>>,
+}
+
+impl Hub {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn subscribe(self: &Arc<Self>, device_id: Uuid, session_id: Uuid) -> Subscription {
+        let id = {
+            let mut next_id = self.next_id.lock().unwrap();
+            *next_id += 1;
+            *next_id
+        };
+        let (sender, receiver) = mpsc::unbounded_channel();
+
+        self.devices
+            .lock()
+            .unwrap()
+            .entry(device_id)
+            .or_default()
+            .push(Connection { id, session_id, sender });
+
+        Subscription {
+            hub: self.clone(),
+            device_id,
+            id,
+            receiver,
+        }
+    }
+
+    /// Returns whether the device had at least one open stream.
+    pub fn send_to_device(&self, device_id: Uuid, event: Event) -> bool {
+        let devices = self.devices.lock().unwrap();
+        let mut delivered = false;
+        for connection in devices.get(&device_id).into_iter().flatten() {
+            delivered |= connection.sender.send(event.clone()).is_ok();
+        }
+        delivered
+    }
+
+    /// Open event streams across all devices.
+    pub fn connection_count(&self) -> usize {
+        self.devices.lock().unwrap().values().map(Vec::len).sum()
+    }
+
+    /// Ends every stream opened with the session, e.g. after it is revoked.
+    pub fn disconnect_session(&self, session_id: Uuid) {
+        let mut devices = self.devices.lock().unwrap();
+        for connections in devices.values_mut() {
+            connections.retain(|c| c.session_id != session_id);
+        }
+        devices.retain(|_, connections| !connections.is_empty());
+    }
+
+    /// Ends every stream, e.g. when the server shuts down.
+    pub fn disconnect_all(&self) {
+        self.devices.lock().unwrap().clear();
+    }
+
+    fn unsubscribe(&self, device_id: Uuid, id: u64) {
+        let mut devices = self.devices.lock().unwrap();
+        if let Some(connections) = devices.get_mut(&device_id) {
+            connections.retain(|c| c.id != id);
+            if connections.is_empty() {
+                devices.remove(&device_id);
+            }
+        }
+    }
+}
+
+/// Stream of events for one connection. Dropping it (e.g. when the client
+/// disconnects) unregisters the connection.
+pub struct Subscription {
+    hub: Arc<Hub>,
+    device_id: Uuid,
+    id: u64,
+    receiver: mpsc::UnboundedReceiver<Event>,
+}
+
+impl Stream for Subscription {
+    type Item = Event;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Event>> {
+        self.receiver.poll_recv(cx)
+    }
+}
+
+impl Drop for Subscription {
+    fn drop(&mut self) {
+        self.hub.unsubscribe(self.device_id, self.id);
+    }
+}
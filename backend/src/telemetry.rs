@@ -0,0 +1,103 @@
+use std::sync::OnceLock;
+use std::task::{Context, Poll};
+use std::time::Instant;
+
+use axum::{
+    body::Body,
+    extract::MatchedPath,
+    http::Request,
+    response::Response,
+};
+use futures::future::BoxFuture;
+use metrics_exporter_prometheus::{Matcher, PrometheusBuilder, PrometheusHandle};
+use tower::{Layer, Service};
+
+/// Upper bounds, in seconds, of the request latency histogram buckets.
+const LATENCY_BUCKETS: &[f64] = &[0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0];
+
+/// The process-wide Prometheus recorder, installed on first use. Every
+/// `metrics::counter!` and friends in the server lands here.
+pub fn handle() -> &'static PrometheusHandle {
+    static HANDLE: OnceLock<PrometheusHandle> = OnceLock::new();
+    HANDLE.get_or_init(|| {
+        let recorder = PrometheusBuilder::new()
+            .set_buckets_for_metric(
+                Matcher::Full("pulse_http_request_duration_seconds".to_string()),
+                LATENCY_BUCKETS,
+            )
+            .expect("buckets are not empty")
+            .build_recorder();
+        let handle = recorder.handle();
+        if metrics::set_global_recorder(recorder).is_err() {
+            tracing::warn!("A metrics recorder was already installed; /metrics will be empty");
+        }
+        handle
+    })
+}
+
+/// Tower layer recording request counts and latencies per route. Apply it
+/// with `route_layer`, so that unmatched paths do not each become a series.
+#[derive(Clone, Default)]
+pub struct MetricsLayer;
+
+impl<S> Layer<S> for MetricsLayer {
+    type Service = Metrics<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        Metrics { inner }
+    }
+}
+
+#[derive(Clone)]
+pub struct Metrics<S> {
+    inner: S,
+}
+
+impl<S> Service<Request<Body>> for Metrics<S>
+where
+    S: Service<Request<Body>, Response = Response> + Send + 'static,
+    S::Future: Send + 'static,
+{
+    type Response = Response;
+    type Error = S::Error;
+    type Future = BoxFuture<'static, Result<Response, S::Error>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: Request<Body>) -> Self::Future {
+        let method = req.method().to_string();
+        let route = req
+            .extensions()
+            .get::<MatchedPath>()
+            .map(|p| p.as_str().to_string())
+            .unwrap_or_else(|| "unmatched".to_string());
+        let start = Instant::now();
+        let in_flight = metrics::gauge!("pulse_http_requests_in_flight");
+        in_flight.increment(1.0);
+
+        let future = self.inner.call(req);
+        Box::pin(async move {
+            let result = future.await;
+            in_flight.decrement(1.0);
+            if let Ok(response) = &result {
+                let status = response.status().as_u16().to_string();
+                metrics::counter!(
+                    "pulse_http_requests_total",
+                    "method" => method.clone(),
+                    "route" => route.clone(),
+                    "status" => status,
+                )
+                .increment(1);
+                metrics::histogram!(
+                    "pulse_http_request_duration_seconds",
+                    "method" => method,
+                    "route" => route,
+                )
+                .record(start.elapsed().as_secs_f64());
+            }
+            result
+        })
+    }
+}
@@ -0,0 +1,1753 @@
+//! End-to-end tests of the HTTP API against an in-memory SQLite database.
+
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+
+use axum::{
+    body::{to_bytes, Body},
+    http::{header, Method, Request, StatusCode},
+    Router,
+};
+use futures::StreamExt;
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+use tower::ServiceExt;
+use uuid::Uuid;
+
+use crate::{
+    api::{self, AccountConfig, AppState, AttachmentConfig, BotConfig, EmailConfig, MessageConfig, PrekeyConfig, SessionConfig},
+    audit,
+    blob_store::FsBlobStore,
+    config::LimitsConfig,
+    db,
+    jwt::{JwtConfig, KeyRing},
+    mailer::FileMailer,
+    models::{AuditKind, BotWebhook},
+    presence::Presence,
+    rate_limit::{Quota, RateLimitConfig, RateLimiter},
+    realtime::{Event, Hub, Subscription},
+    totp,
+    webhooks::{self, WebhookSender},
+};
+
+const JWT_SECRET: &str = "test-secret";
+
+/// Shared by every test; each test user has its own address.
+fn mail_dir() -> PathBuf {
+    std::env::temp_dir().join("pulse-test-mail")
+}
+
+pub(super) async fn test_state() -> AppState {
+    let db = db::connect("sqlite::memory:").await.unwrap();
+    db.migrate().await.unwrap();
+
+    let unlimited = Quota::new(u32::MAX, 1);
+    let rate_limits = RateLimitConfig {
+        signup: unlimited,
+        login: unlimited,
+        send_message: unlimited,
+        discovery: unlimited,
+        authenticated: unlimited,
+        anonymous: unlimited,
+        trust_forwarded_for: false,
+    };
+    let blob_dir = std::env::temp_dir().join(format!("pulse-test-{}", Uuid::new_v4()));
+    let sessions = SessionConfig::from_env().unwrap();
+    let keys = Arc::new(KeyRing::new(
+        JwtConfig::from_env(Some(JWT_SECRET.to_string())).unwrap(),
+        sessions.max_token_lifetime(),
+    ));
+    keys.maintain(&db).await.unwrap();
+
+    AppState {
+        db,
+        keys: keys.clone(),
+        blobs: Arc::new(FsBlobStore::new(blob_dir).await.unwrap()),
+        attachments: AttachmentConfig::from_env().unwrap(),
+        rate_limiter: Arc::new(RateLimiter::new(rate_limits, keys)),
+        prekeys: PrekeyConfig::from_env().unwrap(),
+        sessions,
+        hub: Arc::new(Hub::new()),
+        presence: Arc::new(Presence::new()),
+        mailer: Arc::new(FileMailer::new(mail_dir()).await.unwrap()),
+        email: EmailConfig::from_env().unwrap(),
+        account: AccountConfig::from_env().unwrap(),
+        messages: MessageConfig::from_env().unwrap(),
+        bots: BotConfig {
+            allow_insecure_webhooks: true,
+            ..BotConfig::from_env().unwrap()
+        },
+        webhooks: Arc::new(WebhookSender::new(Duration::from_secs(5), true)),
+        limits: LimitsConfig::default(),
+    }
+}
+
+/// Like `test_state`, but on a SQLite file that tests can also open
+/// themselves, e.g. to make writes fail. Returns the database URL.
+async fn file_state() -> (AppState, String) {
+    let path = std::env::temp_dir().join(format!("pulse-test-{}.db", Uuid::new_v4()));
+    let url = format!("sqlite:{}", path.display());
+    let mut state = test_state().await;
+    state.db = db::connect(&url).await.unwrap();
+    state.db.migrate().await.unwrap();
+    state.keys.maintain(&state.db).await.unwrap();
+    (state, url)
+}
+
+pub(super) async fn call(
+    app: &Router,
+    method: Method,
+    uri: &str,
+    token: Option<&str>,
+    body: Option<Value>,
+) -> (StatusCode, Value) {
+    let mut request = Request::builder().method(method).uri(uri);
+    if let Some(token) = token {
+        request = request.header(header::AUTHORIZATION, format!("Bearer {}", token));
+    }
+    let request = match body {
+        Some(body) => request
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Body::from(body.to_string()))
+            .unwrap(),
+        None => request.body(Body::empty()).unwrap(),
+    };
+
+    let (status, bytes) = call_raw(app, request).await;
+    let body = serde_json::from_slice(&bytes).unwrap_or(Value::Null);
+    (status, body)
+}
+
+pub(super) async fn call_raw(app: &Router, request: Request<Body>) -> (StatusCode, Vec<u8>) {
+    let response = app.clone().oneshot(request).await.unwrap();
+    let status = response.status();
+    let bytes = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+    (status, bytes.to_vec())
+}
+
+/// Returns the token from the newest email sent to `to` whose subject
+/// contains `subject`. Some emails are sent in the background, so this
+/// waits for a moment.
+pub(super) async fn mailed_token(to: &str, subject: &str) -> String {
+    for _ in 0..50 {
+        let mut newest: Option<(PathBuf, String)> = None;
+        for entry in std::fs::read_dir(mail_dir()).unwrap() {
+            let path = entry.unwrap().path();
+            let contents = std::fs::read_to_string(&path).unwrap();
+            let matches = contents.starts_with(&format!("To: {}\n", to))
+                && contents.lines().nth(1).is_some_and(|line| line.contains(subject));
+            if matches && newest.as_ref().is_none_or(|(newest, _)| path > *newest) {
+                newest = Some((path, contents));
+            }
+        }
+        if let Some((_, contents)) = newest {
+            let start = contents.find("token=").unwrap() + "token=".len();
+            return contents[start..].split_whitespace().next().unwrap().to_string();
+        }
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+    panic!("No \"{}\" email for {}", subject, to);
+}
+
+/// Registers a user, confirms their email address and logs in from a new
+/// device, returning the login response.
+pub(super) async fn sign_up(app: &Router) -> Value {
+    let email = sign_up_unverified(app).await;
+    let token = mailed_token(&email, "Confirm").await;
+    let (status, _) = call(
+        app,
+        Method::POST,
+        "/api/account/verify-email",
+        None,
+        Some(json!({ "token": token })),
+    )
+    .await;
+    assert_eq!(status, StatusCode::NO_CONTENT);
+
+    log_in(app, &email).await
+}
+
+/// Registers a user without confirming the email address. Returns the
+/// address.
+pub(super) async fn sign_up_unverified(app: &Router) -> String {
+    let name = Uuid::new_v4().simple().to_string();
+    let (status, _) = call(
+        app,
+        Method::POST,
+        "/api/users",
+        None,
+        Some(json!({
+            "username": name,
+            "email": format!("{}@example.com", name),
+            "password": "correct horse battery staple",
+            "public_key": [1, 2, 3],
+        })),
+    )
+    .await;
+    assert_eq!(status, StatusCode::CREATED);
+
+    format!("{}@example.com", name)
+}
+
+pub(super) async fn log_in(app: &Router, email: &str) -> Value {
+    let (status, body) = call(
+        app,
+        Method::POST,
+        "/api/auth/login",
+        None,
+        Some(json!({
+            "email": email,
+            "password": "correct horse battery staple",
+            "device_name": "test device",
+            "public_key": [4, 5, 6],
+        })),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    body
+}
+
+fn str_field<'a>(value: &'a Value, field: &str) -> &'a str {
+    value[field].as_str().unwrap()
+}
+
+#[tokio::test]
+async fn test_refresh_rotates_tokens() {
+    let app = api::create_router(test_state().await);
+    let login = sign_up(&app).await;
+    let refresh_token = str_field(&login, "refresh_token");
+
+    let (status, rotated) = call(
+        &app,
+        Method::POST,
+        "/api/auth/refresh",
+        None,
+        Some(json!({ "refresh_token": refresh_token })),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_ne!(str_field(&rotated, "refresh_token"), refresh_token);
+
+    let (status, _) = call(&app, Method::GET, "/api/sessions", Some(str_field(&rotated, "access_token")), None).await;
+    assert_eq!(status, StatusCode::OK);
+
+    // Replaying the old refresh token revokes the session for everyone.
+    let (status, body) = call(
+        &app,
+        Method::POST,
+        "/api/auth/refresh",
+        None,
+        Some(json!({ "refresh_token": refresh_token })),
+    )
+    .await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+    assert_eq!(body["error"]["code"], "session_expired");
+
+    let (status, body) = call(&app, Method::GET, "/api/sessions", Some(str_field(&rotated, "access_token")), None).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+    assert_eq!(body["error"]["code"], "session_expired");
+}
+
+#[tokio::test]
+async fn test_logout_and_revoke_other_sessions() {
+    let app = api::create_router(test_state().await);
+    let first = sign_up(&app).await;
+    let email = first["user"]["email"].as_str().unwrap().to_string();
+    let second = log_in(&app, &email).await;
+    let third = log_in(&app, &email).await;
+
+    let (_, sessions) = call(&app, Method::GET, "/api/sessions", Some(str_field(&first, "access_token")), None).await;
+    let sessions = sessions.as_array().unwrap();
+    assert_eq!(sessions.len(), 3);
+    assert_eq!(sessions.iter().filter(|s| s["current"] == true).count(), 1);
+
+    let (status, _) = call(&app, Method::POST, "/api/auth/logout", Some(str_field(&third, "access_token")), None).await;
+    assert_eq!(status, StatusCode::NO_CONTENT);
+    let (status, _) = call(&app, Method::GET, "/api/sessions", Some(str_field(&third, "access_token")), None).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+    let (status, _) = call(&app, Method::DELETE, "/api/sessions", Some(str_field(&first, "access_token")), None).await;
+    assert_eq!(status, StatusCode::NO_CONTENT);
+    let (status, _) = call(&app, Method::GET, "/api/sessions", Some(str_field(&second, "access_token")), None).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+    let (_, sessions) = call(&app, Method::GET, "/api/sessions", Some(str_field(&first, "access_token")), None).await;
+    assert_eq!(sessions.as_array().unwrap().len(), 1);
+}
+
+#[tokio::test]
+async fn test_event_stream_ends_with_session() {
+    let mut state = test_state().await;
+    state.sessions.stream_check_interval = Duration::from_millis(50);
+    let app = api::create_router(state.clone());
+    let login = sign_up(&app).await;
+    let user_id: Uuid = login["user"]["id"].as_str().unwrap().parse().unwrap();
+
+    let request = Request::builder()
+        .uri("/api/events")
+        .header(header::AUTHORIZATION, format!("Bearer {}", str_field(&login, "access_token")))
+        .body(Body::empty())
+        .unwrap();
+    let response = app.clone().oneshot(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let mut body = tokio::spawn(to_bytes(response.into_body(), usize::MAX));
+
+    tokio::time::sleep(Duration::from_millis(200)).await;
+    assert!(!body.is_finished());
+
+    // Revoked behind this server's back, as by another server or
+    // pulse-admin, so the hub knows nothing of it.
+    state.db.revoke_user_sessions(user_id, None).await.unwrap();
+    tokio::time::timeout(Duration::from_secs(5), &mut body)
+        .await
+        .expect("stream still open after the session was revoked")
+        .unwrap()
+        .unwrap();
+}
+
+#[tokio::test]
+async fn test_wrong_password_rejected() {
+    let app = api::create_router(test_state().await);
+    let login = sign_up(&app).await;
+
+    for email in [login["user"]["email"].as_str().unwrap(), "nobody@example.com"] {
+        let (status, body) = call(
+            &app,
+            Method::POST,
+            "/api/auth/login",
+            None,
+            Some(json!({
+                "email": email,
+                "password": "wrong password",
+                "device_name": "test device",
+                "public_key": [4, 5, 6],
+            })),
+        )
+        .await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+        assert_eq!(body["error"]["code"], "invalid_credentials");
+    }
+}
+
+#[tokio::test]
+async fn test_two_factor_login() {
+    let state = test_state().await;
+    let app = api::create_router(state.clone());
+    let login = sign_up(&app).await;
+    let token = str_field(&login, "access_token").to_string();
+    let email = login["user"]["email"].as_str().unwrap().to_string();
+    let user_id: Uuid = login["user"]["id"].as_str().unwrap().parse().unwrap();
+
+    let (status, setup) = call(&app, Method::POST, "/api/account/2fa/setup", Some(&token), None).await;
+    assert_eq!(status, StatusCode::OK);
+    assert!(str_field(&setup, "provisioning_uri").starts_with("otpauth://totp/"));
+
+    let secret = state.db.get_totp_secret(user_id).await.unwrap().unwrap().secret;
+    let step = totp::step_at(chrono::Utc::now().timestamp());
+    let code = |step| format!("{:06}", totp::code_at(&secret, step, totp::DIGITS));
+
+    let (status, enabled) = call(
+        &app,
+        Method::POST,
+        "/api/account/2fa/enable",
+        Some(&token),
+        Some(json!({ "code": code(step) })),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    let recovery_codes: Vec<String> = serde_json::from_value(enabled["recovery_codes"].clone()).unwrap();
+    assert_eq!(recovery_codes.len(), 10);
+    assert!(recovery_codes.iter().all(|c| c.len() == 19 && c.split('-').count() == 4));
+    let stored = state.db.get_unused_recovery_codes(user_id).await.unwrap();
+    assert!(stored.iter().all(|hash| hash.starts_with("$argon2")));
+
+    // The password alone now only earns a challenge.
+    let challenge = log_in(&app, &email).await;
+    assert_eq!(challenge["mfa_required"], true);
+    assert!(challenge.get("access_token").is_none());
+    let mfa_token = str_field(&challenge, "mfa_token");
+
+    let login_mfa = |code: String| {
+        call(
+            &app,
+            Method::POST,
+            "/api/auth/login/mfa",
+            None,
+            Some(json!({ "mfa_token": mfa_token, "code": code })),
+        )
+    };
+
+    let (status, tokens) = login_mfa(code(step + 1)).await;
+    assert_eq!(status, StatusCode::OK);
+    assert!(tokens["access_token"].is_string());
+
+    // Neither a replayed code nor a used recovery code works twice.
+    let (status, body) = login_mfa(code(step + 1)).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+    assert_eq!(body["error"]["code"], "invalid_code");
+
+    let (status, _) = login_mfa(recovery_codes[0].to_uppercase()).await;
+    assert_eq!(status, StatusCode::OK);
+    let (status, _) = login_mfa(recovery_codes[0].clone()).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+    let (status, body) = call(
+        &app,
+        Method::POST,
+        "/api/account/2fa/disable",
+        Some(&token),
+        Some(json!({ "password": "wrong password", "code": recovery_codes[1] })),
+    )
+    .await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+    assert_eq!(body["error"]["code"], "invalid_credentials");
+
+    let (status, _) = call(
+        &app,
+        Method::POST,
+        "/api/account/2fa/disable",
+        Some(&token),
+        Some(json!({ "password": "correct horse battery staple", "code": recovery_codes[1] })),
+    )
+    .await;
+    assert_eq!(status, StatusCode::NO_CONTENT);
+    assert!(log_in(&app, &email).await["access_token"].is_string());
+}
+
+#[tokio::test]
+async fn test_two_factor_attempts_are_limited() {
+    let state = test_state().await;
+    let app = api::create_router(state.clone());
+    let login = sign_up(&app).await;
+    let email = login["user"]["email"].as_str().unwrap().to_string();
+    let user_id: Uuid = login["user"]["id"].as_str().unwrap().parse().unwrap();
+
+    call(&app, Method::POST, "/api/account/2fa/setup", Some(str_field(&login, "access_token")), None).await;
+    let secret = state.db.get_totp_secret(user_id).await.unwrap().unwrap().secret;
+    let step = totp::step_at(chrono::Utc::now().timestamp());
+    let code = |step| format!("{:06}", totp::code_at(&secret, step, totp::DIGITS));
+    let (status, _) = call(
+        &app,
+        Method::POST,
+        "/api/account/2fa/enable",
+        Some(str_field(&login, "access_token")),
+        Some(json!({ "code": code(step) })),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    let wrong = if code(step + 1) == "000000" { "000001" } else { "000000" };
+
+    let login_mfa = |mfa_token: String, code: String| {
+        call(
+            &app,
+            Method::POST,
+            "/api/auth/login/mfa",
+            None,
+            Some(json!({ "mfa_token": mfa_token, "code": code })),
+        )
+    };
+
+    // Five wrong codes void the token, even for the right code after them.
+    let mfa_token = str_field(&log_in(&app, &email).await, "mfa_token").to_string();
+    for _ in 0..5 {
+        let (status, body) = login_mfa(mfa_token.clone(), wrong.to_string()).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+        assert_eq!(body["error"]["code"], "invalid_code");
+    }
+    let (status, body) = login_mfa(mfa_token, code(step + 1)).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+    assert_ne!(body["error"]["code"], "invalid_code");
+
+    // Ten across tokens lock the user out for a while.
+    let mfa_token = str_field(&log_in(&app, &email).await, "mfa_token").to_string();
+    for _ in 0..5 {
+        login_mfa(mfa_token.clone(), wrong.to_string()).await;
+    }
+    let mfa_token = str_field(&log_in(&app, &email).await, "mfa_token").to_string();
+    let (status, _) = login_mfa(mfa_token, code(step + 1)).await;
+    assert_eq!(status, StatusCode::TOO_MANY_REQUESTS);
+}
+
+#[tokio::test]
+async fn test_unverified_account_is_restricted() {
+    let app = api::create_router(test_state().await);
+    let other = sign_up(&app).await;
+    let other_id = other["user"]["id"].as_str().unwrap();
+
+    let email = sign_up_unverified(&app).await;
+    let login = log_in(&app, &email).await;
+    assert!(login["user"]["email_verified_at"].is_null());
+    let token = str_field(&login, "access_token").to_string();
+
+    let (status, body) = call(
+        &app,
+        Method::GET,
+        &format!("/api/users/{}/prekeys", other_id),
+        Some(&token),
+        None,
+    )
+    .await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+    assert_eq!(body["error"]["code"], "email_not_verified");
+    let typing = json!({ "recipient_id": other_id, "typing": true });
+    let (status, _) = call(&app, Method::POST, "/api/typing", Some(&token), Some(typing)).await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+
+    // Asking again invalidates the first link.
+    let first = mailed_token(&email, "Confirm").await;
+    let (status, _) = call(&app, Method::POST, "/api/account/verify-email/resend", Some(&token), None).await;
+    assert_eq!(status, StatusCode::ACCEPTED);
+    let second = mailed_token(&email, "Confirm").await;
+    assert_ne!(first, second);
+
+    let verify = |token: String| {
+        call(&app, Method::POST, "/api/account/verify-email", None, Some(json!({ "token": token })))
+    };
+    let (status, body) = verify(first).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(body["error"]["code"], "invalid_token");
+    let (status, _) = verify(second.clone()).await;
+    assert_eq!(status, StatusCode::NO_CONTENT);
+    let (status, _) = verify(second).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+
+    let (status, _) = call(
+        &app,
+        Method::GET,
+        &format!("/api/users/{}/prekeys", other_id),
+        Some(&token),
+        None,
+    )
+    .await;
+    assert_ne!(status, StatusCode::FORBIDDEN);
+}
+
+#[tokio::test]
+async fn test_password_reset() {
+    let app = api::create_router(test_state().await);
+    let login = sign_up(&app).await;
+    let email = login["user"]["email"].as_str().unwrap().to_string();
+
+    for address in [email.as_str(), "nobody@example.com"] {
+        let (status, _) = call(
+            &app,
+            Method::POST,
+            "/api/auth/password-reset",
+            None,
+            Some(json!({ "email": address })),
+        )
+        .await;
+        assert_eq!(status, StatusCode::ACCEPTED);
+    }
+    let token = mailed_token(&email, "Reset").await;
+
+    let reset = |password: &str| {
+        call(
+            &app,
+            Method::POST,
+            "/api/auth/password-reset/confirm",
+            None,
+            Some(json!({ "token": token, "password": password })),
+        )
+    };
+    // A rejected password does not use up the link.
+    let (status, _) = reset("short").await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    let (status, _) = reset("a brand new password").await;
+    assert_eq!(status, StatusCode::NO_CONTENT);
+    let (status, body) = reset("another new password").await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(body["error"]["code"], "invalid_token");
+
+    // Existing sessions are signed out and the old password stops working.
+    let (status, _) = call(&app, Method::GET, "/api/sessions", Some(str_field(&login, "access_token")), None).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+    let (status, _) = call(
+        &app,
+        Method::POST,
+        "/api/auth/login",
+        None,
+        Some(json!({
+            "email": email,
+            "password": "correct horse battery staple",
+            "device_name": "test device",
+            "public_key": [4, 5, 6],
+        })),
+    )
+    .await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+    let (status, _) = call(
+        &app,
+        Method::POST,
+        "/api/auth/login",
+        None,
+        Some(json!({
+            "email": email,
+            "password": "a brand new password",
+            "device_name": "test device",
+            "public_key": [4, 5, 6],
+        })),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+}
+
+#[tokio::test]
+async fn test_data_export() {
+    let app = api::create_router(test_state().await);
+    let login = sign_up(&app).await;
+    let other = sign_up(&app).await;
+    let token = str_field(&login, "access_token");
+
+    let (status, _) = call(
+        &app,
+        Method::POST,
+        "/api/messages",
+        Some(token),
+        Some(json!({ "recipient_id": other["user"]["id"], "content": [9, 9, 9] })),
+    )
+    .await;
+    assert_eq!(status, StatusCode::CREATED);
+
+    let request = Request::get("/api/account/export")
+        .header(header::AUTHORIZATION, format!("Bearer {}", token))
+        .body(Body::empty())
+        .unwrap();
+    let (status, archive) = call_raw(&app, request).await;
+    assert_eq!(status, StatusCode::OK);
+
+    let mut zip = zip::ZipArchive::new(std::io::Cursor::new(archive)).unwrap();
+    let mut entry = |name: &str| -> Value {
+        let mut contents = String::new();
+        std::io::Read::read_to_string(&mut zip.by_name(name).unwrap(), &mut contents).unwrap();
+        serde_json::from_str(&contents).unwrap()
+    };
+    let profile = entry("profile.json");
+    assert_eq!(profile["user"]["id"], login["user"]["id"]);
+    assert!(profile["user"].get("password_hash").is_none());
+    assert_eq!(entry("devices.json").as_array().unwrap().len(), 1);
+    assert!(entry("sessions.json")[0].get("refresh_token_hash").is_none());
+    let messages = entry("messages.json");
+    assert_eq!(messages.as_array().unwrap().len(), 1);
+    assert_eq!(messages[0]["size"], 3);
+    assert!(messages[0].get("content").is_none());
+    let events = entry("security_events.json");
+    let events = events.as_array().unwrap();
+    assert!(!events.is_empty());
+    assert!(events.iter().all(|e| e.get("hash").is_none() && e.get("actor_id").is_none()));
+    assert_eq!(events.last().unwrap()["kind"], "login");
+    assert_eq!(events.last().unwrap()["details"]["device_name"], "test device");
+}
+
+async fn delete_account(app: &Router, token: &str, password: &str) -> (StatusCode, Value) {
+    call(
+        app,
+        Method::POST,
+        "/api/account/delete",
+        Some(token),
+        Some(json!({ "password": password })),
+    )
+    .await
+}
+
+#[tokio::test]
+async fn test_account_deletion() {
+    let mut state = test_state().await;
+    state.account.deletion_grace_period = chrono::Duration::zero();
+    let app = api::create_router(state.clone());
+    let login = sign_up(&app).await;
+    let email = login["user"]["email"].as_str().unwrap().to_string();
+
+    let (status, _) = delete_account(&app, str_field(&login, "access_token"), "wrong password").await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+    let (status, body) = delete_account(&app, str_field(&login, "access_token"), "correct horse battery staple").await;
+    assert_eq!(status, StatusCode::ACCEPTED);
+    assert!(body["delete_after"].is_string());
+    let (status, _) = call(&app, Method::GET, "/api/sessions", Some(str_field(&login, "access_token")), None).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+    // Logging in during the grace period keeps the account.
+    let login = log_in(&app, &email).await;
+    assert!(login["user"]["delete_after"].is_null());
+    assert_eq!(api::purge_deleted_accounts(&state.db, state.blobs.as_ref(), &state.hub).await.unwrap().deleted, 0);
+
+    let (status, _) = delete_account(&app, str_field(&login, "access_token"), "correct horse battery staple").await;
+    assert_eq!(status, StatusCode::ACCEPTED);
+    assert_eq!(api::purge_deleted_accounts(&state.db, state.blobs.as_ref(), &state.hub).await.unwrap().deleted, 1);
+
+    let (status, body) = call(
+        &app,
+        Method::POST,
+        "/api/auth/login",
+        None,
+        Some(json!({
+            "email": email,
+            "password": "correct horse battery staple",
+            "device_name": "test device",
+            "public_key": [4, 5, 6],
+        })),
+    )
+    .await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+    assert_eq!(body["error"]["code"], "invalid_credentials");
+}
+
+#[tokio::test]
+async fn test_failed_account_purge_skips_only_that_account() {
+    let (mut state, url) = file_state().await;
+    state.account.deletion_grace_period = chrono::Duration::zero();
+    let app = api::create_router(state.clone());
+    let mut user_ids = Vec::new();
+    for _ in 0..3 {
+        let login = sign_up(&app).await;
+        let (status, _) = delete_account(&app, str_field(&login, "access_token"), "correct horse battery staple").await;
+        assert_eq!(status, StatusCode::ACCEPTED);
+        user_ids.push(Uuid::parse_str(str_field(&login["user"], "id")).unwrap());
+    }
+
+    let pool = sqlx::sqlite::SqlitePool::connect(&url).await.unwrap();
+    let stuck = format!(
+        "CREATE TRIGGER users_stuck BEFORE DELETE ON users WHEN OLD.id = '{}' BEGIN SELECT RAISE(ABORT, 'stuck'); END",
+        user_ids[1]
+    );
+    sqlx::query(&stuck).execute(&pool).await.unwrap();
+
+    let purge = api::purge_deleted_accounts(&state.db, state.blobs.as_ref(), &state.hub).await.unwrap();
+    assert_eq!(purge, api::AccountPurge { deleted: 2, failed: 1 });
+    assert!(state.db.get_user(user_ids[0]).await.unwrap().is_none());
+    assert!(state.db.get_user(user_ids[1]).await.unwrap().is_some());
+    assert!(state.db.get_user(user_ids[2]).await.unwrap().is_none());
+
+    // Tried again on the next run.
+    sqlx::query("DROP TRIGGER users_stuck").execute(&pool).await.unwrap();
+    let purge = api::purge_deleted_accounts(&state.db, state.blobs.as_ref(), &state.hub).await.unwrap();
+    assert_eq!(purge, api::AccountPurge { deleted: 1, failed: 0 });
+}
+
+#[tokio::test]
+async fn test_lookup_discovery_and_contacts() {
+    let app = api::create_router(test_state().await);
+    let alice = sign_up(&app).await;
+    let bob = sign_up(&app).await;
+    let token = str_field(&alice, "access_token");
+    let bob_token = str_field(&bob, "access_token");
+    let bob_id = str_field(&bob["user"], "id");
+    let bob_name = str_field(&bob["user"], "username");
+
+    let lookup = format!("/api/users/lookup?username={}", bob_name);
+    let (status, body) = call(&app, Method::GET, &lookup, Some(token), None).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["id"], bob["user"]["id"]);
+    assert!(body.get("email").is_none());
+
+    // Bob hides from username lookup and opts into discovery by email.
+    let (status, _) = call(
+        &app,
+        Method::PUT,
+        "/api/account/privacy",
+        Some(bob_token),
+        Some(json!({ "discoverable_by_username": false, "discoverable_by_email": true })),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    let (status, _) = call(&app, Method::GET, &lookup, Some(token), None).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+
+    let bob_email = str_field(&bob["user"], "email").to_uppercase();
+    let bob_hash: String = Sha256::digest(bob_email.to_lowercase().as_bytes())
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect();
+    let (status, body) = call(
+        &app,
+        Method::POST,
+        "/api/users/discover",
+        Some(token),
+        Some(json!({ "email_hashes": [bob_hash, "00".repeat(32)] })),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body.as_array().unwrap().len(), 1);
+    assert_eq!(body[0]["user"]["id"], bob["user"]["id"]);
+    assert_eq!(body[0]["email_hash"], bob_hash.as_str());
+
+    // Lookup by id still works; the caller already knows who this is.
+    let (status, body) = call(&app, Method::GET, &format!("/api/users/{}", bob_id), Some(token), None).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["username"], bob_name);
+
+    let contact = format!("/api/contacts/{}", bob_id);
+    let (status, _) = call(&app, Method::PUT, &contact, Some(token), Some(json!({ "nickname": "Bobby" }))).await;
+    assert_eq!(status, StatusCode::NO_CONTENT);
+    let (status, body) = call(&app, Method::GET, "/api/contacts", Some(token), None).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body[0]["contact"]["id"], bob["user"]["id"]);
+    assert_eq!(body[0]["nickname"], "Bobby");
+
+    let own = format!("/api/contacts/{}", str_field(&alice["user"], "id"));
+    let (status, _) = call(&app, Method::PUT, &own, Some(token), None).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+
+    let (status, _) = call(&app, Method::DELETE, &contact, Some(token), None).await;
+    assert_eq!(status, StatusCode::NO_CONTENT);
+    let (status, _) = call(&app, Method::DELETE, &contact, Some(token), None).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn test_email_discovery_requires_verified_email() {
+    let app = api::create_router(test_state().await);
+    let email = sign_up_unverified(&app).await;
+    let login = log_in(&app, &email).await;
+
+    let (status, body) = call(
+        &app,
+        Method::PUT,
+        "/api/account/privacy",
+        Some(str_field(&login, "access_token")),
+        Some(json!({ "discoverable_by_username": true, "discoverable_by_email": true })),
+    )
+    .await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+    assert_eq!(body["error"]["code"], "email_not_verified");
+}
+
+#[tokio::test]
+async fn test_blocked_sender_is_not_delivered() {
+    let app = api::create_router(test_state().await);
+    let alice = sign_up(&app).await;
+    let bob = sign_up(&app).await;
+    let alice_token = str_field(&alice, "access_token");
+    let bob_token = str_field(&bob, "access_token");
+
+    let block = format!("/api/blocks/{}", str_field(&bob["user"], "id"));
+    let (status, _) = call(&app, Method::PUT, &block, Some(alice_token), None).await;
+    assert_eq!(status, StatusCode::NO_CONTENT);
+
+    // The sender cannot tell that they were blocked.
+    let send = json!({ "recipient_id": alice["user"]["id"], "content": [1] });
+    let (status, _) = call(&app, Method::POST, "/api/messages", Some(bob_token), Some(send.clone())).await;
+    assert_eq!(status, StatusCode::CREATED);
+    let (_, inbox) = call(&app, Method::GET, "/api/messages", Some(alice_token), None).await;
+    assert!(inbox.as_array().unwrap().is_empty());
+
+    let (status, _) = call(&app, Method::DELETE, &block, Some(alice_token), None).await;
+    assert_eq!(status, StatusCode::NO_CONTENT);
+    let (status, _) = call(&app, Method::POST, "/api/messages", Some(bob_token), Some(send)).await;
+    assert_eq!(status, StatusCode::CREATED);
+    let (_, inbox) = call(&app, Method::GET, "/api/messages", Some(alice_token), None).await;
+    assert_eq!(inbox.as_array().unwrap().len(), 1);
+}
+
+#[tokio::test]
+async fn test_report_review_and_suspension() {
+    let state = test_state().await;
+    let app = api::create_router(state.clone());
+    let alice = sign_up(&app).await;
+    let bob = sign_up(&app).await;
+    let admin = sign_up(&app).await;
+    let alice_token = str_field(&alice, "access_token");
+    let admin_token = str_field(&admin, "access_token");
+    let bob_id = str_field(&bob["user"], "id");
+
+    let (status, sent) = call(
+        &app,
+        Method::POST,
+        "/api/messages",
+        Some(str_field(&bob, "access_token")),
+        Some(json!({ "recipient_id": alice["user"]["id"], "content": [1] })),
+    )
+    .await;
+    assert_eq!(status, StatusCode::CREATED);
+
+    // Only messages the reported user sent to the reporter can be cited.
+    let report = |message_id: &Value| {
+        json!({
+            "user_id": bob_id,
+            "message_id": message_id,
+            "category": "harassment",
+            "evidence": "decrypted text",
+            "block": true,
+        })
+    };
+    let (status, _) = call(&app, Method::POST, "/api/reports", Some(alice_token), Some(report(&json!(Uuid::new_v4())))).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    let (status, created) = call(&app, Method::POST, "/api/reports", Some(alice_token), Some(report(&sent["id"]))).await;
+    assert_eq!(status, StatusCode::CREATED);
+    let report_id = str_field(&created, "id");
+    let (_, blocks) = call(&app, Method::GET, "/api/blocks", Some(alice_token), None).await;
+    assert_eq!(blocks[0]["blocked"]["id"], bob_id);
+
+    let (status, _) = call(&app, Method::GET, "/api/admin/reports", Some(admin_token), None).await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+    let admin_id = Uuid::parse_str(str_field(&admin["user"], "id")).unwrap();
+    state.db.set_admin(admin_id, true).await.unwrap();
+
+    let (status, queue) = call(&app, Method::GET, "/api/admin/reports", Some(admin_token), None).await;
+    assert_eq!(status, StatusCode::OK);
+    assert!(queue.as_array().unwrap().iter().any(|r| r["id"] == report_id));
+    let (status, details) = call(&app, Method::GET, &format!("/api/admin/reports/{}", report_id), Some(admin_token), None).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(details["report"]["evidence"], "decrypted text");
+    assert_eq!(details["reported_user"]["id"], bob_id);
+
+    let resolve = format!("/api/admin/reports/{}/resolve", report_id);
+    let (status, resolved) = call(&app, Method::POST, &resolve, Some(admin_token), Some(json!({ "action": "suspend" }))).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(resolved["status"], "actioned");
+    let (status, _) = call(&app, Method::POST, &resolve, Some(admin_token), Some(json!({ "action": "dismiss" }))).await;
+    assert_eq!(status, StatusCode::CONFLICT);
+
+    // Suspension ends every session and prevents logging in again.
+    let (status, _) = call(&app, Method::GET, "/api/sessions", Some(str_field(&bob, "access_token")), None).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+    let bob_email = str_field(&bob["user"], "email");
+    let (status, body) = call(
+        &app,
+        Method::POST,
+        "/api/auth/login",
+        None,
+        Some(json!({
+            "email": bob_email,
+            "password": "correct horse battery staple",
+            "device_name": "test device",
+            "public_key": [4, 5, 6],
+        })),
+    )
+    .await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+    assert_eq!(body["error"]["code"], "account_suspended");
+
+    let unsuspend = format!("/api/admin/users/{}/unsuspend", bob_id);
+    let (status, _) = call(&app, Method::POST, &unsuspend, Some(admin_token), None).await;
+    assert_eq!(status, StatusCode::NO_CONTENT);
+    log_in(&app, bob_email).await;
+}
+
+/// Connects the user's device to the event hub, as the events endpoint
+/// does, without going through HTTP.
+async fn connect(state: &AppState, login: &Value) -> (Uuid, Subscription) {
+    let user_id = Uuid::parse_str(str_field(&login["user"], "id")).unwrap();
+    let device_id = state.db.get_user_devices(user_id).await.unwrap()[0].id;
+    state.presence.connect(user_id, device_id);
+    (user_id, state.hub.subscribe(device_id, Uuid::new_v4()))
+}
+
+#[tokio::test]
+async fn test_upload_resumes_after_unrecorded_chunk() {
+    let state = test_state().await;
+    let app = api::create_router(state.clone());
+    let login = sign_up(&app).await;
+    let token = str_field(&login, "access_token").to_string();
+
+    let (status, created) = call(&app, Method::POST, "/api/attachments", Some(&token), Some(json!({ "size": 6 }))).await;
+    assert_eq!(status, StatusCode::CREATED);
+    let uri = format!("/api/attachments/{}", str_field(&created, "id"));
+
+    let upload = |offset: i64, chunk: &'static [u8]| {
+        Request::builder()
+            .method(Method::PATCH)
+            .uri(&uri)
+            .header(header::AUTHORIZATION, format!("Bearer {}", token))
+            .header("upload-offset", offset)
+            .body(Body::from(chunk))
+            .unwrap()
+    };
+    let offset = |response: &axum::response::Response| response.headers()["upload-offset"].to_str().unwrap().to_string();
+
+    // The chunk reached the blob store, but recording the progress failed.
+    state.blobs.append(str_field(&created, "id"), 0, b"abc").await.unwrap();
+
+    // The client retries the chunk and is told to resume after it.
+    let response = app.clone().oneshot(upload(0, b"abc")).await.unwrap();
+    assert_eq!(response.status(), StatusCode::CONFLICT);
+    assert_eq!(offset(&response), "3");
+
+    let response = app.clone().oneshot(upload(3, b"def")).await.unwrap();
+    assert_eq!(response.status(), StatusCode::NO_CONTENT);
+    assert_eq!(offset(&response), "6");
+
+    let request = Request::builder()
+        .uri(&uri)
+        .header(header::AUTHORIZATION, format!("Bearer {}", token))
+        .body(Body::empty())
+        .unwrap();
+    let (status, bytes) = call_raw(&app, request).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(bytes, b"abcdef");
+}
+
+#[tokio::test]
+async fn test_chats_and_members() {
+    let app = api::create_router(test_state().await);
+    let alice = sign_up(&app).await;
+    let bob = sign_up(&app).await;
+    let carol = sign_up(&app).await;
+    let alice_token = str_field(&alice, "access_token");
+    let bob_token = str_field(&bob, "access_token");
+    let carol_token = str_field(&carol, "access_token");
+    let (bob_id, carol_id) = (&bob["user"]["id"], &carol["user"]["id"]);
+
+    // Starting a direct chat twice gives the same chat.
+    let direct = json!({ "member_ids": [bob_id] });
+    let (status, chat) = call(&app, Method::POST, "/api/chats", Some(alice_token), Some(direct.clone())).await;
+    assert_eq!(status, StatusCode::CREATED);
+    assert_eq!(chat["is_group"], false);
+    let (status, again) = call(&app, Method::POST, "/api/chats", Some(bob_token), Some(json!({ "member_ids": [alice["user"]["id"]] }))).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(again["id"], chat["id"]);
+
+    let group = json!({ "name": "Group", "member_ids": [bob_id] });
+    let (status, group) = call(&app, Method::POST, "/api/chats", Some(alice_token), Some(group)).await;
+    assert_eq!(status, StatusCode::CREATED);
+    let group_uri = format!("/api/chats/{}", str_field(&group, "id"));
+    let (_, chats) = call(&app, Method::GET, "/api/chats", Some(bob_token), None).await;
+    assert_eq!(chats.as_array().unwrap().len(), 2);
+
+    let (status, _) = call(&app, Method::GET, &group_uri, Some(carol_token), None).await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+    let add = json!({ "user_id": carol_id });
+    let members_uri = format!("{}/members", group_uri);
+    let (status, _) = call(&app, Method::POST, &members_uri, Some(bob_token), Some(add.clone())).await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+    let (status, _) = call(&app, Method::POST, &members_uri, Some(alice_token), Some(add.clone())).await;
+    assert_eq!(status, StatusCode::CREATED);
+    let (status, _) = call(&app, Method::POST, &members_uri, Some(alice_token), Some(add)).await;
+    assert_eq!(status, StatusCode::CONFLICT);
+    let (status, details) = call(&app, Method::GET, &group_uri, Some(carol_token), None).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(details["members"].as_array().unwrap().len(), 3);
+
+    // The only admin must hand over before leaving.
+    let alice_uri = format!("{}/members/{}", group_uri, str_field(&alice["user"], "id"));
+    let (status, _) = call(&app, Method::DELETE, &alice_uri, Some(alice_token), None).await;
+    assert_eq!(status, StatusCode::CONFLICT);
+    let bob_uri = format!("{}/members/{}", group_uri, str_field(&bob["user"], "id"));
+    let (status, _) = call(&app, Method::PUT, &bob_uri, Some(alice_token), Some(json!({ "role": "admin" }))).await;
+    assert_eq!(status, StatusCode::NO_CONTENT);
+    let (status, _) = call(&app, Method::DELETE, &alice_uri, Some(alice_token), None).await;
+    assert_eq!(status, StatusCode::NO_CONTENT);
+    let (status, _) = call(&app, Method::GET, &group_uri, Some(alice_token), None).await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+}
+
+#[tokio::test]
+async fn test_typing_is_relayed_to_chat_members() {
+    let state = test_state().await;
+    let app = api::create_router(state.clone());
+    let alice = sign_up(&app).await;
+    let bob = sign_up(&app).await;
+    let carol = sign_up(&app).await;
+    let alice_token = str_field(&alice, "access_token");
+    let (alice_id, _alice_events) = connect(&state, &alice).await;
+    let (_, mut bob_events) = connect(&state, &bob).await;
+
+    let (_, chat) = call(&app, Method::POST, "/api/chats", Some(alice_token), Some(json!({ "member_ids": [bob["user"]["id"]] }))).await;
+    let chat_id = Uuid::parse_str(str_field(&chat, "id")).unwrap();
+
+    let typing = json!({ "chat_id": chat_id, "typing": true });
+    let (status, _) = call(&app, Method::POST, "/api/typing", Some(alice_token), Some(typing.clone())).await;
+    assert_eq!(status, StatusCode::NO_CONTENT);
+    assert_eq!(
+        bob_events.next().await,
+        Some(Event::TypingStarted { chat_id: Some(chat_id), user_id: alice_id })
+    );
+    let stopped = json!({ "chat_id": chat_id, "typing": false });
+    call(&app, Method::POST, "/api/typing", Some(alice_token), Some(stopped)).await;
+    assert_eq!(
+        bob_events.next().await,
+        Some(Event::TypingStopped { chat_id: Some(chat_id), user_id: alice_id })
+    );
+
+    let (status, _) = call(&app, Method::POST, "/api/typing", Some(str_field(&carol, "access_token")), Some(typing)).await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+
+    // Direct typing needs a contact or a shared chat.
+    let stranger = json!({ "recipient_id": carol["user"]["id"], "typing": true });
+    let (status, _) = call(&app, Method::POST, "/api/typing", Some(alice_token), Some(stranger)).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+
+    // Typing to someone who blocked you goes nowhere.
+    let block = format!("/api/blocks/{}", alice_id);
+    call(&app, Method::PUT, &block, Some(str_field(&bob, "access_token")), None).await;
+    let direct = json!({ "recipient_id": bob["user"]["id"], "typing": true });
+    let (status, _) = call(&app, Method::POST, "/api/typing", Some(alice_token), Some(direct)).await;
+    assert_eq!(status, StatusCode::NO_CONTENT);
+    assert!(futures::poll!(bob_events.next()).is_pending());
+}
+
+#[tokio::test]
+async fn test_presence_respects_last_seen_setting() {
+    let state = test_state().await;
+    let app = api::create_router(state.clone());
+    let alice = sign_up(&app).await;
+    let bob = sign_up(&app).await;
+    let alice_token = str_field(&alice, "access_token");
+    let bob_token = str_field(&bob, "access_token");
+    let presence_uri = format!("/api/users/{}/presence", str_field(&alice["user"], "id"));
+
+    // Only contacts and chat peers may look.
+    let (status, _) = call(&app, Method::GET, &presence_uri, Some(bob_token), None).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    let contact = format!("/api/contacts/{}", str_field(&alice["user"], "id"));
+    call(&app, Method::PUT, &contact, Some(bob_token), None).await;
+
+    let (status, presence) = call(&app, Method::GET, &presence_uri, Some(bob_token), None).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(presence["online"], false);
+    let (_alice_id, _alice_events) = connect(&state, &alice).await;
+    let (_, presence) = call(&app, Method::GET, &presence_uri, Some(bob_token), None).await;
+    assert_eq!(presence["online"], true);
+    assert!(presence["last_seen"].is_string());
+
+    let hidden = json!({
+        "discoverable_by_username": true,
+        "discoverable_by_email": false,
+        "show_last_seen": false,
+    });
+    let (status, _) = call(&app, Method::PUT, "/api/account/privacy", Some(alice_token), Some(hidden)).await;
+    assert_eq!(status, StatusCode::OK);
+    let (status, _) = call(&app, Method::GET, &presence_uri, Some(bob_token), None).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    let (_, own) = call(&app, Method::GET, &presence_uri, Some(alice_token), None).await;
+    assert_eq!(own["online"], true);
+}
+
+#[tokio::test]
+async fn test_receipts_reach_the_sender() {
+    let state = test_state().await;
+    let app = api::create_router(state.clone());
+    let alice = sign_up(&app).await;
+    let bob = sign_up(&app).await;
+    let (alice_id, mut alice_events) = connect(&state, &alice).await;
+
+    // The server only routes the receipt; what it acknowledges is encrypted.
+    let receipt = json!({ "recipient_id": alice_id, "content": [9, 9], "kind": "receipt" });
+    let (status, sent) = call(&app, Method::POST, "/api/messages", Some(str_field(&bob, "access_token")), Some(receipt)).await;
+    assert_eq!(status, StatusCode::CREATED);
+    let bob_id = Uuid::parse_str(str_field(&bob["user"], "id")).unwrap();
+    let receipt_id = Uuid::parse_str(str_field(&sent, "id")).unwrap();
+    assert_eq!(
+        alice_events.next().await,
+        Some(Event::Receipt { id: receipt_id, sender_id: bob_id, content: vec![9, 9] })
+    );
+
+    // Devices that were offline find it with their messages.
+    let (_, inbox) = call(&app, Method::GET, "/api/messages", Some(str_field(&alice, "access_token")), None).await;
+    assert_eq!(inbox[0]["kind"], "receipt");
+
+    let with_attachment = json!({ "recipient_id": alice_id, "content": [1], "kind": "receipt", "attachment_ids": ["x"] });
+    let (status, _) = call(&app, Method::POST, "/api/messages", Some(str_field(&bob, "access_token")), Some(with_attachment)).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+async fn test_edit_delete_and_react() {
+    let state = test_state().await;
+    let app = api::create_router(state.clone());
+    let alice = sign_up(&app).await;
+    let bob = sign_up(&app).await;
+    let carol = sign_up(&app).await;
+    let alice_token = str_field(&alice, "access_token");
+    let bob_token = str_field(&bob, "access_token");
+    let (alice_id, bob_id) = (&alice["user"]["id"], &bob["user"]["id"]);
+
+    let (_, original) = call(&app, Method::POST, "/api/messages", Some(alice_token), Some(json!({ "recipient_id": bob_id, "content": [1] }))).await;
+    let control = |kind: &str, recipient_id: &Value| {
+        json!({ "recipient_id": recipient_id, "content": [2], "kind": kind, "target_id": original["id"] })
+    };
+
+    // Only the sender may edit, and only for a while.
+    let (status, _) = call(&app, Method::POST, "/api/messages", Some(bob_token), Some(control("edit", alice_id))).await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+    let (status, _) = call(&app, Method::POST, "/api/messages", Some(alice_token), Some(control("edit", bob_id))).await;
+    assert_eq!(status, StatusCode::CREATED);
+    let mut strict = state.clone();
+    strict.messages.edit_window = chrono::Duration::zero();
+    let strict_app = api::create_router(strict);
+    let (status, body) = call(&strict_app, Method::POST, "/api/messages", Some(alice_token), Some(control("edit", bob_id))).await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+    assert_eq!(body["error"]["code"], "edit_window_expired");
+
+    // Either party may react, to the other party only; nobody else may.
+    let (status, _) = call(&app, Method::POST, "/api/messages", Some(bob_token), Some(control("reaction", alice_id))).await;
+    assert_eq!(status, StatusCode::CREATED);
+    let (status, _) = call(&app, Method::POST, "/api/messages", Some(bob_token), Some(control("reaction", bob_id))).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    let (status, _) = call(&app, Method::POST, "/api/messages", Some(str_field(&carol, "access_token")), Some(control("reaction", alice_id))).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+
+    // Deleting removes the original and what referred to it; the deletion
+    // itself is delivered so that clients drop their copies.
+    let (status, _) = call(&app, Method::POST, "/api/messages", Some(alice_token), Some(control("delete", bob_id))).await;
+    assert_eq!(status, StatusCode::CREATED);
+    let (_, inbox) = call(&app, Method::GET, "/api/messages", Some(bob_token), None).await;
+    let kinds: Vec<&str> = inbox.as_array().unwrap().iter().map(|m| m["kind"].as_str().unwrap()).collect();
+    assert_eq!(kinds, vec!["delete"]);
+    let (status, _) = call(&app, Method::POST, "/api/messages", Some(bob_token), Some(control("reaction", alice_id))).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn test_threads_in_chats() {
+    let app = api::create_router(test_state().await);
+    let alice = sign_up(&app).await;
+    let bob = sign_up(&app).await;
+    let carol = sign_up(&app).await;
+    let alice_token = str_field(&alice, "access_token");
+    let bob_token = str_field(&bob, "access_token");
+    let (bob_id, carol_id) = (&bob["user"]["id"], &carol["user"]["id"]);
+
+    let (_, group) = call(&app, Method::POST, "/api/chats", Some(alice_token), Some(json!({ "name": "Group", "member_ids": [bob_id] }))).await;
+    let chat_id = str_field(&group, "id");
+    let thread_id = Uuid::new_v4();
+    let reply = |recipient_id: &Value| {
+        json!({ "recipient_id": recipient_id, "content": [1], "chat_id": chat_id, "thread_id": thread_id })
+    };
+
+    // Threads only exist within chats, between their members.
+    let (status, _) = call(&app, Method::POST, "/api/messages", Some(alice_token), Some(json!({ "recipient_id": bob_id, "content": [1], "thread_id": thread_id }))).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    let (status, _) = call(&app, Method::POST, "/api/messages", Some(alice_token), Some(reply(carol_id))).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    let (status, _) = call(&app, Method::POST, "/api/messages", Some(str_field(&carol, "access_token")), Some(reply(bob_id))).await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+
+    let mut sent = Vec::new();
+    for _ in 0..3 {
+        let (status, message) = call(&app, Method::POST, "/api/messages", Some(alice_token), Some(reply(bob_id))).await;
+        assert_eq!(status, StatusCode::CREATED);
+        sent.push(message);
+    }
+
+    let threads_uri = format!("/api/chats/{}/threads", chat_id);
+    let (_, threads) = call(&app, Method::GET, &threads_uri, Some(bob_token), None).await;
+    assert_eq!(threads[0]["thread_id"], thread_id.to_string());
+    assert_eq!(threads[0]["unread_count"], 3);
+
+    let messages_uri = format!("{}/{}/messages", threads_uri, thread_id);
+    let (_, page) = call(&app, Method::GET, &format!("{}?limit=2", messages_uri), Some(bob_token), None).await;
+    assert_eq!(page.as_array().unwrap().len(), 2);
+    assert_eq!(page[0]["id"], sent[2]["id"]);
+    let next = format!("{}?limit=2&before={}", messages_uri, str_field(&page[1], "id"));
+    let (_, page) = call(&app, Method::GET, &next, Some(bob_token), None).await;
+    assert_eq!(page.as_array().unwrap().len(), 1);
+    assert_eq!(page[0]["id"], sent[0]["id"]);
+
+    let read_uri = format!("{}/{}/read", threads_uri, thread_id);
+    let (status, _) = call(&app, Method::POST, &read_uri, Some(alice_token), Some(json!({ "up_to": sent[1]["id"] }))).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    let (status, _) = call(&app, Method::POST, &read_uri, Some(bob_token), Some(json!({ "up_to": sent[1]["id"] }))).await;
+    assert_eq!(status, StatusCode::NO_CONTENT);
+    let (_, threads) = call(&app, Method::GET, &threads_uri, Some(bob_token), None).await;
+    assert_eq!(threads[0]["unread_count"], 1);
+    let (status, _) = call(&app, Method::GET, &threads_uri, Some(str_field(&carol, "access_token")), None).await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+}
+
+#[tokio::test]
+async fn test_invite_links() {
+    let state = test_state().await;
+    let app = api::create_router(state.clone());
+    let alice = sign_up(&app).await;
+    let bob = sign_up(&app).await;
+    let carol = sign_up(&app).await;
+    let alice_token = str_field(&alice, "access_token");
+    let bob_token = str_field(&bob, "access_token");
+    let carol_token = str_field(&carol, "access_token");
+    let (_, mut alice_events) = connect(&state, &alice).await;
+
+    let (_, group) = call(&app, Method::POST, "/api/chats", Some(alice_token), Some(json!({ "name": "Group", "member_ids": [bob["user"]["id"]] }))).await;
+    let chat_id = Uuid::parse_str(str_field(&group, "id")).unwrap();
+    let invites_uri = format!("/api/chats/{}/invites", chat_id);
+
+    // Only admins make invites.
+    let (status, _) = call(&app, Method::POST, &invites_uri, Some(bob_token), Some(json!({}))).await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+    let (status, invite) = call(&app, Method::POST, &invites_uri, Some(alice_token), Some(json!({ "max_uses": 1 }))).await;
+    assert_eq!(status, StatusCode::CREATED);
+    let join_uri = format!("/api/invites/{}/join", str_field(&invite, "code"));
+
+    let (_, preview) = call(&app, Method::GET, &format!("/api/invites/{}", str_field(&invite, "code")), Some(carol_token), None).await;
+    assert_eq!(preview["name"], "Group");
+    assert_eq!(preview["member_count"], 2);
+    let (status, _) = call(&app, Method::POST, &join_uri, Some(bob_token), None).await;
+    assert_eq!(status, StatusCode::CONFLICT);
+    let (status, joined) = call(&app, Method::POST, &join_uri, Some(carol_token), None).await;
+    assert_eq!(status, StatusCode::CREATED);
+    assert_eq!(joined["status"], "joined");
+    assert_eq!(alice_events.next().await, Some(Event::ChatMembersChanged { chat_id }));
+
+    // Used up.
+    let dave = sign_up(&app).await;
+    let dave_token = str_field(&dave, "access_token");
+    let (status, _) = call(&app, Method::POST, &join_uri, Some(dave_token), None).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+
+    // With approval, joining only files a request for the admins.
+    let (_, invite) = call(&app, Method::POST, &invites_uri, Some(alice_token), Some(json!({ "requires_approval": true }))).await;
+    let join_uri = format!("/api/invites/{}/join", str_field(&invite, "code"));
+    let (status, pending) = call(&app, Method::POST, &join_uri, Some(dave_token), None).await;
+    assert_eq!(status, StatusCode::ACCEPTED);
+    assert_eq!(pending["status"], "pending");
+    let dave_id = Uuid::parse_str(str_field(&dave["user"], "id")).unwrap();
+    assert_eq!(alice_events.next().await, Some(Event::JoinRequested { chat_id, user_id: dave_id }));
+    let request_uri = format!("/api/chats/{}/join-requests/{}", chat_id, dave_id);
+    let (status, _) = call(&app, Method::POST, &request_uri, Some(bob_token), None).await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+    let (status, _) = call(&app, Method::POST, &request_uri, Some(alice_token), None).await;
+    assert_eq!(status, StatusCode::CREATED);
+    assert_eq!(alice_events.next().await, Some(Event::ChatMembersChanged { chat_id }));
+
+    // Revoked invites stop working.
+    let revoke_uri = format!("{}/{}", invites_uri, str_field(&invite, "id"));
+    let (status, _) = call(&app, Method::DELETE, &revoke_uri, Some(alice_token), None).await;
+    assert_eq!(status, StatusCode::NO_CONTENT);
+    let erin = sign_up(&app).await;
+    let (status, _) = call(&app, Method::POST, &join_uri, Some(str_field(&erin, "access_token")), None).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    let (_, invites) = call(&app, Method::GET, &invites_uri, Some(alice_token), None).await;
+    assert_eq!(invites.as_array().unwrap().len(), 2);
+}
+
+#[tokio::test]
+async fn test_broadcast_channels() {
+    let state = test_state().await;
+    let app = api::create_router(state.clone());
+    let alice = sign_up(&app).await;
+    let bob = sign_up(&app).await;
+    let carol = sign_up(&app).await;
+    let alice_token = str_field(&alice, "access_token");
+    let bob_token = str_field(&bob, "access_token");
+    let carol_token = str_field(&carol, "access_token");
+    let (_, mut alice_events) = connect(&state, &alice).await;
+    let (_, mut bob_events) = connect(&state, &bob).await;
+
+    let (status, _) = call(&app, Method::POST, "/api/chats", Some(alice_token), Some(json!({ "channel": true }))).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    let (status, channel) = call(&app, Method::POST, "/api/chats", Some(alice_token), Some(json!({ "name": "News", "channel": true, "member_ids": [bob["user"]["id"]] }))).await;
+    assert_eq!(status, StatusCode::CREATED);
+    assert_eq!(channel["is_channel"], true);
+    let chat_id = Uuid::parse_str(str_field(&channel, "id")).unwrap();
+
+    // Subscribers joining later are read-only too.
+    let chat_uri = format!("/api/chats/{}", chat_id);
+    let (status, member) = call(&app, Method::POST, &format!("{}/members", chat_uri), Some(alice_token), Some(json!({ "user_id": carol["user"]["id"] }))).await;
+    assert_eq!(status, StatusCode::CREATED);
+    assert_eq!(member["role"], "read_only");
+    // Only the admins hear of it; subscribers are not told of each other.
+    assert_eq!(alice_events.next().await, Some(Event::ChatMembersChanged { chat_id }));
+
+    let posts_uri = format!("{}/posts", chat_uri);
+    let (status, _) = call(&app, Method::POST, &posts_uri, Some(bob_token), Some(json!({ "content": [1] }))).await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+    let (status, _) = call(&app, Method::POST, "/api/typing", Some(bob_token), Some(json!({ "chat_id": chat_id, "typing": true }))).await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+    let (status, post) = call(&app, Method::POST, &posts_uri, Some(alice_token), Some(json!({ "content": [1, 2, 3] }))).await;
+    assert_eq!(status, StatusCode::CREATED);
+    let post_id = Uuid::parse_str(str_field(&post, "id")).unwrap();
+    // The first event Bob gets, as Carol joining was not one.
+    assert_eq!(bob_events.next().await, Some(Event::ChannelPost { chat_id, id: post_id }));
+
+    let (status, posts) = call(&app, Method::GET, &posts_uri, Some(carol_token), None).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(posts[0]["content"], json!([1, 2, 3]));
+    let (_, messages) = call(&app, Method::GET, "/api/messages", Some(carol_token), None).await;
+    assert!(messages.as_array().unwrap().is_empty());
+
+    // Subscribers see how many there are, but not who.
+    let (_, details) = call(&app, Method::GET, &chat_uri, Some(bob_token), None).await;
+    assert_eq!(details["member_count"], 3);
+    assert_eq!(details["members"].as_array().unwrap().len(), 1);
+    let (_, details) = call(&app, Method::GET, &chat_uri, Some(alice_token), None).await;
+    assert_eq!(details["members"].as_array().unwrap().len(), 3);
+
+    // A subscriber made a writer is news to the other subscribers.
+    let carol_uri = format!("{}/members/{}", chat_uri, str_field(&carol["user"], "id"));
+    let (status, _) = call(&app, Method::PUT, &carol_uri, Some(alice_token), Some(json!({ "role": "member" }))).await;
+    assert_eq!(status, StatusCode::NO_CONTENT);
+    assert_eq!(bob_events.next().await, Some(Event::ChatMembersChanged { chat_id }));
+}
+
+#[tokio::test]
+async fn test_chat_settings_pins_and_stars() {
+    let state = test_state().await;
+    let app = api::create_router(state.clone());
+    let alice = sign_up(&app).await;
+    let bob = sign_up(&app).await;
+    let alice_token = str_field(&alice, "access_token");
+    let bob_token = str_field(&bob, "access_token");
+    let bob_id = &bob["user"]["id"];
+    let (_, mut bob_events) = connect(&state, &bob).await;
+
+    let (_, group) = call(&app, Method::POST, "/api/chats", Some(alice_token), Some(json!({ "name": "Group", "member_ids": [bob_id] }))).await;
+    let chat_id = Uuid::parse_str(str_field(&group, "id")).unwrap();
+    let chat_uri = format!("/api/chats/{}", chat_id);
+
+    // Only admins change what everyone sees.
+    let settings = json!({ "topic": "Plans", "disappear_after_seconds": 60 });
+    let (status, _) = call(&app, Method::PUT, &format!("{}/settings", chat_uri), Some(bob_token), Some(settings.clone())).await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+    let (status, _) = call(&app, Method::PUT, &format!("{}/settings", chat_uri), Some(alice_token), Some(settings)).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(bob_events.next().await, Some(Event::ChatUpdated { chat_id }));
+    let (_, details) = call(&app, Method::GET, &chat_uri, Some(bob_token), None).await;
+    assert_eq!(details["topic"], "Plans");
+
+    // Messages to the chat disappear by default.
+    let (_, message) = call(&app, Method::POST, "/api/messages", Some(alice_token), Some(json!({ "recipient_id": bob_id, "content": [1], "chat_id": chat_id }))).await;
+    assert!(message["expires_at"].is_string());
+    let message_id = str_field(&message, "id");
+
+    // Muting is the member's own business, shared by their devices.
+    let (status, _) = call(&app, Method::PUT, &format!("{}/mute", chat_uri), Some(bob_token), Some(json!({ "until": chrono::Utc::now() + chrono::Duration::hours(1) }))).await;
+    assert_eq!(status, StatusCode::NO_CONTENT);
+    assert_eq!(bob_events.next().await, Some(Event::ChatUpdated { chat_id }));
+    let (_, details) = call(&app, Method::GET, &chat_uri, Some(bob_token), None).await;
+    assert!(details["muted_until"].is_string());
+    let (_, details) = call(&app, Method::GET, &chat_uri, Some(alice_token), None).await;
+    assert!(details["muted_until"].is_null());
+    assert!(details["members"].as_array().unwrap().iter().all(|m| m.get("muted_until").is_none()));
+
+    let pins_uri = format!("{}/pins", chat_uri);
+    let (status, _) = call(&app, Method::POST, &pins_uri, Some(bob_token), Some(json!({ "content": [7] }))).await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+    let (status, pin) = call(&app, Method::POST, &pins_uri, Some(alice_token), Some(json!({ "content": [7] }))).await;
+    assert_eq!(status, StatusCode::CREATED);
+    assert_eq!(bob_events.next().await, Some(Event::ChatUpdated { chat_id }));
+    let (_, pins) = call(&app, Method::GET, &pins_uri, Some(bob_token), None).await;
+    assert_eq!(pins[0]["content"], json!([7]));
+    let (status, _) = call(&app, Method::DELETE, &format!("{}/{}", pins_uri, str_field(&pin, "id")), Some(alice_token), None).await;
+    assert_eq!(status, StatusCode::NO_CONTENT);
+
+    // Stars are per user.
+    let star_uri = format!("/api/stars/{}", message_id);
+    let (status, _) = call(&app, Method::PUT, &star_uri, Some(bob_token), None).await;
+    assert_eq!(status, StatusCode::NO_CONTENT);
+    let (_, stars) = call(&app, Method::GET, "/api/stars", Some(bob_token), None).await;
+    assert_eq!(stars[0]["message_id"], message_id);
+    let (_, stars) = call(&app, Method::GET, "/api/stars", Some(alice_token), None).await;
+    assert!(stars.as_array().unwrap().is_empty());
+    let carol = sign_up(&app).await;
+    let (status, _) = call(&app, Method::PUT, &star_uri, Some(str_field(&carol, "access_token")), None).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    let (status, _) = call(&app, Method::DELETE, &star_uri, Some(bob_token), None).await;
+    assert_eq!(status, StatusCode::NO_CONTENT);
+}
+
+#[tokio::test]
+async fn test_bot_api_keys() {
+    let app = api::create_router(test_state().await);
+    let alice = sign_up(&app).await;
+    let mallory = sign_up(&app).await;
+    let alice_token = str_field(&alice, "access_token");
+    let mallory_token = str_field(&mallory, "access_token");
+
+    let (status, created) = call(&app, Method::POST, "/api/bots", Some(alice_token), Some(json!({ "username": Uuid::new_v4().simple().to_string(), "public_key": [4, 5, 6] }))).await;
+    assert_eq!(status, StatusCode::CREATED);
+    let bot_id = str_field(&created["bot"], "id").to_string();
+    let bot_key = str_field(&created["key"], "api_key").to_string();
+    assert_eq!(created["bot"]["bot_owner_id"], alice["user"]["id"]);
+    assert!(created["key"].get("key_hash").is_none());
+    let (_, profile) = call(&app, Method::GET, &format!("/api/users/{}", bot_id), Some(mallory_token), None).await;
+    assert_eq!(profile["is_bot"], true);
+
+    // The key works wherever an access token does, but bots cannot make
+    // bots of their own.
+    let (status, group) = call(&app, Method::POST, "/api/chats", Some(alice_token), Some(json!({ "name": "Ops", "member_ids": [bot_id] }))).await;
+    assert_eq!(status, StatusCode::CREATED);
+    let (status, chats) = call(&app, Method::GET, "/api/chats", Some(&bot_key), None).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(chats[0]["id"], group["id"]);
+    let (status, _) = call(&app, Method::POST, "/api/messages", Some(&bot_key), Some(json!({ "recipient_id": alice["user"]["id"], "content": [9], "chat_id": group["id"] }))).await;
+    assert_eq!(status, StatusCode::CREATED);
+    let (status, _) = call(&app, Method::POST, "/api/bots", Some(&bot_key), Some(json!({ "username": "nested", "public_key": [1] }))).await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+
+    // Only the owner manages the bot.
+    let keys_uri = format!("/api/bots/{}/keys", bot_id);
+    let (status, _) = call(&app, Method::GET, &keys_uri, Some(mallory_token), None).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    let (status, second) = call(&app, Method::POST, &keys_uri, Some(alice_token), Some(json!({ "public_key": [7] }))).await;
+    assert_eq!(status, StatusCode::CREATED);
+    let (status, _) = call(&app, Method::DELETE, &format!("{}/{}", keys_uri, str_field(&created["key"], "id")), Some(alice_token), None).await;
+    assert_eq!(status, StatusCode::NO_CONTENT);
+    let (status, _) = call(&app, Method::GET, "/api/chats", Some(&bot_key), None).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+    let (status, _) = call(&app, Method::GET, "/api/chats", Some(str_field(&second, "api_key")), None).await;
+    assert_eq!(status, StatusCode::OK);
+
+    let (status, _) = call(&app, Method::DELETE, &format!("/api/bots/{}", bot_id), Some(alice_token), None).await;
+    assert_eq!(status, StatusCode::NO_CONTENT);
+    let (_, bots) = call(&app, Method::GET, "/api/bots", Some(alice_token), None).await;
+    assert!(bots.as_array().unwrap().is_empty());
+    let (status, _) = call(&app, Method::GET, "/api/chats", Some(str_field(&second, "api_key")), None).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+}
+
+#[tokio::test]
+async fn test_bot_webhooks_are_signed() {
+    let (deliveries, mut received) = tokio::sync::mpsc::unbounded_channel();
+    let endpoint = Router::new().route(
+        "/hook",
+        axum::routing::post(move |headers: axum::http::HeaderMap, body: axum::body::Bytes| {
+            let deliveries = deliveries.clone();
+            async move {
+                let _ = deliveries.send((headers, body));
+                StatusCode::NO_CONTENT
+            }
+        }),
+    );
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move { axum::serve(listener, endpoint).await });
+
+    let app = api::create_router(test_state().await);
+    let alice = sign_up(&app).await;
+    let alice_token = str_field(&alice, "access_token");
+    let (_, created) = call(&app, Method::POST, "/api/bots", Some(alice_token), Some(json!({ "username": Uuid::new_v4().simple().to_string(), "public_key": [4] }))).await;
+    let bot_id = str_field(&created["bot"], "id").to_string();
+    let webhook_uri = format!("/api/bots/{}/webhook", bot_id);
+
+    let (status, _) = call(&app, Method::PUT, &webhook_uri, Some(alice_token), Some(json!({ "url": "ftp://example.com/hook" }))).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    let (status, webhook) = call(&app, Method::PUT, &webhook_uri, Some(alice_token), Some(json!({ "url": format!("http://{}/hook", addr) }))).await;
+    assert_eq!(status, StatusCode::OK);
+    let secret = str_field(&webhook, "secret").to_string();
+    let (_, shown) = call(&app, Method::GET, &webhook_uri, Some(alice_token), None).await;
+    assert!(shown.get("secret").is_none());
+
+    // The bot gets the message as sent: still encrypted.
+    let (_, message) = call(&app, Method::POST, "/api/messages", Some(alice_token), Some(json!({ "recipient_id": bot_id, "content": [1, 2, 3] }))).await;
+    let (headers, body) = tokio::time::timeout(Duration::from_secs(5), received.recv()).await.unwrap().unwrap();
+    let timestamp: i64 = headers[webhooks::TIMESTAMP_HEADER].to_str().unwrap().parse().unwrap();
+    assert_eq!(headers[webhooks::SIGNATURE_HEADER].to_str().unwrap(), webhooks::sign(&secret, timestamp, &body));
+    let payload: Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(payload["type"], "message");
+    assert_eq!(payload["message"]["id"], message["id"]);
+    assert_eq!(payload["message"]["content"], json!([1, 2, 3]));
+
+    let (status, _) = call(&app, Method::DELETE, &webhook_uri, Some(alice_token), None).await;
+    assert_eq!(status, StatusCode::NO_CONTENT);
+}
+
+#[tokio::test]
+async fn test_bot_webhooks_must_be_public() {
+    let (deliveries, mut received) = tokio::sync::mpsc::unbounded_channel();
+    let endpoint = Router::new().route(
+        "/hook",
+        axum::routing::post(move || {
+            let deliveries = deliveries.clone();
+            async move {
+                let _ = deliveries.send(());
+                StatusCode::NO_CONTENT
+            }
+        }),
+    );
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move { axum::serve(listener, endpoint).await });
+
+    let mut state = test_state().await;
+    state.bots.allow_insecure_webhooks = false;
+    state.webhooks = Arc::new(WebhookSender::new(Duration::from_secs(5), false));
+    let app = api::create_router(state.clone());
+    let alice = sign_up(&app).await;
+    let alice_token = str_field(&alice, "access_token");
+    let (_, created) = call(&app, Method::POST, "/api/bots", Some(alice_token), Some(json!({ "username": Uuid::new_v4().simple().to_string(), "public_key": [4] }))).await;
+    let bot_id: Uuid = str_field(&created["bot"], "id").parse().unwrap();
+    let webhook_uri = format!("/api/bots/{}/webhook", bot_id);
+
+    for url in [
+        "https://127.0.0.1/hook",
+        "https://localhost/hook",
+        "https://169.254.169.254/latest/meta-data",
+        "https://10.1.2.3/hook",
+        "https://192.168.0.1/hook",
+        "https://[::1]/hook",
+        "https://[::ffff:127.0.0.1]/hook",
+        "https://0.0.0.0/hook",
+        "https://0.1.2.3/hook",
+        "https://[64:ff9b::7f00:1]/hook",
+        "https://[2002:a9fe:a9fe::]/hook",
+    ] {
+        let (status, _) = call(&app, Method::PUT, &webhook_uri, Some(alice_token), Some(json!({ "url": url }))).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST, "{}", url);
+    }
+
+    // A webhook stored before the check, or whose name resolved elsewhere
+    // then, is still not delivered to.
+    for host in [addr.to_string(), format!("localhost:{}", addr.port())] {
+        let webhook = BotWebhook {
+            bot_id,
+            url: format!("http://{}/hook", host),
+            secret: "secret".to_string(),
+            created_at: chrono::Utc::now(),
+        };
+        state.db.set_bot_webhook(&webhook).await.unwrap();
+        let (status, _) = call(&app, Method::POST, "/api/messages", Some(alice_token), Some(json!({ "recipient_id": bot_id, "content": [1] }))).await;
+        assert_eq!(status, StatusCode::CREATED);
+        assert!(tokio::time::timeout(Duration::from_millis(500), received.recv()).await.is_err(), "{}", host);
+    }
+}
+
+#[tokio::test]
+async fn test_health_and_metrics() {
+    crate::telemetry::handle();
+    let app = api::create_router(test_state().await);
+
+    for uri in ["/healthz", "/readyz"] {
+        let response = app.clone().oneshot(Request::get(uri).body(Body::empty()).unwrap()).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+
+    let (status, _) = call(&app, Method::GET, "/api/chats", Some("not-a-token"), None).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+    let response = app.clone().oneshot(Request::get("/metrics").body(Body::empty()).unwrap()).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+    let text = String::from_utf8(body.to_vec()).unwrap();
+    assert!(text.contains(r#"pulse_http_requests_total{method="GET",route="/api/chats",status="401"}"#));
+    assert!(text.contains("pulse_http_request_duration_seconds_bucket"));
+    assert!(text.contains(r#"pulse_auth_failures_total{code="unauthorized"}"#));
+    assert!(text.contains("pulse_messages_queued"));
+    assert!(text.contains(r#"pulse_db_pool_connections{state="idle"}"#));
+    // Probes are not counted.
+    assert!(!text.contains(r#"route="/healthz""#));
+}
+
+#[tokio::test]
+async fn test_request_body_limit() {
+    let mut state = test_state().await;
+    state.limits.max_body_bytes = 1024;
+    let app = api::create_router(state);
+    let login = sign_up(&app).await;
+    let token = str_field(&login, "access_token");
+
+    let (status, _) = call(&app, Method::POST, "/api/messages", Some(token), Some(json!({ "recipient_id": login["user"]["id"], "content": vec![1; 2048] }))).await;
+    assert_eq!(status, StatusCode::PAYLOAD_TOO_LARGE);
+    let (status, _) = call(&app, Method::POST, "/api/messages", Some(token), Some(json!({ "recipient_id": login["user"]["id"], "content": [1] }))).await;
+    assert_eq!(status, StatusCode::CREATED);
+}
+
+#[tokio::test]
+async fn test_jwks_and_legacy_tokens() {
+    let app = api::create_router(test_state().await);
+    let login = sign_up(&app).await;
+    let token = str_field(&login, "access_token");
+
+    let response = app.clone().oneshot(Request::get("/.well-known/jwks.json").body(Body::empty()).unwrap()).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert!(response.headers().contains_key(header::CACHE_CONTROL));
+    let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+    let jwks: jsonwebtoken::jwk::JwkSet = serde_json::from_slice(&body).unwrap();
+    let header = jsonwebtoken::decode_header(token).unwrap();
+    assert_eq!(header.typ.as_deref(), Some("at+jwt"));
+    let jwk = jwks.find(&header.kid.unwrap()).unwrap();
+
+    // Another service can check tokens with the published key alone.
+    let mut validation = jsonwebtoken::Validation::new(header.alg);
+    validation.set_required_spec_claims(&["exp", "sub"]);
+    let key = jsonwebtoken::DecodingKey::from_jwk(jwk).unwrap();
+    let claims = jsonwebtoken::decode::<Value>(token, &key, &validation).unwrap().claims;
+    assert_eq!(claims["sub"], login["user"]["id"]);
+
+    // Tokens signed with the old shared secret still work until they expire.
+    let legacy = jsonwebtoken::encode(
+        &jsonwebtoken::Header::default(),
+        &claims,
+        &jsonwebtoken::EncodingKey::from_secret(JWT_SECRET.as_bytes()),
+    )
+    .unwrap();
+    let (status, _) = call(&app, Method::GET, "/api/chats", Some(&legacy), None).await;
+    assert_eq!(status, StatusCode::OK);
+}
+
+#[tokio::test]
+async fn test_security_events() {
+    let state = test_state().await;
+    let app = api::create_router(state.clone());
+    let alice = sign_up(&app).await;
+    let email = str_field(&alice["user"], "email").to_string();
+    let (_, mut alice_events) = connect(&state, &alice).await;
+
+    // Devices already signed in hear about attempts and sign-ins elsewhere.
+    let wrong = json!({ "email": email, "password": "wrong", "device_name": "laptop", "public_key": [1] });
+    let (status, _) = call(&app, Method::POST, "/api/auth/login", None, Some(wrong)).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+    let second = log_in(&app, &email).await;
+    let second_token = str_field(&second, "access_token");
+    assert!(matches!(alice_events.next().await, Some(Event::SecurityEvent { kind: AuditKind::LoginFailed, .. })));
+    assert!(matches!(alice_events.next().await, Some(Event::SecurityEvent { kind: AuditKind::Login, .. })));
+
+    let (status, _) = call(&app, Method::DELETE, "/api/sessions", Some(second_token), None).await;
+    assert_eq!(status, StatusCode::NO_CONTENT);
+
+    let (status, events) = call(&app, Method::GET, "/api/account/security-events", Some(second_token), None).await;
+    assert_eq!(status, StatusCode::OK);
+    let kinds: Vec<&str> = events.as_array().unwrap().iter().map(|e| str_field(e, "kind")).collect();
+    assert_eq!(kinds, ["sessions_revoked", "login", "login_failed", "login"]);
+    assert_eq!(events[0]["details"]["reason"], "signed_out_elsewhere");
+    assert_eq!(events[1]["details"]["device_name"], "test device");
+    assert_eq!(events[2]["details"]["reason"], "password");
+    assert!(events[0].get("hash").is_none() && events[0].get("actor_id").is_none());
+
+    let older = format!("/api/account/security-events?before={}&limit=1", events[2]["id"]);
+    let (_, page) = call(&app, Method::GET, &older, Some(second_token), None).await;
+    assert_eq!(page.as_array().unwrap().len(), 1);
+    assert_eq!(page[0]["id"], events[3]["id"]);
+
+    // Other users do not see them.
+    let bob = sign_up(&app).await;
+    let (_, bobs) = call(&app, Method::GET, "/api/account/security-events", Some(str_field(&bob, "access_token")), None).await;
+    assert_eq!(bobs.as_array().unwrap().len(), 1);
+
+    let verification = audit::verify(&state.db).await.unwrap();
+    assert!(verification.broken_at.is_none() && verification.entries >= 5);
+}
+
+#[tokio::test]
+async fn test_login_survives_audit_failure() {
+    let (state, url) = file_state().await;
+    let app = api::create_router(state.clone());
+    let login = sign_up(&app).await;
+    let user_id: Uuid = login["user"]["id"].as_str().unwrap().parse().unwrap();
+
+    let pool = sqlx::sqlite::SqlitePool::connect(&url).await.unwrap();
+    sqlx::query("CREATE TRIGGER audit_events_down BEFORE INSERT ON audit_events BEGIN SELECT RAISE(ABORT, 'down'); END")
+        .execute(&pool)
+        .await
+        .unwrap();
+    let (status, _) = call(
+        &app,
+        Method::POST,
+        "/api/auth/login",
+        None,
+        Some(json!({
+            "email": login["user"]["email"],
+            "password": "correct horse battery staple",
+            "device_name": "unrecorded device",
+            "public_key": [4, 5, 6],
+        })),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(state.db.list_sessions(user_id).await.unwrap().len(), 2);
+
+    let (status, _) = call(
+        &app,
+        Method::POST,
+        "/api/auth/login",
+        None,
+        Some(json!({
+            "email": login["user"]["email"],
+            "password": "wrong password entirely",
+            "device_name": "unrecorded device",
+            "public_key": [4, 5, 6],
+        })),
+    )
+    .await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+}
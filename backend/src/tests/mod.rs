@@ -7,7 +7,7 @@ mod crypto_tests {
         let crypto = Crypto::new().unwrap();
         let message = b"Test message for Pulse";
         
-        let encrypted = crypto.encrypt(message).unwrap();
+        let encrypted = crypto.encrypt(message, None).unwrap();
         let decrypted = crypto.decrypt(&encrypted).unwrap();
         
         assert_eq!(message, decrypted.as_slice());
@@ -30,4 +30,681 @@ mod server_tests {
         info!("Testing server initialization...");
         // TODO: Add actual server initialization tests
     }
-} 
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod migration_tests {
+    use chrono::Utc;
+    use crate::db::migrations::{self, AppliedMigration, MigrationError};
+
+    fn applied(version: i64, checksum: String) -> AppliedMigration {
+        AppliedMigration {
+            version,
+            checksum,
+            applied_at: Utc::now(),
+        }
+    }
+
+    #[test]
+    fn test_backends_have_matching_versions() {
+        let sqlite: Vec<_> = migrations::SQLITE.iter().map(|m| (m.version, m.name)).collect();
+        let postgres: Vec<_> = migrations::POSTGRES.iter().map(|m| (m.version, m.name)).collect();
+        assert_eq!(sqlite, postgres);
+        assert!(sqlite.windows(2).all(|w| w[0].0 < w[1].0));
+    }
+
+    #[test]
+    fn test_pending_skips_applied() {
+        let first = &migrations::SQLITE[0];
+        let pending = migrations::pending(
+            migrations::SQLITE,
+            &[applied(first.version, first.checksum())],
+        )
+        .unwrap();
+        assert_eq!(pending.len(), migrations::SQLITE.len() - 1);
+        assert!(pending.iter().all(|m| m.version != first.version));
+    }
+
+    #[test]
+    fn test_refuses_newer_database() {
+        let future = migrations::latest_version(migrations::SQLITE) + 1;
+        assert!(matches!(
+            migrations::pending(migrations::SQLITE, &[applied(future, String::new())]),
+            Err(MigrationError::DatabaseTooNew { .. })
+        ));
+    }
+
+    #[test]
+    fn test_detects_modified_migration() {
+        assert!(matches!(
+            migrations::pending(migrations::SQLITE, &[applied(1, "tampered".to_string())]),
+            Err(MigrationError::ChecksumMismatch { version: 1, .. })
+        ));
+    }
+}
+
+#[cfg(test)]
+mod config_tests {
+    use std::collections::HashMap;
+    use std::time::Duration;
+    use crate::config::{env_at_least, env_flag, env_or, ConfigError, ServerConfig};
+
+    const FILE: &str = r#"
+        database_url = "sqlite:pulse.db"
+        jwt_secret = "from-file"
+        port = 9000
+
+        [tls]
+        cert_path = "cert.pem"
+        key_path = "key.pem"
+
+        [limits]
+        request_timeout_seconds = 10
+    "#;
+
+    fn env(vars: &[(&str, &str)]) -> impl Fn(&str) -> Option<String> {
+        let vars: HashMap<String, String> = vars.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect();
+        move |name| vars.get(name).cloned()
+    }
+
+    #[test]
+    fn test_environment_overrides_file() {
+        let config = ServerConfig::from_toml(FILE, env(&[("JWT_SECRET", "from-env"), ("SERVER_HOST", "0.0.0.0")])).unwrap();
+        assert_eq!(config.jwt_secret.as_deref(), Some("from-env"));
+        assert_eq!(config.database_url, "sqlite:pulse.db");
+        assert_eq!(config.listen, "0.0.0.0:9000".parse().unwrap());
+        assert_eq!(config.tls.unwrap().cert_path.to_str(), Some("cert.pem"));
+        assert_eq!(config.limits.request_timeout, Duration::from_secs(10));
+        assert_eq!(config.limits.shutdown_grace, Duration::from_secs(30));
+    }
+
+    #[test]
+    fn test_validation_errors() {
+        assert!(matches!(
+            ServerConfig::from_toml("", env(&[("JWT_SECRET", "secret")])),
+            Err(ConfigError::Missing("database_url", "DATABASE_URL"))
+        ));
+        let required = [("DATABASE_URL", "sqlite::memory:"), ("JWT_SECRET", "secret")];
+        let config = ServerConfig::from_toml("", env(&required[..1])).unwrap();
+        assert!(config.jwt_secret.is_none() && config.tls.is_none());
+        assert!(matches!(
+            ServerConfig::from_toml("[tls]\ncert_path = \"cert.pem\"", env(&required)),
+            Err(ConfigError::Invalid("tls", _))
+        ));
+        assert!(matches!(
+            ServerConfig::from_toml("prot = 80", env(&required)),
+            Err(ConfigError::Parse { .. })
+        ));
+        assert!(matches!(
+            ServerConfig::from_toml("", env(&[required[0], required[1], ("SERVER_PORT", "http")])),
+            Err(ConfigError::Invalid("SERVER_PORT", _))
+        ));
+        assert!(matches!(
+            ServerConfig::from_toml("[limits]\nmax_body_bytes = 0", env(&required)),
+            Err(ConfigError::Invalid("limits.max_body_bytes", _))
+        ));
+    }
+
+    #[test]
+    fn test_environment_settings() {
+        // Names no other test reads, as the environment is shared.
+        std::env::set_var("PULSE_TEST_NUMBER", "12");
+        std::env::set_var("PULSE_TEST_TYPO", "12O");
+        std::env::set_var("PULSE_TEST_FLAG", "yes");
+        assert_eq!(env_or("PULSE_TEST_NUMBER", 5).unwrap(), 12);
+        assert_eq!(env_or("PULSE_TEST_UNSET", 5).unwrap(), 5);
+        assert!(matches!(env_or("PULSE_TEST_TYPO", 5), Err(ConfigError::Invalid("PULSE_TEST_TYPO", _))));
+        assert!(matches!(env_at_least("PULSE_TEST_NUMBER", 5, 13), Err(ConfigError::Invalid(..))));
+        assert!(matches!(env_flag("PULSE_TEST_FLAG", false), Err(ConfigError::Invalid(..))));
+        assert!(!env_flag("PULSE_TEST_UNSET", false).unwrap());
+    }
+}
+
+#[cfg(test)]
+mod storage_tests;
+
+#[cfg(test)]
+mod api_tests;
+
+#[cfg(test)]
+mod admin_tests {
+    use std::sync::Arc;
+    use chrono::{Duration, Utc};
+    use uuid::Uuid;
+    use crate::admin::{Admin, AdminError};
+    use crate::blob_store::FsBlobStore;
+    use crate::db;
+    use crate::models::{AuditKind, Device, Session, User};
+
+    #[tokio::test]
+    async fn test_admin_commands() {
+        let db = db::connect("sqlite::memory:").await.unwrap();
+        db.migrate().await.unwrap();
+        let root = std::env::temp_dir().join(format!("pulse-admin-{}", Uuid::new_v4()));
+        let admin = Admin::new(db.clone(), Arc::new(FsBlobStore::new(&root).await.unwrap()));
+
+        let now = Utc::now();
+        let user = User {
+            id: Uuid::new_v4(),
+            username: "mallory".to_string(),
+            email: "mallory@example.com".to_string(),
+            public_key: vec![1],
+            created_at: now,
+            last_seen: now,
+            password_hash: None,
+            email_verified_at: None,
+            delete_after: None,
+            is_admin: false,
+            suspended_at: None,
+            bot_owner_id: None,
+        };
+        db.create_user(&user).await.unwrap();
+        let device = Device {
+            id: Uuid::new_v4(),
+            user_id: user.id,
+            name: "phone".to_string(),
+            public_key: vec![2],
+            last_seen: now,
+            is_online: false,
+        };
+        db.create_device(&device).await.unwrap();
+        let session = Session {
+            id: Uuid::new_v4(),
+            user_id: user.id,
+            device_id: device.id,
+            refresh_token_hash: "hash".to_string(),
+            previous_refresh_token_hash: None,
+            created_at: now,
+            last_used_at: None,
+            expires_at: now + Duration::days(1),
+            revoked_at: None,
+        };
+        db.create_session(&session).await.unwrap();
+
+        for who in [user.id.to_string(), user.email.clone(), user.username.clone()] {
+            assert_eq!(admin.find_user(&who).await.unwrap().id, user.id);
+        }
+        assert!(matches!(admin.find_user("nobody").await, Err(AdminError::UserNotFound(_))));
+
+        let details = admin.user_details("mallory").await.unwrap();
+        assert_eq!((details.devices.len(), details.sessions.len()), (1, 1));
+
+        let suspended = admin.set_suspended("mallory", true).await.unwrap();
+        assert!(suspended.suspended_at.is_some());
+        assert!(db.get_active_session(session.id).await.unwrap().is_none());
+        assert!(admin.set_suspended("mallory", false).await.unwrap().suspended_at.is_none());
+        assert!(admin.set_admin("mallory", true).await.unwrap().is_admin);
+
+        assert!(matches!(
+            admin.revoke_device("mallory", Uuid::new_v4()).await,
+            Err(AdminError::DeviceNotFound(..))
+        ));
+        admin.revoke_device("mallory", device.id).await.unwrap();
+        assert!(db.get_user_devices(user.id).await.unwrap().is_empty());
+
+        db.set_delete_after(user.id, Some(now - Duration::seconds(1))).await.unwrap();
+        let report = admin.purge().await.unwrap();
+        assert_eq!(report.accounts, 1);
+        assert!(db.get_user(user.id).await.unwrap().is_none());
+
+        // Entries about deleted accounts are still found by id.
+        let events = admin.audit_log(Some(&user.id.to_string()), None, None, 100).await.unwrap();
+        let kinds: Vec<AuditKind> = events.iter().map(|e| e.kind).collect();
+        assert_eq!(
+            kinds,
+            [
+                AuditKind::AccountDeleted,
+                AuditKind::DeviceRemoved,
+                AuditKind::RoleChanged,
+                AuditKind::AccountUnsuspended,
+                AuditKind::AccountSuspended,
+            ]
+        );
+        assert_eq!(events[1].details["via"], "pulse-admin");
+        assert_eq!(events[4].details["sessions"], serde_json::json!([session.id]));
+        assert!(admin.audit_log(None, Some(now + Duration::days(1)), None, 100).await.unwrap().is_empty());
+        assert!(admin.verify_audit_log().await.unwrap().broken_at.is_none());
+
+        assert!(admin.signing_keys().await.unwrap().is_empty());
+        let key = admin.rotate_signing_key(true).await.unwrap();
+        assert_eq!(admin.signing_keys().await.unwrap()[0].id, key.id);
+
+        tokio::fs::remove_dir_all(root).await.unwrap();
+    }
+}
+
+#[cfg(test)]
+mod jwt_tests {
+    use chrono::{Duration, Utc};
+    use jsonwebtoken::{encode, EncodingKey, Header};
+    use crate::api::Claims;
+    use crate::db::{self, Database};
+    use crate::jwt::{JwtConfig, KeyRing, TokenError, ACCESS_TOKEN_TYPE};
+    use crate::models::SigningAlgorithm;
+
+    async fn database() -> Database {
+        let db = db::connect("sqlite::memory:").await.unwrap();
+        db.migrate().await.unwrap();
+        db
+    }
+
+    fn config(algorithm: SigningAlgorithm) -> JwtConfig {
+        JwtConfig {
+            algorithm,
+            rotation_interval: Duration::days(30),
+            publish_delay: Duration::minutes(10),
+            legacy_secret: Some("legacy".to_string()),
+        }
+    }
+
+    fn claims(exp: chrono::DateTime<Utc>) -> Claims {
+        Claims {
+            sub: "user".to_string(),
+            did: None,
+            sid: None,
+            exp: exp.timestamp() as usize,
+            iat: Utc::now().timestamp() as usize,
+        }
+    }
+
+    fn kid(token: &str) -> String {
+        jsonwebtoken::decode_header(token).unwrap().kid.unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_tokens_verify_across_rotation() {
+        let db = database().await;
+        let keys = KeyRing::new(config(SigningAlgorithm::EdDsa), Duration::minutes(15));
+        let first = keys.maintain(&db).await.unwrap().unwrap();
+        assert!(keys.maintain(&db).await.unwrap().is_none());
+
+        let token = keys.sign(ACCESS_TOKEN_TYPE, &claims(Utc::now() + Duration::minutes(5))).unwrap();
+        assert_eq!(kid(&token), first.id);
+        let verified: Claims = keys.verify(&token, ACCESS_TOKEN_TYPE).unwrap();
+        assert_eq!(verified.sub, "user");
+        assert_eq!(keys.verify::<Claims>(&token, "mfa+jwt").unwrap_err(), TokenError::Invalid);
+        let mut tampered = token.clone();
+        tampered.pop();
+        assert_eq!(keys.verify::<Claims>(&tampered, ACCESS_TOKEN_TYPE).unwrap_err(), TokenError::Invalid);
+        let expired = keys.sign(ACCESS_TOKEN_TYPE, &claims(Utc::now() - Duration::minutes(5))).unwrap();
+        assert_eq!(keys.verify::<Claims>(&expired, ACCESS_TOKEN_TYPE).unwrap_err(), TokenError::Expired);
+
+        // A scheduled key is published but does not sign yet.
+        let next = keys.rotate(&db, false).await.unwrap();
+        assert_eq!(keys.jwks().keys.len(), 2);
+        assert!(keys.jwks().find(&next.id).is_some());
+        let still_first = keys.sign(ACCESS_TOKEN_TYPE, &claims(Utc::now() + Duration::minutes(5))).unwrap();
+        assert_eq!(kid(&still_first), first.id);
+
+        // Once replaced, the old key verifies what it signed.
+        let now = keys.rotate(&db, true).await.unwrap();
+        let rotated = keys.sign(ACCESS_TOKEN_TYPE, &claims(Utc::now() + Duration::minutes(5))).unwrap();
+        assert_eq!(kid(&rotated), now.id);
+        assert!(keys.verify::<Claims>(&token, ACCESS_TOKEN_TYPE).is_ok());
+
+        // Other instances learn of new keys, and drop keys whose tokens
+        // have all expired.
+        let other = KeyRing::new(config(SigningAlgorithm::EdDsa), Duration::zero());
+        assert_eq!(other.verify::<Claims>(&rotated, ACCESS_TOKEN_TYPE).unwrap_err(), TokenError::UnknownKey);
+        assert!(other.verify_or_reload::<Claims>(&db, &rotated, ACCESS_TOKEN_TYPE).await.is_ok());
+        assert!(other.maintain(&db).await.unwrap().is_none());
+        assert!(db.get_signing_keys().await.unwrap().iter().all(|k| k.id != first.id));
+        keys.reload(&db).await.unwrap();
+        assert_eq!(keys.verify::<Claims>(&token, ACCESS_TOKEN_TYPE).unwrap_err(), TokenError::UnknownKey);
+    }
+
+    #[tokio::test]
+    async fn test_es256_and_legacy_tokens() {
+        let db = database().await;
+        let keys = KeyRing::new(config(SigningAlgorithm::Es256), Duration::minutes(15));
+        let key = keys.maintain(&db).await.unwrap().unwrap();
+        assert_eq!(key.algorithm, SigningAlgorithm::Es256);
+        assert_eq!(key.public_key.len(), 65);
+
+        let token = keys.sign(ACCESS_TOKEN_TYPE, &claims(Utc::now() + Duration::minutes(5))).unwrap();
+        assert!(keys.verify::<Claims>(&token, ACCESS_TOKEN_TYPE).is_ok());
+
+        let legacy = |secret: &str| {
+            let claims = claims(Utc::now() + Duration::minutes(5));
+            encode(&Header::default(), &claims, &EncodingKey::from_secret(secret.as_bytes())).unwrap()
+        };
+        assert!(keys.verify::<Claims>(&legacy("legacy"), ACCESS_TOKEN_TYPE).is_ok());
+        assert_eq!(keys.verify::<Claims>(&legacy("wrong"), ACCESS_TOKEN_TYPE).unwrap_err(), TokenError::Invalid);
+
+        let without_legacy = KeyRing::new(JwtConfig { legacy_secret: None, ..config(SigningAlgorithm::Es256) }, Duration::minutes(15));
+        assert_eq!(without_legacy.verify::<Claims>(&legacy("legacy"), ACCESS_TOKEN_TYPE).unwrap_err(), TokenError::Invalid);
+        assert!(matches!(without_legacy.sign(ACCESS_TOKEN_TYPE, &claims(Utc::now())), Err(crate::jwt::KeyError::NoSigningKey)));
+    }
+}
+
+#[cfg(test)]
+mod audit_tests {
+    use sqlx::sqlite::SqlitePool;
+    use uuid::Uuid;
+    use crate::audit::{self, Entry, GENESIS_HASH};
+    use crate::db;
+    use crate::models::AuditKind;
+
+    #[tokio::test]
+    async fn test_chain_detects_tampering() {
+        let path = std::env::temp_dir().join(format!("pulse-audit-{}.db", Uuid::new_v4()));
+        let url = format!("sqlite:{}", path.display());
+        let db = db::connect(&url).await.unwrap();
+        db.migrate().await.unwrap();
+        assert_eq!(audit::verify(&db).await.unwrap().last_hash, None);
+
+        let user_id = Uuid::new_v4();
+        let first = audit::append(&db, Entry::new(AuditKind::Login, Some(user_id))).await.unwrap();
+        assert_eq!((first.id, first.prev_hash.as_str()), (1, GENESIS_HASH));
+
+        // Concurrent appends each find a place in the chain.
+        let appends = (0..8).map(|_| {
+            let db = db.clone();
+            tokio::spawn(async move {
+                let entry = Entry::new(AuditKind::LoginFailed, Some(user_id)).detail("reason", "password");
+                audit::append(&db, entry).await.unwrap()
+            })
+        });
+        for append in appends.collect::<Vec<_>>() {
+            append.await.unwrap();
+        }
+        let report = audit::verify(&db).await.unwrap();
+        assert_eq!((report.entries, report.broken_at), (9, None));
+        assert_eq!(report.last_hash, Some(db.last_audit_event().await.unwrap().unwrap().hash));
+
+        // The table refuses changes. Whoever can drop the triggers still
+        // cannot make the hashes match.
+        let pool = SqlitePool::connect(&url).await.unwrap();
+        let tamper = r#"UPDATE audit_events SET details = '{"reason":"second_factor"}' WHERE id = 4"#;
+        assert!(sqlx::query(tamper).execute(&pool).await.is_err());
+        assert!(sqlx::query("DELETE FROM audit_events WHERE id = 9").execute(&pool).await.is_err());
+        sqlx::query("DROP TRIGGER audit_events_no_update").execute(&pool).await.unwrap();
+        sqlx::query(tamper).execute(&pool).await.unwrap();
+
+        let report = audit::verify(&db).await.unwrap();
+        assert_eq!((report.entries, report.broken_at, report.last_hash), (3, Some(4), None));
+
+        pool.close().await;
+        let _ = std::fs::remove_file(&path);
+    }
+}
+
+#[cfg(test)]
+mod blob_store_tests {
+    use tokio::io::AsyncReadExt;
+    use uuid::Uuid;
+    use crate::blob_store::{BlobError, BlobStore, FsBlobStore};
+
+    #[tokio::test]
+    async fn test_resumable_append() {
+        let root = std::env::temp_dir().join(format!("pulse-blobs-{}", Uuid::new_v4()));
+        let store = FsBlobStore::new(&root).await.unwrap();
+
+        assert_eq!(store.append("blob", 0, b"hello ").await.unwrap(), 6);
+        assert!(matches!(
+            store.append("blob", 0, b"again").await,
+            Err(BlobError::OffsetMismatch { expected: 6, actual: 0 })
+        ));
+        assert_eq!(store.append("blob", 6, b"world").await.unwrap(), 11);
+        assert_eq!(store.size("blob").await.unwrap(), Some(11));
+
+        let mut contents = Vec::new();
+        store.get("blob").await.unwrap().read_to_end(&mut contents).await.unwrap();
+        assert_eq!(contents, b"hello world");
+
+        store.delete("blob").await.unwrap();
+        assert_eq!(store.size("blob").await.unwrap(), None);
+        assert!(matches!(store.append("../escape", 0, b"x").await, Err(BlobError::InvalidKey(_))));
+
+        tokio::fs::remove_dir_all(root).await.unwrap();
+    }
+}
+
+#[cfg(test)]
+mod rate_limit_tests {
+    use std::sync::Arc;
+    use std::time::{Duration, Instant};
+    use axum::{body::Body, http::{Request, StatusCode}, routing::post, Router};
+    use tower::ServiceExt;
+    use crate::jwt::{JwtConfig, KeyRing};
+    use crate::rate_limit::{Quota, RateKey, RateLimitConfig, RateLimitLayer, RateLimiter, RouteClass, TokenBucket};
+
+    fn keys() -> Arc<KeyRing> {
+        Arc::new(KeyRing::new(JwtConfig::from_env(None).unwrap(), chrono::Duration::minutes(15)))
+    }
+
+    #[test]
+    fn test_bucket_burst_and_refill() {
+        let quota = Quota::new(2, 10);
+        let start = Instant::now();
+        let mut bucket = TokenBucket::full(quota, start);
+
+        assert!(bucket.try_take(quota, start).is_ok());
+        assert!(bucket.try_take(quota, start).is_ok());
+        let retry_after = bucket.try_take(quota, start).unwrap_err();
+        assert!(retry_after > Duration::from_secs(4) && retry_after <= Duration::from_secs(5));
+
+        assert!(bucket.try_take(quota, start + Duration::from_secs(5)).is_ok());
+        assert!(bucket.try_take(quota, start + Duration::from_secs(5)).is_err());
+    }
+
+    #[test]
+    fn test_quota_parse() {
+        assert_eq!(Quota::parse("10/60"), Some(Quota::new(10, 60)));
+        assert_eq!(Quota::parse("0/60"), None);
+        assert_eq!(Quota::parse("ten"), None);
+    }
+
+    #[test]
+    fn test_keys_are_limited_independently() {
+        let config = RateLimitConfig {
+            login: Quota::new(1, 60),
+            ..RateLimitConfig::default()
+        };
+        let limiter = RateLimiter::new(config, keys());
+        let now = Instant::now();
+        let a = RateKey::Ip("10.0.0.1".parse().unwrap());
+        let b = RateKey::Ip("10.0.0.2".parse().unwrap());
+
+        assert!(limiter.check_at(RouteClass::Login, a, now).is_ok());
+        assert!(limiter.check_at(RouteClass::Login, a, now).is_err());
+        assert!(limiter.check_at(RouteClass::Login, b, now).is_ok());
+        assert!(limiter.check_at(RouteClass::Signup, a, now).is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_layer_returns_429_with_retry_after() {
+        let config = RateLimitConfig {
+            login: Quota::new(2, 60),
+            ..RateLimitConfig::default()
+        };
+        let limiter = Arc::new(RateLimiter::new(config, keys()));
+        let app = Router::new()
+            .route("/api/auth/login", post(|| async { "ok" }))
+            .route_layer(RateLimitLayer::new(limiter));
+
+        let login = || Request::post("/api/auth/login").body(Body::empty()).unwrap();
+        for _ in 0..2 {
+            let response = app.clone().oneshot(login()).await.unwrap();
+            assert_eq!(response.status(), StatusCode::OK);
+        }
+
+        let response = app.clone().oneshot(login()).await.unwrap();
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+        let retry_after: u64 = response.headers()["retry-after"].to_str().unwrap().parse().unwrap();
+        assert!((1..=30).contains(&retry_after));
+    }
+}
+
+#[cfg(test)]
+mod api_error_tests {
+    use axum::{body::to_bytes, http::StatusCode, response::IntoResponse};
+    use crate::api::ApiError;
+    use crate::db::DatabaseError;
+
+    #[tokio::test]
+    async fn test_error_body_is_json() {
+        let response = ApiError::UsernameTaken.into_response();
+        assert_eq!(response.status(), StatusCode::CONFLICT);
+
+        let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body["error"]["code"], "username_taken");
+        assert!(body["error"]["message"].is_string());
+    }
+
+    #[test]
+    fn test_database_errors_are_not_leaked() {
+        let error = ApiError::from(DatabaseError::InvalidData("SELECT * FROM users".to_string()));
+        assert_eq!(error.code(), "internal_error");
+        assert!(!error.to_string().contains("SELECT"));
+
+        let error = ApiError::from(DatabaseError::UniqueViolation("users.email".to_string()));
+        assert_eq!(error.code(), "email_taken");
+    }
+}
+
+#[cfg(test)]
+mod realtime_tests {
+    use std::sync::Arc;
+    use futures::StreamExt;
+    use uuid::Uuid;
+    use crate::presence::Presence;
+    use crate::realtime::{Event, Hub};
+
+    #[tokio::test]
+    async fn test_events_reach_every_stream_of_a_device() {
+        let hub = Arc::new(Hub::new());
+        let device_id = Uuid::new_v4();
+        let mut first = hub.subscribe(device_id, Uuid::new_v4());
+        let mut second = hub.subscribe(device_id, Uuid::new_v4());
+        let mut other = hub.subscribe(Uuid::new_v4(), Uuid::new_v4());
+
+        assert!(hub.send_to_device(device_id, Event::PrekeysLow { remaining: 3 }));
+        assert_eq!(first.next().await, Some(Event::PrekeysLow { remaining: 3 }));
+        assert_eq!(second.next().await, Some(Event::PrekeysLow { remaining: 3 }));
+        assert!(futures::poll!(other.next()).is_pending());
+    }
+
+    #[tokio::test]
+    async fn test_dropped_subscription_unregisters() {
+        let hub = Arc::new(Hub::new());
+        let device_id = Uuid::new_v4();
+        let subscription = hub.subscribe(device_id, Uuid::new_v4());
+        drop(subscription);
+
+        assert!(!hub.send_to_device(device_id, Event::PrekeysLow { remaining: 0 }));
+    }
+
+    #[tokio::test]
+    async fn test_revoked_session_stream_ends() {
+        let hub = Arc::new(Hub::new());
+        let device_id = Uuid::new_v4();
+        let session_id = Uuid::new_v4();
+        let mut revoked = hub.subscribe(device_id, session_id);
+        let mut kept = hub.subscribe(device_id, Uuid::new_v4());
+
+        hub.disconnect_session(session_id);
+        assert_eq!(revoked.next().await, None);
+
+        assert!(hub.send_to_device(device_id, Event::PrekeysLow { remaining: 1 }));
+        assert_eq!(kept.next().await, Some(Event::PrekeysLow { remaining: 1 }));
+    }
+
+    #[tokio::test]
+    async fn test_disconnect_all_ends_every_stream() {
+        let hub = Arc::new(Hub::new());
+        let mut first = hub.subscribe(Uuid::new_v4(), Uuid::new_v4());
+        let mut second = hub.subscribe(Uuid::new_v4(), Uuid::new_v4());
+
+        hub.disconnect_all();
+        assert_eq!(first.next().await, None);
+        assert_eq!(second.next().await, None);
+        assert_eq!(hub.connection_count(), 0);
+    }
+
+    #[test]
+    fn test_user_is_online_until_last_stream_closes() {
+        let presence = Presence::new();
+        let (user_id, phone, laptop) = (Uuid::new_v4(), Uuid::new_v4(), Uuid::new_v4());
+
+        assert!(presence.connect(user_id, phone));
+        assert!(!presence.connect(user_id, phone));
+        assert!(!presence.connect(user_id, laptop));
+        assert!(!presence.disconnect(user_id, phone));
+        assert!(presence.is_device_online(user_id, phone));
+        assert!(!presence.disconnect(user_id, phone));
+        assert_eq!(presence.online_devices(user_id), vec![laptop]);
+        assert!(presence.disconnect(user_id, laptop));
+        assert!(!presence.is_online(user_id));
+    }
+}
+
+#[cfg(test)]
+mod totp_tests {
+    use crate::totp;
+
+    // RFC 6238 appendix B, SHA-1 column.
+    const SECRET: &[u8] = b"12345678901234567890";
+
+    #[test]
+    fn test_rfc6238_vectors() {
+        for (time, expected) in [(59, 94287082), (1111111109, 7081804), (1234567890, 89005924), (2000000000, 69279037)] {
+            assert_eq!(totp::code_at(SECRET, totp::step_at(time), 8), expected);
+        }
+    }
+
+    #[test]
+    fn test_verify_allows_skew_and_rejects_replay() {
+        let now = 1_700_000_000;
+        let step = totp::step_at(now);
+        let code = |step| format!("{:06}", totp::code_at(SECRET, step, totp::DIGITS));
+
+        assert_eq!(totp::verify(SECRET, &code(step), now, None), Some(step));
+        assert_eq!(totp::verify(SECRET, &code(step - 1), now, None), Some(step - 1));
+        assert_eq!(totp::verify(SECRET, &code(step - 2), now, None), None);
+        assert_eq!(totp::verify(SECRET, &code(step), now, Some(step)), None);
+        assert_eq!(totp::verify(SECRET, "12345", now, None), None);
+    }
+
+    #[test]
+    fn test_base32() {
+        assert_eq!(totp::base32_encode(b"foobar"), "MZXW6YTBOI");
+        assert_eq!(totp::base32_encode(SECRET), "GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ");
+    }
+}
+
+#[cfg(test)]
+mod webhook_tests {
+    use std::net::IpAddr;
+    use crate::webhooks::is_public;
+
+    fn public(ip: &str) -> bool {
+        is_public(ip.parse::<IpAddr>().unwrap())
+    }
+
+    #[test]
+    fn test_embedded_ipv4_addresses() {
+        for ip in [
+            "0.1.2.3",
+            "::ffff:10.0.0.1",
+            "64:ff9b::7f00:1",
+            "64:ff9b::a9fe:a9fe",
+            "2002:7f00:1::",
+            "2002:c0a8:1::1",
+            // Teredo with client 127.0.0.1.
+            "2001:0:4136:e378:8000:63bf:80ff:fffe",
+        ] {
+            assert!(!public(ip), "{}", ip);
+        }
+        for ip in [
+            "93.184.216.34",
+            "64:ff9b::5db8:d822",
+            "2002:5db8:d822::1",
+            "2001:0:4136:e378:8000:63bf:a247:27dd",
+            "2606:4700::1111",
+        ] {
+            assert!(public(ip), "{}", ip);
+        }
+    }
+}
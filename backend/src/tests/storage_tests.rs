@@ -0,0 +1,1219 @@
+//! Conformance suite shared by every `Storage` backend. SQLite always runs;
+//! PostgreSQL runs when `PULSE_TEST_POSTGRES_URL` points at a scratch
+//! database.
+
+use chrono::{Duration, Utc};
+use uuid::Uuid;
+use crate::audit::{self, Entry};
+use crate::db::{self, AuditQuery, Database, DatabaseError};
+use crate::models::{User, Message, MessageKind, Device, Chat, ChatMember, ChatRole, Session, Attachment, SignedPrekey, OneTimePrekey, EmailToken, EmailTokenPurpose, PrivacySettings, Report, ReportCategory, ReportStatus, ChatInvite, JoinRequest, ChannelPost, ChatSettings, ChatPin, ApiKey, BotWebhook, SigningKey, SigningAlgorithm, AuditKind};
+
+async fn backends() -> Vec<Database> {
+    let mut backends = vec![db::connect("sqlite::memory:").await.unwrap()];
+    if let Ok(url) = std::env::var("PULSE_TEST_POSTGRES_URL") {
+        backends.push(db::connect(&url).await.unwrap());
+    }
+    for db in &backends {
+        db.migrate().await.unwrap();
+    }
+    backends
+}
+
+fn user() -> User {
+    let id = Uuid::new_v4();
+    User {
+        id,
+        username: format!("user-{}", id),
+        email: format!("{}@example.com", id),
+        public_key: vec![1, 2, 3],
+        created_at: Utc::now(),
+        last_seen: Utc::now(),
+        password_hash: None,
+        email_verified_at: None,
+        delete_after: None,
+        is_admin: false,
+        suspended_at: None,
+        bot_owner_id: None,
+    }
+}
+
+fn device(user_id: Uuid) -> Device {
+    Device {
+        id: Uuid::new_v4(),
+        user_id,
+        name: "test device".to_string(),
+        public_key: vec![4, 5, 6],
+        last_seen: Utc::now(),
+        is_online: false,
+    }
+}
+
+fn message(sender_id: Uuid, recipient_id: Uuid) -> Message {
+    Message {
+        id: Uuid::new_v4(),
+        sender_id,
+        recipient_id,
+        content: b"ciphertext".to_vec(),
+        associated_data: None,
+        created_at: Utc::now(),
+        expires_at: None,
+        kind: MessageKind::Message,
+        target_id: None,
+        chat_id: None,
+        thread_id: None,
+    }
+}
+
+#[tokio::test]
+async fn test_migrate_is_idempotent() {
+    for db in backends().await {
+        assert!(db.migrate().await.unwrap().is_empty());
+        let status = db.migration_status().await.unwrap();
+        assert!(status.iter().all(|m| m.applied_at.is_some()));
+        db.ping().await.unwrap();
+        assert!(db.pool_stats().size >= 1);
+    }
+}
+
+#[tokio::test]
+async fn test_user_roundtrip() {
+    for db in backends().await {
+        let user = user();
+        db.create_user(&user).await.unwrap();
+
+        let fetched = db.get_user(user.id).await.unwrap().unwrap();
+        assert_eq!(fetched.username, user.username);
+        assert_eq!(fetched.public_key, user.public_key);
+        assert_eq!(fetched.created_at.timestamp(), user.created_at.timestamp());
+
+        let by_email = db.get_user_by_email(&user.email).await.unwrap().unwrap();
+        assert_eq!(by_email.id, user.id);
+
+        assert!(db.get_user(Uuid::new_v4()).await.unwrap().is_none());
+    }
+}
+
+#[tokio::test]
+async fn test_duplicate_username_rejected() {
+    for db in backends().await {
+        let user = user();
+        db.create_user(&user).await.unwrap();
+
+        let mut duplicate = self::user();
+        duplicate.username = user.username.clone();
+        assert!(matches!(
+            db.create_user(&duplicate).await,
+            Err(DatabaseError::UniqueViolation(target)) if target == "users.username"
+        ));
+
+        let mut duplicate = self::user();
+        duplicate.email = user.email.clone();
+        assert!(matches!(
+            db.create_user(&duplicate).await,
+            Err(DatabaseError::UniqueViolation(target)) if target == "users.email"
+        ));
+    }
+}
+
+#[tokio::test]
+async fn test_messages_newest_first() {
+    for db in backends().await {
+        let sender = user();
+        let recipient = user();
+        db.create_user(&sender).await.unwrap();
+        db.create_user(&recipient).await.unwrap();
+
+        let mut older = message(sender.id, recipient.id);
+        older.created_at = Utc::now() - Duration::minutes(5);
+        older.expires_at = Some(Utc::now() + Duration::hours(1));
+        let mut newer = message(sender.id, recipient.id);
+        newer.kind = MessageKind::Receipt;
+        db.create_message(&older).await.unwrap();
+        db.create_message(&newer).await.unwrap();
+
+        let messages = db.get_messages(recipient.id, 10).await.unwrap();
+        assert_eq!(messages.iter().map(|m| m.id).collect::<Vec<_>>(), vec![newer.id, older.id]);
+        assert!(messages[1].expires_at.is_some());
+        assert_eq!(messages[0].kind, MessageKind::Receipt);
+
+        assert_eq!(db.get_messages(recipient.id, 1).await.unwrap().len(), 1);
+        assert!(db.get_messages(sender.id, 10).await.unwrap().is_empty());
+        // Other tests may share the database.
+        assert!(db.count_messages().await.unwrap() >= 2);
+    }
+}
+
+#[tokio::test]
+async fn test_delete_message_takes_its_edits_along() {
+    for db in backends().await {
+        let (sender, recipient) = (user(), user());
+        db.create_user(&sender).await.unwrap();
+        db.create_user(&recipient).await.unwrap();
+
+        let original = message(sender.id, recipient.id);
+        let mut reaction = message(recipient.id, sender.id);
+        reaction.kind = MessageKind::Reaction;
+        reaction.target_id = Some(original.id);
+        let unrelated = message(sender.id, recipient.id);
+        for m in [&original, &reaction, &unrelated] {
+            db.create_message(m).await.unwrap();
+        }
+        let stored = db.get_message(reaction.id).await.unwrap().unwrap();
+        assert_eq!(stored.target_id, Some(original.id));
+
+        assert!(db.delete_message(original.id).await.unwrap());
+        assert!(!db.delete_message(original.id).await.unwrap());
+        assert!(db.get_message(reaction.id).await.unwrap().is_none());
+        assert!(db.get_message(unrelated.id).await.unwrap().is_some());
+    }
+}
+
+fn session(user_id: Uuid, device_id: Uuid) -> Session {
+    let now = Utc::now();
+    Session {
+        id: Uuid::new_v4(),
+        user_id,
+        device_id,
+        refresh_token_hash: Uuid::new_v4().to_string(),
+        previous_refresh_token_hash: None,
+        created_at: now,
+        last_used_at: None,
+        expires_at: now + Duration::days(1),
+        revoked_at: None,
+    }
+}
+
+#[tokio::test]
+async fn test_session_validation() {
+    for db in backends().await {
+        let user = user();
+        let device = device(user.id);
+        db.create_user(&user).await.unwrap();
+        db.create_device(&device).await.unwrap();
+
+        let live = session(user.id, device.id);
+        let expired = Session {
+            expires_at: Utc::now() - Duration::seconds(1),
+            ..session(user.id, device.id)
+        };
+        db.create_session(&live).await.unwrap();
+        db.create_session(&expired).await.unwrap();
+
+        let active = db.get_active_session(live.id).await.unwrap().unwrap();
+        assert_eq!(active.device_id, device.id);
+        assert!(db.get_active_session(expired.id).await.unwrap().is_none());
+        assert!(db.get_active_session(Uuid::new_v4()).await.unwrap().is_none());
+        assert_eq!(db.list_sessions(user.id).await.unwrap().len(), 1);
+    }
+}
+
+#[tokio::test]
+async fn test_refresh_token_rotation() {
+    for db in backends().await {
+        let user = user();
+        let device = device(user.id);
+        db.create_user(&user).await.unwrap();
+        db.create_device(&device).await.unwrap();
+
+        let session = session(user.id, device.id);
+        db.create_session(&session).await.unwrap();
+        let first = session.refresh_token_hash.clone();
+        let second = Uuid::new_v4().to_string();
+        let expires_at = Utc::now() + Duration::days(2);
+
+        assert!(db.rotate_refresh_token(session.id, &first, &second, expires_at).await.unwrap());
+        assert!(!db.rotate_refresh_token(session.id, &first, &Uuid::new_v4().to_string(), expires_at).await.unwrap());
+
+        // The rotated-out token still finds the session, so reuse can be detected.
+        let found = db.find_session_by_refresh_token(&first).await.unwrap().unwrap();
+        assert_eq!(found.id, session.id);
+        assert_eq!(found.refresh_token_hash, second);
+        assert_eq!(found.previous_refresh_token_hash.as_deref(), Some(first.as_str()));
+        assert!(found.last_used_at.is_some());
+        assert_eq!(found.expires_at.timestamp(), expires_at.timestamp());
+    }
+}
+
+#[tokio::test]
+async fn test_session_revocation() {
+    for db in backends().await {
+        let user = user();
+        let device = device(user.id);
+        db.create_user(&user).await.unwrap();
+        db.create_device(&device).await.unwrap();
+
+        let current = session(user.id, device.id);
+        let others = [session(user.id, device.id), session(user.id, device.id)];
+        db.create_session(&current).await.unwrap();
+        for other in &others {
+            db.create_session(other).await.unwrap();
+        }
+
+        assert!(db.revoke_session(others[0].id).await.unwrap());
+        assert!(!db.revoke_session(others[0].id).await.unwrap());
+        assert!(db.get_active_session(others[0].id).await.unwrap().is_none());
+        assert!(!db
+            .rotate_refresh_token(others[0].id, &others[0].refresh_token_hash, "new", Utc::now() + Duration::days(1))
+            .await
+            .unwrap());
+
+        let revoked = db.revoke_user_sessions(user.id, Some(current.id)).await.unwrap();
+        assert_eq!(revoked, vec![others[1].id]);
+        assert!(db.get_active_session(current.id).await.unwrap().is_some());
+
+        assert!(db.purge_sessions().await.unwrap() >= 2);
+        assert!(db.find_session_by_refresh_token(&others[1].refresh_token_hash).await.unwrap().is_none());
+        assert!(db.find_session_by_refresh_token(&current.refresh_token_hash).await.unwrap().is_some());
+    }
+}
+
+#[tokio::test]
+async fn test_attachment_lifecycle() {
+    for db in backends().await {
+        let sender = user();
+        let recipient = user();
+        let stranger = user();
+        for u in [&sender, &recipient, &stranger] {
+            db.create_user(u).await.unwrap();
+        }
+
+        let now = Utc::now();
+        let attachment = Attachment {
+            id: Uuid::new_v4().simple().to_string(),
+            uploader_id: sender.id,
+            size: 10,
+            received: 0,
+            created_at: now,
+            completed_at: None,
+            expires_at: now + Duration::hours(1),
+        };
+        assert!(db.create_attachment(&attachment, 20).await.unwrap());
+        assert_eq!(db.attachment_usage(sender.id).await.unwrap(), 10);
+
+        // A second upload may fill the quota but not pass it.
+        let over = Attachment {
+            id: Uuid::new_v4().simple().to_string(),
+            size: 11,
+            ..attachment.clone()
+        };
+        assert!(!db.create_attachment(&over, 20).await.unwrap());
+        assert!(db.get_attachment(&over.id).await.unwrap().is_none());
+        let fill = Attachment {
+            id: Uuid::new_v4().simple().to_string(),
+            ..attachment.clone()
+        };
+        assert!(db.create_attachment(&fill, 20).await.unwrap());
+        assert_eq!(db.attachment_usage(sender.id).await.unwrap(), 20);
+
+        assert!(db.record_attachment_progress(&attachment.id, 0, 6, None).await.unwrap());
+        assert!(!db.record_attachment_progress(&attachment.id, 0, 6, None).await.unwrap());
+        assert!(db.record_attachment_progress(&attachment.id, 6, 10, Some(Utc::now())).await.unwrap());
+        let stored = db.get_attachment(&attachment.id).await.unwrap().unwrap();
+        assert_eq!(stored.received, 10);
+        assert!(stored.completed_at.is_some());
+
+        assert!(db.can_access_attachment(&attachment.id, sender.id).await.unwrap());
+        assert!(!db.can_access_attachment(&attachment.id, recipient.id).await.unwrap());
+
+        let message = message(sender.id, recipient.id);
+        db.create_message(&message).await.unwrap();
+        let retained = now + Duration::days(30);
+        db.link_attachments(message.id, std::slice::from_ref(&attachment.id), retained).await.unwrap();
+
+        assert!(db.can_access_attachment(&attachment.id, recipient.id).await.unwrap());
+        assert!(!db.can_access_attachment(&attachment.id, stranger.id).await.unwrap());
+        let stored = db.get_attachment(&attachment.id).await.unwrap().unwrap();
+        assert_eq!(stored.expires_at.timestamp(), retained.timestamp());
+
+        db.delete_attachment(&attachment.id).await.unwrap();
+        assert!(db.get_attachment(&attachment.id).await.unwrap().is_none());
+        assert!(!db.can_access_attachment(&attachment.id, recipient.id).await.unwrap());
+    }
+}
+
+#[tokio::test]
+async fn test_expired_attachments_listed() {
+    for db in backends().await {
+        let owner = user();
+        db.create_user(&owner).await.unwrap();
+
+        let now = Utc::now();
+        let expired = Attachment {
+            id: Uuid::new_v4().simple().to_string(),
+            uploader_id: owner.id,
+            size: 1,
+            received: 0,
+            created_at: now - Duration::days(2),
+            completed_at: None,
+            expires_at: now - Duration::days(1),
+        };
+        assert!(db.create_attachment(&expired, i64::MAX).await.unwrap());
+
+        assert_eq!(db.attachment_usage(owner.id).await.unwrap(), 0);
+        assert!(db.expired_attachments(1000).await.unwrap().contains(&expired.id));
+    }
+}
+
+#[tokio::test]
+async fn test_signed_prekey_replaced() {
+    for db in backends().await {
+        let user = user();
+        let device = device(user.id);
+        db.create_user(&user).await.unwrap();
+        db.create_device(&device).await.unwrap();
+        assert!(db.get_signed_prekey(device.id).await.unwrap().is_none());
+
+        for key_id in [1, 2] {
+            let prekey = SignedPrekey {
+                device_id: device.id,
+                key_id,
+                public_key: vec![key_id as u8; 32],
+                signature: vec![9; 64],
+                created_at: Utc::now(),
+            };
+            db.set_signed_prekey(&prekey).await.unwrap();
+        }
+
+        let stored = db.get_signed_prekey(device.id).await.unwrap().unwrap();
+        assert_eq!(stored.key_id, 2);
+        assert_eq!(stored.public_key, vec![2; 32]);
+
+        let devices = db.get_user_devices(user.id).await.unwrap();
+        assert_eq!(devices.iter().map(|d| d.id).collect::<Vec<_>>(), vec![device.id]);
+    }
+}
+
+#[tokio::test]
+async fn test_one_time_prekeys_claimed_once() {
+    for db in backends().await {
+        let user = user();
+        let device = device(user.id);
+        db.create_user(&user).await.unwrap();
+        db.create_device(&device).await.unwrap();
+
+        let prekeys: Vec<OneTimePrekey> = (0..20)
+            .map(|key_id| OneTimePrekey { key_id, public_key: vec![key_id as u8; 32] })
+            .collect();
+        db.add_one_time_prekeys(device.id, &prekeys).await.unwrap();
+        assert_eq!(db.count_one_time_prekeys(device.id).await.unwrap(), 20);
+        assert!(db.add_one_time_prekeys(device.id, &prekeys[..1]).await.is_err());
+
+        let claims = (0..25).map(|_| {
+            let db = db.clone();
+            tokio::spawn(async move { db.claim_one_time_prekey(device.id).await.unwrap() })
+        });
+        let mut claimed: Vec<i64> = futures::future::join_all(claims)
+            .await
+            .into_iter()
+            .filter_map(|claim| claim.unwrap())
+            .map(|prekey| prekey.key_id)
+            .collect();
+        claimed.sort();
+
+        assert_eq!(claimed, (0..20).collect::<Vec<_>>());
+        assert_eq!(db.count_one_time_prekeys(device.id).await.unwrap(), 0);
+    }
+}
+
+#[tokio::test]
+async fn test_totp_enrollment() {
+    for db in backends().await {
+        let user = user();
+        db.create_user(&user).await.unwrap();
+
+        db.set_pending_totp_secret(user.id, b"first").await.unwrap();
+        db.set_pending_totp_secret(user.id, b"second").await.unwrap();
+        let pending = db.get_totp_secret(user.id).await.unwrap().unwrap();
+        assert_eq!(pending.secret, b"second");
+        assert!(pending.enabled_at.is_none());
+
+        let codes = vec!["a".to_string(), "b".to_string()];
+        db.enable_totp(user.id, 100, &codes).await.unwrap();
+        // Enabled secrets are not replaced by a new enrollment.
+        db.set_pending_totp_secret(user.id, b"third").await.unwrap();
+        let enabled = db.get_totp_secret(user.id).await.unwrap().unwrap();
+        assert_eq!(enabled.secret, b"second");
+        assert_eq!(enabled.last_used_step, Some(100));
+
+        assert!(!db.record_totp_step(user.id, 100).await.unwrap());
+        assert!(db.record_totp_step(user.id, 101).await.unwrap());
+
+        let mut unused = db.get_unused_recovery_codes(user.id).await.unwrap();
+        unused.sort();
+        assert_eq!(unused, codes);
+        assert!(db.use_recovery_code(user.id, "a").await.unwrap());
+        assert_eq!(db.get_unused_recovery_codes(user.id).await.unwrap(), vec!["b".to_string()]);
+        assert!(!db.use_recovery_code(user.id, "a").await.unwrap());
+        assert!(!db.use_recovery_code(user.id, "c").await.unwrap());
+
+        db.disable_totp(user.id).await.unwrap();
+        assert!(db.get_totp_secret(user.id).await.unwrap().is_none());
+        assert!(!db.use_recovery_code(user.id, "b").await.unwrap());
+        assert!(db.get_unused_recovery_codes(user.id).await.unwrap().is_empty());
+
+        let (first, second) = (Uuid::new_v4(), Uuid::new_v4());
+        let since = Utc::now() - Duration::minutes(15);
+        db.record_mfa_failure(first, user.id, since).await.unwrap();
+        db.record_mfa_failure(first, user.id, since).await.unwrap();
+        db.record_mfa_failure(second, user.id, since).await.unwrap();
+        assert_eq!(db.count_mfa_failures(first, user.id, since).await.unwrap(), (2, 3));
+        assert_eq!(db.count_mfa_failures(second, user.id, Utc::now()).await.unwrap(), (1, 0));
+        // Failures from before the window are dropped with the next one.
+        db.record_mfa_failure(second, user.id, Utc::now()).await.unwrap();
+        assert_eq!(db.count_mfa_failures(first, user.id, since).await.unwrap(), (0, 1));
+    }
+}
+
+#[tokio::test]
+async fn test_email_tokens() {
+    for db in backends().await {
+        let user = user();
+        db.create_user(&user).await.unwrap();
+        let token = |hash: &str, purpose, ttl: Duration| EmailToken {
+            token_hash: hash.to_string(),
+            user_id: user.id,
+            purpose,
+            created_at: Utc::now(),
+            expires_at: Utc::now() + ttl,
+        };
+        let first = Uuid::new_v4().to_string();
+        let second = Uuid::new_v4().to_string();
+        let reset = Uuid::new_v4().to_string();
+        let expired = Uuid::new_v4().to_string();
+
+        db.create_email_token(&token(&first, EmailTokenPurpose::VerifyEmail, Duration::hours(1))).await.unwrap();
+        db.create_email_token(&token(&reset, EmailTokenPurpose::ResetPassword, Duration::hours(1))).await.unwrap();
+        db.create_email_token(&token(&second, EmailTokenPurpose::VerifyEmail, Duration::hours(1))).await.unwrap();
+
+        // A new token replaces older ones for the same purpose only.
+        assert_eq!(db.consume_email_token(&first, EmailTokenPurpose::VerifyEmail).await.unwrap(), None);
+        assert_eq!(db.consume_email_token(&reset, EmailTokenPurpose::VerifyEmail).await.unwrap(), None);
+        assert_eq!(db.consume_email_token(&second, EmailTokenPurpose::VerifyEmail).await.unwrap(), Some(user.id));
+        assert_eq!(db.consume_email_token(&second, EmailTokenPurpose::VerifyEmail).await.unwrap(), None);
+        assert_eq!(db.consume_email_token(&reset, EmailTokenPurpose::ResetPassword).await.unwrap(), Some(user.id));
+
+        db.create_email_token(&token(&expired, EmailTokenPurpose::ResetPassword, Duration::hours(-1))).await.unwrap();
+        assert_eq!(db.consume_email_token(&expired, EmailTokenPurpose::ResetPassword).await.unwrap(), None);
+        assert!(db.purge_email_tokens().await.unwrap() >= 1);
+
+        db.mark_email_verified(user.id).await.unwrap();
+        db.set_password_hash(user.id, "hash").await.unwrap();
+        let stored = db.get_user(user.id).await.unwrap().unwrap();
+        assert!(stored.email_verified_at.is_some());
+        assert_eq!(stored.password_hash.as_deref(), Some("hash"));
+    }
+}
+
+#[tokio::test]
+async fn test_delete_user() {
+    for db in backends().await {
+        let other = user();
+        let user = user();
+        let device = device(user.id);
+        db.create_user(&user).await.unwrap();
+        db.create_user(&other).await.unwrap();
+        db.create_device(&device).await.unwrap();
+        db.create_session(&session(user.id, device.id)).await.unwrap();
+        db.add_contact(other.id, user.id, None).await.unwrap();
+
+        let sent = message(user.id, other.id);
+        let received = message(other.id, user.id);
+        let unrelated = message(other.id, other.id);
+        for m in [&sent, &received, &unrelated] {
+            db.create_message(m).await.unwrap();
+        }
+
+        let now = Utc::now();
+        let attachment = Attachment {
+            id: Uuid::new_v4().simple().to_string(),
+            uploader_id: user.id,
+            size: 10,
+            received: 10,
+            created_at: now,
+            completed_at: Some(now),
+            expires_at: now + Duration::hours(1),
+        };
+        assert!(db.create_attachment(&attachment, i64::MAX).await.unwrap());
+        db.link_attachments(sent.id, std::slice::from_ref(&attachment.id), now + Duration::hours(1)).await.unwrap();
+
+        let metadata = db.get_message_metadata(user.id).await.unwrap();
+        assert_eq!(metadata.len(), 2);
+        assert!(metadata.iter().all(|m| m.size == 10));
+        assert_eq!(db.get_user_attachments(user.id).await.unwrap().len(), 1);
+
+        db.set_delete_after(user.id, Some(now + Duration::days(1))).await.unwrap();
+        assert!(!db.users_due_for_deletion(100).await.unwrap().contains(&user.id));
+        db.set_delete_after(user.id, Some(now - Duration::seconds(1))).await.unwrap();
+        assert!(db.users_due_for_deletion(100).await.unwrap().contains(&user.id));
+
+        assert_eq!(db.delete_user(user.id).await.unwrap(), vec![attachment.id.clone()]);
+        assert!(db.get_user(user.id).await.unwrap().is_none());
+        assert!(db.get_user_devices(user.id).await.unwrap().is_empty());
+        assert!(db.list_sessions(user.id).await.unwrap().is_empty());
+        assert!(db.get_attachment(&attachment.id).await.unwrap().is_none());
+        assert!(db.list_contacts(other.id).await.unwrap().is_empty());
+
+        // Only the other user's unrelated message survives.
+        let remaining = db.get_message_metadata(other.id).await.unwrap();
+        assert_eq!(remaining.len(), 1);
+        assert_eq!(remaining[0].id, unrelated.id);
+    }
+}
+
+#[tokio::test]
+async fn test_contacts_and_discovery() {
+    for db in backends().await {
+        let (alice, bob, carol) = (user(), user(), user());
+        for u in [&alice, &bob, &carol] {
+            db.create_user(u).await.unwrap();
+        }
+
+        // Discoverable by username only, by default.
+        let settings = db.get_privacy_settings(bob.id).await.unwrap();
+        assert!(settings.discoverable_by_username && !settings.discoverable_by_email);
+        let found = db.find_user_by_username(&bob.username).await.unwrap().unwrap();
+        assert_eq!(found.id, bob.id);
+
+        let bob_hash = format!("hash-{}", bob.id);
+        let hidden = PrivacySettings {
+            discoverable_by_username: false,
+            discoverable_by_email: true,
+            show_last_seen: false,
+            send_read_receipts: false,
+        };
+        db.set_privacy_settings(bob.id, hidden, &bob_hash).await.unwrap();
+        assert_eq!(db.get_privacy_settings(bob.id).await.unwrap(), hidden);
+        assert!(db.find_user_by_username(&bob.username).await.unwrap().is_none());
+
+        let hashes = vec![bob_hash.clone(), format!("hash-{}", carol.id)];
+        let matches = db.find_users_by_email_hash(&hashes).await.unwrap();
+        assert_eq!(matches.len(), 1);
+        assert_eq!(matches[0].0, bob_hash);
+        assert_eq!(matches[0].1.id, bob.id);
+        assert!(db.find_users_by_email_hash(&[]).await.unwrap().is_empty());
+
+        db.add_contact(alice.id, bob.id, None).await.unwrap();
+        db.add_contact(alice.id, carol.id, Some("Carol")).await.unwrap();
+        db.add_contact(alice.id, bob.id, Some("Bob")).await.unwrap();
+        let contacts = db.list_contacts(alice.id).await.unwrap();
+        let names: Vec<_> = contacts.iter().map(|c| c.nickname.as_deref()).collect();
+        assert_eq!(names, vec![Some("Bob"), Some("Carol")]);
+        assert_eq!(contacts[0].contact.public_key, bob.public_key);
+
+        assert!(db.remove_contact(alice.id, bob.id).await.unwrap());
+        assert!(!db.remove_contact(alice.id, bob.id).await.unwrap());
+        assert_eq!(db.list_contacts(alice.id).await.unwrap().len(), 1);
+        assert!(db.list_contacts(bob.id).await.unwrap().is_empty());
+    }
+}
+
+#[tokio::test]
+async fn test_blocks_and_reports() {
+    for db in backends().await {
+        let (alice, bob, admin) = (user(), user(), user());
+        for u in [&alice, &bob, &admin] {
+            db.create_user(u).await.unwrap();
+        }
+
+        db.block_user(alice.id, bob.id).await.unwrap();
+        db.block_user(alice.id, bob.id).await.unwrap();
+        assert!(db.is_blocked(alice.id, bob.id).await.unwrap());
+        assert!(!db.is_blocked(bob.id, alice.id).await.unwrap());
+        let blocks = db.list_blocks(alice.id).await.unwrap();
+        assert_eq!(blocks.len(), 1);
+        assert_eq!(blocks[0].blocked.id, bob.id);
+        assert!(db.unblock_user(alice.id, bob.id).await.unwrap());
+        assert!(!db.unblock_user(alice.id, bob.id).await.unwrap());
+
+        let message = message(bob.id, alice.id);
+        db.create_message(&message).await.unwrap();
+        assert_eq!(db.get_message(message.id).await.unwrap().unwrap().sender_id, bob.id);
+
+        let report = Report {
+            id: Uuid::new_v4(),
+            reporter_id: Some(alice.id),
+            reported_id: Some(bob.id),
+            message_id: Some(message.id),
+            category: ReportCategory::Harassment,
+            description: Some("rude".to_string()),
+            evidence: Some("the message".to_string()),
+            status: ReportStatus::Open,
+            created_at: Utc::now(),
+            resolved_at: None,
+            resolved_by: None,
+            resolution_note: None,
+        };
+        db.create_report(&report).await.unwrap();
+        let open = db.list_reports(Some(ReportStatus::Open), 1000).await.unwrap();
+        assert!(open.iter().any(|r| r.id == report.id));
+        assert_eq!(db.get_user_reports(alice.id).await.unwrap().len(), 1);
+
+        assert!(db.resolve_report(report.id, ReportStatus::Actioned, admin.id, Some("spam bot")).await.unwrap());
+        assert!(!db.resolve_report(report.id, ReportStatus::Dismissed, admin.id, None).await.unwrap());
+        let resolved = db.get_report(report.id).await.unwrap().unwrap();
+        assert_eq!(resolved.status, ReportStatus::Actioned);
+        assert_eq!(resolved.resolved_by, Some(admin.id));
+        assert_eq!(resolved.resolution_note.as_deref(), Some("spam bot"));
+        assert!(resolved.resolved_at.is_some());
+        let all = db.list_reports(None, 1000).await.unwrap();
+        assert!(all.iter().any(|r| r.id == report.id));
+
+        db.set_suspended(bob.id, true).await.unwrap();
+        let suspended_at = db.get_user(bob.id).await.unwrap().unwrap().suspended_at.unwrap();
+        db.set_suspended(bob.id, true).await.unwrap();
+        let again = db.get_user(bob.id).await.unwrap().unwrap().suspended_at.unwrap();
+        assert_eq!(again.timestamp(), suspended_at.timestamp());
+        db.set_suspended(bob.id, false).await.unwrap();
+        assert!(db.get_user(bob.id).await.unwrap().unwrap().suspended_at.is_none());
+
+        db.set_admin(admin.id, true).await.unwrap();
+        assert!(db.get_user(admin.id).await.unwrap().unwrap().is_admin);
+
+        // Reports outlive both accounts, without naming them.
+        db.delete_user(alice.id).await.unwrap();
+        assert_eq!(db.get_report(report.id).await.unwrap().unwrap().reporter_id, None);
+        db.delete_user(bob.id).await.unwrap();
+        let kept = db.get_report(report.id).await.unwrap().unwrap();
+        assert_eq!(kept.reported_id, None);
+        assert_eq!(kept.status, ReportStatus::Actioned);
+    }
+}
+
+fn member(chat_id: Uuid, user_id: Uuid, role: ChatRole) -> ChatMember {
+    ChatMember {
+        chat_id,
+        user_id,
+        role,
+        joined_at: Utc::now(),
+        muted_until: None,
+    }
+}
+
+#[tokio::test]
+async fn test_chats_and_members() {
+    for db in backends().await {
+        let (alice, bob, carol) = (user(), user(), user());
+        for u in [&alice, &bob, &carol] {
+            db.create_user(u).await.unwrap();
+        }
+
+        let direct = Chat {
+            id: Uuid::new_v4(),
+            name: None,
+            is_group: false,
+            is_channel: false,
+            settings: ChatSettings::default(),
+            created_at: Utc::now(),
+            last_message_at: Utc::now() - Duration::minutes(5),
+        };
+        let members = [
+            member(direct.id, alice.id, ChatRole::Admin),
+            member(direct.id, bob.id, ChatRole::Admin),
+        ];
+        db.create_chat(&direct, &members).await.unwrap();
+        let group = Chat {
+            id: Uuid::new_v4(),
+            name: Some("group".to_string()),
+            is_group: true,
+            is_channel: false,
+            settings: ChatSettings::default(),
+            created_at: Utc::now(),
+            last_message_at: Utc::now(),
+        };
+        db.create_chat(&group, &[member(group.id, alice.id, ChatRole::Admin)]).await.unwrap();
+
+        let found = db.find_direct_chat(bob.id, alice.id).await.unwrap().unwrap();
+        assert_eq!(found.id, direct.id);
+        assert!(db.find_direct_chat(alice.id, carol.id).await.unwrap().is_none());
+        let chats: Vec<_> = db.get_user_chats(alice.id).await.unwrap().iter().map(|c| c.id).collect();
+        assert_eq!(chats, vec![group.id, direct.id]);
+
+        assert!(db.add_chat_member(&member(group.id, carol.id, ChatRole::Member)).await.unwrap());
+        assert!(!db.add_chat_member(&member(group.id, carol.id, ChatRole::Admin)).await.unwrap());
+        assert!(db.set_chat_member_role(group.id, carol.id, ChatRole::ReadOnly).await.unwrap());
+        assert!(!db.set_chat_member_role(group.id, bob.id, ChatRole::Admin).await.unwrap());
+        let carol_member = db.get_chat_member(group.id, carol.id).await.unwrap().unwrap();
+        assert_eq!(carol_member.role, ChatRole::ReadOnly);
+        assert_eq!(db.get_chat_members(group.id).await.unwrap().len(), 2);
+
+        // The chat goes away with its last member.
+        assert!(db.remove_chat_member(group.id, carol.id).await.unwrap());
+        assert!(!db.remove_chat_member(group.id, carol.id).await.unwrap());
+        assert!(db.get_chat(group.id).await.unwrap().is_some());
+        assert!(db.remove_chat_member(group.id, alice.id).await.unwrap());
+        assert!(db.get_chat(group.id).await.unwrap().is_none());
+    }
+}
+
+#[tokio::test]
+async fn test_channel_posts_and_subscriber_counts() {
+    for db in backends().await {
+        let (admin, sub1, sub2) = (user(), user(), user());
+        for u in [&admin, &sub1, &sub2] {
+            db.create_user(u).await.unwrap();
+        }
+        let channel = Chat {
+            id: Uuid::new_v4(),
+            name: Some("news".to_string()),
+            is_group: true,
+            is_channel: true,
+            settings: ChatSettings::default(),
+            created_at: Utc::now(),
+            last_message_at: Utc::now() - Duration::minutes(5),
+        };
+        let members = [
+            member(channel.id, admin.id, ChatRole::Admin),
+            member(channel.id, sub1.id, ChatRole::ReadOnly),
+            member(channel.id, sub2.id, ChatRole::ReadOnly),
+        ];
+        db.create_chat(&channel, &members).await.unwrap();
+        assert!(db.get_chat(channel.id).await.unwrap().unwrap().is_channel);
+        assert_eq!(db.count_chat_members(channel.id).await.unwrap(), 3);
+        let admins = db.get_chat_members_with_role(channel.id, ChatRole::Admin).await.unwrap();
+        assert_eq!(admins.len(), 1);
+        assert_eq!(admins[0].user_id, admin.id);
+        let among = db.get_chat_members_among(channel.id, &[sub2.id, Uuid::new_v4()]).await.unwrap();
+        assert_eq!(among.len(), 1);
+        assert_eq!((among[0].user_id, among[0].role), (sub2.id, ChatRole::ReadOnly));
+        assert!(db.get_chat_members_among(channel.id, &[]).await.unwrap().is_empty());
+
+        let now = Utc::now();
+        let posts: Vec<_> = (0..3)
+            .map(|i| ChannelPost {
+                id: Uuid::new_v4(),
+                chat_id: channel.id,
+                sender_id: admin.id,
+                content: vec![i],
+                associated_data: None,
+                created_at: now + Duration::seconds(i as i64),
+            })
+            .collect();
+        for post in &posts {
+            db.create_channel_post(post).await.unwrap();
+        }
+        let chat = db.get_chat(channel.id).await.unwrap().unwrap();
+        assert_eq!(chat.last_message_at.timestamp(), posts[2].created_at.timestamp());
+
+        // Stored once for the channel, not once per subscriber.
+        assert!(db.get_messages(sub1.id, 50).await.unwrap().is_empty());
+        let page = db.get_channel_posts(channel.id, None, 2).await.unwrap();
+        assert_eq!(page.iter().map(|p| p.id).collect::<Vec<_>>(), vec![posts[2].id, posts[1].id]);
+        let page = db.get_channel_posts(channel.id, Some(posts[1].id), 2).await.unwrap();
+        assert_eq!(page.len(), 1);
+        assert_eq!(page[0].id, posts[0].id);
+        assert_eq!(page[0].content, vec![0]);
+    }
+}
+
+#[tokio::test]
+async fn test_chat_settings_pins_and_stars() {
+    for db in backends().await {
+        let (alice, bob) = (user(), user());
+        for u in [&alice, &bob] {
+            db.create_user(u).await.unwrap();
+        }
+        let chat = Chat {
+            id: Uuid::new_v4(),
+            name: Some("group".to_string()),
+            is_group: true,
+            is_channel: false,
+            settings: ChatSettings::default(),
+            created_at: Utc::now(),
+            last_message_at: Utc::now(),
+        };
+        let members = [member(chat.id, alice.id, ChatRole::Admin), member(chat.id, bob.id, ChatRole::Member)];
+        db.create_chat(&chat, &members).await.unwrap();
+
+        let settings = ChatSettings {
+            topic: Some("Plans".to_string()),
+            avatar: Some("attachment:1".to_string()),
+            disappear_after_seconds: Some(3600),
+        };
+        assert!(db.update_chat_settings(chat.id, &settings).await.unwrap());
+        assert!(!db.update_chat_settings(Uuid::new_v4(), &settings).await.unwrap());
+        assert_eq!(db.get_chat(chat.id).await.unwrap().unwrap().settings, settings);
+
+        let until = Utc::now() + Duration::hours(8);
+        assert!(db.set_chat_muted(chat.id, bob.id, Some(until)).await.unwrap());
+        assert!(!db.set_chat_muted(chat.id, Uuid::new_v4(), Some(until)).await.unwrap());
+        let muted = db.get_chat_member(chat.id, bob.id).await.unwrap().unwrap().muted_until.unwrap();
+        assert_eq!(muted.timestamp(), until.timestamp());
+        assert!(db.get_chat_member(chat.id, alice.id).await.unwrap().unwrap().muted_until.is_none());
+        assert!(db.set_chat_muted(chat.id, bob.id, None).await.unwrap());
+        assert!(db.get_chat_member(chat.id, bob.id).await.unwrap().unwrap().muted_until.is_none());
+
+        let pins: Vec<_> = (0..2)
+            .map(|i| ChatPin {
+                id: Uuid::new_v4(),
+                chat_id: chat.id,
+                pinned_by: alice.id,
+                content: vec![i],
+                pinned_at: Utc::now() + Duration::seconds(i as i64),
+            })
+            .collect();
+        for pin in &pins {
+            db.create_pin(pin).await.unwrap();
+        }
+        let found: Vec<_> = db.get_chat_pins(chat.id).await.unwrap().iter().map(|p| p.id).collect();
+        assert_eq!(found, vec![pins[0].id, pins[1].id]);
+        assert!(!db.delete_pin(Uuid::new_v4(), pins[0].id).await.unwrap());
+        assert!(db.delete_pin(chat.id, pins[0].id).await.unwrap());
+        assert_eq!(db.get_chat_pins(chat.id).await.unwrap().len(), 1);
+
+        let (first, second) = (message(alice.id, bob.id), message(alice.id, bob.id));
+        db.create_message(&first).await.unwrap();
+        db.create_message(&second).await.unwrap();
+        assert!(db.star_message(bob.id, first.id, Utc::now()).await.unwrap());
+        assert!(!db.star_message(bob.id, first.id, Utc::now()).await.unwrap());
+        assert!(db.star_message(bob.id, second.id, Utc::now() + Duration::seconds(1)).await.unwrap());
+        let starred: Vec<_> = db.get_starred_messages(bob.id).await.unwrap().iter().map(|s| s.message_id).collect();
+        assert_eq!(starred, vec![second.id, first.id]);
+        assert!(db.get_starred_messages(alice.id).await.unwrap().is_empty());
+
+        // Stars go away with the message.
+        assert!(db.unstar_message(bob.id, second.id).await.unwrap());
+        assert!(!db.unstar_message(bob.id, second.id).await.unwrap());
+        db.delete_message(first.id).await.unwrap();
+        assert!(db.get_starred_messages(bob.id).await.unwrap().is_empty());
+    }
+}
+
+#[tokio::test]
+async fn test_bots_keys_and_webhooks() {
+    for db in backends().await {
+        let owner = user();
+        db.create_user(&owner).await.unwrap();
+        let bot = User { bot_owner_id: Some(owner.id), ..user() };
+        db.create_user(&bot).await.unwrap();
+        let bot_device = device(bot.id);
+        db.create_device(&bot_device).await.unwrap();
+
+        let bots: Vec<_> = db.get_user_bots(owner.id).await.unwrap().iter().map(|b| b.id).collect();
+        assert_eq!(bots, vec![bot.id]);
+        assert_eq!(db.get_user(bot.id).await.unwrap().unwrap().bot_owner_id, Some(owner.id));
+        assert!(db.get_user_bots(bot.id).await.unwrap().is_empty());
+
+        let key = ApiKey {
+            id: Uuid::new_v4(),
+            bot_id: bot.id,
+            device_id: bot_device.id,
+            key_hash: format!("hash-{}", Uuid::new_v4()),
+            created_at: Utc::now(),
+            revoked_at: None,
+        };
+        db.create_api_key(&key).await.unwrap();
+        assert!(matches!(
+            db.create_api_key(&ApiKey { id: Uuid::new_v4(), ..key.clone() }).await,
+            Err(DatabaseError::UniqueViolation(_))
+        ));
+        assert_eq!(db.get_api_key_by_hash(&key.key_hash).await.unwrap().unwrap().id, key.id);
+        assert!(!db.revoke_api_key(owner.id, key.id, Utc::now()).await.unwrap());
+        assert!(db.revoke_api_key(bot.id, key.id, Utc::now()).await.unwrap());
+        assert!(!db.revoke_api_key(bot.id, key.id, Utc::now()).await.unwrap());
+        assert!(db.get_api_key_by_hash(&key.key_hash).await.unwrap().is_none());
+        assert!(db.get_bot_api_keys(bot.id).await.unwrap()[0].revoked_at.is_some());
+
+        let webhook = BotWebhook {
+            bot_id: bot.id,
+            url: "https://example.com/hook".to_string(),
+            secret: "secret".to_string(),
+            created_at: Utc::now(),
+        };
+        db.set_bot_webhook(&webhook).await.unwrap();
+        db.set_bot_webhook(&BotWebhook { url: "https://example.com/other".to_string(), ..webhook.clone() }).await.unwrap();
+        assert_eq!(db.get_bot_webhook(bot.id).await.unwrap().unwrap().url, "https://example.com/other");
+
+        // Keys and the webhook go with the bot.
+        db.delete_user(bot.id).await.unwrap();
+        assert!(db.get_bot_api_keys(bot.id).await.unwrap().is_empty());
+        assert!(db.get_bot_webhook(bot.id).await.unwrap().is_none());
+        assert!(!db.delete_bot_webhook(bot.id).await.unwrap());
+        assert!(db.get_user_bots(owner.id).await.unwrap().is_empty());
+    }
+}
+
+#[tokio::test]
+async fn test_invites_and_join_requests() {
+    for db in backends().await {
+        let (alice, bob) = (user(), user());
+        for u in [&alice, &bob] {
+            db.create_user(u).await.unwrap();
+        }
+        let group = Chat {
+            id: Uuid::new_v4(),
+            name: Some("group".to_string()),
+            is_group: true,
+            is_channel: false,
+            settings: ChatSettings::default(),
+            created_at: Utc::now(),
+            last_message_at: Utc::now(),
+        };
+        db.create_chat(&group, &[member(group.id, alice.id, ChatRole::Admin)]).await.unwrap();
+
+        let now = Utc::now();
+        let invite = ChatInvite {
+            id: Uuid::new_v4(),
+            chat_id: group.id,
+            code: Uuid::new_v4().to_string(),
+            created_by: alice.id,
+            created_at: now,
+            expires_at: Some(now + Duration::hours(1)),
+            max_uses: Some(2),
+            use_count: 0,
+            requires_approval: true,
+            revoked_at: None,
+        };
+        db.create_invite(&invite).await.unwrap();
+        let found = db.get_invite_by_code(&invite.code).await.unwrap().unwrap();
+        assert_eq!(found.id, invite.id);
+        assert_eq!(found.max_uses, Some(2));
+
+        // Uses stop at the limit, and at the expiry.
+        assert!(!db.redeem_invite(invite.id, now + Duration::hours(2)).await.unwrap());
+        assert!(db.redeem_invite(invite.id, now).await.unwrap());
+        assert!(db.redeem_invite(invite.id, now).await.unwrap());
+        assert!(!db.redeem_invite(invite.id, now).await.unwrap());
+        let used = db.get_invite(invite.id).await.unwrap().unwrap();
+        assert_eq!(used.use_count, 2);
+        assert!(!used.is_usable(now));
+
+        let request = JoinRequest {
+            chat_id: group.id,
+            user_id: bob.id,
+            invite_id: invite.id,
+            created_at: now,
+        };
+        assert!(db.create_join_request(&request).await.unwrap());
+        assert!(!db.create_join_request(&request).await.unwrap());
+        assert_eq!(db.get_join_requests(group.id).await.unwrap().len(), 1);
+        let taken = db.take_join_request(group.id, bob.id).await.unwrap().unwrap();
+        assert_eq!(taken.invite_id, invite.id);
+        assert!(db.take_join_request(group.id, bob.id).await.unwrap().is_none());
+
+        assert!(db.revoke_invite(invite.id, now).await.unwrap());
+        assert!(!db.revoke_invite(invite.id, now).await.unwrap());
+        assert_eq!(db.get_chat_invites(group.id).await.unwrap().len(), 1);
+    }
+}
+
+#[tokio::test]
+async fn test_thread_pages_and_unread_counts() {
+    for db in backends().await {
+        let (alice, bob) = (user(), user());
+        for u in [&alice, &bob] {
+            db.create_user(u).await.unwrap();
+        }
+        let group = Chat {
+            id: Uuid::new_v4(),
+            name: Some("group".to_string()),
+            is_group: true,
+            is_channel: false,
+            settings: ChatSettings::default(),
+            created_at: Utc::now(),
+            last_message_at: Utc::now(),
+        };
+        let members = [
+            member(group.id, alice.id, ChatRole::Admin),
+            member(group.id, bob.id, ChatRole::Member),
+        ];
+        db.create_chat(&group, &members).await.unwrap();
+
+        let (thread, other_thread) = (Uuid::new_v4(), Uuid::new_v4());
+        let start = Utc::now() - Duration::minutes(10);
+        let mut replies = Vec::new();
+        for i in 0..5 {
+            let mut reply = message(alice.id, bob.id);
+            reply.chat_id = Some(group.id);
+            reply.thread_id = Some(thread);
+            reply.created_at = start + Duration::minutes(i);
+            db.create_message(&reply).await.unwrap();
+            replies.push(reply);
+        }
+        let mut other = message(alice.id, bob.id);
+        other.chat_id = Some(group.id);
+        other.thread_id = Some(other_thread);
+        db.create_message(&other).await.unwrap();
+        // Not in a thread, and not addressed to Bob.
+        let mut unthreaded = message(alice.id, bob.id);
+        unthreaded.chat_id = Some(group.id);
+        db.create_message(&unthreaded).await.unwrap();
+        let mut own = message(bob.id, alice.id);
+        own.chat_id = Some(group.id);
+        own.thread_id = Some(thread);
+        db.create_message(&own).await.unwrap();
+
+        let page = db.get_thread_messages(bob.id, group.id, thread, None, 3).await.unwrap();
+        let ids: Vec<_> = page.iter().map(|m| m.id).collect();
+        assert_eq!(ids, vec![replies[4].id, replies[3].id, replies[2].id]);
+        let page = db.get_thread_messages(bob.id, group.id, thread, Some(replies[2].id), 3).await.unwrap();
+        let ids: Vec<_> = page.iter().map(|m| m.id).collect();
+        assert_eq!(ids, vec![replies[1].id, replies[0].id]);
+
+        let summaries = db.get_thread_summaries(bob.id, group.id).await.unwrap();
+        let ids: Vec<_> = summaries.iter().map(|t| t.thread_id).collect();
+        assert_eq!(ids, vec![other_thread, thread]);
+        assert_eq!((summaries[1].message_count, summaries[1].unread_count), (5, 5));
+
+        db.mark_thread_read(bob.id, group.id, thread, replies[2].created_at).await.unwrap();
+        // Marking an earlier message read does not move the marker back.
+        db.mark_thread_read(bob.id, group.id, thread, replies[0].created_at).await.unwrap();
+        let summaries = db.get_thread_summaries(bob.id, group.id).await.unwrap();
+        assert_eq!(summaries[1].unread_count, 2);
+        assert_eq!(summaries[0].unread_count, 1);
+    }
+}
+
+#[tokio::test]
+async fn test_presence_subscribers_and_last_seen() {
+    for db in backends().await {
+        let (alice, bob, carol, dave) = (user(), user(), user(), user());
+        for u in [&alice, &bob, &carol, &dave] {
+            db.create_user(u).await.unwrap();
+        }
+        let alice_device = device(alice.id);
+        db.create_device(&alice_device).await.unwrap();
+
+        // Bob has Alice as a contact and Carol shares a chat with her; Alice
+        // having Dave as a contact does not tell Dave about her.
+        db.add_contact(bob.id, alice.id, None).await.unwrap();
+        db.add_contact(alice.id, dave.id, None).await.unwrap();
+        let chat = Chat {
+            id: Uuid::new_v4(),
+            name: None,
+            is_group: false,
+            is_channel: false,
+            settings: ChatSettings::default(),
+            created_at: Utc::now(),
+            last_message_at: Utc::now(),
+        };
+        let members = [
+            member(chat.id, alice.id, ChatRole::Admin),
+            member(chat.id, carol.id, ChatRole::Admin),
+        ];
+        db.create_chat(&chat, &members).await.unwrap();
+        db.add_contact(carol.id, alice.id, None).await.unwrap();
+
+        let mut subscribers = db.get_presence_subscribers(alice.id).await.unwrap();
+        subscribers.sort();
+        let mut expected = vec![bob.id, carol.id];
+        expected.sort();
+        assert_eq!(subscribers, expected);
+        assert!(db.is_presence_subscriber(alice.id, bob.id).await.unwrap());
+        assert!(db.is_presence_subscriber(alice.id, carol.id).await.unwrap());
+        assert!(!db.is_presence_subscriber(alice.id, dave.id).await.unwrap());
+
+        let seen = Utc::now() + Duration::minutes(1);
+        db.record_last_seen(alice.id, alice_device.id, seen).await.unwrap();
+        let stored = db.get_user(alice.id).await.unwrap().unwrap();
+        assert_eq!(stored.last_seen.timestamp(), seen.timestamp());
+        let devices = db.get_user_devices(alice.id).await.unwrap();
+        assert_eq!(devices[0].last_seen.timestamp(), seen.timestamp());
+
+        let settings = db.get_privacy_settings(alice.id).await.unwrap();
+        assert!(settings.show_last_seen);
+    }
+}
+
+#[tokio::test]
+async fn test_admin_lookups_and_queue_stats() {
+    for db in backends().await {
+        let (alice, bob) = (user(), user());
+        db.create_user(&alice).await.unwrap();
+        db.create_user(&bob).await.unwrap();
+
+        let by_name = db.get_user_by_username(&alice.username).await.unwrap().unwrap();
+        assert_eq!(by_name.id, alice.id);
+        let found = db.search_users(Some(&alice.id.to_string().to_uppercase()), 10).await.unwrap();
+        assert_eq!(found.iter().map(|u| u.id).collect::<Vec<_>>(), vec![alice.id]);
+        assert!(db.search_users(Some("%"), 10).await.unwrap().is_empty());
+        assert_eq!(db.search_users(None, 1).await.unwrap().len(), 1);
+
+        let device = device(alice.id);
+        db.create_device(&device).await.unwrap();
+        let session = session(alice.id, device.id);
+        db.create_session(&session).await.unwrap();
+        assert!(!db.delete_device(bob.id, device.id).await.unwrap());
+        assert!(db.delete_device(alice.id, device.id).await.unwrap());
+        assert!(db.get_user_devices(alice.id).await.unwrap().is_empty());
+        assert!(db.get_active_session(session.id).await.unwrap().is_none());
+
+        let first = message(alice.id, bob.id);
+        db.create_message(&first).await.unwrap();
+        db.create_message(&message(alice.id, bob.id)).await.unwrap();
+        db.create_message(&message(bob.id, alice.id)).await.unwrap();
+
+        let stats = db.queue_stats(10_000).await.unwrap();
+        assert!(stats.messages >= 3);
+        assert!(stats.oldest_at.unwrap() <= first.created_at);
+        assert!(stats.recipients.windows(2).all(|w| w[0].messages >= w[1].messages));
+        let queued = stats.recipients.iter().find(|r| r.user_id == bob.id).unwrap();
+        assert_eq!(queued.messages, 2);
+        assert_eq!(queued.oldest_at.timestamp(), first.created_at.timestamp());
+    }
+}
+
+#[tokio::test]
+async fn test_signing_keys() {
+    for db in backends().await {
+        let now = Utc::now();
+        let key = |activates_at| SigningKey {
+            id: Uuid::new_v4().simple().to_string(),
+            algorithm: SigningAlgorithm::EdDsa,
+            private_key: vec![1, 2, 3],
+            public_key: vec![4, 5, 6],
+            created_at: now,
+            activates_at,
+        };
+        let (later, sooner) = (key(now + Duration::days(3650)), key(now + Duration::days(3649)));
+        db.create_signing_key(&later).await.unwrap();
+        db.create_signing_key(&sooner).await.unwrap();
+        assert!(matches!(db.create_signing_key(&later).await, Err(DatabaseError::UniqueViolation(_))));
+
+        let keys = db.get_signing_keys().await.unwrap();
+        let ours: Vec<&SigningKey> = keys.iter().filter(|k| k.id == later.id || k.id == sooner.id).collect();
+        assert_eq!(ours.iter().map(|k| &k.id).collect::<Vec<_>>(), vec![&sooner.id, &later.id]);
+        assert_eq!(ours[0].private_key, vec![1, 2, 3]);
+        assert_eq!(ours[0].activates_at.timestamp(), sooner.activates_at.timestamp());
+
+        assert!(db.delete_signing_key(&later.id).await.unwrap());
+        assert!(db.delete_signing_key(&sooner.id).await.unwrap());
+        assert!(!db.delete_signing_key(&sooner.id).await.unwrap());
+    }
+}
+
+#[tokio::test]
+async fn test_audit_events() {
+    for db in backends().await {
+        let (alice, bob, device_id) = (Uuid::new_v4(), Uuid::new_v4(), Uuid::new_v4());
+        let login = audit::append(&db, Entry::new(AuditKind::Login, Some(alice)).device(device_id).detail("device_name", "phone"))
+            .await
+            .unwrap();
+        let failed = audit::append(&db, Entry::new(AuditKind::LoginFailed, Some(alice)).detail("reason", "password"))
+            .await
+            .unwrap();
+        audit::append(&db, Entry::new(AuditKind::RoleChanged, Some(bob)).actor(alice)).await.unwrap();
+        assert_eq!(failed.id, login.id + 1);
+        assert_eq!(failed.prev_hash, login.hash);
+        assert!(matches!(db.append_audit_event(&failed).await, Err(DatabaseError::UniqueViolation(_))));
+
+        let query = |user_id, since, until, before| AuditQuery { user_id, since, until, before, limit: 10 };
+        let events = db.list_audit_events(&query(Some(alice), None, None, None)).await.unwrap();
+        assert_eq!(events.iter().map(|e| e.id).collect::<Vec<_>>(), vec![failed.id, login.id]);
+        let stored = &events[1];
+        assert_eq!((stored.kind, stored.device_id, stored.actor_id), (AuditKind::Login, Some(device_id), None));
+        assert_eq!(stored.details["device_name"], "phone");
+        assert_eq!(stored.created_at, login.created_at);
+        assert_eq!(audit::hash(stored), login.hash);
+
+        let paged = db.list_audit_events(&query(Some(alice), None, None, Some(failed.id))).await.unwrap();
+        assert_eq!(paged.iter().map(|e| e.id).collect::<Vec<_>>(), vec![login.id]);
+        let window = query(Some(alice), Some(failed.created_at), Some(failed.created_at + Duration::seconds(1)), None);
+        assert_eq!(db.list_audit_events(&window).await.unwrap()[0].id, failed.id);
+        let before = query(Some(alice), None, Some(login.created_at), None);
+        assert!(db.list_audit_events(&before).await.unwrap().is_empty());
+
+        let after = db.audit_events_after(login.id, 2).await.unwrap();
+        assert_eq!(after.iter().map(|e| e.id).collect::<Vec<_>>(), vec![failed.id, failed.id + 1]);
+        assert_eq!(db.last_audit_event().await.unwrap().unwrap().actor_id, Some(alice));
+    }
+}
@@ -0,0 +1,186 @@
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::sync::Arc;
+use std::time::Duration;
+
+use chrono::Utc;
+use hmac::{Hmac, Mac};
+use serde::Serialize;
+use sha2::Sha256;
+
+use crate::models::{BotWebhook, Message};
+
+pub const TIMESTAMP_HEADER: &str = "X-Pulse-Timestamp";
+pub const SIGNATURE_HEADER: &str = "X-Pulse-Signature";
+
+#[derive(Debug, thiserror::Error)]
+pub enum WebhookError {
+    #[error("HTTP error: {0}")]
+    Http(#[from] reqwest::Error),
+    #[error("Endpoint answered {0}")]
+    Status(reqwest::StatusCode),
+    #[error("{0} is not a public address")]
+    NotPublic(String),
+    #[error("Cannot resolve {0}")]
+    Unresolvable(String),
+}
+
+/// What a bot's endpoint receives. Message content stays encrypted; the bot
+/// decrypts it itself.
+#[derive(Debug, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum WebhookPayload<'a> {
+    Message { message: &'a Message },
+}
+
+/// Whether `ip` is reachable from the internet at large, as opposed to the
+/// host itself, a private network or a link (which includes cloud metadata
+/// services at 169.254.169.254). IPv6 addresses that tunnel or translate to
+/// an IPv4 address are judged by that address.
+pub fn is_public(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => {
+            !(ip.octets()[0] == 0 // "This network", 0.0.0.0/8.
+                || ip.is_loopback()
+                || ip.is_private()
+                || ip.is_link_local()
+                || ip.is_unspecified()
+                || ip.is_broadcast()
+                || ip.is_multicast()
+                // Carrier-grade NAT, 100.64.0.0/10.
+                || (ip.octets()[0] == 100 && ip.octets()[1] & 0xc0 == 64))
+        }
+        IpAddr::V6(ip) => match embedded_ipv4(ip) {
+            Some(ip) => is_public(IpAddr::V4(ip)),
+            None => {
+                let first = ip.segments()[0];
+                !(ip.is_loopback()
+                    || ip.is_unspecified()
+                    || ip.is_multicast()
+                    // Unique local, fc00::/7, and link-local, fe80::/10.
+                    || first & 0xfe00 == 0xfc00
+                    || first & 0xffc0 == 0xfe80)
+            }
+        },
+    }
+}
+
+/// The IPv4 address an IPv6 address stands in for: IPv4-mapped (::ffff:0:0/96),
+/// NAT64 (64:ff9b::/96), 6to4 (2002::/16) or the Teredo client
+/// (2001::/32, stored inverted in the last 32 bits).
+fn embedded_ipv4(ip: Ipv6Addr) -> Option<Ipv4Addr> {
+    let s = ip.segments();
+    let from = |high: u16, low: u16| Ipv4Addr::from(((high as u32) << 16) | low as u32);
+    match s {
+        [0x64, 0xff9b, 0, 0, 0, 0, ..] => Some(from(s[6], s[7])),
+        [0x2002, ..] => Some(from(s[1], s[2])),
+        [0x2001, 0, ..] => Some(from(!s[6], !s[7])),
+        _ => ip.to_ipv4_mapped(),
+    }
+}
+
+/// Checks that every address `url`'s host resolves to is public, so that
+/// bot owners cannot have the server post into its own network.
+pub async fn check_url(url: &reqwest::Url) -> Result<(), WebhookError> {
+    let host = url.host_str().unwrap_or_default();
+    let port = url.port_or_known_default().unwrap_or(443);
+    // IPv6 literals come bracketed.
+    let addrs: Vec<SocketAddr> = tokio::net::lookup_host((host.trim_matches(['[', ']']), port))
+        .await
+        .map_err(|_| WebhookError::Unresolvable(host.to_string()))?
+        .collect();
+    if addrs.is_empty() {
+        return Err(WebhookError::Unresolvable(host.to_string()));
+    }
+    match addrs.iter().find(|addr| !is_public(addr.ip())) {
+        Some(addr) => Err(WebhookError::NotPublic(addr.ip().to_string())),
+        None => Ok(()),
+    }
+}
+
+/// Resolves host names to their public addresses only. Checking the URL
+/// before sending is not enough: the name may resolve differently by the
+/// time the connection is made.
+struct PublicResolver;
+
+impl reqwest::dns::Resolve for PublicResolver {
+    fn resolve(&self, name: reqwest::dns::Name) -> reqwest::dns::Resolving {
+        Box::pin(async move {
+            let addrs: Vec<SocketAddr> = tokio::net::lookup_host((name.as_str(), 0))
+                .await?
+                .filter(|addr| is_public(addr.ip()))
+                .collect();
+            if addrs.is_empty() {
+                return Err(WebhookError::NotPublic(name.as_str().to_string()).into());
+            }
+            Ok(Box::new(addrs.into_iter()) as reqwest::dns::Addrs)
+        })
+    }
+}
+
+/// Posts events to bot webhooks. Each delivery is tried once; a bot that
+/// misses one still finds the message with `GET /api/messages`.
+pub struct WebhookSender {
+    client: reqwest::Client,
+    allow_private: bool,
+}
+
+impl WebhookSender {
+    /// With `allow_private`, webhooks may point at private and loopback
+    /// addresses; only for local development.
+    pub fn new(timeout: Duration, allow_private: bool) -> Self {
+        let mut builder = reqwest::Client::builder()
+            .timeout(timeout)
+            .redirect(reqwest::redirect::Policy::none());
+        if !allow_private {
+            builder = builder.dns_resolver(Arc::new(PublicResolver));
+        }
+        let client = builder.build().expect("TLS backend is available");
+        Self { client, allow_private }
+    }
+
+    pub async fn deliver(&self, webhook: &BotWebhook, payload: &WebhookPayload<'_>) -> Result<(), WebhookError> {
+        if !self.allow_private {
+            // Addresses in the URL itself bypass the resolver.
+            let host = reqwest::Url::parse(&webhook.url)
+                .ok()
+                .and_then(|url| url.host_str().map(|host| host.trim_matches(['[', ']']).to_string()))
+                .unwrap_or_default();
+            if let Ok(ip) = host.parse::<IpAddr>() {
+                if !is_public(ip) {
+                    return Err(WebhookError::NotPublic(host));
+                }
+            }
+        }
+        let body = serde_json::to_vec(payload).expect("payload serializes");
+        let timestamp = Utc::now().timestamp();
+
+        let response = self
+            .client
+            .post(&webhook.url)
+            .header(reqwest::header::CONTENT_TYPE, "application/json")
+            .header(TIMESTAMP_HEADER, timestamp)
+            .header(SIGNATURE_HEADER, sign(&webhook.secret, timestamp, &body))
+            .body(body)
+            .send()
+            .await?;
+
+        if !response.status().is_success() {
+            return Err(WebhookError::Status(response.status()));
+        }
+        Ok(())
+    }
+}
+
+/// Hex HMAC-SHA256 of `"{timestamp}.{body}"`. Covering the timestamp lets
+/// receivers reject replayed deliveries.
+pub fn sign(secret: &str, timestamp: i64, body: &[u8]) -> String {
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC accepts any key length");
+    mac.update(timestamp.to_string().as_bytes());
+    mac.update(b".");
+    mac.update(body);
+    mac.finalize()
+        .into_bytes()
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
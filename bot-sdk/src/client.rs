@@ -0,0 +1,136 @@
+use reqwest::{Client, RequestBuilder, Response};
+use serde::Deserialize;
+use serde_json::json;
+use uuid::Uuid;
+
+use crate::{BotError, Keyring, Message, MessageKind};
+
+/// Prefix of every API key; the bot's id follows it.
+const API_KEY_PREFIX: &str = "pulse_bot_";
+
+#[derive(Debug, Deserialize)]
+struct ErrorBody {
+    error: ErrorDetail,
+}
+
+#[derive(Debug, Deserialize)]
+struct ErrorDetail {
+    code: String,
+    message: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct JoinResponse {
+    status: String,
+}
+
+/// Talks to the server as a bot, encrypting what it sends and decrypting
+/// what it receives with the keys in its keyring.
+pub struct BotClient {
+    client: Client,
+    base_url: String,
+    api_key: String,
+    bot_id: Uuid,
+    keyring: Keyring,
+}
+
+impl BotClient {
+    pub fn new(base_url: impl Into<String>, api_key: impl Into<String>, keyring: Keyring) -> Result<Self, BotError> {
+        let api_key = api_key.into();
+        let bot_id = api_key
+            .strip_prefix(API_KEY_PREFIX)
+            .and_then(|rest| rest.split_once('_'))
+            .and_then(|(id, _)| Uuid::try_parse(id).ok())
+            .ok_or(BotError::InvalidApiKey)?;
+
+        Ok(Self {
+            client: Client::new(),
+            base_url: base_url.into().trim_end_matches('/').to_string(),
+            api_key,
+            bot_id,
+            keyring,
+        })
+    }
+
+    pub fn bot_id(&self) -> Uuid {
+        self.bot_id
+    }
+
+    pub fn keyring(&self) -> &Keyring {
+        &self.keyring
+    }
+
+    pub fn keyring_mut(&mut self) -> &mut Keyring {
+        &mut self.keyring
+    }
+
+    fn request(&self, builder: RequestBuilder) -> RequestBuilder {
+        builder.bearer_auth(&self.api_key)
+    }
+
+    async fn check(response: Response) -> Result<Response, BotError> {
+        if response.status().is_success() {
+            return Ok(response);
+        }
+        let status = response.status();
+        match response.json::<ErrorBody>().await {
+            Ok(body) => Err(BotError::Server {
+                code: body.error.code,
+                message: body.error.message,
+            }),
+            Err(_) => Err(BotError::Server {
+                code: status.as_u16().to_string(),
+                message: format!("Unexpected response ({})", status),
+            }),
+        }
+    }
+
+    /// Encrypts `text` under the conversation's key and sends it: to a chat
+    /// member when `chat_id` is set, otherwise directly to `recipient_id`.
+    pub async fn send_text(&self, recipient_id: Uuid, chat_id: Option<Uuid>, text: &str) -> Result<Message, BotError> {
+        let content = self.keyring.encrypt(chat_id.unwrap_or(recipient_id), text.as_bytes())?;
+        let response = self
+            .request(self.client.post(format!("{}/api/messages", self.base_url)))
+            .json(&json!({
+                "recipient_id": recipient_id,
+                "content": content,
+                "kind": MessageKind::Message,
+                "chat_id": chat_id,
+            }))
+            .send()
+            .await?;
+
+        Ok(Self::check(response).await?.json().await?)
+    }
+
+    /// Messages waiting for the bot, for bots that poll rather than take
+    /// webhooks.
+    pub async fn messages(&self) -> Result<Vec<Message>, BotError> {
+        let response = self
+            .request(self.client.get(format!("{}/api/messages", self.base_url)))
+            .send()
+            .await?;
+
+        Ok(Self::check(response).await?.json().await?)
+    }
+
+    /// Joins a chat through an invite link. Returns false if an admin has to
+    /// approve the request first.
+    pub async fn join(&self, invite_code: &str) -> Result<bool, BotError> {
+        let response = self
+            .request(self.client.post(format!("{}/api/invites/{}/join", self.base_url, invite_code)))
+            .send()
+            .await?;
+
+        let joined: JoinResponse = Self::check(response).await?.json().await?;
+        Ok(joined.status == "joined")
+    }
+
+    pub fn decrypt(&self, message: &Message) -> Result<Vec<u8>, BotError> {
+        self.keyring.decrypt(message.conversation_id(self.bot_id), &message.content)
+    }
+
+    pub fn decrypt_text(&self, message: &Message) -> Result<String, BotError> {
+        String::from_utf8(self.decrypt(message)?).map_err(|_| BotError::InvalidText)
+    }
+}
@@ -0,0 +1,102 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+use pulse_crypto::{Crypto, EncryptedMessage};
+use uuid::Uuid;
+
+use crate::BotError;
+
+/// The bot's conversation keys, one per chat or direct peer, kept in a JSON
+/// file next to the bot. The file holds secrets; keep it private.
+pub struct Keyring {
+    path: PathBuf,
+    keys: HashMap<Uuid, Vec<u8>>,
+}
+
+impl Keyring {
+    /// Loads the keyring at `path`, or starts an empty one if there is no
+    /// file yet.
+    pub async fn open(path: impl AsRef<Path>) -> Result<Self, BotError> {
+        let path = path.as_ref().to_path_buf();
+        let keys = match tokio::fs::read(&path).await {
+            Ok(bytes) => serde_json::from_slice(&bytes)?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => HashMap::new(),
+            Err(e) => return Err(e.into()),
+        };
+        Ok(Self { path, keys })
+    }
+
+    pub async fn save(&self) -> Result<(), BotError> {
+        // Written aside and renamed, so a crash never leaves half a file.
+        let tmp = self.path.with_extension("tmp");
+        tokio::fs::write(&tmp, serde_json::to_vec(&self.keys)?).await?;
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            tokio::fs::set_permissions(&tmp, std::fs::Permissions::from_mode(0o600)).await?;
+        }
+        tokio::fs::rename(&tmp, &self.path).await?;
+        Ok(())
+    }
+
+    /// Stores a key another member shared with the bot.
+    pub fn insert(&mut self, conversation_id: Uuid, key: Vec<u8>) -> Result<(), BotError> {
+        Crypto::from_key(&key)?;
+        self.keys.insert(conversation_id, key);
+        Ok(())
+    }
+
+    /// Makes a fresh key for a conversation the bot starts, and returns it
+    /// for sharing with the other members.
+    pub fn generate(&mut self, conversation_id: Uuid) -> Result<Vec<u8>, BotError> {
+        let key = Crypto::new()?.key_bytes();
+        self.keys.insert(conversation_id, key.clone());
+        Ok(key)
+    }
+
+    pub fn remove(&mut self, conversation_id: Uuid) -> bool {
+        self.keys.remove(&conversation_id).is_some()
+    }
+
+    fn crypto(&self, conversation_id: Uuid) -> Result<Crypto, BotError> {
+        let key = self.keys.get(&conversation_id).ok_or(BotError::MissingKey(conversation_id))?;
+        Ok(Crypto::from_key(key)?)
+    }
+
+    pub fn encrypt(&self, conversation_id: Uuid, plaintext: &[u8]) -> Result<Vec<u8>, BotError> {
+        Ok(self.crypto(conversation_id)?.encrypt(plaintext, None)?.to_bytes())
+    }
+
+    pub fn decrypt(&self, conversation_id: Uuid, content: &[u8]) -> Result<Vec<u8>, BotError> {
+        let message = EncryptedMessage::from_bytes(content)?;
+        Ok(self.crypto(conversation_id)?.decrypt(&message)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_keyring_roundtrip() {
+        let path = std::env::temp_dir().join(format!("pulse-keyring-{}.json", Uuid::new_v4()));
+        let conversation = Uuid::new_v4();
+
+        let mut keyring = Keyring::open(&path).await.unwrap();
+        let shared = keyring.generate(conversation).unwrap();
+        let content = keyring.encrypt(conversation, b"hello").unwrap();
+        keyring.save().await.unwrap();
+
+        // A peer holding the shared key reads what the bot wrote.
+        let mut peer = Keyring::open(std::env::temp_dir().join("pulse-keyring-unsaved.json")).await.unwrap();
+        peer.insert(conversation, shared).unwrap();
+        assert_eq!(peer.decrypt(conversation, &content).unwrap(), b"hello");
+
+        let reopened = Keyring::open(&path).await.unwrap();
+        assert_eq!(reopened.decrypt(conversation, &content).unwrap(), b"hello");
+        assert!(matches!(reopened.encrypt(Uuid::new_v4(), b"x"), Err(BotError::MissingKey(_))));
+        assert!(peer.insert(conversation, vec![0; 16]).is_err());
+
+        tokio::fs::remove_file(&path).await.unwrap();
+    }
+}
@@ -0,0 +1,82 @@
+//! Client library for Pulse bots.
+//!
+//! A bot is an account managed by a regular user, who creates it with
+//! `POST /api/bots` and receives an API key. The server never sees message
+//! plaintext, bots included: this crate keeps the bot's conversation keys in
+//! a [`Keyring`] and encrypts and decrypts with `pulse-crypto` itself.
+
+mod client;
+mod keys;
+pub mod webhook;
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+use uuid::Uuid;
+
+pub use client::BotClient;
+pub use keys::Keyring;
+pub use pulse_crypto::CryptoError;
+
+#[derive(Error, Debug)]
+pub enum BotError {
+    #[error("Network error: {0}")]
+    Network(#[from] reqwest::Error),
+    /// The server's `{"error": {"code", "message"}}` body.
+    #[error("Server error {code}: {message}")]
+    Server { code: String, message: String },
+    #[error("Crypto error: {0}")]
+    Crypto(#[from] CryptoError),
+    #[error("I/O error: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("Invalid data: {0}")]
+    Json(#[from] serde_json::Error),
+    #[error("Not a Pulse API key")]
+    InvalidApiKey,
+    #[error("No key for conversation {0}")]
+    MissingKey(Uuid),
+    #[error("Webhook signature does not match")]
+    InvalidSignature,
+    #[error("Message is not valid UTF-8")]
+    InvalidText,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MessageKind {
+    Message,
+    Receipt,
+    Edit,
+    Delete,
+    Reaction,
+}
+
+/// A message as the server stores it, content still encrypted.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Message {
+    pub id: Uuid,
+    pub sender_id: Uuid,
+    pub recipient_id: Uuid,
+    pub content: Vec<u8>,
+    pub created_at: DateTime<Utc>,
+    pub expires_at: Option<DateTime<Utc>>,
+    pub kind: MessageKind,
+    #[serde(default)]
+    pub target_id: Option<Uuid>,
+    #[serde(default)]
+    pub chat_id: Option<Uuid>,
+    #[serde(default)]
+    pub thread_id: Option<Uuid>,
+}
+
+impl Message {
+    /// Whose key the content is encrypted under: the chat's for chat
+    /// messages, otherwise the other party's.
+    pub fn conversation_id(&self, bot_id: Uuid) -> Uuid {
+        match self.chat_id {
+            Some(chat_id) => chat_id,
+            None if self.sender_id == bot_id => self.recipient_id,
+            None => self.sender_id,
+        }
+    }
+}
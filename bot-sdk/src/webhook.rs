@@ -0,0 +1,92 @@
+//! Checking and reading the server's webhook deliveries.
+//!
+//! Each delivery is a JSON body with an `X-Pulse-Timestamp` header (Unix
+//! seconds) and an `X-Pulse-Signature` header: the hex HMAC-SHA256 of
+//! `"{timestamp}.{body}"` under the secret returned when the webhook was
+//! set.
+
+use chrono::Utc;
+use hmac::{Hmac, Mac};
+use serde::Deserialize;
+use sha2::Sha256;
+
+use crate::{BotError, Message};
+
+pub const TIMESTAMP_HEADER: &str = "X-Pulse-Timestamp";
+pub const SIGNATURE_HEADER: &str = "X-Pulse-Signature";
+
+/// How far a delivery's timestamp may be from now before it is treated as
+/// a replay.
+pub const DEFAULT_TOLERANCE_SECONDS: i64 = 300;
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum WebhookEvent {
+    /// A message addressed to the bot, still encrypted; decrypt it with
+    /// `BotClient::decrypt`.
+    Message { message: Message },
+}
+
+/// Checks the signature and age of a delivery.
+pub fn verify(secret: &str, timestamp: &str, body: &[u8], signature: &str) -> bool {
+    let Ok(sent_at) = timestamp.parse::<i64>() else {
+        return false;
+    };
+    if (Utc::now().timestamp() - sent_at).abs() > DEFAULT_TOLERANCE_SECONDS {
+        return false;
+    }
+    let Some(signature) = decode_hex(signature) else {
+        return false;
+    };
+
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC accepts any key length");
+    mac.update(timestamp.as_bytes());
+    mac.update(b".");
+    mac.update(body);
+    mac.verify_slice(&signature).is_ok()
+}
+
+/// Verifies a delivery and parses its body.
+pub fn parse(secret: &str, timestamp: &str, body: &[u8], signature: &str) -> Result<WebhookEvent, BotError> {
+    if !verify(secret, timestamp, body, signature) {
+        return Err(BotError::InvalidSignature);
+    }
+    Ok(serde_json::from_slice(body)?)
+}
+
+fn decode_hex(s: &str) -> Option<Vec<u8>> {
+    if !s.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..s.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(s.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sign(secret: &str, timestamp: &str, body: &[u8]) -> String {
+        let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes()).unwrap();
+        mac.update(format!("{}.", timestamp).as_bytes());
+        mac.update(body);
+        mac.finalize().into_bytes().iter().map(|b| format!("{:02x}", b)).collect()
+    }
+
+    #[test]
+    fn test_verify() {
+        let body = br#"{"type":"message"}"#;
+        let now = Utc::now().timestamp().to_string();
+        let signature = sign("secret", &now, body);
+
+        assert!(verify("secret", &now, body, &signature));
+        assert!(!verify("other", &now, body, &signature));
+        assert!(!verify("secret", &now, b"{}", &signature));
+        assert!(!verify("secret", &now, body, "zz"));
+
+        let old = (Utc::now().timestamp() - DEFAULT_TOLERANCE_SECONDS - 1).to_string();
+        assert!(!verify("secret", &old, body, &sign("secret", &old, body)));
+    }
+}
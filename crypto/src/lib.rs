@@ -34,6 +34,28 @@ pub struct EncryptedMessage {
     associated_data: Option<Vec<u8>>,
 }
 
+impl EncryptedMessage {
+    /// Encodes as `nonce || ciphertext` for storage as an opaque blob, e.g.
+    /// an attachment. Associated data is not included.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = self.nonce.clone();
+        bytes.extend_from_slice(&self.ciphertext);
+        bytes
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, CryptoError> {
+        if bytes.len() < 12 {
+            return Err(CryptoError::InvalidMessageFormat("Message too short".to_string()));
+        }
+        let (nonce, ciphertext) = bytes.split_at(12);
+        Ok(Self {
+            nonce: nonce.to_vec(),
+            ciphertext: ciphertext.to_vec(),
+            associated_data: None,
+        })
+    }
+}
+
 pub struct Crypto {
     key: Key<Aes256Gcm>,
     key_pair: Option<KeyPair>,
@@ -50,6 +72,25 @@ impl Crypto {
         })
     }
 
+    /// Restores a cipher from a key previously exported with `key_bytes`,
+    /// e.g. a per-attachment key received inside an encrypted message.
+    pub fn from_key(key_bytes: &[u8]) -> Result<Self, CryptoError> {
+        if key_bytes.len() != 32 {
+            return Err(CryptoError::InvalidKeyFormat(format!(
+                "Expected 32 key bytes, got {}",
+                key_bytes.len()
+            )));
+        }
+        Ok(Self {
+            key: *Key::<Aes256Gcm>::from_slice(key_bytes),
+            key_pair: None,
+        })
+    }
+
+    pub fn key_bytes(&self) -> Vec<u8> {
+        self.key.to_vec()
+    }
+
     pub fn generate_key_pair(&mut self) -> Result<PublicKey, CryptoError> {
         let private_key = EphemeralSecret::random_from_rng(&mut OsRng);
         let public_key = PublicKey::from(&private_key);
@@ -144,4 +185,18 @@ mod tests {
         
         assert_eq!(message, decrypted.as_slice());
     }
-} 
\ No newline at end of file
+
+    #[test]
+    fn test_exported_key_roundtrip() {
+        let crypto = Crypto::new().unwrap();
+        let encrypted = crypto.encrypt(b"attachment bytes", None).unwrap();
+        let blob = encrypted.to_bytes();
+
+        let restored = Crypto::from_key(&crypto.key_bytes()).unwrap();
+        let decrypted = restored.decrypt(&EncryptedMessage::from_bytes(&blob).unwrap()).unwrap();
+        assert_eq!(decrypted, b"attachment bytes");
+
+        assert!(Crypto::from_key(&[0u8; 16]).is_err());
+        assert!(EncryptedMessage::from_bytes(&[0u8; 4]).is_err());
+    }
+}
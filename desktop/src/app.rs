@@ -1,3 +1,5 @@
+use std::sync::mpsc;
+
 use eframe::egui;
 use serde::{Deserialize, Serialize};
 use chrono::{DateTime, Utc};
@@ -20,6 +22,78 @@ pub struct User {
     pub username: String,
     pub email: String,
     pub public_key: Vec<u8>,
+    /// `None` until the address is confirmed; some features are unavailable
+    /// until then.
+    #[serde(default)]
+    pub email_verified_at: Option<DateTime<Utc>>,
+    /// Set while a requested account deletion is pending.
+    #[serde(default)]
+    pub delete_after: Option<DateTime<Utc>>,
+}
+
+/// Another user as the server shows them: no email address.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct UserProfile {
+    pub id: Uuid,
+    pub username: String,
+    pub public_key: Vec<u8>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Contact {
+    pub contact: UserProfile,
+    pub nickname: Option<String>,
+    pub added_at: DateTime<Utc>,
+}
+
+impl Contact {
+    pub fn display_name(&self) -> &str {
+        self.nickname.as_deref().unwrap_or(&self.contact.username)
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Block {
+    pub blocked: UserProfile,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PrivacySettings {
+    pub discoverable_by_username: bool,
+    pub discoverable_by_email: bool,
+    /// Whether others can see when this user is online or was last seen.
+    pub show_last_seen: bool,
+    /// With this off, read receipts are neither sent nor shown.
+    pub send_read_receipts: bool,
+}
+
+/// Another user's online status. Both fields are `None` if they hide it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Presence {
+    pub user_id: Uuid,
+    pub online: Option<bool>,
+    pub last_seen: Option<DateTime<Utc>>,
+}
+
+/// Notifications from the server's event stream.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ServerEvent {
+    PrekeysLow { remaining: i64 },
+    Presence {
+        user_id: Uuid,
+        online: bool,
+        last_seen: Option<DateTime<Utc>>,
+    },
+    /// Shown until `TypingStopped`, or for a few seconds if that never comes.
+    TypingStarted { chat_id: Option<Uuid>, user_id: Uuid },
+    TypingStopped { chat_id: Option<Uuid>, user_id: Uuid },
+    /// An encrypted `ReceiptBody` for messages this user sent.
+    Receipt { id: Uuid, sender_id: Uuid, content: Vec<u8> },
+    /// Sent by a newer server.
+    #[serde(other)]
+    Unknown,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -29,6 +103,74 @@ pub struct Message {
     pub content: String,
     pub timestamp: DateTime<Utc>,
     pub is_encrypted: bool,
+    #[serde(default)]
+    pub attachments: Vec<AttachmentRef>,
+    #[serde(default)]
+    pub kind: MessageKind,
+    /// The message an edit, deletion or reaction refers to.
+    #[serde(default)]
+    pub target_id: Option<Uuid>,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MessageKind {
+    #[default]
+    Message,
+    /// `content` is an encrypted `ReceiptBody`.
+    Receipt,
+    /// `content` is an encrypted `MessageBody` replacing the target's.
+    Edit,
+    /// Removes the target for everyone; `content` is empty.
+    Delete,
+    /// `content` is an encrypted `ReactionBody`.
+    Reaction,
+}
+
+/// How far a sent message has got, in order.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ReceiptStatus {
+    Delivered,
+    Read,
+}
+
+/// The plaintext that gets encrypted into a receipt's content, so that the
+/// server cannot tell which messages were read.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ReceiptBody {
+    pub status: ReceiptStatus,
+    pub message_ids: Vec<Uuid>,
+}
+
+/// The plaintext that gets encrypted into a reaction's content. Each user
+/// has at most one reaction per message; a new one replaces the old.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ReactionBody {
+    pub emoji: String,
+    #[serde(default)]
+    pub remove: bool,
+}
+
+/// An uploaded, encrypted attachment. Everything but the id travels only
+/// inside the encrypted message body.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AttachmentRef {
+    pub id: String,
+    pub file_name: String,
+    pub size: u64,
+    pub key: Vec<u8>,
+}
+
+/// The plaintext that gets encrypted into `Message::content`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MessageBody {
+    pub text: String,
+    #[serde(default)]
+    pub attachments: Vec<AttachmentRef>,
+    /// The message this one replies to. Kept out of the server's sight.
+    #[serde(default)]
+    pub reply_to: Option<Uuid>,
 }
 
 pub enum Screen {
@@ -41,6 +183,10 @@ pub struct PulseApp {
     screen: Screen,
     user: Option<User>,
     messages: Vec<Message>,
+    /// Kept across frames so that contacts, presence and typing state
+    /// survive repaints.
+    chat_screen: Option<ChatScreen>,
+    events: Option<mpsc::Receiver<ServerEvent>>,
     api_client: ApiClient,
     config: Config,
     crypto: CryptoManager,
@@ -56,6 +202,8 @@ impl PulseApp {
             screen: Screen::Login,
             user: None,
             messages: Vec::new(),
+            chat_screen: None,
+            events: None,
             api_client,
             config,
             crypto,
@@ -69,16 +217,17 @@ impl eframe::App for PulseApp {
             Screen::Login => {
                 let login_screen = LoginScreen::new();
                 if let Some(user) = login_screen.show(ctx, &mut self.api_client) {
+                    self.chat_screen = Some(ChatScreen::new(&user, &self.messages, &self.crypto));
+                    self.events = Some(spawn_event_reader(self.api_client.clone(), ctx.clone()));
                     self.user = Some(user);
                     self.screen = Screen::Chat;
                 }
             }
             Screen::Chat => {
-                let chat_screen = ChatScreen::new(
-                    self.user.as_ref().unwrap(),
-                    &self.messages,
-                    &self.crypto,
-                );
+                let chat_screen = self.chat_screen.as_mut().unwrap();
+                for event in self.events.iter().flat_map(|events| events.try_iter()) {
+                    chat_screen.handle_event(&event);
+                }
                 if let Some(new_message) = chat_screen.show(ctx, &mut self.api_client) {
                     self.messages.push(new_message);
                 }
@@ -107,7 +256,14 @@ impl eframe::App for PulseApp {
                 if let Some(user) = &self.user {
                     ui.with_layout(egui::Layout::right_to_left(egui::Align::Center), |ui| {
                         if ui.button("Logout").clicked() {
+                            // Local state is cleared even if the server
+                            // cannot be told; the session then expires.
+                            let _ = self.api_client.logout();
                             self.user = None;
+                            self.chat_screen = None;
+                            // The reader stops once the server ends the
+                            // stream of the revoked session.
+                            self.events = None;
                             self.screen = Screen::Login;
                         }
                         ui.label(format!("Logged in as: {}", user.username));
@@ -116,4 +272,32 @@ impl eframe::App for PulseApp {
             });
         });
     }
-} 
\ No newline at end of file
+}
+
+/// Reads the server's event stream on a background thread, so that the UI
+/// never waits on it, and wakes the UI for each event.
+fn spawn_event_reader(api_client: ApiClient, ctx: egui::Context) -> mpsc::Receiver<ServerEvent> {
+    let (sender, receiver) = mpsc::channel();
+    std::thread::spawn(move || {
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap();
+        runtime.block_on(async move {
+            let mut events = match api_client.events().await {
+                Ok(events) => events,
+                Err(e) => {
+                    tracing::warn!("Could not open event stream: {}", e);
+                    return;
+                }
+            };
+            while let Ok(Some(event)) = events.next().await {
+                if sender.send(event).is_err() {
+                    break;
+                }
+                ctx.request_repaint();
+            }
+        });
+    });
+    receiver
+}
@@ -1,14 +1,63 @@
+use std::collections::{HashMap, HashSet};
+use std::time::{Duration, Instant};
+
 use eframe::egui;
+use pulse_crypto::{Crypto, EncryptedMessage};
 use crate::api::ApiClient;
-use crate::app::{User, Message};
+use crate::app::{
+    User, Message, MessageBody, MessageKind, AttachmentRef, Contact, Presence, ReactionBody, ReceiptBody,
+    ReceiptStatus, ServerEvent,
+};
 use crate::crypto::CryptoManager;
 use uuid::Uuid;
 
+/// How long a typing indicator is shown without being repeated.
+const TYPING_TIMEOUT: Duration = Duration::from_secs(6);
+/// How often to repeat our own typing indicator while typing continues.
+const TYPING_RESEND: Duration = Duration::from_secs(3);
+/// Offered in every message's menu.
+const QUICK_REACTIONS: [&str; 3] = ["👍", "❤", "😂"];
+
+/// Something the user picked from a message's menu.
+enum MessageAction {
+    Reply(Uuid),
+    Edit(Uuid, String),
+    Delete(Uuid),
+    React(Uuid, &'static str),
+}
+
 pub struct ChatScreen {
     user: User,
     messages: Vec<Message>,
     new_message: String,
+    pending_attachments: Vec<AttachmentRef>,
+    error: Option<String>,
     selected_contact: Option<Uuid>,
+    /// `None` until loaded from the server.
+    contacts: Option<Vec<Contact>>,
+    show_add_contact: bool,
+    new_contact_username: String,
+    /// Fetched when a contact is selected, then kept current by events.
+    presence: HashMap<Uuid, Presence>,
+    /// Contacts typing to us, and until when to show it.
+    typing: HashMap<Uuid, Instant>,
+    /// When we last told the selected contact that we are typing.
+    typing_sent_at: Option<Instant>,
+    /// How far each of our own messages has got, from its recipient's
+    /// receipts.
+    receipts: HashMap<Uuid, ReceiptStatus>,
+    /// What we have already told senders about the messages we received.
+    acknowledged: HashMap<Uuid, ReceiptStatus>,
+    /// `None` until loaded from the privacy settings.
+    send_read_receipts: Option<bool>,
+    /// Messages that have been edited since they were sent.
+    edited: HashSet<Uuid>,
+    /// Reactions per message, by reacting user.
+    reactions: HashMap<Uuid, HashMap<Uuid, String>>,
+    /// The own message being edited in the input box, if any.
+    editing: Option<Uuid>,
+    /// The message the one being written replies to, if any.
+    replying_to: Option<Uuid>,
     crypto: CryptoManager,
 }
 
@@ -18,29 +67,442 @@ impl ChatScreen {
             user: user.clone(),
             messages: messages.to_vec(),
             new_message: String::new(),
+            pending_attachments: Vec::new(),
+            error: None,
             selected_contact: None,
+            contacts: None,
+            show_add_contact: false,
+            new_contact_username: String::new(),
+            presence: HashMap::new(),
+            typing: HashMap::new(),
+            typing_sent_at: None,
+            receipts: HashMap::new(),
+            acknowledged: HashMap::new(),
+            send_read_receipts: None,
+            edited: HashSet::new(),
+            reactions: HashMap::new(),
+            editing: None,
+            replying_to: None,
             crypto: crypto.clone(),
         }
     }
 
+    /// Records a receipt for our own messages. Receipts that cannot be
+    /// decrypted are ignored.
+    fn apply_receipt(&mut self, content: &str) {
+        let Ok(plaintext) = self.crypto.decrypt_message(content) else {
+            return;
+        };
+        let Ok(receipt) = serde_json::from_str::<ReceiptBody>(&plaintext) else {
+            return;
+        };
+        for id in receipt.message_ids {
+            let status = self.receipts.entry(id).or_insert(receipt.status);
+            *status = (*status).max(receipt.status);
+        }
+    }
+
+    /// Sends `status` receipts for received messages that have not had one
+    /// yet, one receipt per sender. Only messages from `from` if given.
+    fn acknowledge(&mut self, status: ReceiptStatus, from: Option<Uuid>, api_client: &ApiClient) {
+        let mut pending: HashMap<Uuid, Vec<Uuid>> = HashMap::new();
+        for message in &self.messages {
+            let incoming = message.sender_id != self.user.id && message.kind == MessageKind::Message;
+            let done = self.acknowledged.get(&message.id).is_some_and(|s| *s >= status);
+            if incoming && !done && from.map_or(true, |id| id == message.sender_id) {
+                pending.entry(message.sender_id).or_default().push(message.id);
+            }
+        }
+
+        for (sender_id, message_ids) in pending {
+            let body = ReceiptBody { status, message_ids };
+            let plaintext = serde_json::to_string(&body).unwrap();
+            let Ok(encrypted) = self.crypto.encrypt_message(&plaintext) else {
+                continue;
+            };
+            let receipt = Message {
+                id: Uuid::new_v4(),
+                sender_id: self.user.id,
+                content: encrypted,
+                timestamp: chrono::Utc::now(),
+                is_encrypted: true,
+                attachments: Vec::new(),
+                kind: MessageKind::Receipt,
+                target_id: None,
+            };
+            // Retried on the next frame if it fails.
+            if api_client.send_message(sender_id, &receipt).is_ok() {
+                for id in body.message_ids {
+                    self.acknowledged.insert(id, status);
+                }
+            }
+        }
+    }
+
+    /// Applies receipts, edits, deletions and reactions in the message list
+    /// to the messages they refer to, and removes them from the list. Edits
+    /// and deletions only count when they come from the target's sender.
+    fn absorb_control_messages(&mut self) {
+        let (control, messages): (Vec<Message>, Vec<Message>) = std::mem::take(&mut self.messages)
+            .into_iter()
+            .partition(|m| m.kind != MessageKind::Message);
+        self.messages = messages;
+
+        for message in control {
+            let Some(target_id) = message.target_id else {
+                if message.kind == MessageKind::Receipt && message.sender_id != self.user.id {
+                    self.apply_receipt(&message.content);
+                }
+                continue;
+            };
+            match message.kind {
+                MessageKind::Edit => {
+                    if let Some(target) = self
+                        .messages
+                        .iter_mut()
+                        .find(|m| m.id == target_id && m.sender_id == message.sender_id)
+                    {
+                        target.content = message.content;
+                        target.attachments = message.attachments;
+                        self.edited.insert(target_id);
+                    }
+                }
+                MessageKind::Delete => {
+                    let before = self.messages.len();
+                    self.messages
+                        .retain(|m| !(m.id == target_id && m.sender_id == message.sender_id));
+                    if self.messages.len() < before {
+                        self.reactions.remove(&target_id);
+                        self.edited.remove(&target_id);
+                    }
+                }
+                MessageKind::Reaction => {
+                    let Ok(plaintext) = self.crypto.decrypt_message(&message.content) else {
+                        continue;
+                    };
+                    let Ok(reaction) = serde_json::from_str::<ReactionBody>(&plaintext) else {
+                        continue;
+                    };
+                    let reactions = self.reactions.entry(target_id).or_default();
+                    if reaction.remove {
+                        reactions.remove(&message.sender_id);
+                    } else {
+                        reactions.insert(message.sender_id, reaction.emoji);
+                    }
+                }
+                MessageKind::Message | MessageKind::Receipt => {}
+            }
+        }
+    }
+
+    /// Sends an edit, deletion or reaction for `target_id` and applies it
+    /// locally once the server accepted it.
+    fn send_control(
+        &mut self,
+        kind: MessageKind,
+        target_id: Uuid,
+        recipient_id: Uuid,
+        plaintext: Option<String>,
+        attachments: Vec<AttachmentRef>,
+        api_client: &ApiClient,
+    ) -> bool {
+        let content = match plaintext {
+            Some(plaintext) => match self.crypto.encrypt_message(&plaintext) {
+                Ok(encrypted) => encrypted,
+                Err(e) => {
+                    self.error = Some(e.to_string());
+                    return false;
+                }
+            },
+            None => String::new(),
+        };
+        let mut message = Message {
+            id: Uuid::new_v4(),
+            sender_id: self.user.id,
+            content,
+            timestamp: chrono::Utc::now(),
+            is_encrypted: true,
+            attachments,
+            kind,
+            target_id: Some(target_id),
+        };
+        match api_client.send_message(recipient_id, &message) {
+            Ok(id) => {
+                message.id = id;
+                self.messages.push(message);
+                self.absorb_control_messages();
+                self.error = None;
+                true
+            }
+            Err(e) => {
+                self.error = Some(e.to_string());
+                false
+            }
+        }
+    }
+
+    fn apply_action(&mut self, action: MessageAction, contact_id: Uuid, api_client: &ApiClient) {
+        match action {
+            MessageAction::Reply(id) => {
+                self.editing = None;
+                self.replying_to = Some(id);
+            }
+            MessageAction::Edit(id, text) => {
+                self.replying_to = None;
+                self.editing = Some(id);
+                self.new_message = text;
+            }
+            MessageAction::Delete(id) => {
+                self.send_control(MessageKind::Delete, id, contact_id, None, Vec::new(), api_client);
+            }
+            MessageAction::React(id, emoji) => {
+                // Picking one's current reaction again takes it back.
+                let remove = self
+                    .reactions
+                    .get(&id)
+                    .and_then(|r| r.get(&self.user.id))
+                    .is_some_and(|current| current == emoji);
+                let body = ReactionBody { emoji: emoji.to_string(), remove };
+                let plaintext = serde_json::to_string(&body).unwrap();
+                self.send_control(MessageKind::Reaction, id, contact_id, Some(plaintext), Vec::new(), api_client);
+            }
+        }
+    }
+
+    /// The message's body, decrypted. Content that is not a `MessageBody`
+    /// is shown as plain text.
+    fn decode(&self, message: &Message) -> MessageBody {
+        let mut text = message.content.clone();
+        if message.is_encrypted {
+            if let Ok(decrypted) = self.crypto.decrypt_message(&text) {
+                text = decrypted;
+            }
+        }
+        serde_json::from_str(&text).unwrap_or(MessageBody {
+            text,
+            attachments: Vec::new(),
+            reply_to: None,
+        })
+    }
+
+    /// The start of a message's text, for quoting it in replies. `None` if
+    /// the message is not here, e.g. because it was deleted.
+    fn preview(&self, message_id: Uuid) -> Option<String> {
+        let message = self.messages.iter().find(|m| m.id == message_id)?;
+        let text = self.decode(message).text;
+        let first_line = text.lines().next().unwrap_or_default();
+        let mut preview: String = first_line.chars().take(60).collect();
+        if preview.len() < text.len() {
+            preview.push('…');
+        }
+        Some(preview)
+    }
+
+    /// ✓ sent, ✓✓ delivered, and ✓✓ in blue once read. Read status is only
+    /// shown to users who send read receipts themselves.
+    fn check_marks(&self, message_id: Uuid) -> egui::RichText {
+        let show_read = self.send_read_receipts.unwrap_or(false);
+        match self.receipts.get(&message_id) {
+            Some(ReceiptStatus::Read) if show_read => {
+                egui::RichText::new("✓✓").color(egui::Color32::LIGHT_BLUE)
+            }
+            Some(_) => egui::RichText::new("✓✓").weak(),
+            None => egui::RichText::new("✓").weak(),
+        }
+    }
+
+    pub fn handle_event(&mut self, event: &ServerEvent) {
+        match event {
+            ServerEvent::Presence { user_id, online, last_seen } => {
+                let presence = self.presence.entry(*user_id).or_insert(Presence {
+                    user_id: *user_id,
+                    online: None,
+                    last_seen: None,
+                });
+                presence.online = Some(*online);
+                presence.last_seen = last_seen.or(presence.last_seen);
+                if !online {
+                    self.typing.remove(user_id);
+                }
+            }
+            // Group chats are not shown here yet.
+            ServerEvent::TypingStarted { chat_id: None, user_id } => {
+                self.typing.insert(*user_id, Instant::now() + TYPING_TIMEOUT);
+            }
+            ServerEvent::TypingStopped { chat_id: None, user_id } => {
+                self.typing.remove(user_id);
+            }
+            ServerEvent::Receipt { content, .. } => {
+                self.apply_receipt(&String::from_utf8_lossy(content));
+            }
+            _ => {}
+        }
+    }
+
+    /// "typing…", "online" or "last seen …" for the chat header, or `None`
+    /// if the contact hides it.
+    fn status_line(&mut self, ctx: &egui::Context, contact_id: Uuid) -> Option<String> {
+        let now = Instant::now();
+        self.typing.retain(|_, until| *until > now);
+        if let Some(until) = self.typing.get(&contact_id) {
+            ctx.request_repaint_after(*until - now);
+            return Some("typing…".to_string());
+        }
+
+        let presence = self.presence.get(&contact_id)?;
+        match (presence.online, presence.last_seen) {
+            (Some(true), _) => Some("online".to_string()),
+            (_, Some(last_seen)) => Some(format!(
+                "last seen {}",
+                last_seen.with_timezone(&chrono::Local).format("%d %b %H:%M")
+            )),
+            _ => None,
+        }
+    }
+
+    fn select_contact(&mut self, contact_id: Uuid, api_client: &ApiClient) {
+        self.selected_contact = Some(contact_id);
+        self.typing_sent_at = None;
+        if !self.presence.contains_key(&contact_id) {
+            match api_client.get_presence(contact_id) {
+                Ok(presence) => {
+                    self.presence.insert(contact_id, presence);
+                }
+                Err(e) => self.error = Some(e.to_string()),
+            }
+        }
+    }
+
+    fn notify_typing(&mut self, contact_id: Uuid, api_client: &ApiClient) {
+        let typing = !self.new_message.trim().is_empty();
+        let due = self.typing_sent_at.map_or(true, |at| at.elapsed() >= TYPING_RESEND);
+        // Indicators are best effort; failures are not worth showing.
+        if typing && due {
+            let _ = api_client.send_typing(contact_id, true);
+            self.typing_sent_at = Some(Instant::now());
+        } else if !typing && self.typing_sent_at.take().is_some() {
+            let _ = api_client.send_typing(contact_id, false);
+        }
+    }
+
     pub fn show(&mut self, ctx: &egui::Context, api_client: &mut ApiClient) -> Option<Message> {
         let mut result = None;
 
+        if self.contacts.is_none() {
+            match api_client.get_contacts() {
+                Ok(contacts) => self.contacts = Some(contacts),
+                Err(e) => {
+                    self.contacts = Some(Vec::new());
+                    self.error = Some(e.to_string());
+                }
+            }
+        }
+
+        if self.send_read_receipts.is_none() {
+            match api_client.get_privacy_settings() {
+                Ok(settings) => self.send_read_receipts = Some(settings.send_read_receipts),
+                Err(e) => {
+                    self.send_read_receipts = Some(false);
+                    self.error = Some(e.to_string());
+                }
+            }
+        }
+
+        // Receipts, edits and the like that arrived while no event stream
+        // was open.
+        self.absorb_control_messages();
+        self.acknowledge(ReceiptStatus::Delivered, None, api_client);
+        if let (Some(contact_id), Some(true)) = (self.selected_contact, self.send_read_receipts) {
+            self.acknowledge(ReceiptStatus::Read, Some(contact_id), api_client);
+        }
+
         egui::SidePanel::left("contacts_panel")
             .default_width(200.0)
             .show(ctx, |ui| {
                 ui.heading("Contacts");
                 ui.separator();
-                
-                // TODO: Add contact list
+
+                let mut removed = None;
+                let mut blocked = None;
+                let mut clicked = None;
+                for contact in self.contacts.iter().flatten() {
+                    let selected = self.selected_contact == Some(contact.contact.id);
+                    let response = ui.selectable_label(selected, contact.display_name());
+                    if response.clicked() {
+                        clicked = Some(contact.contact.id);
+                    }
+                    response.context_menu(|ui| {
+                        if ui.button("Remove").clicked() {
+                            removed = Some(contact.contact.id);
+                            ui.close_menu();
+                        }
+                        if ui.button("Block").clicked() {
+                            blocked = Some(contact.contact.id);
+                            ui.close_menu();
+                        }
+                    });
+                }
+                if let Some(contact_id) = clicked {
+                    self.select_contact(contact_id, api_client);
+                }
+                if let Some(contact_id) = removed {
+                    self.remove_contact(contact_id, api_client);
+                }
+                if let Some(contact_id) = blocked {
+                    if let Err(e) = api_client.block_user(contact_id) {
+                        self.error = Some(e.to_string());
+                    } else {
+                        self.remove_contact(contact_id, api_client);
+                    }
+                }
+
+                ui.separator();
                 if ui.button("Add Contact").clicked() {
-                    // TODO: Show add contact dialog
+                    self.show_add_contact = true;
                 }
             });
 
+        if self.show_add_contact {
+            let mut open = true;
+            egui::Window::new("Add Contact")
+                .open(&mut open)
+                .collapsible(false)
+                .resizable(false)
+                .show(ctx, |ui| {
+                    ui.label("Username");
+                    let response = ui.text_edit_singleline(&mut self.new_contact_username);
+                    let submitted = response.lost_focus() && ui.input(|i| i.key_pressed(egui::Key::Enter));
+                    if ui.button("Add").clicked() || submitted {
+                        self.add_contact(api_client);
+                    }
+                });
+            if !open {
+                self.show_add_contact = false;
+                self.new_contact_username.clear();
+            }
+        }
+
         egui::CentralPanel::default().show(ctx, |ui| {
             if let Some(contact_id) = self.selected_contact {
+                // Header
+                let name = self
+                    .contacts
+                    .iter()
+                    .flatten()
+                    .find(|c| c.contact.id == contact_id)
+                    .map(|c| c.display_name().to_string())
+                    .unwrap_or_default();
+                let status = self.status_line(ctx, contact_id);
+                ui.horizontal(|ui| {
+                    ui.heading(name);
+                    if let Some(status) = status {
+                        ui.weak(status);
+                    }
+                });
+                ui.separator();
+
                 // Chat view
+                let mut action = None;
                 egui::ScrollArea::vertical()
                     .id_source("chat_messages")
                     .show(ui, |ui| {
@@ -53,69 +515,148 @@ impl ChatScreen {
                             };
 
                             ui.with_layout(egui::Layout::top_down(alignment), |ui| {
-                                let mut text = message.content.clone();
-                                if message.is_encrypted {
-                                    if let Ok(decrypted) = self.crypto.decrypt_message(&text) {
-                                        text = decrypted;
-                                    }
+                                let body = self.decode(message);
+                                if let Some(quoted) = body.reply_to.and_then(|id| self.preview(id)) {
+                                    ui.weak(format!("↪ {}", quoted));
                                 }
+                                let attachments = body.attachments;
+                                let mut text = body.text;
 
+                                let shown = text.clone();
                                 let response = ui.add(egui::TextEdit::multiline(&mut text)
                                     .frame(true)
                                     .interactive(false));
 
+                                for attachment in &attachments {
+                                    let label = format!("📎 {} ({} KB)", attachment.file_name, attachment.size / 1024);
+                                    if ui.button(label).clicked() {
+                                        if let Err(e) = save_attachment(api_client, attachment) {
+                                            self.error = Some(e);
+                                        }
+                                    }
+                                }
+
+                                if let Some(reactions) = self.reactions.get(&message.id) {
+                                    let mut counts: Vec<(&str, usize)> = Vec::new();
+                                    for emoji in reactions.values() {
+                                        match counts.iter_mut().find(|(e, _)| *e == emoji) {
+                                            Some((_, count)) => *count += 1,
+                                            None => counts.push((emoji, 1)),
+                                        }
+                                    }
+                                    if !counts.is_empty() {
+                                        let line: Vec<String> =
+                                            counts.iter().map(|(e, n)| format!("{} {}", e, n)).collect();
+                                        ui.label(line.join("  "));
+                                    }
+                                }
+
+                                ui.horizontal(|ui| {
+                                    if is_own {
+                                        ui.label(self.check_marks(message.id));
+                                    }
+                                    if self.edited.contains(&message.id) {
+                                        ui.weak("(edited)");
+                                    }
+                                    ui.menu_button("⋯", |ui| {
+                                        if ui.button("Reply").clicked() {
+                                            action = Some(MessageAction::Reply(message.id));
+                                            ui.close_menu();
+                                        }
+                                        ui.horizontal(|ui| {
+                                            for emoji in QUICK_REACTIONS {
+                                                if ui.button(emoji).clicked() {
+                                                    action = Some(MessageAction::React(message.id, emoji));
+                                                    ui.close_menu();
+                                                }
+                                            }
+                                        });
+                                        if is_own {
+                                            if ui.button("Edit").clicked() {
+                                                action = Some(MessageAction::Edit(message.id, shown.clone()));
+                                                ui.close_menu();
+                                            }
+                                            if ui.button("Delete for everyone").clicked() {
+                                                action = Some(MessageAction::Delete(message.id));
+                                                ui.close_menu();
+                                            }
+                                        }
+                                    });
+                                });
                                 if response.hovered() {
                                     ui.label(message.timestamp.format("%H:%M").to_string());
                                 }
                             });
                         }
                     });
+                if let Some(action) = action {
+                    self.apply_action(action, contact_id, api_client);
+                }
 
                 ui.separator();
 
+                if let Some(error) = &self.error {
+                    ui.colored_label(egui::Color32::RED, error);
+                }
+
+                if !self.pending_attachments.is_empty() {
+                    ui.horizontal_wrapped(|ui| {
+                        let mut removed = None;
+                        for (i, attachment) in self.pending_attachments.iter().enumerate() {
+                            if ui.small_button(format!("📎 {} ✕", attachment.file_name)).clicked() {
+                                removed = Some(i);
+                            }
+                        }
+                        if let Some(i) = removed {
+                            self.pending_attachments.remove(i);
+                        }
+                    });
+                }
+
+                if let Some(quoted) = self.replying_to.and_then(|id| self.preview(id)) {
+                    ui.horizontal(|ui| {
+                        ui.weak(format!("Replying to: {}", quoted));
+                        if ui.small_button("Cancel").clicked() {
+                            self.replying_to = None;
+                        }
+                    });
+                }
+                if self.editing.is_some() {
+                    ui.horizontal(|ui| {
+                        ui.weak("Editing message");
+                        if ui.small_button("Cancel").clicked() {
+                            self.editing = None;
+                            self.new_message.clear();
+                        }
+                    });
+                }
+
                 // Message input
                 ui.horizontal(|ui| {
+                    if ui.button("📎").on_hover_text("Attach a file").clicked() {
+                        match pick_and_upload(api_client) {
+                            Ok(Some(attachment)) => self.pending_attachments.push(attachment),
+                            Ok(None) => {}
+                            Err(e) => self.error = Some(e),
+                        }
+                    }
+
                     let response = ui.add(egui::TextEdit::multiline(&mut self.new_message)
                         .hint_text("Type a message...")
                         .desired_width(f32::INFINITY));
+                    if response.changed() {
+                        self.notify_typing(contact_id, api_client);
+                    }
 
                     if response.lost_focus() && ui.input(|i| i.key_pressed(egui::Key::Enter)) {
-                        if !self.new_message.trim().is_empty() {
-                            let encrypted = self.crypto.encrypt_message(&self.new_message)
-                                .unwrap_or_else(|_| self.new_message.clone());
-
-                            let message = Message {
-                                id: Uuid::new_v4(),
-                                sender_id: self.user.id,
-                                content: encrypted,
-                                timestamp: chrono::Utc::now(),
-                                is_encrypted: true,
-                            };
-
-                            if let Ok(_) = api_client.send_message(contact_id, &message) {
-                                result = Some(message);
-                                self.new_message.clear();
-                            }
+                        if let Some(message) = self.send(contact_id, api_client) {
+                            result = Some(message);
                         }
                     }
 
                     if ui.button("Send").clicked() {
-                        if !self.new_message.trim().is_empty() {
-                            let encrypted = self.crypto.encrypt_message(&self.new_message)
-                                .unwrap_or_else(|_| self.new_message.clone());
-
-                            let message = Message {
-                                id: Uuid::new_v4(),
-                                sender_id: self.user.id,
-                                content: encrypted,
-                                timestamp: chrono::Utc::now(),
-                                is_encrypted: true,
-                            };
-
-                            if let Ok(_) = api_client.send_message(contact_id, &message) {
-                                result = Some(message);
-                                self.new_message.clear();
-                            }
+                        if let Some(message) = self.send(contact_id, api_client) {
+                            result = Some(message);
                         }
                     }
                 });
@@ -128,4 +669,150 @@ impl ChatScreen {
 
         result
     }
-} 
\ No newline at end of file
+
+    fn add_contact(&mut self, api_client: &ApiClient) {
+        let username = self.new_contact_username.trim();
+        if username.is_empty() {
+            return;
+        }
+
+        let profile = match api_client.lookup_user(username) {
+            Ok(Some(profile)) => profile,
+            Ok(None) => {
+                self.error = Some(format!("No user named {}", username));
+                return;
+            }
+            Err(e) => {
+                self.error = Some(e.to_string());
+                return;
+            }
+        };
+
+        match api_client.add_contact(profile.id, None) {
+            Ok(_) => {
+                self.select_contact(profile.id, api_client);
+                self.show_add_contact = false;
+                self.new_contact_username.clear();
+                self.error = None;
+                // Reloaded on the next frame.
+                self.contacts = None;
+            }
+            Err(e) => self.error = Some(e.to_string()),
+        }
+    }
+
+    fn remove_contact(&mut self, contact_id: Uuid, api_client: &ApiClient) {
+        match api_client.remove_contact(contact_id) {
+            Ok(_) => {
+                if let Some(contacts) = &mut self.contacts {
+                    contacts.retain(|c| c.contact.id != contact_id);
+                }
+                if self.selected_contact == Some(contact_id) {
+                    self.selected_contact = None;
+                }
+            }
+            Err(e) => self.error = Some(e.to_string()),
+        }
+    }
+
+    fn send(&mut self, contact_id: Uuid, api_client: &mut ApiClient) -> Option<Message> {
+        if self.new_message.trim().is_empty() && self.pending_attachments.is_empty() {
+            return None;
+        }
+
+        // An edit keeps what the original replied to.
+        let reply_to = match self.editing {
+            Some(target_id) => self
+                .messages
+                .iter()
+                .find(|m| m.id == target_id)
+                .and_then(|m| self.decode(m).reply_to),
+            None => self.replying_to,
+        };
+        let body = MessageBody {
+            text: self.new_message.clone(),
+            attachments: self.pending_attachments.clone(),
+            reply_to,
+        };
+        let plaintext = serde_json::to_string(&body).unwrap();
+
+        if let Some(target_id) = self.editing {
+            let attachments = self.pending_attachments.clone();
+            if self.send_control(MessageKind::Edit, target_id, contact_id, Some(plaintext), attachments, api_client) {
+                self.editing = None;
+                self.new_message.clear();
+                self.pending_attachments.clear();
+                self.notify_typing(contact_id, api_client);
+            }
+            return None;
+        }
+
+        let encrypted = self.crypto.encrypt_message(&plaintext)
+            .unwrap_or_else(|_| plaintext.clone());
+
+        let mut message = Message {
+            id: Uuid::new_v4(),
+            sender_id: self.user.id,
+            content: encrypted,
+            timestamp: chrono::Utc::now(),
+            is_encrypted: true,
+            attachments: self.pending_attachments.clone(),
+            kind: MessageKind::Message,
+            target_id: None,
+        };
+
+        match api_client.send_message(contact_id, &message) {
+            Ok(id) => {
+                message.id = id;
+                self.replying_to = None;
+                self.new_message.clear();
+                self.pending_attachments.clear();
+                self.notify_typing(contact_id, api_client);
+                self.error = None;
+                Some(message)
+            }
+            Err(e) => {
+                self.error = Some(e.to_string());
+                None
+            }
+        }
+    }
+}
+
+/// Lets the user pick a file, encrypts it under a fresh key and uploads it.
+/// Returns `None` if the dialog was cancelled.
+fn pick_and_upload(api_client: &ApiClient) -> Result<Option<AttachmentRef>, String> {
+    let Some(path) = rfd::FileDialog::new().pick_file() else {
+        return Ok(None);
+    };
+
+    let data = std::fs::read(&path).map_err(|e| e.to_string())?;
+    let crypto = Crypto::new().map_err(|e| e.to_string())?;
+    let encrypted = crypto.encrypt(&data, None).map_err(|e| e.to_string())?;
+    let id = api_client.upload_attachment(&encrypted.to_bytes()).map_err(|e| e.to_string())?;
+
+    Ok(Some(AttachmentRef {
+        id,
+        file_name: path
+            .file_name()
+            .map(|n| n.to_string_lossy().into_owned())
+            .unwrap_or_else(|| "attachment".to_string()),
+        size: data.len() as u64,
+        key: crypto.key_bytes(),
+    }))
+}
+
+fn save_attachment(api_client: &ApiClient, attachment: &AttachmentRef) -> Result<(), String> {
+    let Some(path) = rfd::FileDialog::new()
+        .set_file_name(&attachment.file_name)
+        .save_file()
+    else {
+        return Ok(());
+    };
+
+    let blob = api_client.download_attachment(&attachment.id).map_err(|e| e.to_string())?;
+    let encrypted = EncryptedMessage::from_bytes(&blob).map_err(|e| e.to_string())?;
+    let crypto = Crypto::from_key(&attachment.key).map_err(|e| e.to_string())?;
+    let data = crypto.decrypt(&encrypted).map_err(|e| e.to_string())?;
+    std::fs::write(path, data).map_err(|e| e.to_string())
+}
@@ -0,0 +1,1007 @@
+use reqwest::Client;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+use uuid::Uuid;
+use chrono::{DateTime, Utc};
+use sha2::{Digest, Sha256};
+
+use crate::{Block, Chat, Contact, Message, Presence, PrivacySettings, ServerEvent, ThreadSummary, User, UserProfile, ChatInvite, ChannelPost, ChatPin, ChatSettings, SecurityEvent, StarredMessage};
+
+/// Most hashes the server accepts in one discovery request.
+const MAX_DISCOVERY_HASHES: usize = 1000;
+
+#[derive(Error, Debug)]
+pub enum ApiError {
+    #[error("Network error: {0}")]
+    NetworkError(#[from] reqwest::Error),
+    #[error("Server error: {0}")]
+    ServerError(String),
+    #[error("Authentication error: {0}")]
+    AuthError(String),
+    #[error("Not logged in")]
+    NotLoggedIn,
+    #[error("Invalid email or password")]
+    InvalidCredentials,
+    #[error("Two-factor authentication code required")]
+    MfaRequired { mfa_token: String },
+    #[error("Invalid two-factor authentication code")]
+    InvalidCode,
+    #[error("Access token expired")]
+    TokenExpired,
+    #[error("Session expired, please log in again")]
+    SessionExpired,
+    #[error("Invalid or expired link")]
+    InvalidToken,
+    #[error("Confirm your email address first")]
+    EmailNotVerified,
+    #[error("This account has been suspended")]
+    AccountSuspended,
+    #[error("Username is already taken")]
+    UsernameTaken,
+    #[error("Email is already registered")]
+    EmailTaken,
+    #[error("You are not a member of this chat")]
+    NotAMember,
+    #[error("Not found")]
+    NotFound,
+    #[error("Too many requests, try again in {retry_after} seconds")]
+    RateLimited { retry_after: u64 },
+}
+
+#[derive(Debug, Deserialize)]
+struct ErrorBody {
+    error: ErrorDetail,
+}
+
+#[derive(Debug, Deserialize)]
+struct ErrorDetail {
+    code: String,
+    message: String,
+}
+
+impl ApiError {
+    /// Maps the server's `{"error": {"code", "message"}}` body to a variant.
+    async fn from_response(response: reqwest::Response) -> Self {
+        let status = response.status();
+        let retry_after = response
+            .headers()
+            .get("Retry-After")
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(1);
+
+        let detail = match response.json::<ErrorBody>().await {
+            Ok(body) => body.error,
+            Err(_) => return ApiError::ServerError(format!("Unexpected response ({})", status)),
+        };
+
+        match detail.code.as_str() {
+            "invalid_credentials" => ApiError::InvalidCredentials,
+            "invalid_code" => ApiError::InvalidCode,
+            "token_expired" => ApiError::TokenExpired,
+            "session_expired" => ApiError::SessionExpired,
+            "invalid_token" => ApiError::InvalidToken,
+            "email_not_verified" => ApiError::EmailNotVerified,
+            "account_suspended" => ApiError::AccountSuspended,
+            "username_taken" => ApiError::UsernameTaken,
+            "email_taken" => ApiError::EmailTaken,
+            "not_a_member" => ApiError::NotAMember,
+            "not_found" => ApiError::NotFound,
+            "rate_limited" => ApiError::RateLimited { retry_after },
+            "unauthorized" => ApiError::AuthError(detail.message),
+            _ => ApiError::ServerError(detail.message),
+        }
+    }
+}
+
+pub struct ApiClient {
+    client: Client,
+    base_url: String,
+    token: Option<String>,
+    refresh_token: Option<String>,
+}
+
+impl ApiClient {
+    pub fn new(base_url: &str) -> Self {
+        Self {
+            client: Client::new(),
+            base_url: base_url.to_string(),
+            token: None,
+            refresh_token: None,
+        }
+    }
+
+    fn bearer(&self) -> Result<String, ApiError> {
+        self.token
+            .as_ref()
+            .map(|token| format!("Bearer {}", token))
+            .ok_or(ApiError::NotLoggedIn)
+    }
+
+    pub async fn login(&mut self, email: &str, password: &str) -> Result<User, ApiError> {
+        let response = self.client
+            .post(&format!("{}/api/auth/login", self.base_url))
+            .json(&serde_json::json!({
+                "email": email,
+                "password": password,
+            }))
+            .send()
+            .await?;
+
+        if !response.status().is_success() {
+            return Err(ApiError::from_response(response).await);
+        }
+
+        match response.json().await? {
+            LoginReply::Complete(login_response) => Ok(self.finish_login(login_response)),
+            LoginReply::MfaRequired { mfa_token } => Err(ApiError::MfaRequired { mfa_token }),
+        }
+    }
+
+    /// Completes a login that failed with `ApiError::MfaRequired`, using a
+    /// code from the authenticator app or a recovery code.
+    pub async fn login_mfa(&mut self, mfa_token: &str, code: &str) -> Result<User, ApiError> {
+        let response = self.client
+            .post(&format!("{}/api/auth/login/mfa", self.base_url))
+            .json(&serde_json::json!({
+                "mfa_token": mfa_token,
+                "code": code,
+            }))
+            .send()
+            .await?;
+
+        if !response.status().is_success() {
+            return Err(ApiError::from_response(response).await);
+        }
+
+        let login_response: LoginResponse = response.json().await?;
+        Ok(self.finish_login(login_response))
+    }
+
+    fn finish_login(&mut self, login_response: LoginResponse) -> User {
+        self.token = Some(login_response.access_token);
+        self.refresh_token = Some(login_response.refresh_token);
+        login_response.user
+    }
+
+    /// Confirms the email address with the token from the verification link.
+    pub async fn verify_email(&self, token: &str) -> Result<(), ApiError> {
+        let response = self.client
+            .post(&format!("{}/api/account/verify-email", self.base_url))
+            .json(&serde_json::json!({ "token": token }))
+            .send()
+            .await?;
+
+        if !response.status().is_success() {
+            return Err(ApiError::from_response(response).await);
+        }
+
+        Ok(())
+    }
+
+    pub async fn resend_verification_email(&self) -> Result<(), ApiError> {
+        let response = self.client
+            .post(&format!("{}/api/account/verify-email/resend", self.base_url))
+            .header("Authorization", self.bearer()?)
+            .send()
+            .await?;
+
+        if !response.status().is_success() {
+            return Err(ApiError::from_response(response).await);
+        }
+
+        Ok(())
+    }
+
+    /// Asks for a password reset link. Succeeds whether or not the address
+    /// is registered.
+    pub async fn request_password_reset(&self, email: &str) -> Result<(), ApiError> {
+        let response = self.client
+            .post(&format!("{}/api/auth/password-reset", self.base_url))
+            .json(&serde_json::json!({ "email": email }))
+            .send()
+            .await?;
+
+        if !response.status().is_success() {
+            return Err(ApiError::from_response(response).await);
+        }
+
+        Ok(())
+    }
+
+    /// Sets a new password with the token from the reset link. Every session
+    /// is signed out, so log in again afterwards.
+    pub async fn reset_password(&self, token: &str, password: &str) -> Result<(), ApiError> {
+        let response = self.client
+            .post(&format!("{}/api/auth/password-reset/confirm", self.base_url))
+            .json(&serde_json::json!({
+                "token": token,
+                "password": password,
+            }))
+            .send()
+            .await?;
+
+        if !response.status().is_success() {
+            return Err(ApiError::from_response(response).await);
+        }
+
+        Ok(())
+    }
+
+    /// Downloads everything the server stores about the account as a zip
+    /// archive.
+    pub async fn export_account(&self) -> Result<Vec<u8>, ApiError> {
+        let response = self.client
+            .get(&format!("{}/api/account/export", self.base_url))
+            .header("Authorization", self.bearer()?)
+            .send()
+            .await?;
+
+        if !response.status().is_success() {
+            return Err(ApiError::from_response(response).await);
+        }
+
+        Ok(response.bytes().await?.to_vec())
+    }
+
+    /// Schedules the account for deletion and signs out everywhere. Logging
+    /// in again before the grace period ends cancels it. `code` is required
+    /// when two-factor authentication is enabled.
+    pub async fn delete_account(&mut self, password: &str, code: Option<&str>) -> Result<(), ApiError> {
+        let response = self.client
+            .post(&format!("{}/api/account/delete", self.base_url))
+            .header("Authorization", self.bearer()?)
+            .json(&serde_json::json!({
+                "password": password,
+                "code": code,
+            }))
+            .send()
+            .await?;
+
+        if !response.status().is_success() {
+            return Err(ApiError::from_response(response).await);
+        }
+
+        self.token = None;
+        self.refresh_token = None;
+        Ok(())
+    }
+
+    /// Trades the refresh token for a new token pair. Call after a request
+    /// fails with `ApiError::TokenExpired`, then retry it.
+    pub async fn refresh(&mut self) -> Result<(), ApiError> {
+        let refresh_token = self.refresh_token.take().ok_or(ApiError::SessionExpired)?;
+        let response = self.client
+            .post(&format!("{}/api/auth/refresh", self.base_url))
+            .json(&serde_json::json!({ "refresh_token": refresh_token }))
+            .send()
+            .await?;
+
+        if !response.status().is_success() {
+            self.token = None;
+            return Err(ApiError::from_response(response).await);
+        }
+
+        let tokens: TokenPair = response.json().await?;
+        self.token = Some(tokens.access_token);
+        self.refresh_token = Some(tokens.refresh_token);
+        Ok(())
+    }
+
+    /// Ends the session on the server and forgets the tokens. The tokens are
+    /// dropped even if the server cannot be reached.
+    pub async fn logout(&mut self) -> Result<(), ApiError> {
+        self.refresh_token = None;
+        let token = match self.token.take() {
+            Some(token) => token,
+            None => return Ok(()),
+        };
+
+        let response = self.client
+            .post(&format!("{}/api/auth/logout", self.base_url))
+            .header("Authorization", format!("Bearer {}", token))
+            .send()
+            .await?;
+
+        if !response.status().is_success() {
+            return Err(ApiError::from_response(response).await);
+        }
+
+        Ok(())
+    }
+
+    /// Finds a user by exact username. Returns `None` if there is no such
+    /// user or they cannot be found by username.
+    pub async fn lookup_user(&self, username: &str) -> Result<Option<UserProfile>, ApiError> {
+        let response = self.client
+            .get(&format!("{}/api/users/lookup", self.base_url))
+            .header("Authorization", self.bearer()?)
+            .query(&[("username", username)])
+            .send()
+            .await?;
+
+        if response.status() == reqwest::StatusCode::NOT_FOUND {
+            return Ok(None);
+        }
+        if !response.status().is_success() {
+            return Err(ApiError::from_response(response).await);
+        }
+
+        Ok(Some(response.json().await?))
+    }
+
+    pub async fn get_user(&self, user_id: Uuid) -> Result<UserProfile, ApiError> {
+        let response = self.client
+            .get(&format!("{}/api/users/{}", self.base_url, user_id))
+            .header("Authorization", self.bearer()?)
+            .send()
+            .await?;
+
+        if !response.status().is_success() {
+            return Err(ApiError::from_response(response).await);
+        }
+
+        Ok(response.json().await?)
+    }
+
+    pub async fn get_contacts(&self) -> Result<Vec<Contact>, ApiError> {
+        let response = self.client
+            .get(&format!("{}/api/contacts", self.base_url))
+            .header("Authorization", self.bearer()?)
+            .send()
+            .await?;
+
+        if !response.status().is_success() {
+            return Err(ApiError::from_response(response).await);
+        }
+
+        Ok(response.json().await?)
+    }
+
+    /// Adds a contact, or changes the nickname of an existing one.
+    pub async fn add_contact(&self, user_id: Uuid, nickname: Option<&str>) -> Result<(), ApiError> {
+        let response = self.client
+            .put(&format!("{}/api/contacts/{}", self.base_url, user_id))
+            .header("Authorization", self.bearer()?)
+            .json(&serde_json::json!({ "nickname": nickname }))
+            .send()
+            .await?;
+
+        if !response.status().is_success() {
+            return Err(ApiError::from_response(response).await);
+        }
+
+        Ok(())
+    }
+
+    pub async fn remove_contact(&self, user_id: Uuid) -> Result<(), ApiError> {
+        let response = self.client
+            .delete(&format!("{}/api/contacts/{}", self.base_url, user_id))
+            .header("Authorization", self.bearer()?)
+            .send()
+            .await?;
+
+        if !response.status().is_success() {
+            return Err(ApiError::from_response(response).await);
+        }
+
+        Ok(())
+    }
+
+    pub async fn get_privacy_settings(&self) -> Result<PrivacySettings, ApiError> {
+        let response = self.client
+            .get(&format!("{}/api/account/privacy", self.base_url))
+            .header("Authorization", self.bearer()?)
+            .send()
+            .await?;
+
+        if !response.status().is_success() {
+            return Err(ApiError::from_response(response).await);
+        }
+
+        Ok(response.json().await?)
+    }
+
+    pub async fn set_privacy_settings(&self, settings: PrivacySettings) -> Result<(), ApiError> {
+        let response = self.client
+            .put(&format!("{}/api/account/privacy", self.base_url))
+            .header("Authorization", self.bearer()?)
+            .json(&settings)
+            .send()
+            .await?;
+
+        if !response.status().is_success() {
+            return Err(ApiError::from_response(response).await);
+        }
+
+        Ok(())
+    }
+    /// Finds which of the given email addresses belong to users who can be
+    /// found by email. Only hashes of the addresses are sent.
+    pub async fn discover_contacts(&self, emails: &[String]) -> Result<Vec<(String, UserProfile)>, ApiError> {
+        let mut by_hash = std::collections::HashMap::new();
+        for email in emails {
+            by_hash.insert(email_hash(email), email.clone());
+        }
+        let hashes: Vec<&String> = by_hash.keys().collect();
+
+        let mut found = Vec::new();
+        for chunk in hashes.chunks(MAX_DISCOVERY_HASHES) {
+            let response = self.client
+                .post(&format!("{}/api/users/discover", self.base_url))
+                .header("Authorization", self.bearer()?)
+                .json(&serde_json::json!({ "email_hashes": chunk }))
+                .send()
+                .await?;
+
+            if !response.status().is_success() {
+                return Err(ApiError::from_response(response).await);
+            }
+
+            let matches: Vec<DiscoveredUser> = response.json().await?;
+            found.extend(matches.into_iter().filter_map(|m| {
+                by_hash.get(&m.email_hash).map(|email| (email.clone(), m.user))
+            }));
+        }
+
+        Ok(found)
+    }
+
+    pub async fn get_blocks(&self) -> Result<Vec<Block>, ApiError> {
+        let response = self.client
+            .get(&format!("{}/api/blocks", self.base_url))
+            .header("Authorization", self.bearer()?)
+            .send()
+            .await?;
+
+        if !response.status().is_success() {
+            return Err(ApiError::from_response(response).await);
+        }
+
+        Ok(response.json().await?)
+    }
+
+    /// Stops messages from the user reaching this account. They are not told.
+    pub async fn block_user(&self, user_id: Uuid) -> Result<(), ApiError> {
+        let response = self.client
+            .put(&format!("{}/api/blocks/{}", self.base_url, user_id))
+            .header("Authorization", self.bearer()?)
+            .send()
+            .await?;
+
+        if !response.status().is_success() {
+            return Err(ApiError::from_response(response).await);
+        }
+
+        Ok(())
+    }
+
+    pub async fn unblock_user(&self, user_id: Uuid) -> Result<(), ApiError> {
+        let response = self.client
+            .delete(&format!("{}/api/blocks/{}", self.base_url, user_id))
+            .header("Authorization", self.bearer()?)
+            .send()
+            .await?;
+
+        if !response.status().is_success() {
+            return Err(ApiError::from_response(response).await);
+        }
+
+        Ok(())
+    }
+
+    /// Reports a user to the admins. `evidence` is the decrypted text of
+    /// `message_id`, which only this client can read.
+    pub async fn report_user(&self, report: &NewReport) -> Result<(), ApiError> {
+        let response = self.client
+            .post(&format!("{}/api/reports", self.base_url))
+            .header("Authorization", self.bearer()?)
+            .json(report)
+            .send()
+            .await?;
+
+        if !response.status().is_success() {
+            return Err(ApiError::from_response(response).await);
+        }
+
+        Ok(())
+    }
+
+    /// Returns the id the server gave the message, which is the one edits,
+    /// deletions and reactions refer to.
+    pub async fn send_message(&self, recipient_id: Uuid, message: &Message) -> Result<Uuid, ApiError> {
+        let response = self.client
+            .post(&format!("{}/api/messages", self.base_url))
+            .header("Authorization", self.bearer()?)
+            .json(&serde_json::json!({
+                "recipient_id": recipient_id,
+                "content": message.content,
+                "is_encrypted": message.is_encrypted,
+                "kind": message.kind,
+                "target_id": message.target_id,
+                "chat_id": message.chat_id,
+                "thread_id": message.thread_id,
+            }))
+            .send()
+            .await?;
+
+        if !response.status().is_success() {
+            return Err(ApiError::from_response(response).await);
+        }
+
+        let created: SentMessageResponse = response.json().await?;
+        Ok(created.id)
+    }
+
+    pub async fn get_messages(&self, chat_id: Uuid, limit: i64) -> Result<Vec<Message>, ApiError> {
+        let response = self.client
+            .get(&format!("{}/api/messages?chat_id={}&limit={}", self.base_url, chat_id, limit))
+            .header("Authorization", self.bearer()?)
+            .send()
+            .await?;
+
+        if !response.status().is_success() {
+            return Err(ApiError::from_response(response).await);
+        }
+
+        Ok(response.json().await?)
+    }
+
+    pub async fn get_chats(&self) -> Result<Vec<Chat>, ApiError> {
+        let response = self.client
+            .get(&format!("{}/api/chats", self.base_url))
+            .header("Authorization", self.bearer()?)
+            .send()
+            .await?;
+
+        if !response.status().is_success() {
+            return Err(ApiError::from_response(response).await);
+        }
+
+        Ok(response.json().await?)
+    }
+
+    pub async fn update_chat_settings(&self, chat_id: Uuid, settings: &ChatSettings) -> Result<(), ApiError> {
+        let response = self.client
+            .put(&format!("{}/api/chats/{}/settings", self.base_url, chat_id))
+            .header("Authorization", self.bearer()?)
+            .json(settings)
+            .send()
+            .await?;
+
+        if !response.status().is_success() {
+            return Err(ApiError::from_response(response).await);
+        }
+
+        Ok(())
+    }
+
+    pub async fn mute_chat(&self, chat_id: Uuid, until: Option<DateTime<Utc>>) -> Result<(), ApiError> {
+        let response = self.client
+            .put(&format!("{}/api/chats/{}/mute", self.base_url, chat_id))
+            .header("Authorization", self.bearer()?)
+            .json(&serde_json::json!({ "until": until }))
+            .send()
+            .await?;
+
+        if !response.status().is_success() {
+            return Err(ApiError::from_response(response).await);
+        }
+
+        Ok(())
+    }
+
+    pub async fn get_pins(&self, chat_id: Uuid) -> Result<Vec<ChatPin>, ApiError> {
+        let response = self.client
+            .get(&format!("{}/api/chats/{}/pins", self.base_url, chat_id))
+            .header("Authorization", self.bearer()?)
+            .send()
+            .await?;
+
+        if !response.status().is_success() {
+            return Err(ApiError::from_response(response).await);
+        }
+
+        Ok(response.json().await?)
+    }
+
+    pub async fn create_pin(&self, chat_id: Uuid, content: &[u8]) -> Result<ChatPin, ApiError> {
+        let response = self.client
+            .post(&format!("{}/api/chats/{}/pins", self.base_url, chat_id))
+            .header("Authorization", self.bearer()?)
+            .json(&serde_json::json!({ "content": content }))
+            .send()
+            .await?;
+
+        if !response.status().is_success() {
+            return Err(ApiError::from_response(response).await);
+        }
+
+        Ok(response.json().await?)
+    }
+
+    pub async fn delete_pin(&self, chat_id: Uuid, pin_id: Uuid) -> Result<(), ApiError> {
+        let response = self.client
+            .delete(&format!("{}/api/chats/{}/pins/{}", self.base_url, chat_id, pin_id))
+            .header("Authorization", self.bearer()?)
+            .send()
+            .await?;
+
+        if !response.status().is_success() {
+            return Err(ApiError::from_response(response).await);
+        }
+
+        Ok(())
+    }
+
+    pub async fn get_starred(&self) -> Result<Vec<StarredMessage>, ApiError> {
+        let response = self.client
+            .get(&format!("{}/api/stars", self.base_url))
+            .header("Authorization", self.bearer()?)
+            .send()
+            .await?;
+
+        if !response.status().is_success() {
+            return Err(ApiError::from_response(response).await);
+        }
+
+        Ok(response.json().await?)
+    }
+
+    pub async fn get_security_events(
+        &self,
+        before: Option<i64>,
+        limit: i64,
+    ) -> Result<Vec<SecurityEvent>, ApiError> {
+        let mut url = format!("{}/api/account/security-events?limit={}", self.base_url, limit);
+        if let Some(before) = before {
+            url.push_str(&format!("&before={}", before));
+        }
+        let response = self.client
+            .get(&url)
+            .header("Authorization", self.bearer()?)
+            .send()
+            .await?;
+
+        if !response.status().is_success() {
+            return Err(ApiError::from_response(response).await);
+        }
+
+        Ok(response.json().await?)
+    }
+
+    pub async fn star(&self, message_id: Uuid) -> Result<(), ApiError> {
+        let response = self.client
+            .put(&format!("{}/api/stars/{}", self.base_url, message_id))
+            .header("Authorization", self.bearer()?)
+            .send()
+            .await?;
+
+        if !response.status().is_success() {
+            return Err(ApiError::from_response(response).await);
+        }
+
+        Ok(())
+    }
+
+    pub async fn unstar(&self, message_id: Uuid) -> Result<(), ApiError> {
+        let response = self.client
+            .delete(&format!("{}/api/stars/{}", self.base_url, message_id))
+            .header("Authorization", self.bearer()?)
+            .send()
+            .await?;
+
+        if !response.status().is_success() {
+            return Err(ApiError::from_response(response).await);
+        }
+
+        Ok(())
+    }
+
+    pub async fn create_invite(
+        &self,
+        chat_id: Uuid,
+        expires_in_minutes: Option<i64>,
+        max_uses: Option<i64>,
+        requires_approval: bool,
+    ) -> Result<ChatInvite, ApiError> {
+        let response = self.client
+            .post(&format!("{}/api/chats/{}/invites", self.base_url, chat_id))
+            .header("Authorization", self.bearer()?)
+            .json(&serde_json::json!({
+                "expires_in_minutes": expires_in_minutes,
+                "max_uses": max_uses,
+                "requires_approval": requires_approval,
+            }))
+            .send()
+            .await?;
+
+        if !response.status().is_success() {
+            return Err(ApiError::from_response(response).await);
+        }
+
+        Ok(response.json().await?)
+    }
+
+    pub async fn revoke_invite(&self, chat_id: Uuid, invite_id: Uuid) -> Result<(), ApiError> {
+        let response = self.client
+            .delete(&format!("{}/api/chats/{}/invites/{}", self.base_url, chat_id, invite_id))
+            .header("Authorization", self.bearer()?)
+            .send()
+            .await?;
+
+        if !response.status().is_success() {
+            return Err(ApiError::from_response(response).await);
+        }
+
+        Ok(())
+    }
+
+    /// Returns whether the user joined straight away, as opposed to waiting
+    /// for approval.
+    pub async fn join_invite(&self, code: &str) -> Result<bool, ApiError> {
+        let response = self.client
+            .post(&format!("{}/api/invites/{}/join", self.base_url, code))
+            .header("Authorization", self.bearer()?)
+            .send()
+            .await?;
+
+        if !response.status().is_success() {
+            return Err(ApiError::from_response(response).await);
+        }
+
+        let result: JoinResponse = response.json().await?;
+        Ok(result.status == "joined")
+    }
+
+    pub async fn post_to_channel(&self, chat_id: Uuid, content: &[u8]) -> Result<Uuid, ApiError> {
+        let response = self.client
+            .post(&format!("{}/api/chats/{}/posts", self.base_url, chat_id))
+            .header("Authorization", self.bearer()?)
+            .json(&serde_json::json!({ "content": content }))
+            .send()
+            .await?;
+
+        if !response.status().is_success() {
+            return Err(ApiError::from_response(response).await);
+        }
+
+        let created: SentMessageResponse = response.json().await?;
+        Ok(created.id)
+    }
+
+    /// Newest first, like `get_thread_messages`.
+    pub async fn get_channel_posts(
+        &self,
+        chat_id: Uuid,
+        before: Option<Uuid>,
+        limit: i64,
+    ) -> Result<Vec<ChannelPost>, ApiError> {
+        let mut url = format!("{}/api/chats/{}/posts?limit={}", self.base_url, chat_id, limit);
+        if let Some(before) = before {
+            url.push_str(&format!("&before={}", before));
+        }
+        let response = self.client
+            .get(&url)
+            .header("Authorization", self.bearer()?)
+            .send()
+            .await?;
+
+        if !response.status().is_success() {
+            return Err(ApiError::from_response(response).await);
+        }
+
+        Ok(response.json().await?)
+    }
+
+    pub async fn get_threads(&self, chat_id: Uuid) -> Result<Vec<ThreadSummary>, ApiError> {
+        let response = self.client
+            .get(&format!("{}/api/chats/{}/threads", self.base_url, chat_id))
+            .header("Authorization", self.bearer()?)
+            .send()
+            .await?;
+
+        if !response.status().is_success() {
+            return Err(ApiError::from_response(response).await);
+        }
+
+        Ok(response.json().await?)
+    }
+
+    /// Newest first; pass the oldest message of a page as `before` to get
+    /// the next one.
+    pub async fn get_thread_messages(
+        &self,
+        chat_id: Uuid,
+        thread_id: Uuid,
+        before: Option<Uuid>,
+        limit: i64,
+    ) -> Result<Vec<Message>, ApiError> {
+        let mut url = format!(
+            "{}/api/chats/{}/threads/{}/messages?limit={}",
+            self.base_url, chat_id, thread_id, limit
+        );
+        if let Some(before) = before {
+            url.push_str(&format!("&before={}", before));
+        }
+        let response = self.client
+            .get(&url)
+            .header("Authorization", self.bearer()?)
+            .send()
+            .await?;
+
+        if !response.status().is_success() {
+            return Err(ApiError::from_response(response).await);
+        }
+
+        Ok(response.json().await?)
+    }
+
+    pub async fn mark_thread_read(&self, chat_id: Uuid, thread_id: Uuid, up_to: Uuid) -> Result<(), ApiError> {
+        let response = self.client
+            .post(&format!("{}/api/chats/{}/threads/{}/read", self.base_url, chat_id, thread_id))
+            .header("Authorization", self.bearer()?)
+            .json(&serde_json::json!({ "up_to": up_to }))
+            .send()
+            .await?;
+
+        if !response.status().is_success() {
+            return Err(ApiError::from_response(response).await);
+        }
+
+        Ok(())
+    }
+
+    pub async fn get_presence(&self, user_id: Uuid) -> Result<Presence, ApiError> {
+        let response = self.client
+            .get(&format!("{}/api/users/{}/presence", self.base_url, user_id))
+            .header("Authorization", self.bearer()?)
+            .send()
+            .await?;
+
+        if !response.status().is_success() {
+            return Err(ApiError::from_response(response).await);
+        }
+
+        Ok(response.json().await?)
+    }
+
+    /// Tells the chat's other members that the user started or stopped
+    /// typing. Repeat `typing: true` every few seconds while typing continues.
+    pub async fn send_typing(&self, chat_id: Uuid, typing: bool) -> Result<(), ApiError> {
+        let response = self.client
+            .post(&format!("{}/api/typing", self.base_url))
+            .header("Authorization", self.bearer()?)
+            .json(&serde_json::json!({ "chat_id": chat_id, "typing": typing }))
+            .send()
+            .await?;
+
+        if !response.status().is_success() {
+            return Err(ApiError::from_response(response).await);
+        }
+
+        Ok(())
+    }
+
+    /// Opens the server-sent event stream. The device counts as online for
+    /// as long as it stays open.
+    pub async fn events(&self) -> Result<EventStream, ApiError> {
+        let response = self.client
+            .get(&format!("{}/api/events", self.base_url))
+            .header("Authorization", self.bearer()?)
+            .header("Accept", "text/event-stream")
+            .send()
+            .await?;
+
+        if !response.status().is_success() {
+            return Err(ApiError::from_response(response).await);
+        }
+
+        Ok(EventStream {
+            response,
+            buffer: String::new(),
+        })
+    }
+}
+
+pub struct EventStream {
+    response: reqwest::Response,
+    buffer: String,
+}
+
+impl EventStream {
+    /// Waits for the next event. Returns `None` once the server closes the
+    /// stream, e.g. when the session is revoked.
+    pub async fn next(&mut self) -> Result<Option<ServerEvent>, ApiError> {
+        loop {
+            // Events are separated by a blank line; keep-alives are comments
+            // and have no data.
+            if let Some(end) = self.buffer.find("\n\n") {
+                let block: String = self.buffer.drain(..end + 2).collect();
+                let data: String = block
+                    .lines()
+                    .filter_map(|line| line.strip_prefix("data:"))
+                    .map(str::trim_start)
+                    .collect();
+                if data.is_empty() {
+                    continue;
+                }
+                return serde_json::from_str(&data)
+                    .map(Some)
+                    .map_err(|e| ApiError::ServerError(format!("Invalid event: {}", e)));
+            }
+
+            match self.response.chunk().await? {
+                Some(chunk) => self.buffer.push_str(&String::from_utf8_lossy(&chunk)),
+                None => return Ok(None),
+            }
+        }
+    }
+}
+
+/// The login endpoint either completes the login or asks for a second
+/// factor.
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum LoginReply {
+    Complete(LoginResponse),
+    MfaRequired { mfa_token: String },
+}
+
+#[derive(Debug, Deserialize)]
+struct JoinResponse {
+    status: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct SentMessageResponse {
+    id: Uuid,
+}
+
+#[derive(Debug, Deserialize)]
+struct LoginResponse {
+    access_token: String,
+    refresh_token: String,
+    user: User,
+}
+
+#[derive(Debug, Deserialize)]
+struct TokenPair {
+    access_token: String,
+    refresh_token: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct DiscoveredUser {
+    email_hash: String,
+    user: UserProfile,
+}
+
+/// Hex SHA-256 of the trimmed, lowercased address, as the server expects.
+fn email_hash(email: &str) -> String {
+    Sha256::digest(email.trim().to_lowercase().as_bytes())
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ReportCategory {
+    Spam,
+    Harassment,
+    Impersonation,
+    IllegalContent,
+    Other,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct NewReport {
+    pub user_id: Uuid,
+    pub message_id: Option<Uuid>,
+    pub category: ReportCategory,
+    pub description: Option<String>,
+    pub evidence: Option<String>,
+    /// Also block the user.
+    pub block: bool,
+}
@@ -1,3 +1,6 @@
+use std::collections::{HashMap, HashSet};
+use std::time::{Duration, Instant};
+
 use flutter_rust_bridge::frb;
 use serde::{Deserialize, Serialize};
 use chrono::{DateTime, Utc};
@@ -8,12 +11,104 @@ mod crypto;
 mod config;
 mod storage;
 
+/// How long a typing indicator is shown without being repeated.
+const TYPING_TIMEOUT: Duration = Duration::from_secs(6);
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct User {
     pub id: Uuid,
     pub username: String,
     pub email: String,
     pub public_key: Vec<u8>,
+    /// `None` until the address is confirmed; some features are unavailable
+    /// until then.
+    #[serde(default)]
+    pub email_verified_at: Option<DateTime<Utc>>,
+    /// Set while a requested account deletion is pending.
+    #[serde(default)]
+    pub delete_after: Option<DateTime<Utc>>,
+}
+
+/// Another user as the server shows them: no email address.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct UserProfile {
+    pub id: Uuid,
+    pub username: String,
+    pub public_key: Vec<u8>,
+    /// Automated account run by another user.
+    #[serde(default)]
+    pub is_bot: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Contact {
+    pub contact: UserProfile,
+    pub nickname: Option<String>,
+    pub added_at: DateTime<Utc>,
+}
+
+impl Contact {
+    pub fn display_name(&self) -> &str {
+        self.nickname.as_deref().unwrap_or(&self.contact.username)
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Block {
+    pub blocked: UserProfile,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PrivacySettings {
+    pub discoverable_by_username: bool,
+    pub discoverable_by_email: bool,
+    /// Whether others can see when this user is online or was last seen.
+    pub show_last_seen: bool,
+    /// With this off, read receipts are neither sent nor shown.
+    pub send_read_receipts: bool,
+}
+
+/// Another user's online status. Both fields are `None` if they hide it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Presence {
+    pub user_id: Uuid,
+    pub online: Option<bool>,
+    pub last_seen: Option<DateTime<Utc>>,
+}
+
+/// Notifications from the server's event stream.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ServerEvent {
+    PrekeysLow { remaining: i64 },
+    Presence {
+        user_id: Uuid,
+        online: bool,
+        last_seen: Option<DateTime<Utc>>,
+    },
+    /// Shown until `TypingStopped`, or for a few seconds if that never comes.
+    TypingStarted { chat_id: Option<Uuid>, user_id: Uuid },
+    TypingStopped { chat_id: Option<Uuid>, user_id: Uuid },
+    /// An encrypted `ReceiptBody` for messages this user sent.
+    Receipt { id: Uuid, sender_id: Uuid, content: Vec<u8> },
+    /// Fetch the chat's members again before sending to it.
+    ChatMembersChanged { chat_id: Uuid },
+    /// For admins: someone asked to join through an invite link.
+    JoinRequested { chat_id: Uuid, user_id: Uuid },
+    /// A new post in a channel; fetch it with `get_channel_posts`.
+    ChannelPost { chat_id: Uuid, id: Uuid },
+    /// The chat's settings or pins changed, or this user muted it on
+    /// another device; fetch it again.
+    ChatUpdated { chat_id: Uuid },
+    /// This user starred or unstarred a message on another device.
+    StarsChanged,
+    /// Something security-relevant happened to the account, such as a
+    /// sign-in on a new device; `get_security_events` has the details.
+    SecurityEvent { id: i64, kind: String },
+    /// Sent by a newer server.
+    #[serde(other)]
+    Unknown,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -23,6 +118,144 @@ pub struct Message {
     pub content: String,
     pub timestamp: DateTime<Utc>,
     pub is_encrypted: bool,
+    #[serde(default)]
+    pub kind: MessageKind,
+    /// The message an edit, deletion or reaction refers to.
+    #[serde(default)]
+    pub target_id: Option<Uuid>,
+    #[serde(default)]
+    pub chat_id: Option<Uuid>,
+    /// Chosen by whoever starts the thread, and visible to the server.
+    #[serde(default)]
+    pub thread_id: Option<Uuid>,
+}
+
+/// The plaintext of a reply. Other messages are plain text.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MessageBody {
+    pub text: String,
+    /// The message this one replies to. Kept out of the server's sight.
+    #[serde(default)]
+    pub reply_to: Option<Uuid>,
+}
+
+/// What a pin names, encrypted under the chat's key. Each member holds a
+/// copy of the message with its own id, so it is found by sender and text.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PinBody {
+    pub sender_id: Uuid,
+    pub text: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ChatPin {
+    pub id: Uuid,
+    pub chat_id: Uuid,
+    pub pinned_by: Uuid,
+    pub content: Vec<u8>,
+    pub pinned_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StarredMessage {
+    pub message_id: Uuid,
+    pub starred_at: DateTime<Utc>,
+}
+
+/// An entry of the account's audit log, e.g. `login` with the device
+/// signed in and its name in `details`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SecurityEvent {
+    pub id: i64,
+    pub kind: String,
+    pub device_id: Option<Uuid>,
+    pub details: serde_json::Value,
+    pub created_at: DateTime<Utc>,
+}
+
+/// Set by the chat's admins for every member.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ChatSettings {
+    #[serde(default)]
+    pub topic: Option<String>,
+    #[serde(default)]
+    pub avatar: Option<String>,
+    /// Messages to the chat expire this long after they are sent.
+    #[serde(default)]
+    pub disappear_after_seconds: Option<i64>,
+}
+
+/// A link for joining a group.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ChatInvite {
+    pub id: Uuid,
+    pub chat_id: Uuid,
+    pub code: String,
+    pub expires_at: Option<DateTime<Utc>>,
+    pub max_uses: Option<i64>,
+    pub use_count: i64,
+    pub requires_approval: bool,
+    pub revoked_at: Option<DateTime<Utc>>,
+}
+
+/// A post to a channel, encrypted under the channel's key and stored once
+/// for all subscribers.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ChannelPost {
+    pub id: Uuid,
+    pub chat_id: Uuid,
+    pub sender_id: Uuid,
+    pub content: Vec<u8>,
+    pub created_at: DateTime<Utc>,
+}
+
+/// A thread of a chat, counting only the messages addressed to this user.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ThreadSummary {
+    pub thread_id: Uuid,
+    pub message_count: i64,
+    pub unread_count: i64,
+    pub last_message_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MessageKind {
+    #[default]
+    Message,
+    /// `content` is an encrypted `ReceiptBody`.
+    Receipt,
+    /// `content` is the new, encrypted text of the target.
+    Edit,
+    /// Removes the target for everyone; `content` is empty.
+    Delete,
+    /// `content` is an encrypted `ReactionBody`.
+    Reaction,
+}
+
+/// How far a sent message has got, in order.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ReceiptStatus {
+    Delivered,
+    Read,
+}
+
+/// The plaintext that gets encrypted into a receipt's content, so that the
+/// server cannot tell which messages were read.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ReceiptBody {
+    pub status: ReceiptStatus,
+    pub message_ids: Vec<Uuid>,
+}
+
+/// The plaintext that gets encrypted into a reaction's content. Each user
+/// has at most one reaction per message; a new one replaces the old.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ReactionBody {
+    pub emoji: String,
+    #[serde(default)]
+    pub remove: bool,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -30,6 +263,11 @@ pub struct Chat {
     pub id: Uuid,
     pub name: Option<String>,
     pub is_group: bool,
+    /// Only admins post; everyone else is a read-only subscriber.
+    #[serde(default)]
+    pub is_channel: bool,
+    #[serde(flatten)]
+    pub settings: ChatSettings,
     pub last_message: Option<Message>,
 }
 
@@ -38,6 +276,18 @@ pub struct PulseMobile {
     crypto: crypto::CryptoManager,
     config: config::Config,
     storage: storage::Storage,
+    events: Option<api::EventStream>,
+    /// Last known presence of chat peers, kept current by events.
+    presence: HashMap<Uuid, Presence>,
+    /// Who is typing in which chat (`None` for direct messages), and until
+    /// when to show it.
+    typing: HashMap<(Option<Uuid>, Uuid), Instant>,
+    /// How far each of our own messages has got, from receipts.
+    receipts: HashMap<Uuid, ReceiptStatus>,
+    /// Messages that have been edited since they were sent.
+    edited: HashSet<Uuid>,
+    /// Reactions per message, by reacting user.
+    reactions: HashMap<Uuid, HashMap<Uuid, String>>,
 }
 
 impl PulseMobile {
@@ -52,6 +302,12 @@ impl PulseMobile {
             crypto,
             config,
             storage,
+            events: None,
+            presence: HashMap::new(),
+            typing: HashMap::new(),
+            receipts: HashMap::new(),
+            edited: HashSet::new(),
+            reactions: HashMap::new(),
         })
     }
 
@@ -68,29 +324,451 @@ impl PulseMobile {
             content.to_string()
         };
 
-        let message = Message {
+        let mut message = Message {
             id: Uuid::new_v4(),
-            sender_id: self.storage.get_current_user()?.id,
+            sender_id: self.storage.get_current_user().await?.id,
             content: encrypted,
             timestamp: Utc::now(),
             is_encrypted: self.config.auto_encrypt,
+            kind: MessageKind::Message,
+            target_id: None,
+            chat_id: None,
+            thread_id: None,
+        };
+
+        message.id = self.api_client.send_message(recipient_id, &message).await?;
+        self.storage.save_message(&message).await?;
+        Ok(message)
+    }
+
+    /// Replies to a message of a chat in a thread. Starting a thread means
+    /// picking a new `thread_id`, which other members then reuse.
+    pub async fn reply_in_thread(
+        &self,
+        chat_id: Uuid,
+        thread_id: Uuid,
+        recipient_id: Uuid,
+        reply_to: Uuid,
+        text: &str,
+    ) -> Result<Message, Box<dyn std::error::Error>> {
+        let body = serde_json::to_string(&MessageBody {
+            text: text.to_string(),
+            reply_to: Some(reply_to),
+        })?;
+        let mut message = Message {
+            id: Uuid::new_v4(),
+            sender_id: self.storage.get_current_user().await?.id,
+            content: self.crypto.encrypt_message(&body)?,
+            timestamp: Utc::now(),
+            is_encrypted: true,
+            kind: MessageKind::Message,
+            target_id: None,
+            chat_id: Some(chat_id),
+            thread_id: Some(thread_id),
         };
 
-        self.api_client.send_message(recipient_id, &message).await?;
-        self.storage.save_message(&message)?;
+        message.id = self.api_client.send_message(recipient_id, &message).await?;
+        self.storage.save_message(&message).await?;
         Ok(message)
     }
 
-    pub async fn get_messages(&self, chat_id: Uuid) -> Result<Vec<Message>, Box<dyn std::error::Error>> {
-        let messages = self.api_client.get_messages(chat_id, 50).await?;
+    /// Creates an invite link for a group the user administers.
+    pub async fn create_invite(
+        &self,
+        chat_id: Uuid,
+        expires_in_minutes: Option<i64>,
+        max_uses: Option<i64>,
+        requires_approval: bool,
+    ) -> Result<ChatInvite, Box<dyn std::error::Error>> {
+        Ok(self
+            .api_client
+            .create_invite(chat_id, expires_in_minutes, max_uses, requires_approval)
+            .await?)
+    }
+
+    pub async fn revoke_invite(&self, chat_id: Uuid, invite_id: Uuid) -> Result<(), Box<dyn std::error::Error>> {
+        Ok(self.api_client.revoke_invite(chat_id, invite_id).await?)
+    }
+
+    /// Joins a group through an invite code. Returns false if an admin has
+    /// to approve the request first.
+    pub async fn join_invite(&self, code: &str) -> Result<bool, Box<dyn std::error::Error>> {
+        Ok(self.api_client.join_invite(code).await?)
+    }
+
+    /// Changes the topic, avatar and disappearing-message timer of a chat
+    /// the user administers.
+    pub async fn update_chat_settings(
+        &self,
+        chat_id: Uuid,
+        settings: &ChatSettings,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        Ok(self.api_client.update_chat_settings(chat_id, settings).await?)
+    }
+
+    /// Mutes the chat on all of the user's devices until `until`, or
+    /// unmutes it with `None`.
+    pub async fn mute_chat(&self, chat_id: Uuid, until: Option<DateTime<Utc>>) -> Result<(), Box<dyn std::error::Error>> {
+        Ok(self.api_client.mute_chat(chat_id, until).await?)
+    }
+
+    /// Pins a message for every member of a chat the user administers.
+    pub async fn pin_message(&self, chat_id: Uuid, message: &Message) -> Result<ChatPin, Box<dyn std::error::Error>> {
+        let body = serde_json::to_string(&PinBody {
+            sender_id: message.sender_id,
+            text: self.decode_body(message)?.text,
+        })?;
+        let content = self.crypto.encrypt_message(&body)?.into_bytes();
+        Ok(self.api_client.create_pin(chat_id, &content).await?)
+    }
+
+    pub async fn unpin(&self, chat_id: Uuid, pin_id: Uuid) -> Result<(), Box<dyn std::error::Error>> {
+        Ok(self.api_client.delete_pin(chat_id, pin_id).await?)
+    }
+
+    /// The chat's pins, oldest first, with what each one names.
+    pub async fn get_pins(&self, chat_id: Uuid) -> Result<Vec<(ChatPin, PinBody)>, Box<dyn std::error::Error>> {
+        let mut pins = Vec::new();
+        for pin in self.api_client.get_pins(chat_id).await? {
+            let text = self.crypto.decrypt_message(&String::from_utf8_lossy(&pin.content))?;
+            pins.push((pin, serde_json::from_str(&text)?));
+        }
+        Ok(pins)
+    }
+
+    pub async fn star(&self, message_id: Uuid) -> Result<(), Box<dyn std::error::Error>> {
+        Ok(self.api_client.star(message_id).await?)
+    }
+
+    pub async fn unstar(&self, message_id: Uuid) -> Result<(), Box<dyn std::error::Error>> {
+        Ok(self.api_client.unstar(message_id).await?)
+    }
+
+    /// Messages the user starred on any device, most recently starred
+    /// first. Fetch again on `StarsChanged`.
+    pub async fn get_starred(&self) -> Result<Vec<StarredMessage>, Box<dyn std::error::Error>> {
+        Ok(self.api_client.get_starred().await?)
+    }
+
+    /// The account's security events, newest first; pass the oldest id of
+    /// a page as `before` to get the next one.
+    pub async fn get_security_events(
+        &self,
+        before: Option<i64>,
+    ) -> Result<Vec<SecurityEvent>, Box<dyn std::error::Error>> {
+        Ok(self.api_client.get_security_events(before, 50).await?)
+    }
+
+    /// Posts to a channel the user administers.
+    pub async fn post_to_channel(&self, chat_id: Uuid, text: &str) -> Result<Uuid, Box<dyn std::error::Error>> {
+        let content = self.crypto.encrypt_message(text)?.into_bytes();
+        Ok(self.api_client.post_to_channel(chat_id, &content).await?)
+    }
+
+    /// A page of the channel, newest first. Pass the oldest post of the
+    /// previous page as `before` to load older ones.
+    pub async fn get_channel_posts(
+        &self,
+        chat_id: Uuid,
+        before: Option<Uuid>,
+    ) -> Result<Vec<ChannelPost>, Box<dyn std::error::Error>> {
+        Ok(self.api_client.get_channel_posts(chat_id, before, 50).await?)
+    }
+
+    /// The chat's threads with unread counts, most recently active first.
+    pub async fn get_threads(&self, chat_id: Uuid) -> Result<Vec<ThreadSummary>, Box<dyn std::error::Error>> {
+        Ok(self.api_client.get_threads(chat_id).await?)
+    }
+
+    /// A page of the thread, newest first. Pass the oldest message of the
+    /// previous page as `before` to load older ones.
+    pub async fn get_thread_messages(
+        &self,
+        chat_id: Uuid,
+        thread_id: Uuid,
+        before: Option<Uuid>,
+    ) -> Result<Vec<Message>, Box<dyn std::error::Error>> {
+        Ok(self.api_client.get_thread_messages(chat_id, thread_id, before, 50).await?)
+    }
+
+    /// Marks `up_to` and everything before it in the thread as read.
+    pub async fn mark_thread_read(
+        &self,
+        chat_id: Uuid,
+        thread_id: Uuid,
+        up_to: Uuid,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        Ok(self.api_client.mark_thread_read(chat_id, thread_id, up_to).await?)
+    }
+
+    /// The decrypted text of a message and what it replies to, if anything.
+    pub fn decode_body(&self, message: &Message) -> Result<MessageBody, Box<dyn std::error::Error>> {
+        let text = if message.is_encrypted {
+            self.crypto.decrypt_message(&message.content)?
+        } else {
+            message.content.clone()
+        };
+        Ok(serde_json::from_str(&text).unwrap_or(MessageBody { text, reply_to: None }))
+    }
+
+    /// Replaces the text of one of the user's own messages, for everyone.
+    /// The server only allows this for a while after sending.
+    pub async fn edit_message(
+        &mut self,
+        recipient_id: Uuid,
+        message_id: Uuid,
+        content: &str,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let encrypted = self.crypto.encrypt_message(content)?;
+        let edit = self.send_control(MessageKind::Edit, recipient_id, message_id, encrypted).await?;
+        self.apply_control(&edit).await
+    }
+
+    /// Deletes one of the user's own messages for everyone. The server only
+    /// allows this for a while after sending.
+    pub async fn delete_message(&mut self, recipient_id: Uuid, message_id: Uuid) -> Result<(), Box<dyn std::error::Error>> {
+        let delete = self.send_control(MessageKind::Delete, recipient_id, message_id, String::new()).await?;
+        self.apply_control(&delete).await
+    }
+
+    /// Sets the user's reaction to a message, replacing any earlier one, or
+    /// takes it back.
+    pub async fn react(
+        &mut self,
+        recipient_id: Uuid,
+        message_id: Uuid,
+        emoji: &str,
+        remove: bool,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let body = serde_json::to_string(&ReactionBody { emoji: emoji.to_string(), remove })?;
+        let encrypted = self.crypto.encrypt_message(&body)?;
+        let reaction = self.send_control(MessageKind::Reaction, recipient_id, message_id, encrypted).await?;
+        self.apply_control(&reaction).await
+    }
+
+    /// Reactions to a message, by reacting user.
+    pub fn reactions(&self, message_id: Uuid) -> HashMap<Uuid, String> {
+        self.reactions.get(&message_id).cloned().unwrap_or_default()
+    }
+
+    pub fn is_edited(&self, message_id: Uuid) -> bool {
+        self.edited.contains(&message_id)
+    }
+
+    async fn send_control(
+        &self,
+        kind: MessageKind,
+        recipient_id: Uuid,
+        target_id: Uuid,
+        content: String,
+    ) -> Result<Message, Box<dyn std::error::Error>> {
+        let mut message = Message {
+            id: Uuid::new_v4(),
+            sender_id: self.storage.get_current_user().await?.id,
+            content,
+            timestamp: Utc::now(),
+            is_encrypted: true,
+            kind,
+            target_id: Some(target_id),
+            chat_id: None,
+            thread_id: None,
+        };
+        message.id = self.api_client.send_message(recipient_id, &message).await?;
+        Ok(message)
+    }
+
+    /// Applies an edit, deletion or reaction to the stored message it refers
+    /// to. Edits and deletions only count when they come from the target's
+    /// sender, which the storage checks.
+    async fn apply_control(&mut self, message: &Message) -> Result<(), Box<dyn std::error::Error>> {
+        let Some(target_id) = message.target_id else {
+            return Ok(());
+        };
+        match message.kind {
+            MessageKind::Edit => {
+                if self.storage.update_message_content(target_id, message.sender_id, &message.content).await? {
+                    self.edited.insert(target_id);
+                }
+            }
+            MessageKind::Delete => {
+                if self.storage.delete_message(target_id, message.sender_id).await? {
+                    self.edited.remove(&target_id);
+                    self.reactions.remove(&target_id);
+                }
+            }
+            MessageKind::Reaction => {
+                // Reactions that cannot be decrypted are ignored.
+                let Ok(plaintext) = self.decrypt_message(&message.content) else {
+                    return Ok(());
+                };
+                let Ok(reaction) = serde_json::from_str::<ReactionBody>(&plaintext) else {
+                    return Ok(());
+                };
+                let reactions = self.reactions.entry(target_id).or_default();
+                if reaction.remove {
+                    reactions.remove(&message.sender_id);
+                } else {
+                    reactions.insert(message.sender_id, reaction.emoji);
+                }
+            }
+            MessageKind::Message | MessageKind::Receipt => {}
+        }
+        Ok(())
+    }
+
+    /// Fetches messages, applying any receipts, edits, deletions and
+    /// reactions among them, and confirms delivery of the rest to their
+    /// senders.
+    pub async fn get_messages(&mut self, chat_id: Uuid) -> Result<Vec<Message>, Box<dyn std::error::Error>> {
+        let (control, mut messages): (Vec<Message>, Vec<Message>) = self
+            .api_client
+            .get_messages(chat_id, 50)
+            .await?
+            .into_iter()
+            .partition(|m| m.kind != MessageKind::Message);
+
+        let mut by_sender: HashMap<Uuid, Vec<Uuid>> = HashMap::new();
         for message in &messages {
-            self.storage.save_message(message)?;
+            self.storage.save_message(message).await?;
+            by_sender.entry(message.sender_id).or_default().push(message.id);
+        }
+        for message in &control {
+            if message.kind == MessageKind::Receipt {
+                self.apply_receipt(&message.content);
+                continue;
+            }
+            self.apply_control(message).await?;
+            let Some(target_id) = message.target_id else {
+                continue;
+            };
+            let target = messages
+                .iter_mut()
+                .find(|m| m.id == target_id && m.sender_id == message.sender_id);
+            match (message.kind, target) {
+                (MessageKind::Edit, Some(target)) => target.content = message.content.clone(),
+                (MessageKind::Delete, Some(_)) => messages.retain(|m| m.id != target_id),
+                _ => {}
+            }
+        }
+        for (sender_id, message_ids) in by_sender {
+            self.send_receipt(sender_id, ReceiptStatus::Delivered, message_ids).await?;
         }
         Ok(messages)
     }
 
+    /// Tells the sender that the user has seen these messages, unless the
+    /// user turned read receipts off.
+    pub async fn mark_read(&self, sender_id: Uuid, message_ids: Vec<Uuid>) -> Result<(), Box<dyn std::error::Error>> {
+        if !self.api_client.get_privacy_settings().await?.send_read_receipts {
+            return Ok(());
+        }
+        self.send_receipt(sender_id, ReceiptStatus::Read, message_ids).await
+    }
+
+    /// The furthest receipt for one of our own messages; `None` if it has
+    /// only been sent.
+    pub fn message_status(&self, message_id: Uuid) -> Option<ReceiptStatus> {
+        self.receipts.get(&message_id).copied()
+    }
+
+    async fn send_receipt(
+        &self,
+        sender_id: Uuid,
+        status: ReceiptStatus,
+        message_ids: Vec<Uuid>,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let body = serde_json::to_string(&ReceiptBody { status, message_ids })?;
+        let receipt = Message {
+            id: Uuid::new_v4(),
+            sender_id: self.storage.get_current_user().await?.id,
+            content: self.crypto.encrypt_message(&body)?,
+            timestamp: Utc::now(),
+            is_encrypted: true,
+            kind: MessageKind::Receipt,
+            target_id: None,
+            chat_id: None,
+            thread_id: None,
+        };
+        self.api_client.send_message(sender_id, &receipt).await?;
+        Ok(())
+    }
+
+    /// Receipts that cannot be decrypted are ignored.
+    fn apply_receipt(&mut self, content: &str) {
+        let Ok(plaintext) = self.decrypt_message(content) else {
+            return;
+        };
+        let Ok(receipt) = serde_json::from_str::<ReceiptBody>(&plaintext) else {
+            return;
+        };
+        for id in receipt.message_ids {
+            let status = self.receipts.entry(id).or_insert(receipt.status);
+            *status = (*status).max(receipt.status);
+        }
+    }
+
     pub async fn get_chats(&self) -> Result<Vec<Chat>, Box<dyn std::error::Error>> {
-        self.api_client.get_chats().await
+        Ok(self.api_client.get_chats().await?)
+    }
+
+    /// Waits for the next server event and updates presence and typing
+    /// state from it. Opens the event stream on first use; returns `None`
+    /// when the server closes it.
+    pub async fn next_event(&mut self) -> Result<Option<ServerEvent>, Box<dyn std::error::Error>> {
+        if self.events.is_none() {
+            self.events = Some(self.api_client.events().await?);
+        }
+        let event = self.events.as_mut().unwrap().next().await?;
+        match &event {
+            Some(ServerEvent::Presence { user_id, online, last_seen }) => {
+                let presence = self.presence.entry(*user_id).or_insert(Presence {
+                    user_id: *user_id,
+                    online: None,
+                    last_seen: None,
+                });
+                presence.online = Some(*online);
+                presence.last_seen = last_seen.or(presence.last_seen);
+            }
+            Some(ServerEvent::TypingStarted { chat_id, user_id }) => {
+                self.typing.insert((*chat_id, *user_id), Instant::now() + TYPING_TIMEOUT);
+            }
+            Some(ServerEvent::TypingStopped { chat_id, user_id }) => {
+                self.typing.remove(&(*chat_id, *user_id));
+            }
+            Some(ServerEvent::Receipt { content, .. }) => {
+                self.apply_receipt(&String::from_utf8_lossy(content));
+            }
+            Some(_) => {}
+            None => self.events = None,
+        }
+        Ok(event)
+    }
+
+    /// The user's presence, fetched once and then updated by events.
+    pub async fn get_presence(&mut self, user_id: Uuid) -> Result<Presence, Box<dyn std::error::Error>> {
+        if let Some(presence) = self.presence.get(&user_id) {
+            return Ok(*presence);
+        }
+        let presence = self.api_client.get_presence(user_id).await?;
+        self.presence.insert(user_id, presence);
+        Ok(presence)
+    }
+
+    /// Users currently typing in the chat, for the chat list and header.
+    pub fn typing_in(&mut self, chat_id: Option<Uuid>) -> Vec<Uuid> {
+        let now = Instant::now();
+        self.typing.retain(|_, until| *until > now);
+        self.typing
+            .keys()
+            .filter(|(chat, _)| *chat == chat_id)
+            .map(|(_, user_id)| *user_id)
+            .collect()
+    }
+
+    /// Call while the user types (at most every few seconds) and with
+    /// `false` when they stop or send.
+    pub async fn set_typing(&self, chat_id: Uuid, typing: bool) -> Result<(), Box<dyn std::error::Error>> {
+        Ok(self.api_client.send_typing(chat_id, typing).await?)
     }
 
     pub fn decrypt_message(&self, message: &str) -> Result<String, Box<dyn std::error::Error>> {
@@ -127,6 +805,8 @@ pub struct _Chat {
     pub id: String,
     pub name: Option<String>,
     pub is_group: bool,
+    pub is_channel: bool,
+    pub settings: ChatSettings,
     pub last_message: Option<_Message>,
 }
 
@@ -3,7 +3,7 @@ use uuid::Uuid;
 use chrono::{DateTime, Utc};
 use thiserror::Error;
 
-use crate::{User, Message};
+use crate::{User, Message, MessageKind};
 
 #[derive(Error, Debug)]
 pub enum StorageError {
@@ -89,6 +89,8 @@ impl Storage {
             username: r.get("username"),
             email: r.get("email"),
             public_key: r.get("public_key"),
+            email_verified_at: None,
+            delete_after: None,
         }))
     }
 
@@ -130,10 +132,53 @@ impl Storage {
                 content: r.get("content"),
                 timestamp: DateTime::parse_from_rfc3339(r.get("timestamp")).unwrap().with_timezone(&Utc),
                 is_encrypted: r.get("is_encrypted"),
+                // Only plain messages are stored; the rest are applied to them.
+                kind: MessageKind::Message,
+                target_id: None,
+                chat_id: None,
+                thread_id: None,
             })
             .collect())
     }
 
+    /// Replaces the content of a message by its sender. Returns whether
+    /// there was such a message.
+    pub async fn update_message_content(
+        &self,
+        id: Uuid,
+        sender_id: Uuid,
+        content: &str,
+    ) -> Result<bool, StorageError> {
+        let result = sqlx::query(
+            r#"
+            UPDATE messages SET content = ? WHERE id = ? AND sender_id = ?
+            "#,
+        )
+        .bind(content)
+        .bind(id.to_string())
+        .bind(sender_id.to_string())
+        .execute(&self.pool)
+        .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    /// Deletes a message by its sender. Returns whether there was such a
+    /// message.
+    pub async fn delete_message(&self, id: Uuid, sender_id: Uuid) -> Result<bool, StorageError> {
+        let result = sqlx::query(
+            r#"
+            DELETE FROM messages WHERE id = ? AND sender_id = ?
+            "#,
+        )
+        .bind(id.to_string())
+        .bind(sender_id.to_string())
+        .execute(&self.pool)
+        .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
     pub async fn get_current_user(&self) -> Result<User, StorageError> {
         let row = sqlx::query(
             r#"
@@ -148,6 +193,8 @@ impl Storage {
             username: row.get("username"),
             email: row.get("email"),
             public_key: row.get("public_key"),
+            email_verified_at: None,
+            delete_after: None,
         })
     }
 } 
\ No newline at end of file